@@ -0,0 +1,225 @@
+use crate::{
+    error::RuntimeError,
+    expr::{KadomClass, KadomFunction, KadomInstance, LiteralValue, NativeFunction},
+    interpreter::Interpreter,
+    lexer::Token,
+};
+use std::{cell::RefCell, collections::HashMap, ops::RangeInclusive, rc::Rc};
+
+// Anything a call expression can invoke.
+pub trait KadomCallable {
+    // Accepted argument counts. Unbounded arities end at usize::MAX.
+    fn arity(&self) -> RangeInclusive<usize>;
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<LiteralValue>,
+        paren: &Token,
+    ) -> Result<LiteralValue, RuntimeError>;
+}
+
+// The single entry point for calls: checks the argument count, then invokes the callee.
+pub fn call(
+    callee: Rc<dyn KadomCallable>,
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let arity = callee.arity();
+
+    if !arity.contains(&arguments.len()) {
+        // A range always reads as plural: "0 to 1 arguments".
+        let (expected, singular) = match (*arity.start(), *arity.end()) {
+            (minimum, usize::MAX) => (format!("at least {}", minimum), minimum == 1),
+            (minimum, maximum) if minimum == maximum => (minimum.to_string(), minimum == 1),
+            (minimum, maximum) => (format!("{} to {}", minimum, maximum), false),
+        };
+        let noun = if singular { "argument" } else { "arguments" };
+
+        return Err(RuntimeError::new(
+            paren,
+            format!("Expected {} {}, got {}", expected, noun, arguments.len()),
+        ));
+    }
+
+    callee.call(interpreter, arguments, paren)
+}
+
+impl KadomCallable for KadomFunction {
+    fn arity(&self) -> RangeInclusive<usize> {
+        let params = &self.declaration.params;
+        let required = params
+            .iter()
+            .filter(|param| param.default.is_none())
+            .count();
+
+        match self.declaration.rest {
+            Some(_) => required..=usize::MAX,
+            None => required..=params.len(),
+        }
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<LiteralValue>,
+        paren: &Token,
+    ) -> Result<LiteralValue, RuntimeError> {
+        interpreter.call_function(&self, arguments, paren)
+    }
+}
+
+impl KadomCallable for NativeFunction {
+    fn arity(&self) -> RangeInclusive<usize> {
+        self.arity.clone()
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<LiteralValue>,
+        paren: &Token,
+    ) -> Result<LiteralValue, RuntimeError> {
+        (self.function)(interpreter, arguments, paren)
+    }
+}
+
+// Calling a class creates an instance and runs its initializer, if it has one.
+impl KadomCallable for KadomClass {
+    fn arity(&self) -> RangeInclusive<usize> {
+        match self.methods.get("init") {
+            Some(initializer) => initializer.arity(),
+            None => 0..=0,
+        }
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<LiteralValue>,
+        paren: &Token,
+    ) -> Result<LiteralValue, RuntimeError> {
+        let initializer = self.methods.get("init").cloned();
+        let instance = LiteralValue::Instance(Rc::new(RefCell::new(KadomInstance {
+            class: self,
+            fields: HashMap::new(),
+        })));
+
+        if let Some(initializer) = initializer {
+            interpreter.call_function(&initializer.bind(instance.clone()), arguments, paren)?;
+        }
+
+        Ok(instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Scanner, lexer::TokenType, parser::Parser, resolver::Resolver};
+
+    fn call_global(
+        interpreter: &mut Interpreter,
+        name: &str,
+        arguments: Vec<LiteralValue>,
+    ) -> Result<LiteralValue, RuntimeError> {
+        let paren = Token::new(TokenType::RightParent, ")".to_string(), None, 1, 1);
+        let callee = interpreter.environment.borrow().lookup(name).unwrap();
+        call(
+            callee.as_callable().unwrap(),
+            interpreter,
+            arguments,
+            &paren,
+        )
+    }
+
+    fn interpreter(source: &str) -> Interpreter {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        Resolver::new().resolve(&statements).unwrap();
+        let mut interpreter = Interpreter::with_output(Box::new(std::io::sink()));
+        interpreter.interpret(statements).unwrap();
+        interpreter
+    }
+
+    #[test]
+    fn calls_every_callable_kind() {
+        let mut interpreter = interpreter(
+            "fun double(x) { return x * 2; }
+             class Box { init(value) { this.value = value; } }",
+        );
+
+        let doubled = call_global(&mut interpreter, "double", vec![LiteralValue::Number(4.0)]);
+        assert_eq!(doubled, Ok(LiteralValue::Number(8.0)));
+
+        let length = call_global(
+            &mut interpreter,
+            "len",
+            vec![LiteralValue::String("abc".into())],
+        );
+        assert_eq!(length, Ok(LiteralValue::Number(3.0)));
+
+        let boxed = call_global(&mut interpreter, "Box", vec![LiteralValue::Nil]).unwrap();
+        assert_eq!(boxed.type_name(), "instance");
+    }
+
+    #[test]
+    fn checks_arity_for_every_callable_kind() {
+        let mut interpreter = interpreter(
+            "fun pair(a, b = 2) {}
+             fun many(first, ...rest) {}
+             class Empty {}",
+        );
+
+        let message = |result: Result<LiteralValue, RuntimeError>| result.err().unwrap().message;
+        assert_eq!(
+            message(call_global(&mut interpreter, "pair", vec![])),
+            "Expected 1 to 2 arguments, got 0"
+        );
+        assert_eq!(
+            message(call_global(&mut interpreter, "many", vec![])),
+            "Expected at least 1 argument, got 0"
+        );
+        assert_eq!(
+            message(call_global(
+                &mut interpreter,
+                "pair",
+                vec![LiteralValue::Nil; 3]
+            )),
+            "Expected 1 to 2 arguments, got 3"
+        );
+        assert_eq!(
+            message(call_global(
+                &mut interpreter,
+                "clock",
+                vec![LiteralValue::Nil]
+            )),
+            "Expected 0 arguments, got 1"
+        );
+        assert_eq!(
+            message(call_global(
+                &mut interpreter,
+                "Empty",
+                vec![LiteralValue::Nil]
+            )),
+            "Expected 0 arguments, got 1"
+        );
+    }
+
+    #[test]
+    fn displays_callables() {
+        let interpreter = interpreter("fun f() {} class C {}");
+        let display = |name: &str| {
+            interpreter
+                .environment
+                .borrow()
+                .lookup(name)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(display("f"), "<fn f>");
+        assert_eq!(display("clock"), "<native clock>");
+        assert_eq!(display("C"), "<class C>");
+    }
+}
@@ -0,0 +1,712 @@
+use crate::{
+    expr::{Expr, LiteralValue, Local, ScopeNames},
+    lexer::{Token, TokenType},
+    stmt::{FunctionDeclaration, Stmt},
+};
+use std::rc::Rc;
+
+// One instruction for the VM. Operands index into the chunk's tables; tokens give both names and
+// the position errors are reported at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Constant(usize),
+    Pop,
+    // Reads or assigns a variable, as the resolver located it. Assignment leaves the value.
+    Get {
+        name: usize,
+        local: Option<Local>,
+    },
+    Set {
+        name: usize,
+        local: Option<Local>,
+    },
+    // Pops a value into a new variable in the current scope.
+    Define(usize),
+    DefineUninitialized(usize),
+    // Pops a list and defines the `count` names starting at `names` from its items.
+    Destructure {
+        bracket: usize,
+        names: usize,
+        count: usize,
+    },
+    Unary(usize),
+    // Binary operators, each with its token. Numbers take a fast path; other operands go through
+    // the same code as the tree-walker's.
+    Add(usize),
+    Subtract(usize),
+    Multiply(usize),
+    Divide(usize),
+    Remainder(usize),
+    Less(usize),
+    LessEqual(usize),
+    Greater(usize),
+    GreaterEqual(usize),
+    Equal(usize),
+    NotEqual(usize),
+    // Any other binary operator, such as `in`.
+    Binary(usize),
+    List(usize),
+    // Pops `count` key/value pairs.
+    Map(usize),
+    // A call to eval made inside a scope indexes the names of the locals around it.
+    Call {
+        paren: usize,
+        arguments: usize,
+        scopes: Option<usize>,
+    },
+    Property(usize),
+    // Reads a property with `?.`: a nil object is left as the value of the whole chain, which
+    // continues at `end`.
+    OptionalProperty {
+        name: usize,
+        end: usize,
+    },
+    // Checks the object of a field assignment, leaving it for SetProperty.
+    SetTarget(usize),
+    SetProperty(usize),
+    Jump(usize),
+    // Pops the condition.
+    JumpIfFalse(usize),
+    // Pops a case value and compares it with the switch subject below it. On a match the subject
+    // is popped too and execution continues at the target.
+    JumpIfCase(usize),
+    Print(usize),
+    // Pops the message of a failed assertion.
+    AssertFailed(usize),
+    Throw(usize),
+    PushScope,
+    PopScope,
+    // Errors until the matching PopHandler continue at the target with the caught value pushed.
+    PushHandler(usize),
+    PopHandler,
+    Function(usize),
+    Class {
+        name: usize,
+        methods: usize,
+        count: usize,
+    },
+    Import(usize),
+    Return,
+}
+
+impl Op {
+    // Index of the token the op reports errors at, for ops that have one.
+    pub fn token(self) -> Option<usize> {
+        match self {
+            Op::Get { name, .. }
+            | Op::Set { name, .. }
+            | Op::Define(name)
+            | Op::DefineUninitialized(name)
+            | Op::Destructure { bracket: name, .. }
+            | Op::Unary(name)
+            | Op::Add(name)
+            | Op::Subtract(name)
+            | Op::Multiply(name)
+            | Op::Divide(name)
+            | Op::Remainder(name)
+            | Op::Less(name)
+            | Op::LessEqual(name)
+            | Op::Greater(name)
+            | Op::GreaterEqual(name)
+            | Op::Equal(name)
+            | Op::NotEqual(name)
+            | Op::Binary(name)
+            | Op::Call { paren: name, .. }
+            | Op::Property(name)
+            | Op::OptionalProperty { name, .. }
+            | Op::SetTarget(name)
+            | Op::SetProperty(name)
+            | Op::Print(name)
+            | Op::AssertFailed(name)
+            | Op::Throw(name)
+            | Op::Class { name, .. }
+            | Op::Import(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<LiteralValue>,
+    pub tokens: Vec<Token>,
+    pub functions: Vec<Rc<FunctionDeclaration>>,
+    pub scopes: Vec<Rc<ScopeNames>>,
+}
+
+// A user function ready for the VM. Defaults run only when their argument is missing.
+pub struct CompiledFunction {
+    pub defaults: Vec<Option<Chunk>>,
+    pub body: Chunk,
+}
+
+// Compiles top-level statements. Running the chunk falls off the end once they're done.
+pub fn compile_program(statements: &[Stmt]) -> Chunk {
+    let mut compiler = Compiler::new(Vec::new());
+    compiler.statements(statements);
+    compiler.chunk
+}
+
+// Compiles an expression evaluated on its own, which the chunk returns.
+pub fn compile_expression(expression: &Expr) -> Chunk {
+    let mut compiler = Compiler::new(Vec::new());
+    compiler.expression(expression);
+    compiler.emit(Op::Return);
+    compiler.chunk
+}
+
+pub fn compile_function(declaration: &FunctionDeclaration) -> CompiledFunction {
+    // Parameters and the body share the call's environment, the one scope the chunk starts in.
+    let defaults = declaration
+        .params
+        .iter()
+        .map(|param| {
+            param.default.as_ref().map(|default| {
+                let mut compiler = Compiler::new(vec![false]);
+                compiler.expression(default);
+                compiler.emit(Op::Return);
+                compiler.chunk
+            })
+        })
+        .collect();
+
+    let mut compiler = Compiler::new(vec![false]);
+    compiler.statements(&declaration.body);
+    CompiledFunction {
+        defaults,
+        body: compiler.chunk,
+    }
+}
+
+struct Loop {
+    // Where `continue` jumps to, once known.
+    continue_target: Option<usize>,
+    continue_jumps: Vec<usize>,
+    break_jumps: Vec<usize>,
+    scopes: usize,
+    handlers: usize,
+}
+
+struct Compiler {
+    chunk: Chunk,
+    // Scopes the resolver saw while compiling this chunk, innermost last. True marks one that
+    // doesn't get an environment at run time.
+    scopes: Vec<bool>,
+    // Environments and handlers pushed at the current point, for unwinding on break and continue.
+    live_scopes: usize,
+    handlers: usize,
+    loops: Vec<Loop>,
+}
+
+impl Compiler {
+    fn new(scopes: Vec<bool>) -> Self {
+        Self {
+            chunk: Chunk::default(),
+            scopes,
+            live_scopes: 0,
+            handlers: 0,
+            loops: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.code.len() - 1
+    }
+
+    fn token(&mut self, token: &Token) -> usize {
+        self.chunk.tokens.push(token.clone());
+        self.chunk.tokens.len() - 1
+    }
+
+    fn constant(&mut self, value: LiteralValue) -> usize {
+        self.chunk.constants.push(value);
+        self.emit(Op::Constant(self.chunk.constants.len() - 1))
+    }
+
+    fn here(&self) -> usize {
+        self.chunk.code.len()
+    }
+
+    // Points a forward jump at the current position.
+    fn patch(&mut self, jump: usize) {
+        let target = self.here();
+        self.chunk.code[jump] = match self.chunk.code[jump] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::JumpIfCase(_) => Op::JumpIfCase(target),
+            Op::PushHandler(_) => Op::PushHandler(target),
+            Op::OptionalProperty { name, .. } => Op::OptionalProperty { name, end: target },
+            op => op,
+        };
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    // Runs the statements in a scope of their own. A scope with nothing declared in it gets no
+    // environment, which saves creating one on every pass through a loop body. Scopes around a
+    // function or class keep theirs, since the closure's body is compiled separately and counts
+    // on every environment the resolver saw.
+    fn scoped(&mut self, statements: &[Stmt]) {
+        let elided = !statements.iter().any(declares) && !statements.iter().any(creates_closure);
+        self.scopes.push(elided);
+        if !elided {
+            self.emit(Op::PushScope);
+            self.live_scopes += 1;
+        }
+
+        self.statements(statements);
+
+        if !elided {
+            self.emit(Op::PopScope);
+            self.live_scopes -= 1;
+        }
+        self.scopes.pop();
+    }
+
+    // The resolver's depth counts every scope; skip the ones without an environment.
+    fn local(&self, local: Option<Local>) -> Option<Local> {
+        local.map(|local| {
+            let elided = self
+                .scopes
+                .iter()
+                .rev()
+                .take(local.depth)
+                .filter(|elided| **elided)
+                .count();
+            Local {
+                depth: local.depth - elided,
+                slot: local.slot,
+            }
+        })
+    }
+
+    // The names of the locals around an eval call, without the scopes that get no environment.
+    fn scope_names(&self, scopes: &ScopeNames) -> Rc<ScopeNames> {
+        let outer = scopes.len() - self.scopes.len();
+        let kept = scopes
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index < outer || !self.scopes[index - outer])
+            .map(|(_, names)| names.clone())
+            .collect();
+        Rc::new(kept)
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Assert {
+                keyword,
+                expression,
+                message,
+            } => {
+                self.expression(expression);
+                let failed = self.emit(Op::JumpIfFalse(0));
+                let passed = self.emit(Op::Jump(0));
+                self.patch(failed);
+                match message {
+                    Some(message) => self.expression(message),
+                    None => {
+                        self.constant(LiteralValue::String(expression.to_string().into()));
+                    }
+                }
+                let keyword = self.token(keyword);
+                self.emit(Op::AssertFailed(keyword));
+                self.patch(passed);
+            }
+            Stmt::Block { statements, .. } => self.scoped(statements),
+            Stmt::Break { .. } => self.jump_out(false),
+            Stmt::Continue { .. } => self.jump_out(true),
+            Stmt::Class { name, methods, .. } => {
+                let start = self.chunk.functions.len();
+                self.chunk.functions.extend(methods.iter().cloned());
+                let name = self.token(name);
+                self.emit(Op::Class {
+                    name,
+                    methods: start,
+                    count: methods.len(),
+                });
+            }
+            Stmt::DoWhile {
+                body, condition, ..
+            } => {
+                let start = self.here();
+                self.begin_loop(None);
+                self.statement(body);
+                self.set_continue_target();
+                self.expression(condition);
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Jump(start));
+                self.patch(exit);
+                self.end_loop();
+            }
+            Stmt::Expression { expression, .. } => {
+                self.expression(expression);
+                self.emit(Op::Pop);
+            }
+            Stmt::Function { declaration, .. } => {
+                self.chunk.functions.push(declaration.clone());
+                self.emit(Op::Function(self.chunk.functions.len() - 1));
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expression(condition);
+                let skip_then = self.emit(Op::JumpIfFalse(0));
+                self.statement(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let skip_else = self.emit(Op::Jump(0));
+                        self.patch(skip_then);
+                        self.statement(else_branch);
+                        self.patch(skip_else);
+                    }
+                    None => self.patch(skip_then),
+                }
+            }
+            Stmt::Import { path, .. } => {
+                let path = self.token(path);
+                self.emit(Op::Import(path));
+            }
+            Stmt::Print {
+                keyword,
+                expression,
+            } => {
+                self.expression(expression);
+                let keyword = self.token(keyword);
+                self.emit(Op::Print(keyword));
+            }
+            Stmt::Return { value, .. } => {
+                match value {
+                    Some(value) => self.expression(value),
+                    None => {
+                        self.constant(LiteralValue::Nil);
+                    }
+                }
+                self.emit(Op::Return);
+            }
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+                ..
+            } => {
+                self.expression(subject);
+                let matches: Vec<usize> = cases
+                    .iter()
+                    .map(|(value, _)| {
+                        self.expression(value);
+                        self.emit(Op::JumpIfCase(0))
+                    })
+                    .collect();
+
+                self.emit(Op::Pop);
+                let mut ends = Vec::new();
+                if let Some(default) = default {
+                    self.scoped(default);
+                }
+                ends.push(self.emit(Op::Jump(0)));
+                for (matched, (_, body)) in matches.into_iter().zip(cases) {
+                    self.patch(matched);
+                    self.scoped(body);
+                    ends.push(self.emit(Op::Jump(0)));
+                }
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+            Stmt::Throw { keyword, value } => {
+                self.expression(value);
+                let keyword = self.token(keyword);
+                self.emit(Op::Throw(keyword));
+            }
+            Stmt::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                let catch = self.emit(Op::PushHandler(0));
+                self.handlers += 1;
+                self.scoped(body);
+                self.handlers -= 1;
+                self.emit(Op::PopHandler);
+                let end = self.emit(Op::Jump(0));
+
+                // The caught value is on the stack, ready to define in the handler's scope.
+                self.patch(catch);
+                self.scopes.push(false);
+                self.emit(Op::PushScope);
+                self.live_scopes += 1;
+                let name = self.token(name);
+                self.emit(Op::Define(name));
+                self.statements(handler);
+                self.emit(Op::PopScope);
+                self.live_scopes -= 1;
+                self.scopes.pop();
+                self.patch(end);
+            }
+            Stmt::Var {
+                name, initialiser, ..
+            } => {
+                let name = self.token(name);
+                match initialiser {
+                    Some(initialiser) => {
+                        self.expression(initialiser);
+                        self.emit(Op::Define(name));
+                    }
+                    None => {
+                        self.emit(Op::DefineUninitialized(name));
+                    }
+                }
+            }
+            Stmt::VarDestructure {
+                bracket,
+                names,
+                initialiser,
+                ..
+            } => {
+                self.expression(initialiser);
+                let bracket = self.token(bracket);
+                let start = self.chunk.tokens.len();
+                for name in names {
+                    self.token(name);
+                }
+                self.emit(Op::Destructure {
+                    bracket,
+                    names: start,
+                    count: names.len(),
+                });
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                let start = self.here();
+                self.begin_loop(Some(start));
+                self.expression(condition);
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.statement(body);
+                self.emit(Op::Jump(start));
+                self.patch(exit);
+                self.end_loop();
+            }
+        }
+    }
+
+    fn begin_loop(&mut self, continue_target: Option<usize>) {
+        self.loops.push(Loop {
+            continue_target,
+            continue_jumps: Vec::new(),
+            break_jumps: Vec::new(),
+            scopes: self.live_scopes,
+            handlers: self.handlers,
+        });
+    }
+
+    fn set_continue_target(&mut self) {
+        let target = self.here();
+        let current = self.loops.last_mut().expect("inside a loop");
+        current.continue_target = Some(target);
+        for jump in std::mem::take(&mut current.continue_jumps) {
+            self.chunk.code[jump] = Op::Jump(target);
+        }
+    }
+
+    fn end_loop(&mut self) {
+        let finished = self.loops.pop().expect("inside a loop");
+        for jump in finished.break_jumps {
+            self.patch(jump);
+        }
+    }
+
+    // Leaves the scopes and handlers entered since the loop began, then jumps. The parser only
+    // allows break and continue inside a loop, so one is always open.
+    fn jump_out(&mut self, is_continue: bool) {
+        let Some(current) = self.loops.last() else {
+            return;
+        };
+        let (scopes, handlers, continue_target) =
+            (current.scopes, current.handlers, current.continue_target);
+        for _ in scopes..self.live_scopes {
+            self.emit(Op::PopScope);
+        }
+        for _ in handlers..self.handlers {
+            self.emit(Op::PopHandler);
+        }
+
+        let target = match (is_continue, continue_target) {
+            (true, Some(target)) => target,
+            _ => 0,
+        };
+        let jump = self.emit(Op::Jump(target));
+        let current = self.loops.last_mut().expect("inside a loop");
+        match (is_continue, current.continue_target) {
+            (true, Some(_)) => (),
+            (true, None) => current.continue_jumps.push(jump),
+            (false, _) => current.break_jumps.push(jump),
+        }
+    }
+
+    fn expression(&mut self, expression: &Expr) {
+        match expression {
+            Expr::Assign { name, value, local } => {
+                self.expression(value);
+                let name = self.token(name);
+                let local = self.local(local.get());
+                self.emit(Op::Set { name, local });
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.expression(right);
+                let op = match operator.token_type() {
+                    TokenType::Plus => Op::Add,
+                    TokenType::Minus => Op::Subtract,
+                    TokenType::Star => Op::Multiply,
+                    TokenType::Slash => Op::Divide,
+                    TokenType::Percent => Op::Remainder,
+                    TokenType::Less => Op::Less,
+                    TokenType::LessEqual => Op::LessEqual,
+                    TokenType::Greater => Op::Greater,
+                    TokenType::GreaterEqual => Op::GreaterEqual,
+                    TokenType::EqualEqual => Op::Equal,
+                    TokenType::BangEqual => Op::NotEqual,
+                    _ => Op::Binary,
+                };
+                let operator = self.token(operator);
+                self.emit(op(operator));
+            }
+            Expr::Call { .. } | Expr::Get { .. } => {
+                let mut ends = Vec::new();
+                self.chain(expression, &mut ends);
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+            Expr::Grouping { expression } => self.expression(expression),
+            Expr::List { elements } => {
+                for element in elements {
+                    self.expression(element);
+                }
+                self.emit(Op::List(elements.len()));
+            }
+            Expr::Literal { value } => {
+                self.constant(value.clone());
+            }
+            Expr::Map { entries } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.emit(Op::Map(entries.len()));
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                let name = self.token(name);
+                self.emit(Op::SetTarget(name));
+                self.expression(value);
+                self.emit(Op::SetProperty(name));
+            }
+            Expr::This { keyword, local } => {
+                let name = self.token(keyword);
+                let local = self.local(local.get());
+                self.emit(Op::Get { name, local });
+            }
+            Expr::Unary { operator, right } => {
+                self.expression(right);
+                let operator = self.token(operator);
+                self.emit(Op::Unary(operator));
+            }
+            Expr::Variable { name, local } => {
+                let name = self.token(name);
+                let local = self.local(local.get());
+                self.emit(Op::Get { name, local });
+            }
+        }
+    }
+
+    // Compiles a chain of calls and property accesses. Each `?.` adds a jump to the end of the
+    // whole chain, taken when its object is nil.
+    fn chain(&mut self, expression: &Expr, ends: &mut Vec<usize>) {
+        match expression {
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                scopes,
+            } => {
+                self.chain(callee, ends);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                let paren = self.token(paren);
+                let scopes = scopes.borrow().as_ref().map(|scopes| {
+                    self.chunk.scopes.push(self.scope_names(scopes));
+                    self.chunk.scopes.len() - 1
+                });
+                self.emit(Op::Call {
+                    paren,
+                    arguments: arguments.len(),
+                    scopes,
+                });
+            }
+            Expr::Get {
+                object,
+                name,
+                optional,
+            } => {
+                self.chain(object, ends);
+                let name = self.token(name);
+                if *optional {
+                    ends.push(self.emit(Op::OptionalProperty { name, end: 0 }));
+                } else {
+                    self.emit(Op::Property(name));
+                }
+            }
+            _ => self.expression(expression),
+        }
+    }
+}
+
+fn declares(statement: &Stmt) -> bool {
+    matches!(
+        statement,
+        Stmt::Var { .. } | Stmt::VarDestructure { .. } | Stmt::Function { .. } | Stmt::Class { .. }
+    )
+}
+
+// Whether a function or class is declared anywhere within the statement.
+fn creates_closure(statement: &Stmt) -> bool {
+    match statement {
+        Stmt::Function { .. } | Stmt::Class { .. } => true,
+        Stmt::Block { statements, .. } => statements.iter().any(creates_closure),
+        Stmt::DoWhile { body, .. } | Stmt::While { body, .. } => creates_closure(body),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => creates_closure(then_branch) || else_branch.as_deref().is_some_and(creates_closure),
+        Stmt::Switch { cases, default, .. } => cases
+            .iter()
+            .flat_map(|(_, body)| body)
+            .chain(default.iter().flatten())
+            .any(creates_closure),
+        Stmt::Try { body, handler, .. } => body.iter().chain(handler).any(creates_closure),
+        _ => false,
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{
+    stmt::Stmt,
+    walk::{walk_program, Walker},
+};
+use std::collections::BTreeMap;
+
+// How many times statements starting on each line ran. Lines where no statement starts aren't
+// in it, which is what tells a line that never ran from one that can't.
+pub type LineCounts = BTreeMap<u64, u64>;
+
+// Adds each line a statement of the program starts on, including in function and method bodies,
+// with a count of 0 unless it's already there.
+pub fn add_executable_lines(statements: &[Stmt], counts: &mut LineCounts) {
+    walk_program(statements, &mut ExecutableLines { counts });
+}
+
+struct ExecutableLines<'a> {
+    counts: &'a mut LineCounts,
+}
+
+impl Walker for ExecutableLines<'_> {
+    fn statement(&mut self, statement: &Stmt) {
+        self.counts.entry(statement.line()).or_insert(0);
+    }
+}
+
+// The source with each line prefixed by how many times it ran, or `-` where no statement starts,
+// followed by the share of executable lines that ran at least once.
+pub fn report(source: &str, counts: &LineCounts) -> String {
+    let width = counts
+        .values()
+        .map(|count| count.to_string().len())
+        .max()
+        .unwrap_or(1);
+
+    let mut report = String::new();
+    for (number, line) in (1..).zip(source.lines()) {
+        let count = counts
+            .get(&number)
+            .map_or("-".to_string(), |count| count.to_string());
+        // Blank lines get no trailing space.
+        report.push_str(format!("{:>width$} | {}", count, line).trim_end());
+        report.push('\n');
+    }
+
+    let executable = counts.len();
+    let covered = counts.values().filter(|count| **count > 0).count();
+    let percent = match executable {
+        0 => 100.0,
+        _ => covered as f64 * 100.0 / executable as f64,
+    };
+    report.push_str(&format!(
+        "\n{} of {} lines covered ({:.1}%)\n",
+        covered, executable, percent
+    ));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, interpreter::Interpreter};
+
+    fn covered(source: &str) -> LineCounts {
+        let mut interpreter = Interpreter::with_output(Box::new(std::io::sink()));
+        interpreter.coverage = Some(LineCounts::new());
+        interpreter.interpret(compile(source).unwrap()).unwrap();
+        interpreter.coverage.unwrap()
+    }
+
+    #[test]
+    fn shows_lines_that_never_ran() {
+        let source = "// pick one
+var x = 2;
+
+if (x > 1)
+    print \"big\";
+else
+    print \"small\";
+fun never() {
+    return 1;
+}
+var i = 0; while (i < 3) i = i + 1;";
+        assert_eq!(
+            report(source, &covered(source)),
+            "- | // pick one
+1 | var x = 2;
+- |
+1 | if (x > 1)
+1 |     print \"big\";
+- | else
+0 |     print \"small\";
+1 | fun never() {
+0 |     return 1;
+- | }
+5 | var i = 0; while (i < 3) i = i + 1;
+
+5 of 7 lines covered (71.4%)
+"
+        );
+    }
+
+    #[test]
+    fn summarises_covered_lines() {
+        let counts = LineCounts::from([(1, 12), (2, 0), (3, 1)]);
+        assert_eq!(
+            report("a;\nb;\nc;\n", &counts),
+            "12 | a;\n 0 | b;\n 1 | c;\n\n2 of 3 lines covered (66.7%)\n"
+        );
+        assert_eq!(
+            report("// nothing to run\n", &LineCounts::new()),
+            "- | // nothing to run\n\n0 of 0 lines covered (100.0%)\n"
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{error::RuntimeError, style::Style};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    // Worth pointing out, but the program still runs.
+    Warning,
+}
+
+// A problem in a program, located by line and, where known, column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: u64,
+    // 1-based byte offset within the line.
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(line: u64, column: Option<usize>, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            line,
+            column,
+            message,
+        }
+    }
+
+    pub fn warning(line: u64, column: Option<usize>, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(line, column, message)
+        }
+    }
+
+    // Formats the diagnostic with the offending line of the source and a caret under the column:
+    //
+    //   error: Expected expression
+    //    --> line 2:9
+    //     |
+    //   2 | var x = ;
+    //     |         ^
+    pub fn render(&self, source: &str) -> String {
+        self.render_from(source, 1)
+    }
+
+    // As render, for source whose first line is line `first_line` of a longer input.
+    pub fn render_from(&self, source: &str, first_line: u64) -> String {
+        self.render_styled(source, first_line, Style::PLAIN)
+    }
+
+    // As render_from, with the label and caret in the severity's colour and the frame around the
+    // source picked out, when `style` is coloured.
+    pub fn render_styled(&self, source: &str, first_line: u64, style: Style) -> String {
+        let paint = |text: &str| match self.severity {
+            Severity::Error => style.error(text),
+            Severity::Warning => style.warning(text),
+        };
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut rendered = format!(
+            "{}: {}\n{} line {}",
+            paint(label),
+            self.message,
+            style.frame(" -->"),
+            self.line
+        );
+        if let Some(column) = self.column {
+            rendered.push_str(&format!(":{}", column));
+        }
+
+        let index = self.line.wrapping_sub(first_line) as usize;
+        let Some(text) = source.lines().nth(index) else {
+            return rendered;
+        };
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let bar = style.frame("|");
+        rendered.push_str(&format!(
+            "\n{} {}\n{} {} {}",
+            gutter,
+            bar,
+            style.frame(&number),
+            bar,
+            text
+        ));
+
+        if let Some(column) = self.column {
+            // Keep tabs so the caret lines up however wide the terminal draws them.
+            let indent: String = text
+                .char_indices()
+                .take_while(|(offset, _)| offset + 1 < column)
+                .map(|(_, c)| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            rendered.push_str(&format!("\n{} {} {}{}", gutter, bar, indent, paint("^")));
+        }
+        rendered
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        Self::new(error.line, Some(error.column), error.message.clone())
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_at_the_column() {
+        let source = "var a = 1;\nvar b = a +;\nprint b;";
+        let diagnostic = Diagnostic::new(2, Some(12), "Expected expression".to_string());
+        assert_eq!(
+            diagnostic.render(source),
+            "error: Expected expression
+ --> line 2:12
+  |
+2 | var b = a +;
+  |            ^"
+        );
+    }
+
+    #[test]
+    fn keeps_tabs_before_the_caret() {
+        let source = "fun f() {\n\tvar b = a +;\n}";
+        let diagnostic = Diagnostic::new(2, Some(12), "Expected expression".to_string());
+        assert_eq!(
+            diagnostic.render(source),
+            "error: Expected expression
+ --> line 2:12
+  |
+2 | \tvar b = a +;
+  | \t          ^"
+        );
+    }
+
+    #[test]
+    fn widens_the_gutter_for_long_line_numbers() {
+        let source = "\n".repeat(9) + "print x;";
+        let diagnostic = Diagnostic::new(10, Some(7), "undefined variable 'x'".to_string());
+        assert_eq!(
+            diagnostic.render(&source),
+            "error: undefined variable 'x'
+ --> line 10:7
+   |
+10 | print x;
+   |       ^"
+        );
+    }
+
+    #[test]
+    fn numbers_lines_from_the_first_line() {
+        let source = "var a = 1;\nvar b = a +;";
+        let diagnostic = Diagnostic::new(8, Some(12), "Expected expression".to_string());
+        assert_eq!(
+            diagnostic.render_from(source, 7),
+            "error: Expected expression
+ --> line 8:12
+  |
+8 | var b = a +;
+  |            ^"
+        );
+        let earlier = Diagnostic::new(3, None, "Expected expression".to_string());
+        assert_eq!(
+            earlier.render_from(source, 7),
+            "error: Expected expression\n --> line 3"
+        );
+    }
+
+    #[test]
+    fn lines_outside_the_source_only_get_the_header() {
+        let diagnostic = Diagnostic::new(5, None, "Expected expression".to_string());
+        assert_eq!(
+            diagnostic.render("print 1;"),
+            "error: Expected expression\n --> line 5"
+        );
+    }
+
+    #[test]
+    fn colours_the_label_caret_and_frame() {
+        let source = "var a = 1;\nvar b = a +;";
+        let diagnostic = Diagnostic::new(2, Some(12), "Expected expression".to_string());
+        assert_eq!(
+            diagnostic.render_styled(source, 1, Style::COLOR),
+            "\x1b[1;31merror\x1b[0m: Expected expression
+\x1b[1;34m -->\x1b[0m line 2:12
+  \x1b[1;34m|\x1b[0m
+\x1b[1;34m2\x1b[0m \x1b[1;34m|\x1b[0m var b = a +;
+  \x1b[1;34m|\x1b[0m            \x1b[1;31m^\x1b[0m"
+        );
+
+        let warning = Diagnostic::warning(1, None, "unused variable 'a'".to_string());
+        assert_eq!(
+            warning.render_styled(source, 1, Style::COLOR),
+            "\x1b[1;33mwarning\x1b[0m: unused variable 'a'
+\x1b[1;34m -->\x1b[0m line 1
+  \x1b[1;34m|\x1b[0m
+\x1b[1;34m1\x1b[0m \x1b[1;34m|\x1b[0m var a = 1;"
+        );
+        assert_eq!(
+            diagnostic.render_styled(source, 1, Style::PLAIN),
+            diagnostic.render(source)
+        );
+    }
+}
@@ -1,26 +1,305 @@
 use crate::expr::LiteralValue;
-use crate::lexer::Token;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
+// Why a read found no value, or an assignment couldn't be made.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unbound {
+    Undeclared,
+    // Declared with `var x;` and not assigned since.
+    Uninitialized,
+    // A built-in constant, which can be read but never assigned.
+    Constant,
+}
+
+// The global environment keys its variables by name. Every other scope stores its locals in
+// declaration order, which matches the slots the resolver assigns. None marks a variable that
+// hasn't been given a value yet.
 pub struct Environment {
-    values: HashMap<String, LiteralValue>,
+    values: HashMap<String, Option<LiteralValue>>,
+    slots: Vec<Option<LiteralValue>>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+    // Globals that assign refuses to change.
+    constants: HashSet<String>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
             values: HashMap::new(),
+            slots: Vec::new(),
+            enclosing: None,
+            constants: HashSet::new(),
+        }
+    }
+
+    pub fn new_enclosed(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            values: HashMap::new(),
+            slots: Vec::new(),
+            enclosing: Some(enclosing),
+            constants: HashSet::new(),
         }
     }
 
     pub fn define(&mut self, name: String, value: LiteralValue) {
-        self.values.insert(name, value);
+        self.declare(name, Some(value));
+    }
+
+    // Defines a global that can't be assigned to afterwards.
+    pub fn define_constant(&mut self, name: String, value: LiteralValue) {
+        self.constants.insert(name.clone());
+        self.define(name, value);
+    }
+
+    pub fn define_uninitialized(&mut self, name: String) {
+        self.declare(name, None);
+    }
+
+    fn declare(&mut self, name: String, value: Option<LiteralValue>) {
+        match self.enclosing {
+            Some(_) => self.slots.push(value),
+            None => {
+                self.values.insert(name, value);
+            }
+        }
+    }
+
+    // Updates a global. Unlike define, this never creates a variable.
+    pub fn assign(&mut self, name: &str, value: LiteralValue) -> Result<(), Unbound> {
+        if self.constants.contains(name) {
+            return Err(Unbound::Constant);
+        }
+        if let Some(slot) = self.values.get_mut(name) {
+            *slot = Some(value);
+            return Ok(());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(Unbound::Undeclared),
+        }
     }
 
-    pub fn get(&self, name: &String) -> Result<LiteralValue, String> {
-        match self.values.get(name) {
-            Some(value) => Ok(value.clone()),
-            None => Err(format!("Variable {} not declared yet!", name)),
+    // Reads the local in the given slot of the scope exactly `distance` levels out.
+    pub fn get_at(&self, distance: usize, slot: usize) -> Result<LiteralValue, Unbound> {
+        match (distance, &self.enclosing) {
+            (0, _) => read(self.slots.get(slot)),
+            (_, Some(enclosing)) => enclosing.borrow().get_at(distance - 1, slot),
+            (_, None) => Err(Unbound::Undeclared),
         }
     }
+
+    pub fn assign_at(&mut self, distance: usize, slot: usize, value: LiteralValue) -> bool {
+        match (distance, &self.enclosing) {
+            (0, _) => match self.slots.get_mut(slot) {
+                Some(local) => {
+                    *local = Some(value);
+                    true
+                }
+                None => false,
+            },
+            (_, Some(enclosing)) => enclosing.borrow_mut().assign_at(distance - 1, slot, value),
+            (_, None) => false,
+        }
+    }
+
+    // Variables defined by name in this scope, sorted by name. None marks an uninitialized one.
+    pub fn variables(&self) -> Vec<(String, Option<LiteralValue>)> {
+        let mut variables: Vec<_> = self
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    // Names of the globals visible from this scope, sorted. Locals are kept by slot, so they have
+    // no names to give.
+    pub fn names(&self) -> Vec<String> {
+        if let Some(enclosing) = &self.enclosing {
+            return enclosing.borrow().names();
+        }
+        let mut names: Vec<String> = self.values.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // Finds a global by name.
+    pub fn lookup(&self, name: &str) -> Result<LiteralValue, Unbound> {
+        match (self.values.get(name), &self.enclosing) {
+            (Some(value), _) => read(Some(value)),
+            (None, Some(enclosing)) => enclosing.borrow().lookup(name),
+            (None, None) => Err(Unbound::Undeclared),
+        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read(value: Option<&Option<LiteralValue>>) -> Result<LiteralValue, Unbound> {
+    match value {
+        Some(Some(value)) => Ok(value.clone()),
+        Some(None) => Err(Unbound::Uninitialized),
+        None => Err(Unbound::Undeclared),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(x: f64) -> LiteralValue {
+        LiteralValue::Number(x)
+    }
+
+    fn enclosed(enclosing: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        Rc::new(RefCell::new(Environment::new_enclosed(enclosing.clone())))
+    }
+
+    #[test]
+    fn inner_definitions_shadow_outer() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let outer = enclosed(&globals);
+        outer.borrow_mut().define("x".to_string(), number(1.0));
+        let inner = enclosed(&outer);
+        inner.borrow_mut().define("x".to_string(), number(2.0));
+
+        assert_eq!(inner.borrow().get_at(0, 0), Ok(number(2.0)));
+        assert_eq!(inner.borrow().get_at(1, 0), Ok(number(1.0)));
+    }
+
+    #[test]
+    fn names_come_from_the_globals() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().define("b".to_string(), number(1.0));
+        globals.borrow_mut().define("a".to_string(), number(2.0));
+        globals.borrow_mut().define("b".to_string(), number(3.0));
+        let inner = enclosed(&enclosed(&globals));
+        inner.borrow_mut().define("local".to_string(), number(4.0));
+
+        assert_eq!(inner.borrow().names(), ["a", "b"]);
+        assert_eq!(globals.borrow().names(), ["a", "b"]);
+    }
+
+    #[test]
+    fn lookup_walks_two_levels_out() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let outer = enclosed(&globals);
+        outer.borrow_mut().define("x".to_string(), number(1.0));
+        outer.borrow_mut().define("y".to_string(), number(2.0));
+        let inner = enclosed(&enclosed(&outer));
+
+        assert_eq!(inner.borrow().get_at(2, 1), Ok(number(2.0)));
+        assert_eq!(inner.borrow().get_at(2, 2), Err(Unbound::Undeclared));
+        assert_eq!(inner.borrow().get_at(5, 0), Err(Unbound::Undeclared));
+    }
+
+    #[test]
+    fn assign_updates_outer_binding() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let outer = enclosed(&globals);
+        outer.borrow_mut().define("x".to_string(), number(1.0));
+        let inner = enclosed(&outer);
+
+        assert!(inner.borrow_mut().assign_at(1, 0, number(2.0)));
+        assert_eq!(outer.borrow().get_at(0, 0), Ok(number(2.0)));
+        assert!(!inner.borrow_mut().assign_at(0, 0, number(3.0)));
+    }
+
+    #[test]
+    fn globals_are_found_by_name_from_any_scope() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().define("x".to_string(), number(1.0));
+        let inner = enclosed(&enclosed(&globals));
+
+        inner.borrow_mut().assign("x", number(2.0)).unwrap();
+        assert_eq!(inner.borrow().lookup("x"), Ok(number(2.0)));
+        assert_eq!(globals.borrow().lookup("x"), Ok(number(2.0)));
+    }
+
+    #[test]
+    fn assign_to_undeclared_errors() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let inner = enclosed(&globals);
+
+        assert_eq!(
+            inner.borrow_mut().assign("y", number(3.0)),
+            Err(Unbound::Undeclared)
+        );
+        assert_eq!(globals.borrow().lookup("y"), Err(Unbound::Undeclared));
+    }
+
+    #[test]
+    fn define_does_not_leak_upward() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let inner = enclosed(&globals);
+        inner.borrow_mut().define("x".to_string(), number(1.0));
+
+        assert_eq!(inner.borrow().get_at(0, 0), Ok(number(1.0)));
+        assert_eq!(globals.borrow().lookup("x"), Err(Unbound::Undeclared));
+    }
+
+    #[test]
+    fn define_overwrites_existing_global() {
+        let mut environment = Environment::new();
+        environment.define("x".to_string(), number(1.0));
+        environment.define("x".to_string(), number(2.0));
+
+        assert_eq!(environment.lookup("x"), Ok(number(2.0)));
+    }
+
+    #[test]
+    fn variables_are_listed_by_name() {
+        let mut environment = Environment::new();
+        environment.define("b".to_string(), number(2.0));
+        environment.define_uninitialized("c".to_string());
+        environment.define("a".to_string(), number(1.0));
+
+        assert_eq!(
+            environment.variables(),
+            vec![
+                ("a".to_string(), Some(number(1.0))),
+                ("b".to_string(), Some(number(2.0))),
+                ("c".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn constants_cannot_be_assigned() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals
+            .borrow_mut()
+            .define_constant("PI".to_string(), number(3.0));
+        let inner = enclosed(&globals);
+
+        assert_eq!(
+            inner.borrow_mut().assign("PI", number(4.0)),
+            Err(Unbound::Constant)
+        );
+        assert_eq!(globals.borrow().lookup("PI"), Ok(number(3.0)));
+    }
+
+    #[test]
+    fn uninitialized_variables_are_unbound_until_assigned() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().define_uninitialized("x".to_string());
+        let inner = enclosed(&globals);
+        inner.borrow_mut().define_uninitialized("y".to_string());
+
+        assert_eq!(globals.borrow().lookup("x"), Err(Unbound::Uninitialized));
+        assert_eq!(inner.borrow().get_at(0, 0), Err(Unbound::Uninitialized));
+
+        globals.borrow_mut().assign("x", number(1.0)).unwrap();
+        assert!(inner.borrow_mut().assign_at(0, 0, number(2.0)));
+        assert_eq!(globals.borrow().lookup("x"), Ok(number(1.0)));
+        assert_eq!(inner.borrow().get_at(0, 0), Ok(number(2.0)));
+    }
 }
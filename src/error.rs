@@ -0,0 +1,171 @@
+use crate::{diagnostics::Diagnostic, expr::LiteralValue, lexer::Token};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub line: u64,
+    pub column: usize,
+    // The value given to `throw`, if this error came from one.
+    pub thrown: Option<LiteralValue>,
+    // The user function calls active when the error happened, innermost first.
+    pub trace: Vec<CallFrame>,
+    // The status passed to exit(), if this is the program ending itself rather than an error.
+    // Nothing catches it.
+    pub exit: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallFrame {
+    pub function: String,
+    // Line of the call site.
+    pub line: u64,
+}
+
+impl fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at {} (line {})", self.function, self.line)
+    }
+}
+
+impl RuntimeError {
+    pub fn new(token: &Token, message: String) -> Self {
+        Self {
+            message,
+            line: token.line(),
+            column: token.column(),
+            thrown: None,
+            trace: Vec::new(),
+            exit: None,
+        }
+    }
+
+    pub fn thrown(token: &Token, value: LiteralValue) -> Self {
+        Self {
+            message: value.to_string(),
+            line: token.line(),
+            column: token.column(),
+            thrown: Some(value),
+            trace: Vec::new(),
+            exit: None,
+        }
+    }
+
+    pub fn exit(token: &Token, code: i32) -> Self {
+        Self {
+            exit: Some(code),
+            ..Self::new(token, format!("exited with status {}", code))
+        }
+    }
+
+    // What a catch clause binds: the thrown value, or the message of a built-in error.
+    pub fn value(self) -> LiteralValue {
+        match self.thrown {
+            Some(value) => value,
+            None => LiteralValue::String(self.message.into()),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[line {}] RuntimeError: {}", self.line, self.message)?;
+        for frame in &self.trace {
+            write!(f, "\n  {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+// Every way running kadom source can fail. Scan, parse and resolve errors are found before any of
+// the program runs; a runtime error keeps its trace and any thrown value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KadomError {
+    Scan { line: u64, message: String },
+    Parse { line: u64, message: String },
+    Resolve { line: u64, message: String },
+    Runtime(RuntimeError),
+    // The program called exit() with this status. It's not a failure, but it stops the run.
+    Exit(i32),
+    // Several errors from one pass over the source, in the order they were found.
+    Multiple(Vec<KadomError>),
+}
+
+impl KadomError {
+    // The line of the error, or of the first one when there are several.
+    pub fn line(&self) -> u64 {
+        match self {
+            Self::Scan { line, .. } | Self::Parse { line, .. } | Self::Resolve { line, .. } => {
+                *line
+            }
+            Self::Runtime(err) => err.line,
+            Self::Exit(_) => 0,
+            Self::Multiple(errors) => errors.first().map_or(0, |err| err.line()),
+        }
+    }
+
+    // Whether none of the program ran because the source didn't scan, parse or resolve.
+    pub fn is_static(&self) -> bool {
+        match self {
+            Self::Runtime(_) | Self::Exit(_) => false,
+            Self::Multiple(errors) => errors.iter().all(|err| err.is_static()),
+            _ => true,
+        }
+    }
+
+    // Turns the diagnostics from one pass into errors of the given kind. A single diagnostic
+    // becomes a single error rather than a list of one.
+    pub(crate) fn from_diagnostics(
+        diagnostics: Vec<Diagnostic>,
+        kind: fn(u64, String) -> Self,
+    ) -> Self {
+        let mut errors: Vec<Self> = diagnostics
+            .into_iter()
+            .map(|diagnostic| kind(diagnostic.line, diagnostic.message))
+            .collect();
+        match errors.len() {
+            1 => errors.remove(0),
+            _ => Self::Multiple(errors),
+        }
+    }
+
+    pub(crate) fn scan(line: u64, message: String) -> Self {
+        Self::Scan { line, message }
+    }
+
+    pub(crate) fn parse(line: u64, message: String) -> Self {
+        Self::Parse { line, message }
+    }
+
+    pub(crate) fn resolve(line: u64, message: String) -> Self {
+        Self::Resolve { line, message }
+    }
+}
+
+// Static errors keep their line beside the message, so it is put back when shown on its own.
+impl fmt::Display for KadomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Scan { line, message }
+            | Self::Parse { line, message }
+            | Self::Resolve { line, message } => write!(f, "{} on line {}", message, line),
+            Self::Runtime(err) => write!(f, "{}", err),
+            Self::Exit(code) => write!(f, "exited with status {}", code),
+            Self::Multiple(errors) => {
+                let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for KadomError {}
+
+impl From<RuntimeError> for KadomError {
+    fn from(err: RuntimeError) -> Self {
+        match err.exit {
+            Some(code) => Self::Exit(code),
+            None => Self::Runtime(err),
+        }
+    }
+}
@@ -1,41 +1,219 @@
 use crate::{
+    callable::{self, KadomCallable},
     environment::Environment,
+    error::{KadomError, RuntimeError},
+    interpreter::Interpreter,
     lexer::{self, Token, TokenType},
+    stmt::FunctionDeclaration,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ops::RangeInclusive,
+    rc::Rc,
 };
 
-fn unwrap_as_f32(literal: Option<lexer::LiteralValue>) -> Result<f32, String> {
+fn unwrap_as_f64(literal: Option<&lexer::LiteralValue>) -> Result<f64, String> {
     match literal {
-        Some(lexer::LiteralValue::IntVal(s)) => Ok(s as f32),
-        Some(lexer::LiteralValue::FVal(s)) => Ok(s as f32),
-        _ => Err("Could not unwrap as f32".to_string()),
+        Some(lexer::LiteralValue::FVal(s)) => Ok(*s),
+        _ => Err("Could not unwrap as f64".to_string()),
     }
 }
 
-fn unwrap_as_string(literal: Option<lexer::LiteralValue>) -> Result<String, String> {
+fn unwrap_as_string(literal: Option<&lexer::LiteralValue>) -> Result<Rc<str>, String> {
     match literal {
-        Some(lexer::LiteralValue::StringVal(s)) => Ok(s),
-        Some(lexer::LiteralValue::IdentifierVal(s)) => Ok(s),
+        Some(lexer::LiteralValue::StringVal(s)) => Ok(s.clone()),
         _ => Err("Could not unwrap as string".to_string()),
     }
 }
 
+// Longest string that repetition is allowed to build.
+const MAX_REPEAT_LENGTH: usize = 1 << 24;
+
+fn repeat(string: &str, count: f64, operator: &Token) -> Result<LiteralValue, RuntimeError> {
+    if count < 0.0 || count.fract() != 0.0 {
+        return Err(RuntimeError::new(
+            operator,
+            format!(
+                "String repetition count must be a non-negative whole number, got {}",
+                LiteralValue::Number(count)
+            ),
+        ));
+    }
+    if string.len() as f64 * count > MAX_REPEAT_LENGTH as f64 {
+        return Err(RuntimeError::new(
+            operator,
+            format!(
+                "String repetition would exceed the maximum length of {} bytes",
+                MAX_REPEAT_LENGTH
+            ),
+        ));
+    }
+
+    Ok(LiteralValue::String(string.repeat(count as usize).into()))
+}
+
+pub struct KadomFunction {
+    pub declaration: Rc<FunctionDeclaration>,
+    pub closure: Rc<RefCell<Environment>>,
+    pub is_initializer: bool,
+}
+
+impl KadomFunction {
+    // Copy of this method with `this` bound to the given instance.
+    pub fn bind(&self, instance: LiteralValue) -> Self {
+        let mut environment = Environment::new_enclosed(self.closure.clone());
+        environment.define("this".to_string(), instance);
+
+        Self {
+            declaration: self.declaration.clone(),
+            closure: Rc::new(RefCell::new(environment)),
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+impl std::fmt::Debug for KadomFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.lexeme())
+    }
+}
+
+// Functions are only ever equal to themselves.
+impl PartialEq for KadomFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// Natives are given the call's closing paren, for errors and for calling back into kadom code.
+pub type NativeFn =
+    dyn Fn(&mut Interpreter, Vec<LiteralValue>, &Token) -> Result<LiteralValue, RuntimeError>;
+
+// A function implemented in Rust.
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: RangeInclusive<usize>,
+    pub function: Box<NativeFn>,
+    // Given the names of the caller's locals, which only the built-in eval needs.
+    pub sees_locals: bool,
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<native {}>", self.name)
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+pub struct KadomClass {
+    pub name: String,
+    pub methods: HashMap<String, Rc<KadomFunction>>,
+}
+
+impl std::fmt::Debug for KadomClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<class {}>", self.name)
+    }
+}
+
+impl PartialEq for KadomClass {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+pub struct KadomInstance {
+    pub class: Rc<KadomClass>,
+    pub fields: HashMap<String, LiteralValue>,
+}
+
+impl KadomInstance {
+    // Fields shadow methods; methods come back bound to the instance.
+    pub fn get(instance: &Rc<RefCell<Self>>, name: &Token) -> Result<LiteralValue, RuntimeError> {
+        if let Some(value) = instance.borrow().fields.get(name.lexeme()) {
+            return Ok(value.clone());
+        }
+
+        let method = instance.borrow().class.methods.get(name.lexeme()).cloned();
+        match method {
+            Some(method) => Ok(LiteralValue::Function(Rc::new(
+                method.bind(LiteralValue::Instance(instance.clone())),
+            ))),
+            None => Err(RuntimeError::new(
+                name,
+                format!("Undefined property \'{}\'", name.lexeme()),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Debug for KadomInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<{} instance>", self.class.name)
+    }
+}
+
+impl PartialEq for KadomInstance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// Only the variants the parser produces as literals can be serialized.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LiteralValue {
-    Number(f32),
-    String(String),
+    Number(f64),
+    String(Rc<str>),
     True,
     False,
     Nil,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    List(Rc<RefCell<Vec<LiteralValue>>>),
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Map(Rc<RefCell<Vec<(LiteralValue, LiteralValue)>>>),
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Function(Rc<KadomFunction>),
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Native(Rc<NativeFunction>),
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Class(Rc<KadomClass>),
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Instance(Rc<RefCell<KadomInstance>>),
 }
 
 impl std::fmt::Display for LiteralValue {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let string_value = match self {
+            // Negative zero prints as 0; Display already drops the fraction of whole numbers.
+            Self::Number(x) if *x == 0.0 => "0".to_string(),
             Self::Number(x) => x.to_string(),
-            Self::String(x) => x.clone(),
+            Self::String(x) => x.to_string(),
             Self::True => "true".to_string(),
             Self::False => "false".to_string(),
             Self::Nil => "nil".to_string(),
+            Self::List(items) => {
+                let items: Vec<String> = items.borrow().iter().map(|x| x.to_string()).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Self::Map(entries) => {
+                let entries: Vec<String> = entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Self::Function(function) => format!("{:?}", function),
+            Self::Native(native) => format!("{:?}", native),
+            Self::Class(class) => format!("{:?}", class),
+            Self::Instance(instance) => format!("{:?}", instance.borrow()),
         };
 
         write!(f, "{}", string_value)
@@ -43,39 +221,50 @@ impl std::fmt::Display for LiteralValue {
 }
 
 impl LiteralValue {
-    pub fn from_token(token: Token) -> Result<Self, String> {
-        match token.token_type {
-            TokenType::Number => Ok(Self::Number(unwrap_as_f32(token.literal_option)?)),
-            TokenType::StringLiteral => Ok(Self::String(unwrap_as_string(token.literal_option)?)),
+    pub fn from_token(token: &Token) -> Result<Self, String> {
+        match token.token_type() {
+            TokenType::Number => Ok(Self::Number(unwrap_as_f64(token.literal())?)),
+            TokenType::StringLiteral => Ok(Self::String(unwrap_as_string(token.literal())?)),
             TokenType::False => Ok(Self::False),
             TokenType::True => Ok(Self::True),
             TokenType::Nil => Ok(Self::Nil),
-            _ => panic!("Could not convert to LiteralValue"),
+            other => Err(format!("Cannot make a literal from a {:?} token", other)),
         }
     }
 
-    fn not(&self) -> Self {
+    pub fn is_truthy(&self) -> bool {
+        // Only nil and false are falsey; 0, "" and empty collections are truthy.
+        !matches!(self, Self::False | Self::Nil)
+    }
+
+    pub fn type_name(&self) -> &'static str {
         match self {
-            Self::False | Self::Nil => Self::True,
-            Self::Number(x) => {
-                if *x == 0 as f32 {
-                    Self::True
-                } else {
-                    Self::False
-                }
-            }
-            Self::String(str) => {
-                if str.is_empty() {
-                    Self::True
-                } else {
-                    Self::False
-                }
-            }
-            Self::True => Self::False,
+            Self::Number(_) => "number",
+            Self::String(_) => "string",
+            Self::True | Self::False => "boolean",
+            Self::Nil => "nil",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
+            Self::Function(_) | Self::Native(_) => "function",
+            Self::Class(_) => "class",
+            Self::Instance(_) => "instance",
         }
     }
 
-    fn from_bool(boolean: bool) -> Self {
+    pub fn as_callable(&self) -> Option<Rc<dyn KadomCallable>> {
+        match self {
+            Self::Function(function) => Some(function.clone()),
+            Self::Native(native) => Some(native.clone()),
+            Self::Class(class) => Some(class.clone()),
+            _ => None,
+        }
+    }
+
+    fn not(&self) -> Self {
+        Self::from_bool(!self.is_truthy())
+    }
+
+    pub(crate) fn from_bool(boolean: bool) -> Self {
         match boolean {
             true => Self::True,
             false => Self::False,
@@ -83,130 +272,689 @@ impl LiteralValue {
     }
 }
 
+// Where the resolver found a local: how many scopes out, and its slot in that scope.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Local {
+    pub depth: usize,
+    pub slot: usize,
+}
+
+// The locals in scope at a call to eval, each scope's names mapped to their slots, innermost last.
+pub type ScopeNames = Vec<HashMap<String, usize>>;
+
+// Variable, Assign and This carry the Local filled in by the resolver. None means the name is
+// global. A call to the global eval from inside a scope also gets the names of the locals around
+// it. Resolution isn't serialized, so a deserialized tree needs resolving again.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
+    Assign {
+        name: Token,
+        value: Box<Expr>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        local: Cell<Option<Local>>,
+    },
     Binary {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        scopes: RefCell<Option<Rc<ScopeNames>>>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+        optional: bool,
+    },
     Grouping {
         expression: Box<Expr>,
     },
+    List {
+        elements: Vec<Expr>,
+    },
     Literal {
         value: LiteralValue,
     },
+    Map {
+        entries: Vec<(Expr, Expr)>,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    This {
+        keyword: Token,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        local: Cell<Option<Local>>,
+    },
     Unary {
         operator: Token,
         right: Box<Expr>,
     },
     Variable {
         name: Token,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        local: Cell<Option<Local>>,
     },
 }
 
+// One method per Expr variant, called by Expr::accept with that variant's fields. There are no
+// default methods, so adding a variant means every visitor has to decide how to handle it. The
+// resolver's Local slots and the scopes of eval calls are left out, since they're interpreter
+// bookkeeping rather than syntax.
+pub trait ExprVisitor<R> {
+    fn visit_assign(&mut self, name: &Token, value: &Expr) -> R;
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> R;
+    fn visit_call(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> R;
+    fn visit_get(&mut self, object: &Expr, name: &Token, optional: bool) -> R;
+    fn visit_grouping(&mut self, expression: &Expr) -> R;
+    fn visit_list(&mut self, elements: &[Expr]) -> R;
+    fn visit_literal(&mut self, value: &LiteralValue) -> R;
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> R;
+    fn visit_set(&mut self, object: &Expr, name: &Token, value: &Expr) -> R;
+    fn visit_this(&mut self, keyword: &Token) -> R;
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> R;
+    fn visit_variable(&mut self, name: &Token) -> R;
+}
+
+// Writes an expression in S-expression form.
+struct AstPrinter<'a, 'b> {
+    f: &'a mut std::fmt::Formatter<'b>,
+}
+
+impl AstPrinter<'_, '_> {
+    // Writes each expression preceded by a space, for operands of variable-length forms.
+    fn write_all(&mut self, expressions: &[Expr]) -> std::fmt::Result {
+        for expression in expressions {
+            write!(self.f, " ")?;
+            expression.accept(self)?;
+        }
+        Ok(())
+    }
+}
+
+impl ExprVisitor<std::fmt::Result> for AstPrinter<'_, '_> {
+    fn visit_assign(&mut self, name: &Token, value: &Expr) -> std::fmt::Result {
+        write!(self.f, "(= {} ", name.lexeme())?;
+        value.accept(self)?;
+        write!(self.f, ")")
+    }
+
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> std::fmt::Result {
+        write!(self.f, "({} ", operator.lexeme())?;
+        left.accept(self)?;
+        write!(self.f, " ")?;
+        right.accept(self)?;
+        write!(self.f, ")")
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _: &Token, arguments: &[Expr]) -> std::fmt::Result {
+        write!(self.f, "(call ")?;
+        callee.accept(self)?;
+        self.write_all(arguments)?;
+        write!(self.f, ")")
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Token, optional: bool) -> std::fmt::Result {
+        write!(self.f, "({} ", if optional { "?." } else { "." })?;
+        object.accept(self)?;
+        write!(self.f, " {})", name.lexeme())
+    }
+
+    fn visit_grouping(&mut self, expression: &Expr) -> std::fmt::Result {
+        write!(self.f, "(group ")?;
+        expression.accept(self)?;
+        write!(self.f, ")")
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) -> std::fmt::Result {
+        write!(self.f, "(list")?;
+        self.write_all(elements)?;
+        write!(self.f, ")")
+    }
+
+    fn visit_literal(&mut self, value: &LiteralValue) -> std::fmt::Result {
+        write!(self.f, "{}", value)
+    }
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> std::fmt::Result {
+        write!(self.f, "(map")?;
+        for (key, value) in entries {
+            write!(self.f, " (")?;
+            key.accept(self)?;
+            write!(self.f, " ")?;
+            value.accept(self)?;
+            write!(self.f, ")")?;
+        }
+        write!(self.f, ")")
+    }
+
+    fn visit_set(&mut self, object: &Expr, name: &Token, value: &Expr) -> std::fmt::Result {
+        write!(self.f, "(= (. ")?;
+        object.accept(self)?;
+        write!(self.f, " {}) ", name.lexeme())?;
+        value.accept(self)?;
+        write!(self.f, ")")
+    }
+
+    fn visit_this(&mut self, _: &Token) -> std::fmt::Result {
+        write!(self.f, "this")
+    }
+
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) -> std::fmt::Result {
+        write!(self.f, "({} ", operator.lexeme())?;
+        right.accept(self)?;
+        write!(self.f, ")")
+    }
+
+    fn visit_variable(&mut self, name: &Token) -> std::fmt::Result {
+        write!(self.f, "var {}", name.lexeme())
+    }
+}
+
 impl std::fmt::Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.accept(&mut AstPrinter { f })
+    }
+}
+
+impl Expr {
+    // The token that locates the expression, for the kinds that have one.
+    pub(crate) fn token(&self) -> Option<&Token> {
+        match self {
+            Expr::Assign { name, .. }
+            | Expr::Get { name, .. }
+            | Expr::Set { name, .. }
+            | Expr::Variable { name, .. } => Some(name),
+            Expr::Binary { operator, .. } | Expr::Unary { operator, .. } => Some(operator),
+            Expr::Call { paren, .. } => Some(paren),
+            Expr::This { keyword, .. } => Some(keyword),
+            Expr::Grouping { .. } | Expr::List { .. } | Expr::Literal { .. } | Expr::Map { .. } => {
+                None
+            }
+        }
+    }
+
+    pub fn accept<R>(&self, visitor: &mut dyn ExprVisitor<R>) -> R {
         match self {
-            Self::Binary {
+            Expr::Assign { name, value, .. } => visitor.visit_assign(name, value),
+            Expr::Binary {
                 left,
                 operator,
                 right,
-            } => write!(f, "({} {} {})", operator.lexeme, left, right),
-            Self::Grouping { expression } => write!(f, "(group {})", expression),
-            Self::Literal { value } => write!(f, "{}", value),
-            Self::Unary { operator, right } => {
-                write!(f, "({} {})", operator.lexeme, right)
-            }
-            Self::Variable { name } => {
-                write!(f, "var {}", name.lexeme)
-            }
+            } => visitor.visit_binary(left, operator, right),
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                ..
+            } => visitor.visit_call(callee, paren, arguments),
+            Expr::Get {
+                object,
+                name,
+                optional,
+            } => visitor.visit_get(object, name, *optional),
+            Expr::Grouping { expression } => visitor.visit_grouping(expression),
+            Expr::List { elements } => visitor.visit_list(elements),
+            Expr::Literal { value } => visitor.visit_literal(value),
+            Expr::Map { entries } => visitor.visit_map(entries),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => visitor.visit_set(object, name, value),
+            Expr::This { keyword, .. } => visitor.visit_this(keyword),
+            Expr::Unary { operator, right } => visitor.visit_unary(operator, right),
+            Expr::Variable { name, .. } => visitor.visit_variable(name),
         }
     }
-}
 
-impl Expr {
-    pub fn evaluate(&self, environment: &Environment) -> Result<LiteralValue, String> {
+    pub fn evaluate(&self, interpreter: &mut Interpreter) -> Result<LiteralValue, KadomError> {
+        Ok(self.eval(interpreter)?)
+    }
+
+    // Evaluation as the interpreter runs it, where a failure can only be a runtime error.
+    pub(crate) fn eval(&self, interpreter: &mut Interpreter) -> Result<LiteralValue, RuntimeError> {
+        if interpreter.is_limited() {
+            interpreter.step(self.token())?;
+        }
+        if let Some(profiler) = &mut interpreter.profile {
+            profiler.expression();
+        }
+
         match self {
+            Expr::Assign { name, value, local } => {
+                let value = value.eval(interpreter)?;
+                interpreter.assign_variable(name, local.get(), value.clone())?;
+                Ok(value)
+            }
             Expr::Literal { value } => Ok(value.clone()),
-            Expr::Grouping { expression } => Ok(expression.evaluate(environment)?),
-            Expr::Unary { operator, right } => {
-                let evaluate_right = right.evaluate(environment)?;
-
-                match (evaluate_right, &operator.token_type) {
-                    (LiteralValue::Number(x), TokenType::Minus) => Ok(LiteralValue::Number(-x)),
-                    (non_number, TokenType::Minus) => {
-                        Err(format!("Negation not implemented for {:?}", non_number))
-                    }
-                    (any, TokenType::Bang) => Ok(any.not()),
-                    (_, _) => Err("Unreachable".to_string()),
+            Expr::Grouping { expression } => Ok(expression.eval(interpreter)?),
+            Expr::List { elements } => {
+                let mut values = Vec::new();
+                for element in elements {
+                    values.push(element.eval(interpreter)?);
                 }
+
+                Ok(LiteralValue::List(Rc::new(RefCell::new(values))))
+            }
+            Expr::Map { entries } => {
+                let mut values = Vec::new();
+                for (key, value) in entries {
+                    values.push((key.eval(interpreter)?, value.eval(interpreter)?));
+                }
+
+                Ok(map(values))
+            }
+            Expr::Call { .. } | Expr::Get { .. } => Ok(self
+                .evaluate_chain(interpreter)?
+                .unwrap_or(LiteralValue::Nil)),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let instance = field_target(object.eval(interpreter)?, name)?;
+                let value = value.eval(interpreter)?;
+                instance
+                    .borrow_mut()
+                    .fields
+                    .insert(name.lexeme().to_string(), value.clone());
+                Ok(value)
             }
+            Expr::This { keyword, local } => interpreter.look_up_variable(keyword, local.get()),
+            Expr::Unary { operator, right } => unary(operator, right.eval(interpreter)?),
             Expr::Binary {
                 left,
                 operator,
                 right,
             } => {
-                let evaluate_left = left.evaluate(environment)?;
-                let evaluate_right = right.evaluate(environment)?;
-
-                match (evaluate_left, &operator.token_type, evaluate_right) {
-                    (LiteralValue::Number(x), TokenType::Minus, LiteralValue::Number(y)) => {
-                        Ok(LiteralValue::Number(x - y))
-                    }
-                    (LiteralValue::Number(x), TokenType::Slash, LiteralValue::Number(y)) => {
-                        Ok(LiteralValue::Number(x / y))
-                    }
-                    (LiteralValue::Number(x), TokenType::Star, LiteralValue::Number(y)) => {
-                        Ok(LiteralValue::Number(x * y))
-                    }
-                    (LiteralValue::Number(x), TokenType::Plus, LiteralValue::Number(y)) => {
-                        Ok(LiteralValue::Number(x + y))
-                    }
-                    (LiteralValue::String(str1), TokenType::Plus, LiteralValue::String(str2)) => {
-                        Ok(LiteralValue::String(str1 + str2.as_str()))
-                    }
-                    (LiteralValue::Number(x), TokenType::Greater, LiteralValue::Number(y)) => {
-                        Ok(LiteralValue::from_bool(x > y))
-                    }
-                    (LiteralValue::Number(x), TokenType::GreaterEqual, LiteralValue::Number(y)) => {
-                        Ok(LiteralValue::from_bool(x >= y))
-                    }
-                    (LiteralValue::Number(x), TokenType::Less, LiteralValue::Number(y)) => {
-                        Ok(LiteralValue::from_bool(x < y))
-                    }
-                    (LiteralValue::Number(x), TokenType::LessEqual, LiteralValue::Number(y)) => {
-                        Ok(LiteralValue::from_bool(x <= y))
-                    }
-                    (LiteralValue::String(x), TokenType::Greater, LiteralValue::String(y)) => {
-                        Ok(LiteralValue::from_bool(x > y))
-                    }
-                    (LiteralValue::String(x), TokenType::GreaterEqual, LiteralValue::String(y)) => {
-                        Ok(LiteralValue::from_bool(x >= y))
-                    }
-                    (LiteralValue::String(x), TokenType::Less, LiteralValue::String(y)) => {
-                        Ok(LiteralValue::from_bool(x < y))
-                    }
-                    (LiteralValue::String(x), TokenType::LessEqual, LiteralValue::String(y)) => {
-                        Ok(LiteralValue::from_bool(x <= y))
-                    }
-                    (x, TokenType::EqualEqual, y) => Ok(LiteralValue::from_bool(x == y)),
-                    (x, TokenType::BangEqual, y) => Ok(LiteralValue::from_bool(x != y)),
-
-                    // Error handling
-                    (LiteralValue::String(_), oper, LiteralValue::Number(_)) => {
-                        Err(format!("Mismatched types for {oper:?}: String and Number"))
-                    }
-                    (LiteralValue::Number(_), oper, LiteralValue::String(_)) => {
-                        Err(format!("Mismatched types for {oper:?}: Number and String"))
-                    }
-                    (x, oper, y) => Err(format!(
-                        "{:?} cannot be evaluated for {:?} and {:?}",
-                        oper, x, y
-                    )),
+                let left = left.eval(interpreter)?;
+                let right = right.eval(interpreter)?;
+                operate(interpreter, left, operator, right)
+            }
+            Self::Variable { name, local } => interpreter.look_up_variable(name, local.get()),
+        }
+    }
+
+    // Evaluates a chain of calls and property accesses. Returns None when an optional access
+    // (`?.`) finds nil, which short-circuits the rest of the chain.
+    fn evaluate_chain(
+        &self,
+        interpreter: &mut Interpreter,
+    ) -> Result<Option<LiteralValue>, RuntimeError> {
+        match self {
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                scopes,
+            } => {
+                let callee = match callee.evaluate_chain(interpreter)? {
+                    Some(callee) => callee,
+                    None => return Ok(None),
+                };
+
+                let mut values = Vec::new();
+                for argument in arguments {
+                    values.push(argument.eval(interpreter)?);
                 }
+
+                let scopes = scopes.borrow().clone();
+                call_expression(interpreter, callee, values, paren, scopes).map(Some)
             }
-            Self::Variable { name } => environment.get(&name.lexeme),
+            Expr::Get {
+                object,
+                name,
+                optional,
+            } => match object.evaluate_chain(interpreter)? {
+                None => Ok(None),
+                Some(LiteralValue::Nil) if *optional => Ok(None),
+                Some(object) => get_property(object, name).map(Some),
+            },
+            _ => self.eval(interpreter).map(Some),
+        }
+    }
+}
+
+// The helpers below carry out the operations of expressions once their operands are evaluated.
+// The tree-walker and the VM share them, so the two backends behave the same.
+
+// Builds a map from entries in source order. A repeated key keeps its first position and takes
+// the last value.
+pub(crate) fn map(entries: Vec<(LiteralValue, LiteralValue)>) -> LiteralValue {
+    let mut values: Vec<(LiteralValue, LiteralValue)> = Vec::new();
+    for (key, value) in entries {
+        match values.iter_mut().find(|(existing, _)| *existing == key) {
+            Some(entry) => entry.1 = value,
+            None => values.push((key, value)),
+        }
+    }
+
+    LiteralValue::Map(Rc::new(RefCell::new(values)))
+}
+
+// The instance whose field an assignment sets, checked before the value is evaluated.
+pub(crate) fn field_target(
+    object: LiteralValue,
+    name: &Token,
+) -> Result<Rc<RefCell<KadomInstance>>, RuntimeError> {
+    match object {
+        LiteralValue::Instance(instance) => Ok(instance),
+        other => Err(RuntimeError::new(
+            name,
+            format!("Only instances have fields, got {:?}", other),
+        )),
+    }
+}
+
+pub(crate) fn get_property(
+    object: LiteralValue,
+    name: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    match object {
+        LiteralValue::Instance(instance) => KadomInstance::get(&instance, name),
+        LiteralValue::Nil => Err(RuntimeError::new(
+            name,
+            format!("Cannot read property \'{}\' of nil", name.lexeme()),
+        )),
+        other => Err(RuntimeError::new(
+            name,
+            format!("Only instances have properties, got {:?}", other),
+        )),
+    }
+}
+
+pub(crate) fn call_value(
+    interpreter: &mut Interpreter,
+    callee: LiteralValue,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    match callee.as_callable() {
+        Some(callable) => callable::call(callable, interpreter, arguments, paren),
+        None => Err(RuntimeError::new(
+            paren,
+            format!("Can only call functions and classes, got {:?}", callee),
+        )),
+    }
+}
+
+// Calls the value of a call expression. A native that sees locals, which is eval, is also given
+// the names of the locals around the call, so the source it runs can use them.
+pub(crate) fn call_expression(
+    interpreter: &mut Interpreter,
+    callee: LiteralValue,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+    scopes: Option<Rc<ScopeNames>>,
+) -> Result<LiteralValue, RuntimeError> {
+    let sees_locals = matches!(&callee, LiteralValue::Native(native) if native.sees_locals);
+    interpreter.eval_scopes = scopes.filter(|_| sees_locals);
+    let result = call_value(interpreter, callee, arguments, paren);
+    interpreter.eval_scopes = None;
+    result
+}
+
+pub(crate) fn unary(operator: &Token, right: LiteralValue) -> Result<LiteralValue, RuntimeError> {
+    match (right, operator.token_type()) {
+        (LiteralValue::Number(x), TokenType::Minus) => Ok(LiteralValue::Number(-x)),
+        (non_number, TokenType::Minus) => Err(RuntimeError::new(
+            operator,
+            format!("Negation not implemented for {:?}", non_number),
+        )),
+        (any, TokenType::Bang) => Ok(any.not()),
+        (_, _) => Err(RuntimeError::new(operator, "Unreachable".to_string())),
+    }
+}
+
+// The method a class defines to give its instances an operator. The comparisons all derive from
+// `less` and `==`.
+fn operator_method(operator: &TokenType) -> Option<&'static str> {
+    match operator {
+        TokenType::Plus => Some("plus"),
+        TokenType::Minus => Some("minus"),
+        TokenType::Star => Some("times"),
+        TokenType::Slash => Some("div"),
+        TokenType::EqualEqual | TokenType::BangEqual => Some("equals"),
+        TokenType::Less | TokenType::LessEqual | TokenType::Greater | TokenType::GreaterEqual => {
+            Some("less")
+        }
+        _ => None,
+    }
+}
+
+// Applies a binary operator, first giving an instance on the left the chance to handle it with
+// its operator method. Without one, instances compare by identity and anything else is a type
+// error that names the method to define.
+pub(crate) fn operate(
+    interpreter: &mut Interpreter,
+    left: LiteralValue,
+    operator: &Token,
+    right: LiteralValue,
+) -> Result<LiteralValue, RuntimeError> {
+    let Some(method) = operator_method(operator.token_type()) else {
+        return binary(left, operator, right, interpreter.ieee_division);
+    };
+    let LiteralValue::Instance(instance) = &left else {
+        let hint = match &right {
+            LiteralValue::Instance(instance) => format!(
+                "; only the left operand's '{}' method is used, so put the {} instance first",
+                method,
+                instance.borrow().class.name
+            ),
+            _ => String::new(),
+        };
+        return binary(left, operator, right, interpreter.ieee_division).map_err(|mut err| {
+            err.message.push_str(&hint);
+            err
+        });
+    };
+
+    let instance = instance.clone();
+    let class = instance.borrow().class.clone();
+    let result = match operator.token_type() {
+        TokenType::EqualEqual => equals(interpreter, &instance, operator, right)?,
+        TokenType::BangEqual => !equals(interpreter, &instance, operator, right)?,
+        _ if !class.methods.contains_key(method) => {
+            return binary(left, operator, right, interpreter.ieee_division).map_err(|mut err| {
+                err.message.push_str(&format!(
+                    "; define a '{}' method on {} to support {}",
+                    method,
+                    class.name,
+                    operator.lexeme()
+                ));
+                err
+            });
+        }
+        TokenType::Less => {
+            call_operator(interpreter, &instance, "less", operator, right)?.is_truthy()
+        }
+        TokenType::GreaterEqual => {
+            !call_operator(interpreter, &instance, "less", operator, right)?.is_truthy()
+        }
+        TokenType::LessEqual | TokenType::Greater => {
+            let less =
+                call_operator(interpreter, &instance, "less", operator, right.clone())?.is_truthy();
+            let at_most = less || equals(interpreter, &instance, operator, right)?;
+            at_most == (operator.token_type() == &TokenType::LessEqual)
+        }
+        _ => return call_operator(interpreter, &instance, method, operator, right),
+    };
+
+    Ok(LiteralValue::from_bool(result))
+}
+
+// Whether two values are equal as == says, for natives that compare values the way the language
+// does.
+pub(crate) fn equal(
+    interpreter: &mut Interpreter,
+    left: &LiteralValue,
+    right: &LiteralValue,
+    at: &Token,
+) -> Result<bool, RuntimeError> {
+    match left {
+        LiteralValue::Instance(instance) => equals(interpreter, instance, at, right.clone()),
+        _ => Ok(left == right),
+    }
+}
+
+// Equality for an instance on the left: its `equals` method when the class has one, identity
+// otherwise.
+fn equals(
+    interpreter: &mut Interpreter,
+    instance: &Rc<RefCell<KadomInstance>>,
+    operator: &Token,
+    right: LiteralValue,
+) -> Result<bool, RuntimeError> {
+    if !instance.borrow().class.methods.contains_key("equals") {
+        return Ok(matches!(right, LiteralValue::Instance(other) if Rc::ptr_eq(instance, &other)));
+    }
+
+    Ok(call_operator(interpreter, instance, "equals", operator, right)?.is_truthy())
+}
+
+fn call_operator(
+    interpreter: &mut Interpreter,
+    instance: &Rc<RefCell<KadomInstance>>,
+    method: &str,
+    operator: &Token,
+    right: LiteralValue,
+) -> Result<LiteralValue, RuntimeError> {
+    let method = instance.borrow().class.methods[method].clone();
+    let bound = method.bind(LiteralValue::Instance(instance.clone()));
+    call_value(
+        interpreter,
+        LiteralValue::Function(Rc::new(bound)),
+        vec![right],
+        operator,
+    )
+}
+
+pub(crate) fn binary(
+    left: LiteralValue,
+    operator: &Token,
+    right: LiteralValue,
+    ieee_division: bool,
+) -> Result<LiteralValue, RuntimeError> {
+    match (left, operator.token_type(), right) {
+        (LiteralValue::Number(x), TokenType::Minus, LiteralValue::Number(y)) => {
+            Ok(LiteralValue::Number(x - y))
+        }
+        (
+            LiteralValue::Number(_),
+            TokenType::Slash | TokenType::Percent,
+            LiteralValue::Number(y),
+        ) if y == 0.0 && !ieee_division => {
+            Err(RuntimeError::new(operator, "Division by zero".to_string()))
+        }
+        (LiteralValue::Number(x), TokenType::Slash, LiteralValue::Number(y)) => {
+            Ok(LiteralValue::Number(x / y))
+        }
+        (LiteralValue::Number(x), TokenType::Percent, LiteralValue::Number(y)) => {
+            Ok(LiteralValue::Number(x % y))
+        }
+        (LiteralValue::Number(x), TokenType::Star, LiteralValue::Number(y)) => {
+            Ok(LiteralValue::Number(x * y))
+        }
+        (LiteralValue::String(string), TokenType::Star, LiteralValue::Number(count))
+        | (LiteralValue::Number(count), TokenType::Star, LiteralValue::String(string)) => {
+            repeat(&string, count, operator)
         }
+        (LiteralValue::Number(x), TokenType::Plus, LiteralValue::Number(y)) => {
+            Ok(LiteralValue::Number(x + y))
+        }
+        (LiteralValue::String(str1), TokenType::Plus, LiteralValue::String(str2)) => {
+            Ok(LiteralValue::String(format!("{}{}", str1, str2).into()))
+        }
+        (LiteralValue::String(string), TokenType::Plus, x @ LiteralValue::Number(_)) => {
+            Ok(LiteralValue::String(format!("{}{}", string, x).into()))
+        }
+        (x @ LiteralValue::Number(_), TokenType::Plus, LiteralValue::String(string)) => {
+            Ok(LiteralValue::String(format!("{}{}", x, string).into()))
+        }
+        (x @ LiteralValue::String(_), TokenType::Plus, y)
+        | (x, TokenType::Plus, y @ LiteralValue::String(_)) => Err(RuntimeError::new(
+            operator,
+            format!(
+                "Cannot concatenate {} and {}; convert the {} with str() first",
+                x.type_name(),
+                y.type_name(),
+                if matches!(x, LiteralValue::String(_)) {
+                    y.type_name()
+                } else {
+                    x.type_name()
+                }
+            ),
+        )),
+        (LiteralValue::Number(x), TokenType::Greater, LiteralValue::Number(y)) => {
+            Ok(LiteralValue::from_bool(x > y))
+        }
+        (LiteralValue::Number(x), TokenType::GreaterEqual, LiteralValue::Number(y)) => {
+            Ok(LiteralValue::from_bool(x >= y))
+        }
+        (LiteralValue::Number(x), TokenType::Less, LiteralValue::Number(y)) => {
+            Ok(LiteralValue::from_bool(x < y))
+        }
+        (LiteralValue::Number(x), TokenType::LessEqual, LiteralValue::Number(y)) => {
+            Ok(LiteralValue::from_bool(x <= y))
+        }
+        (LiteralValue::String(x), TokenType::Greater, LiteralValue::String(y)) => {
+            Ok(LiteralValue::from_bool(x > y))
+        }
+        (LiteralValue::String(x), TokenType::GreaterEqual, LiteralValue::String(y)) => {
+            Ok(LiteralValue::from_bool(x >= y))
+        }
+        (LiteralValue::String(x), TokenType::Less, LiteralValue::String(y)) => {
+            Ok(LiteralValue::from_bool(x < y))
+        }
+        (LiteralValue::String(x), TokenType::LessEqual, LiteralValue::String(y)) => {
+            Ok(LiteralValue::from_bool(x <= y))
+        }
+        (LiteralValue::String(x), TokenType::In, LiteralValue::String(y)) => {
+            Ok(LiteralValue::from_bool(y.contains(&*x)))
+        }
+        (x, TokenType::In, LiteralValue::List(items)) => {
+            Ok(LiteralValue::from_bool(items.borrow().contains(&x)))
+        }
+        (x, TokenType::In, LiteralValue::Map(entries)) => Ok(LiteralValue::from_bool(
+            entries.borrow().iter().any(|(key, _)| *key == x),
+        )),
+        (x, TokenType::In, y) => Err(RuntimeError::new(
+            operator,
+            format!(
+                "Cannot test membership of {} in {}",
+                x.type_name(),
+                y.type_name()
+            ),
+        )),
+        (x, TokenType::EqualEqual, y) => Ok(LiteralValue::from_bool(x == y)),
+        (x, TokenType::BangEqual, y) => Ok(LiteralValue::from_bool(x != y)),
+
+        // Error handling
+        (LiteralValue::String(_), oper, LiteralValue::Number(_)) => Err(RuntimeError::new(
+            operator,
+            format!("Mismatched types for {oper:?}: String and Number"),
+        )),
+        (LiteralValue::Number(_), oper, LiteralValue::String(_)) => Err(RuntimeError::new(
+            operator,
+            format!("Mismatched types for {oper:?}: Number and String"),
+        )),
+        (x, oper, y) => Err(RuntimeError::new(
+            operator,
+            format!(
+                "{:?} cannot be evaluated for {} and {}",
+                oper,
+                x.type_name(),
+                y.type_name()
+            ),
+        )),
     }
 }
 
@@ -218,7 +966,7 @@ mod tests {
 
     #[test]
     fn printy_print_ast() {
-        let minus_token = Token::new(TokenType::Minus, "-".to_string(), None, u64::MAX);
+        let minus_token = Token::new(TokenType::Minus, "-".to_string(), None, u64::MAX, 1);
         let one_two_three = Literal {
             value: Number(123.0),
         };
@@ -227,7 +975,7 @@ mod tests {
                 value: Number(45.67),
             }),
         };
-        let multiply_token = Token::new(TokenType::Star, "*".to_string(), None, u64::MAX);
+        let multiply_token = Token::new(TokenType::Star, "*".to_string(), None, u64::MAX, 1);
         let ast = Binary {
             left: Box::new(Unary {
                 operator: minus_token,
@@ -239,4 +987,155 @@ mod tests {
 
         assert_eq!(ast.to_string(), "(* (- 123) (group 45.67))");
     }
+
+    fn parse(source: &str) -> Expr {
+        let tokens = lexer::Scanner::new(source.to_string())
+            .scan_tokens()
+            .unwrap();
+        crate::parser::Parser::new(tokens)
+            .parse_expression()
+            .unwrap()
+    }
+
+    // Counts every node in an expression.
+    struct NodeCounter;
+
+    impl NodeCounter {
+        fn count_all(&mut self, expressions: &[Expr]) -> usize {
+            expressions.iter().map(|e| e.accept(self)).sum()
+        }
+    }
+
+    impl ExprVisitor<usize> for NodeCounter {
+        fn visit_assign(&mut self, _: &Token, value: &Expr) -> usize {
+            1 + value.accept(self)
+        }
+        fn visit_binary(&mut self, left: &Expr, _: &Token, right: &Expr) -> usize {
+            1 + left.accept(self) + right.accept(self)
+        }
+        fn visit_call(&mut self, callee: &Expr, _: &Token, arguments: &[Expr]) -> usize {
+            1 + callee.accept(self) + self.count_all(arguments)
+        }
+        fn visit_get(&mut self, object: &Expr, _: &Token, _: bool) -> usize {
+            1 + object.accept(self)
+        }
+        fn visit_grouping(&mut self, expression: &Expr) -> usize {
+            1 + expression.accept(self)
+        }
+        fn visit_list(&mut self, elements: &[Expr]) -> usize {
+            1 + self.count_all(elements)
+        }
+        fn visit_literal(&mut self, _: &LiteralValue) -> usize {
+            1
+        }
+        fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> usize {
+            let entries: usize = entries
+                .iter()
+                .map(|(key, value)| key.accept(self) + value.accept(self))
+                .sum();
+            1 + entries
+        }
+        fn visit_set(&mut self, object: &Expr, _: &Token, value: &Expr) -> usize {
+            1 + object.accept(self) + value.accept(self)
+        }
+        fn visit_this(&mut self, _: &Token) -> usize {
+            1
+        }
+        fn visit_unary(&mut self, _: &Token, right: &Expr) -> usize {
+            1 + right.accept(self)
+        }
+        fn visit_variable(&mut self, _: &Token) -> usize {
+            1
+        }
+    }
+
+    // Collects the names of variables read or assigned, in the order they appear.
+    #[derive(Default)]
+    struct VariableNames(Vec<std::string::String>);
+
+    impl ExprVisitor<()> for VariableNames {
+        fn visit_assign(&mut self, name: &Token, value: &Expr) {
+            self.0.push(name.lexeme().to_string());
+            value.accept(self);
+        }
+        fn visit_binary(&mut self, left: &Expr, _: &Token, right: &Expr) {
+            left.accept(self);
+            right.accept(self);
+        }
+        fn visit_call(&mut self, callee: &Expr, _: &Token, arguments: &[Expr]) {
+            callee.accept(self);
+            arguments.iter().for_each(|argument| argument.accept(self));
+        }
+        fn visit_get(&mut self, object: &Expr, _: &Token, _: bool) {
+            object.accept(self);
+        }
+        fn visit_grouping(&mut self, expression: &Expr) {
+            expression.accept(self);
+        }
+        fn visit_list(&mut self, elements: &[Expr]) {
+            elements.iter().for_each(|element| element.accept(self));
+        }
+        fn visit_literal(&mut self, _: &LiteralValue) {}
+        fn visit_map(&mut self, entries: &[(Expr, Expr)]) {
+            for (key, value) in entries {
+                key.accept(self);
+                value.accept(self);
+            }
+        }
+        fn visit_set(&mut self, object: &Expr, _: &Token, value: &Expr) {
+            object.accept(self);
+            value.accept(self);
+        }
+        fn visit_this(&mut self, _: &Token) {}
+        fn visit_unary(&mut self, _: &Token, right: &Expr) {
+            right.accept(self);
+        }
+        fn visit_variable(&mut self, name: &Token) {
+            self.0.push(name.lexeme().to_string());
+        }
+    }
+
+    #[test]
+    fn visitor_counts_nodes() {
+        assert_eq!(parse("1").accept(&mut NodeCounter), 1);
+        assert_eq!(parse("-(a + 2) * f(b, [c])").accept(&mut NodeCounter), 11);
+    }
+
+    #[test]
+    fn visitor_collects_variable_names() {
+        let mut names = VariableNames::default();
+        parse("x = f(y).z + {\"k\": w}").accept(&mut names);
+        assert_eq!(names.0, ["x", "f", "y", "w"]);
+    }
+
+    #[test]
+    fn evaluate_list_literal() {
+        let list = Expr::List {
+            elements: vec![
+                Literal { value: Number(1.0) },
+                Literal {
+                    value: String("two".into()),
+                },
+            ],
+        };
+
+        let value = list.evaluate(&mut Interpreter::new()).unwrap();
+        assert_eq!(value.to_string(), "[1, two]");
+    }
+
+    #[test]
+    fn strings_compare_by_contents() {
+        let built = String(format!("{}{}", "ka", "dom").into());
+        assert_eq!(built, String("kadom".into()));
+        assert_ne!(built, String("kadom ".into()));
+    }
+
+    #[test]
+    fn display_numbers() {
+        assert_eq!(Number(2.0).to_string(), "2");
+        assert_eq!(Number(2.5).to_string(), "2.5");
+        assert_eq!(Number(-0.0).to_string(), "0");
+        assert_eq!(Number(1e20).to_string(), "100000000000000000000");
+        assert_eq!(Number(0.000001).to_string(), "0.000001");
+    }
 }
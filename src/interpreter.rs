@@ -1,38 +1,2505 @@
 use crate::{
-    environment::Environment,
-    expr::{Expr, LiteralValue},
-    stmt::Stmt,
+    compiler::{self, CompiledFunction},
+    coverage::{self, LineCounts},
+    environment::{Environment, Unbound},
+    error::{CallFrame, KadomError, RuntimeError},
+    expr::{Expr, KadomClass, KadomFunction, LiteralValue, Local, NativeFunction, ScopeNames},
+    lexer::{self, Scanner, Token},
+    natives::{self, Rng},
+    optimizer,
+    parser::Parser,
+    printer,
+    profiler::Profiler,
+    resolver::Resolver,
+    stmt::{FunctionDeclaration, Stmt},
+    vm,
 };
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs::read_to_string,
+    io::{stderr, stdout, BufRead, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+// Stack size for the thread running the interpreter. Calls recurse on the host stack, so this
+// leaves room for max_call_depth nested calls even in debug builds.
+pub const STACK_SIZE: usize = 128 * 1024 * 1024;
+
+// Steps between looks at the clock when a run has a timeout.
+pub const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+// Where a run's timeout reads the time, and how sleep() waits. Embedders and tests can supply
+// their own, to record sleeps without waiting or to refuse them.
+pub struct Clock {
+    // Time since some fixed start.
+    pub now: Box<dyn FnMut() -> Duration>,
+    pub sleep: Box<dyn FnMut(Duration) -> Result<(), String>>,
+}
+
+impl Clock {
+    pub fn system() -> Self {
+        let start = Instant::now();
+        Self {
+            now: Box::new(move || start.elapsed()),
+            sleep: Box::new(|duration| {
+                std::thread::sleep(duration);
+                Ok(())
+            }),
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::system()
+    }
+}
+
+pub(crate) enum ControlFlow {
+    Break,
+    Continue,
+    Return(LiteralValue),
+}
+
+// How programs are executed. Both backends give the same output and errors; the VM compiles to
+// bytecode first and runs loops faster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    TreeWalker,
+    Vm,
+}
 
 pub struct Interpreter {
-    environment: Environment,
+    pub environment: Rc<RefCell<Environment>>,
+    // Follow IEEE 754 and produce inf/NaN instead of erroring on a zero divisor.
+    pub ieee_division: bool,
+    // Nested user function calls allowed before erroring instead of overflowing the host stack.
+    pub max_call_depth: usize,
+    // User function calls in progress, innermost last.
+    frames: Vec<CallFrame>,
+    // Where print and other program output goes.
+    pub output: Box<dyn Write>,
+    // Write each statement the tree-walker is about to execute, with its line, to trace_output.
+    // The VM runs bytecode rather than statements, so it isn't traced.
+    pub trace: bool,
+    pub trace_output: Box<dyn Write>,
+    // Per-line hit counts and timings, collected while set. Like trace it sees only the
+    // tree-walker.
+    pub profile: Option<Profiler>,
+    // Times statements on each line ran, collected while set. Each program interpreted adds its
+    // statements' lines with a count of 0 first, so lines that never run still show up.
+    pub coverage: Option<LineCounts>,
+    // Command line arguments for the script, returned by args().
+    pub args: Vec<String>,
+    // Where input() reads lines from; stdin when None.
+    pub input: Option<Box<dyn BufRead>>,
+    // Whether natives may reach the host beyond the program's own input and output, such as its
+    // environment variables, or run source with eval(). Embedders running untrusted code can turn
+    // it off.
+    pub host_access: bool,
+    // Where random() and randomInt() draw from. Seeded from the clock unless seed_rng is called.
+    pub(crate) rng: Rng,
+    globals: Rc<RefCell<Environment>>,
+    // Files currently being executed, innermost last. Imports resolve relative to the last one.
+    files: Vec<PathBuf>,
+    imported: HashSet<PathBuf>,
+    pub backend: Backend,
+    // Fold constant expressions before running anything.
+    pub optimize: bool,
+    // Bytecode for each user function the VM has called, compiled on first call. The declaration
+    // is kept alive so its address can't be reused by another.
+    compiled: HashMap<*const FunctionDeclaration, (Rc<FunctionDeclaration>, Rc<CompiledFunction>)>,
+    // Steps each run may take before stopping with an error, or None for no limit. The
+    // tree-walker counts every statement executed and expression evaluated; the VM counts
+    // instructions.
+    pub step_limit: Option<u64>,
+    steps: u64,
+    // Wall-clock time each run may take before stopping with an error, or None for no limit.
+    pub timeout: Option<Duration>,
+    // Steps between looks at the clock while a timeout is set, to keep the checks cheap.
+    pub timeout_check_interval: u64,
+    pub clock: Clock,
+    // When the current run started by the clock, if it has a timeout, and whether it has run out.
+    // Once it has, every step fails, so catching the error can't keep a program going.
+    started: Option<Duration>,
+    timed_out: bool,
+    // Line and column of the last counted step that had a token, to place the error when the
+    // step that runs out doesn't.
+    step_location: (u64, usize),
+    // The locals around the call to eval being made, so the source it runs can use them.
+    pub(crate) eval_scopes: Option<Rc<ScopeNames>>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        Self::with_output(Box::new(stdout()))
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        let globals = fresh_globals();
+
         Self {
-            environment: Environment::new(),
+            environment: globals.clone(),
+            ieee_division: false,
+            max_call_depth: 1000,
+            frames: Vec::new(),
+            output,
+            trace: false,
+            trace_output: Box::new(stderr()),
+            profile: None,
+            coverage: None,
+            args: Vec::new(),
+            input: None,
+            host_access: true,
+            rng: Rng::from_time(),
+            globals,
+            files: Vec::new(),
+            imported: HashSet::new(),
+            backend: Backend::TreeWalker,
+            optimize: false,
+            compiled: HashMap::new(),
+            step_limit: None,
+            steps: 0,
+            timeout: None,
+            timeout_check_interval: TIMEOUT_CHECK_INTERVAL,
+            clock: Clock::system(),
+            started: None,
+            timed_out: false,
+            step_location: (1, 1),
+            eval_scopes: None,
         }
     }
 
-    pub fn interpret(&mut self, statements: Vec<Stmt>) -> Result<(), String> {
-        for statement in statements {
-            match statement {
-                Stmt::Print { expression } => {
-                    let value = expression.evaluate(&self.environment)?;
-                    println!("{}", value)
+    // An interpreter that stops any run taking more than `limit` steps, for running untrusted
+    // programs.
+    pub fn with_step_limit(limit: u64) -> Self {
+        Self {
+            step_limit: Some(limit),
+            ..Self::new()
+        }
+    }
+
+    // An interpreter that stops any run still going after `timeout`.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..Self::new()
+        }
+    }
+
+    pub fn with_backend(backend: Backend) -> Self {
+        Self {
+            backend,
+            ..Self::new()
+        }
+    }
+
+    // Makes random() and randomInt() give the same sequence on every run with the same seed.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    // Forgets every global the program defined, leaving only the natives.
+    pub fn reset(&mut self) {
+        self.globals = fresh_globals();
+        self.environment = self.globals.clone();
+        self.imported.clear();
+    }
+
+    // Installs a host function as a global. Errors it returns are reported at the call site.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[LiteralValue]) -> Result<LiteralValue, String> + 'static,
+    ) {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity: arity..=arity,
+            function: Box::new(move |_, arguments, paren| {
+                function(&arguments).map_err(|message| RuntimeError::new(paren, message))
+            }),
+            sees_locals: false,
+        };
+
+        self.globals
+            .borrow_mut()
+            .define(name.to_string(), LiteralValue::Native(Rc::new(native)));
+    }
+
+    pub fn set_script_path(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.files = vec![path];
+    }
+
+    pub fn interpret(&mut self, statements: Vec<Stmt>) -> Result<(), KadomError> {
+        if let Some(counts) = &mut self.coverage {
+            coverage::add_executable_lines(&statements, counts);
+        }
+        self.start_run();
+        Ok(self.run_statements(statements)?)
+    }
+
+    fn run_statements(&mut self, mut statements: Vec<Stmt>) -> Result<(), RuntimeError> {
+        if self.optimize {
+            optimizer::fold_constants(&mut statements, self.ieee_division);
+        }
+        if self.backend == Backend::Vm {
+            return vm::run(self, &compiler::compile_program(&statements)).map(|_| ());
+        }
+
+        for statement in &statements {
+            self.execute(statement)?;
+        }
+
+        Ok(())
+    }
+
+    // Evaluates source holding a single expression against the current environment.
+    pub fn evaluate_expression(&mut self, source: &str) -> Result<LiteralValue, KadomError> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+        let mut expression = Parser::new(tokens).parse_expression()?;
+        Resolver::new().resolve_standalone(&expression)?;
+        if self.optimize {
+            optimizer::fold_expression(&mut expression, self.ieee_division);
+        }
+        self.start_run();
+        Ok(self.evaluate(&expression)?)
+    }
+
+    fn evaluate(&mut self, expression: &Expr) -> Result<LiteralValue, RuntimeError> {
+        match self.backend {
+            Backend::TreeWalker => expression.eval(self),
+            Backend::Vm => vm::run(self, &compiler::compile_expression(expression))
+                .map(|value| value.unwrap_or(LiteralValue::Nil)),
+        }
+    }
+
+    // Runs source for eval() where it was called. At the top level it sees and declares globals.
+    // Inside a function or block it also sees the caller's locals, and its own declarations go in
+    // a scope of their own that ends with it. It returns the value of a final expression
+    // statement, whose semicolon may be left off, or nil. It counts as a call, so recursing
+    // through eval meets max_call_depth, and its steps count towards the run's limits. Errors
+    // name the line within the source.
+    pub(crate) fn eval(&mut self, source: &str, at: &Token) -> Result<LiteralValue, RuntimeError> {
+        let scopes = self.eval_scopes.take();
+        if scopes.is_none() && !Rc::ptr_eq(&self.environment, &self.globals) {
+            return Err(RuntimeError::new(
+                at,
+                "eval must be called by its own name inside a function or block".to_string(),
+            ));
+        }
+        if self.frames.len() >= self.max_call_depth {
+            return Err(RuntimeError::new(
+                at,
+                format!("maximum call depth ({}) exceeded", self.max_call_depth),
+            ));
+        }
+        let compile = |source: &str| -> Result<Vec<Stmt>, KadomError> {
+            let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+            let statements = Parser::new(tokens).parse()?;
+            match &scopes {
+                Some(scopes) => Resolver::with_scopes(scopes).resolve(&statements)?,
+                None => Resolver::new().resolve(&statements)?,
+            }
+            Ok(statements)
+        };
+        // On a new line, so a trailing comment can't swallow it.
+        let mut statements = compile(source)
+            .or_else(|err| compile(&format!("{}\n;", source)).map_err(|_| err))
+            .map_err(|err| RuntimeError::new(at, format!("in eval: {}", err)))?;
+        if self.optimize {
+            optimizer::fold_constants(&mut statements, self.ieee_division);
+        }
+        let last = match statements.last() {
+            Some(Stmt::Expression { .. }) => statements.pop(),
+            _ => None,
+        };
+
+        let environment = match scopes {
+            Some(_) => Rc::new(RefCell::new(Environment::new_enclosed(
+                self.environment.clone(),
+            ))),
+            None => self.globals.clone(),
+        };
+        let previous = std::mem::replace(&mut self.environment, environment);
+        self.frames.push(CallFrame {
+            function: "eval".to_string(),
+            line: at.line(),
+        });
+        let result = self.run_statements(statements).and_then(|_| match &last {
+            Some(Stmt::Expression { expression, .. }) => self.evaluate(expression),
+            _ => Ok(LiteralValue::Nil),
+        });
+        self.frames.pop();
+        self.environment = previous;
+
+        result.map_err(|mut err| {
+            // An error from a nested eval already says where it happened.
+            if !err.message.starts_with("in eval: ") {
+                err.message = format!("in eval: {} on line {}", err.message, err.line);
+            }
+            RuntimeError {
+                line: at.line(),
+                column: at.column(),
+                ..err
+            }
+        })
+    }
+
+    fn execute(&mut self, statement: &Stmt) -> Result<Option<ControlFlow>, RuntimeError> {
+        if self.is_limited() {
+            self.step(statement.token())?;
+        }
+        if self.trace {
+            self.trace_statement(statement);
+        }
+        if let Some(counts) = &mut self.coverage {
+            *counts.entry(statement.line()).or_default() += 1;
+        }
+
+        let Some(profiler) = &mut self.profile else {
+            return self.run_statement(statement);
+        };
+        let started = profiler.enter(statement.line());
+        let result = self.run_statement(statement);
+        if let Some(profiler) = &mut self.profile {
+            profiler.exit(started);
+        }
+        result
+    }
+
+    fn run_statement(&mut self, statement: &Stmt) -> Result<Option<ControlFlow>, RuntimeError> {
+        match statement {
+            Stmt::Print {
+                keyword,
+                expression,
+            } => {
+                let value = expression.eval(self)?;
+                self.print(keyword, &value)?;
+            }
+            Stmt::Expression { expression, .. } => {
+                expression.eval(self)?;
+            }
+            Stmt::Import { path, .. } => self.import(path)?,
+            Stmt::Assert {
+                keyword,
+                expression,
+                message,
+            } => {
+                if !expression.eval(self)?.is_truthy() {
+                    let message = match message {
+                        Some(message) => message.eval(self)?.to_string(),
+                        None => expression.to_string(),
+                    };
+                    return Err(assertion_failed(keyword, message));
+                }
+            }
+            Stmt::Throw { keyword, value } => {
+                return Err(RuntimeError::thrown(keyword, value.eval(self)?));
+            }
+            Stmt::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => match self.execute_scoped(body) {
+                Ok(flow) => return Ok(flow),
+                Err(err) if err.exit.is_some() => return Err(err),
+                Err(err) => {
+                    let mut environment = Environment::new_enclosed(self.environment.clone());
+                    environment.define(name.lexeme().to_string(), err.value());
+
+                    let previous = std::mem::replace(
+                        &mut self.environment,
+                        Rc::new(RefCell::new(environment)),
+                    );
+                    let result = self.execute_block(handler);
+                    self.environment = previous;
+                    return result;
+                }
+            },
+            Stmt::Var {
+                name, initialiser, ..
+            } => match initialiser {
+                Some(initialiser) => {
+                    let value = initialiser.eval(self)?;
+
+                    self.environment
+                        .borrow_mut()
+                        .define(name.lexeme().to_string(), value);
                 }
-                Stmt::Expression { expression } => {
-                    expression.evaluate(&self.environment)?;
+                None => self
+                    .environment
+                    .borrow_mut()
+                    .define_uninitialized(name.lexeme().to_string()),
+            },
+            Stmt::VarDestructure {
+                bracket,
+                names,
+                initialiser,
+                ..
+            } => {
+                let value = initialiser.eval(self)?;
+                self.destructure(bracket, names, value)?;
+            }
+            Stmt::Block { statements, .. } => return self.execute_scoped(statements),
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+                ..
+            } => {
+                let subject = subject.eval(self)?;
+
+                for (value, body) in cases {
+                    if value.eval(self)? == subject {
+                        return self.execute_scoped(body);
+                    }
                 }
-                Stmt::Var { name, initialiser } => {
-                    let value = initialiser.evaluate(&self.environment)?;
 
-                    self.environment.define(name.lexeme, value);
+                if let Some(body) = default {
+                    return self.execute_scoped(body);
+                }
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                if condition.eval(self)?.is_truthy() {
+                    return self.execute(then_branch);
+                } else if let Some(else_branch) = else_branch {
+                    return self.execute(else_branch);
+                }
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                while condition.eval(self)?.is_truthy() {
+                    match self.execute(body)? {
+                        Some(ControlFlow::Break) => break,
+                        Some(ControlFlow::Return(value)) => {
+                            return Ok(Some(ControlFlow::Return(value)))
+                        }
+                        Some(ControlFlow::Continue) | None => (),
+                    }
+                }
+            }
+            Stmt::DoWhile {
+                body, condition, ..
+            } => loop {
+                match self.execute(body)? {
+                    Some(ControlFlow::Break) => break,
+                    Some(ControlFlow::Return(value)) => {
+                        return Ok(Some(ControlFlow::Return(value)))
+                    }
+                    Some(ControlFlow::Continue) | None => (),
+                }
+                if !condition.eval(self)?.is_truthy() {
+                    break;
                 }
+            },
+            Stmt::Function { declaration, .. } => self.define_function(declaration),
+            Stmt::Class { name, methods, .. } => self.define_class(name, methods),
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(expression) => expression.eval(self)?,
+                    None => LiteralValue::Nil,
+                };
+
+                return Ok(Some(ControlFlow::Return(value)));
             }
+            Stmt::Break { .. } => return Ok(Some(ControlFlow::Break)),
+            Stmt::Continue { .. } => return Ok(Some(ControlFlow::Continue)),
         }
 
+        Ok(None)
+    }
+
+    fn execute_block(&mut self, statements: &[Stmt]) -> Result<Option<ControlFlow>, RuntimeError> {
+        for statement in statements {
+            if let Some(flow) = self.execute(statement)? {
+                return Ok(Some(flow));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Runs the statements in a new scope nested in the current one.
+    fn execute_scoped(&mut self, statements: &[Stmt]) -> Result<Option<ControlFlow>, RuntimeError> {
+        let environment = Environment::new_enclosed(self.environment.clone());
+        let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        let result = self.execute_block(statements);
+        self.environment = previous;
+        result
+    }
+
+    // The statement helpers below are shared by the tree-walker and the VM.
+
+    // Writes the first line of the statement as formatted source. Program output is flushed first
+    // so the two interleave in the order things ran, even when they go to different places.
+    fn trace_statement(&mut self, statement: &Stmt) {
+        let source = printer::format_program(std::slice::from_ref(statement));
+        let first_line = source.lines().next().unwrap_or_default();
+        // A trace that can't be written shouldn't change how the program runs.
+        let _ = self.output.flush();
+        let _ = writeln!(
+            self.trace_output,
+            "TRACE [line {}] {}",
+            statement.line(),
+            first_line
+        );
+        let _ = self.trace_output.flush();
+    }
+
+    pub(crate) fn print(
+        &mut self,
+        keyword: &Token,
+        value: &LiteralValue,
+    ) -> Result<(), RuntimeError> {
+        writeln!(self.output, "{}", value)
+            .map_err(|err| RuntimeError::new(keyword, format!("Failed to write output: {}", err)))
+    }
+
+    // Defines the names from the start of a list, skipping any `_`.
+    pub(crate) fn destructure(
+        &mut self,
+        bracket: &Token,
+        names: &[Token],
+        value: LiteralValue,
+    ) -> Result<(), RuntimeError> {
+        let items = match value {
+            LiteralValue::List(items) => items,
+            other => {
+                return Err(RuntimeError::new(
+                    bracket,
+                    format!("Cannot destructure {:?}, expected a list", other),
+                ))
+            }
+        };
+
+        let items = items.borrow();
+        if items.len() < names.len() {
+            return Err(RuntimeError::new(
+                bracket,
+                format!(
+                    "Cannot destructure {} values from a list of length {}",
+                    names.len(),
+                    items.len()
+                ),
+            ));
+        }
+
+        for (name, value) in names.iter().zip(items.iter()) {
+            if name.lexeme() != "_" {
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme().to_string(), value.clone());
+            }
+        }
         Ok(())
     }
+
+    pub(crate) fn define_function(&mut self, declaration: &Rc<FunctionDeclaration>) {
+        let function = KadomFunction {
+            declaration: declaration.clone(),
+            closure: self.environment.clone(),
+            is_initializer: false,
+        };
+
+        self.environment.borrow_mut().define(
+            declaration.name.lexeme().to_string(),
+            LiteralValue::Function(Rc::new(function)),
+        );
+    }
+
+    pub(crate) fn define_class(&mut self, name: &Token, methods: &[Rc<FunctionDeclaration>]) {
+        let methods = methods
+            .iter()
+            .map(|declaration| {
+                let method = KadomFunction {
+                    declaration: declaration.clone(),
+                    closure: self.environment.clone(),
+                    is_initializer: declaration.name.lexeme() == "init",
+                };
+                (declaration.name.lexeme().to_string(), Rc::new(method))
+            })
+            .collect();
+
+        let class = KadomClass {
+            name: name.lexeme().to_string(),
+            methods,
+        };
+
+        self.environment.borrow_mut().define(
+            name.lexeme().to_string(),
+            LiteralValue::Class(Rc::new(class)),
+        );
+    }
+
+    // Runs another file in the global environment, once per run.
+    pub(crate) fn import(&mut self, path: &Token) -> Result<(), RuntimeError> {
+        let relative = match path.literal() {
+            Some(lexer::LiteralValue::StringVal(relative)) => relative.clone(),
+            _ => return Err(RuntimeError::new(path, "Invalid import path".to_string())),
+        };
+
+        let directory = match self.files.last().and_then(|file| file.parent()) {
+            Some(directory) => directory.to_path_buf(),
+            None => PathBuf::new(),
+        };
+        let file = directory.join(&*relative).canonicalize().map_err(|err| {
+            RuntimeError::new(path, format!("Failed to import \'{}\': {}", relative, err))
+        })?;
+
+        if self.files.contains(&file) {
+            return Err(RuntimeError::new(
+                path,
+                format!("Circular import of \'{}\'", relative),
+            ));
+        }
+        if self.imported.contains(&file) {
+            return Ok(());
+        }
+
+        let source = read_to_string(&file).map_err(|err| {
+            RuntimeError::new(path, format!("Failed to import \'{}\': {}", relative, err))
+        })?;
+
+        self.files.push(file.clone());
+        let previous = std::mem::replace(&mut self.environment, self.globals.clone());
+        let result = self.run_import(path, &relative, &source);
+        self.environment = previous;
+        self.files.pop();
+
+        // A failed import is run again when retried, rather than taken as done.
+        if result.is_ok() {
+            self.imported.insert(file);
+        }
+        result
+    }
+
+    // A module that fails to compile is reported as a runtime error at the import, so it can be
+    // caught like any other. So is an error while it runs, since its position is in another file;
+    // the message keeps that position, as "lib/util.kdm:2: ...".
+    fn run_import(
+        &mut self,
+        path: &Token,
+        relative: &str,
+        source: &str,
+    ) -> Result<(), RuntimeError> {
+        let statements = crate::compile(source)
+            .map_err(|err| RuntimeError::new(path, format!("{}: {}", relative, err)))?;
+        self.run_statements(statements).map_err(|err| RuntimeError {
+            message: format!("{}:{}: {}", relative, err.line, err.message),
+            line: path.line(),
+            column: path.column(),
+            ..err
+        })
+    }
+
+    fn start_run(&mut self) {
+        self.steps = 0;
+        self.started = self.timeout.map(|_| (self.clock.now)());
+        self.timed_out = false;
+    }
+
+    // Whether steps need counting, because the run has a step limit or a timeout.
+    pub(crate) fn is_limited(&self) -> bool {
+        self.step_limit.is_some() || self.timeout.is_some()
+    }
+
+    // Counts a step against step_limit, and every so often checks the clock against timeout.
+    // `at` is the step's token, if it has one.
+    pub(crate) fn step(&mut self, at: Option<&Token>) -> Result<(), RuntimeError> {
+        if let Some(token) = at {
+            self.step_location = (token.line(), token.column());
+        }
+        self.steps += 1;
+
+        if let Some(limit) = self.step_limit {
+            if self.steps > limit {
+                return Err(
+                    self.limit_error(format!("execution budget exceeded ({} steps)", limit))
+                );
+            }
+        }
+        if let (Some(timeout), Some(started)) = (self.timeout, self.started) {
+            if !self.timed_out
+                && self
+                    .steps
+                    .is_multiple_of(self.timeout_check_interval.max(1))
+            {
+                self.timed_out = (self.clock.now)().saturating_sub(started) > timeout;
+            }
+            if self.timed_out {
+                return Err(self.timeout_error(started, timeout));
+            }
+        }
+        Ok(())
+    }
+
+    fn timeout_error(&mut self, started: Duration, timeout: Duration) -> RuntimeError {
+        let elapsed = (self.clock.now)().saturating_sub(started);
+        self.limit_error(format!(
+            "execution timed out after {}ms (limit {}ms)",
+            elapsed.as_millis(),
+            timeout.as_millis()
+        ))
+    }
+
+    // Waits on the clock for sleep(). Sleeping counts against a timeout, so a sleep running past
+    // it only lasts until the timeout and then stops the run.
+    pub(crate) fn sleep(&mut self, duration: Duration, at: &Token) -> Result<(), RuntimeError> {
+        let sleep = |interpreter: &mut Self, duration| {
+            (interpreter.clock.sleep)(duration).map_err(|message| RuntimeError::new(at, message))
+        };
+        let (Some(timeout), Some(started)) = (self.timeout, self.started) else {
+            return sleep(self, duration);
+        };
+        let remaining = timeout.saturating_sub((self.clock.now)().saturating_sub(started));
+        sleep(self, duration.min(remaining))?;
+        if duration <= remaining {
+            return Ok(());
+        }
+        self.step_location = (at.line(), at.column());
+        self.timed_out = true;
+        Err(self.timeout_error(started, timeout))
+    }
+
+    fn limit_error(&self, message: String) -> RuntimeError {
+        let (line, column) = self.step_location;
+        RuntimeError {
+            message,
+            line,
+            column,
+            thrown: None,
+            trace: Vec::new(),
+            exit: None,
+        }
+    }
+
+    // Reads a variable from the slot the resolver found it in, or from globals when unresolved.
+    pub fn look_up_variable(
+        &self,
+        name: &Token,
+        local: Option<Local>,
+    ) -> Result<LiteralValue, RuntimeError> {
+        let value = match local {
+            Some(local) => self.environment.borrow().get_at(local.depth, local.slot),
+            None => self.globals.borrow().lookup(name.lexeme()),
+        };
+
+        value.map_err(|unbound| {
+            let message = match unbound {
+                Unbound::Undeclared => format!("undefined variable \'{}\'", name.lexeme()),
+                Unbound::Uninitialized => {
+                    format!("variable \'{}\' used before being assigned", name.lexeme())
+                }
+                Unbound::Constant => constant_message(name),
+            };
+            RuntimeError::new(name, message)
+        })
+    }
+
+    pub fn assign_variable(
+        &mut self,
+        name: &Token,
+        local: Option<Local>,
+        value: LiteralValue,
+    ) -> Result<(), RuntimeError> {
+        let assigned = match local {
+            Some(local) => self
+                .environment
+                .borrow_mut()
+                .assign_at(local.depth, local.slot, value)
+                .then_some(())
+                .ok_or(Unbound::Undeclared),
+            None => self.globals.borrow_mut().assign(name.lexeme(), value),
+        };
+
+        assigned.map_err(|unbound| {
+            let message = match unbound {
+                Unbound::Constant => constant_message(name),
+                _ => format!("cannot assign to undeclared variable \'{}\'", name.lexeme()),
+            };
+            RuntimeError::new(name, message)
+        })
+    }
+
+    // Runs a user function. The caller has already checked the argument count.
+    pub fn call_function(
+        &mut self,
+        function: &KadomFunction,
+        arguments: Vec<LiteralValue>,
+        paren: &Token,
+    ) -> Result<LiteralValue, RuntimeError> {
+        if self.frames.len() >= self.max_call_depth {
+            return Err(RuntimeError::new(
+                paren,
+                format!("maximum call depth ({}) exceeded", self.max_call_depth),
+            ));
+        }
+
+        let environment = Environment::new_enclosed(function.closure.clone());
+        let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        self.frames.push(CallFrame {
+            function: function.declaration.name.lexeme().to_string(),
+            line: paren.line(),
+        });
+        let result = self.run_function(function, arguments);
+        self.environment = previous;
+
+        // The innermost call an error passes through records the whole stack.
+        let result = result.map_err(|mut err| {
+            if err.trace.is_empty() {
+                err.trace = self.frames.iter().rev().cloned().collect();
+            }
+            err
+        });
+        self.frames.pop();
+
+        match result? {
+            // A bound initializer's closure holds just `this`.
+            _ if function.is_initializer => Ok(function
+                .closure
+                .borrow()
+                .get_at(0, 0)
+                .unwrap_or(LiteralValue::Nil)),
+            Some(ControlFlow::Return(value)) => Ok(value),
+            _ => Ok(LiteralValue::Nil),
+        }
+    }
+
+    // Binds the arguments in the (already entered) call environment and runs the body. Missing
+    // trailing arguments take their defaults, evaluated here so they can see earlier parameters,
+    // and any surplus arguments are collected into the rest parameter's list.
+    fn run_function(
+        &mut self,
+        function: &KadomFunction,
+        arguments: Vec<LiteralValue>,
+    ) -> Result<Option<ControlFlow>, RuntimeError> {
+        let compiled = match self.backend {
+            Backend::TreeWalker => None,
+            Backend::Vm => Some(self.compiled_function(&function.declaration)),
+        };
+        let mut arguments = arguments.into_iter();
+
+        for (i, param) in function.declaration.params.iter().enumerate() {
+            let value = match (arguments.next(), &param.default, &compiled) {
+                (Some(value), _, _) => value,
+                (None, Some(_), Some(compiled)) => match &compiled.defaults[i] {
+                    Some(default) => vm::run(self, default)?.unwrap_or(LiteralValue::Nil),
+                    None => LiteralValue::Nil,
+                },
+                (None, Some(default), None) => default.eval(self)?,
+                (None, None, _) => LiteralValue::Nil,
+            };
+
+            self.environment
+                .borrow_mut()
+                .define(param.name.lexeme().to_string(), value);
+        }
+
+        if let Some(rest) = &function.declaration.rest {
+            let surplus = LiteralValue::List(Rc::new(RefCell::new(arguments.collect())));
+            self.environment
+                .borrow_mut()
+                .define(rest.lexeme().to_string(), surplus);
+        }
+
+        match compiled {
+            Some(compiled) => Ok(vm::run(self, &compiled.body)?.map(ControlFlow::Return)),
+            None => self.execute_block(&function.declaration.body),
+        }
+    }
+
+    fn compiled_function(&mut self, declaration: &Rc<FunctionDeclaration>) -> Rc<CompiledFunction> {
+        let (_, compiled) = self
+            .compiled
+            .entry(Rc::as_ptr(declaration))
+            .or_insert_with(|| {
+                let compiled = compiler::compile_function(declaration);
+                (declaration.clone(), Rc::new(compiled))
+            });
+        compiled.clone()
+    }
+}
+
+pub(crate) fn assertion_failed(keyword: &Token, message: String) -> RuntimeError {
+    RuntimeError::new(
+        keyword,
+        format!("assertion failed at line {}: {}", keyword.line(), message),
+    )
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn constant_message(name: &Token) -> String {
+    format!("cannot reassign built-in constant \'{}\'", name.lexeme())
+}
+
+fn fresh_globals() -> Rc<RefCell<Environment>> {
+    let globals = Rc::new(RefCell::new(Environment::new()));
+    natives::define(&mut globals.borrow_mut());
+    globals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Vec<Stmt>, String> {
+        crate::compile(source).map_err(|err| err.to_string())
+    }
+
+    // The message of a runtime error, without the line its display adds.
+    fn message(err: KadomError) -> String {
+        match err {
+            KadomError::Runtime(err) => err.message,
+            other => other.to_string(),
+        }
+    }
+
+    fn runtime(err: KadomError) -> RuntimeError {
+        match err {
+            KadomError::Runtime(err) => err,
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+    }
+
+    // Output sink the test can still read after handing it to an interpreter.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    fn interpreter() -> (Interpreter, Output) {
+        let output = Output::default();
+        (Interpreter::with_output(Box::new(output.clone())), output)
+    }
+
+    fn run(source: &str) -> Result<Interpreter, String> {
+        let (mut interpreter, _) = interpreter();
+        interpreter.interpret(parse(source)?).map_err(message)?;
+        Ok(interpreter)
+    }
+
+    // Runs the source and returns everything it printed.
+    fn output(source: &str) -> Result<String, String> {
+        let (mut interpreter, output) = interpreter();
+        interpreter.interpret(parse(source)?).map_err(message)?;
+        Ok(output.contents())
+    }
+
+    fn get(interpreter: &Interpreter, name: &str) -> LiteralValue {
+        interpreter.environment.borrow().lookup(name).unwrap()
+    }
+
+    fn runtime_error(source: &str) -> RuntimeError {
+        runtime(
+            interpreter()
+                .0
+                .interpret(parse(source).unwrap())
+                .unwrap_err(),
+        )
+    }
+
+    #[test]
+    fn print_writes_to_injected_output() {
+        assert_eq!(
+            output("print 1; print \"two\"; print [nil, true];").unwrap(),
+            "1\ntwo\n[nil, true]\n"
+        );
+    }
+
+    #[test]
+    fn failed_writes_are_runtime_errors() {
+        struct Closed;
+
+        impl Write for Closed {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let err = runtime(
+            Interpreter::with_output(Box::new(Closed))
+                .interpret(parse("var a = 1;\nprint a;").unwrap())
+                .unwrap_err(),
+        );
+        assert_eq!(err.line, 2);
+        assert!(err.message.starts_with("Failed to write output: "));
+    }
+
+    #[test]
+    fn evaluates_standalone_expressions() {
+        let (mut interpreter, _) = interpreter();
+        assert_eq!(
+            interpreter.evaluate_expression("1+2*3"),
+            Ok(LiteralValue::Number(7.0))
+        );
+
+        interpreter
+            .interpret(parse("var x = 10;").unwrap())
+            .unwrap();
+        assert_eq!(
+            interpreter.evaluate_expression("x"),
+            Ok(LiteralValue::Number(10.0))
+        );
+    }
+
+    #[test]
+    fn evaluate_expression_rejects_declarations() {
+        let (mut interpreter, _) = interpreter();
+        assert_eq!(
+            interpreter.evaluate_expression("var y = 1;"),
+            Err(KadomError::Parse {
+                line: 1,
+                message: "Expected an expression but found a 'var' declaration; run declarations with interpret".to_string()
+            })
+        );
+        assert!(interpreter.environment.borrow().lookup("y").is_err());
+    }
+
+    #[test]
+    fn reset_forgets_program_globals() {
+        let mut interpreter = run("var x = 1; fun f() {}").unwrap();
+        interpreter.reset();
+
+        assert!(interpreter.environment.borrow().lookup("x").is_err());
+        assert!(interpreter.environment.borrow().lookup("f").is_err());
+        assert!(interpreter.environment.borrow().lookup("clock").is_ok());
+        assert_eq!(
+            interpreter.evaluate_expression("len(\"abc\")"),
+            Ok(LiteralValue::Number(3.0))
+        );
+    }
+
+    #[test]
+    fn type_error_reports_line() {
+        let err = runtime_error("var a = 1;\nvar b = \"two\";\nprint a - b;");
+        assert_eq!(err.line, 3);
+        assert_eq!(
+            err.to_string(),
+            format!("[line 3] RuntimeError: {}", err.message)
+        );
+    }
+
+    #[test]
+    fn undefined_variable_reports_line() {
+        let err = runtime_error(
+            "var a = 1;\nvar b = 2;\nvar c = 3;\n\nprint a;\nprint b;\nprint missing;\nprint c;",
+        );
+        assert_eq!(err.line, 7);
+        assert_eq!(
+            err.to_string(),
+            "[line 7] RuntimeError: undefined variable 'missing'"
+        );
+    }
+
+    #[test]
+    fn division_by_zero_errors() {
+        let err = runtime_error("print 1 / 0;");
+        assert_eq!(err.message, "Division by zero");
+        assert_eq!(err.line, 1);
+        assert_eq!(runtime_error("print 0 / 0;").message, "Division by zero");
+        assert_eq!(
+            runtime_error("var x = 5;\nprint x % 0;").to_string(),
+            "[line 2] RuntimeError: Division by zero"
+        );
+    }
+
+    #[test]
+    fn division_and_remainder() {
+        let interpreter = run("var half = 1 / 2; var rest = 7 % 3;").unwrap();
+        assert_eq!(get(&interpreter, "half"), LiteralValue::Number(0.5));
+        assert_eq!(get(&interpreter, "rest"), LiteralValue::Number(1.0));
+    }
+
+    #[test]
+    fn ieee_division_opt_in() {
+        let (mut interpreter, _) = interpreter();
+        interpreter.ieee_division = true;
+        interpreter
+            .interpret(parse("var inf = 1 / 0; var nan = 0 / 0;").unwrap())
+            .unwrap();
+        assert_eq!(
+            get(&interpreter, "inf"),
+            LiteralValue::Number(f64::INFINITY)
+        );
+        match get(&interpreter, "nan") {
+            LiteralValue::Number(n) => assert!(n.is_nan()),
+            other => panic!("expected a number, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn large_integers_round_trip() {
+        let interpreter = run("var big = 16777217; var bigger = 9007199254740991;").unwrap();
+        assert_eq!(get(&interpreter, "big").to_string(), "16777217");
+        assert_eq!(get(&interpreter, "bigger").to_string(), "9007199254740991");
+    }
+
+    #[test]
+    fn floating_point_addition_matches_f64() {
+        let interpreter =
+            run("var sum = 0.1 + 0.2; var same = sum == 0.30000000000000004;").unwrap();
+        assert_eq!(get(&interpreter, "sum").to_string(), "0.30000000000000004");
+        assert_eq!(get(&interpreter, "same"), LiteralValue::True);
+    }
+
+    #[test]
+    fn string_repetition() {
+        assert_eq!(
+            output("print \"ab\" * 3; print 3 * \"ab\"; print \"ab\" * 0;").unwrap(),
+            "ababab\nababab\n\n"
+        );
+    }
+
+    #[test]
+    fn string_repetition_rejects_bad_counts() {
+        assert_eq!(
+            run("print \"ab\" * -1;").err().unwrap(),
+            "String repetition count must be a non-negative whole number, got -1"
+        );
+        assert_eq!(
+            run("print 1.5 * \"ab\";").err().unwrap(),
+            "String repetition count must be a non-negative whole number, got 1.5"
+        );
+        assert!(run("print \"x\" * 1000000000;")
+            .err()
+            .unwrap()
+            .starts_with("String repetition would exceed the maximum length"));
+    }
+
+    #[test]
+    fn string_number_concatenation() {
+        assert_eq!(
+            output("print \"n=\" + 2; print 2.5 + \" apples\";").unwrap(),
+            "n=2\n2.5 apples\n"
+        );
+    }
+
+    #[test]
+    fn string_concatenation_with_nil_or_bool_errors() {
+        assert_eq!(
+            run("print \"x\" + nil;").err().unwrap(),
+            "Cannot concatenate string and nil; convert the nil with str() first"
+        );
+        assert_eq!(
+            run("print true + \"x\";").err().unwrap(),
+            "Cannot concatenate boolean and string; convert the boolean with str() first"
+        );
+    }
+
+    #[test]
+    fn only_nil_and_false_are_falsey() {
+        let interpreter =
+            run("var a = !0; var b = !\"\"; var c = !nil; var d = !false; var e = ![];").unwrap();
+        assert_eq!(get(&interpreter, "a"), LiteralValue::False);
+        assert_eq!(get(&interpreter, "b"), LiteralValue::False);
+        assert_eq!(get(&interpreter, "c"), LiteralValue::True);
+        assert_eq!(get(&interpreter, "d"), LiteralValue::True);
+        assert_eq!(get(&interpreter, "e"), LiteralValue::False);
+    }
+
+    #[test]
+    fn if_on_zero_takes_then_branch() {
+        let interpreter =
+            run("var taken = \"none\"; if (0) taken = \"then\"; else taken = \"else\";").unwrap();
+        assert_eq!(
+            get(&interpreter, "taken"),
+            LiteralValue::String("then".into())
+        );
+    }
+
+    #[test]
+    fn if_else_chain() {
+        assert_eq!(
+            output(
+                "var x = 2;
+                 if (x < 1) print \"small\"; else if (x < 3) print \"medium\"; else print \"large\";"
+            )
+            .unwrap(),
+            "medium\n"
+        );
+    }
+
+    #[test]
+    fn clock_is_non_decreasing() {
+        let interpreter = run("var a = clock(); var b = clock();").unwrap();
+        match (get(&interpreter, "a"), get(&interpreter, "b")) {
+            (LiteralValue::Number(a), LiteralValue::Number(b)) => assert!(a > 0.0 && a <= b),
+            other => panic!("expected numbers, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn clock_rejects_arguments() {
+        assert_eq!(
+            run("clock(1);").err().unwrap(),
+            "Expected 0 arguments, got 1"
+        );
+    }
+
+    fn run_with_input(source: &str, input: &'static str) -> Result<String, String> {
+        let (mut interpreter, output) = interpreter();
+        interpreter.input = Some(Box::new(input.as_bytes()));
+        interpreter.interpret(parse(source)?).map_err(message)?;
+        Ok(output.contents())
+    }
+
+    #[test]
+    fn input_reads_lines_without_newline() {
+        let output = run_with_input(
+            "var a = input(); var b = input(\"name: \"); var c = input();
+             print a; print b; print c;",
+            "first\nsecond\r\n",
+        )
+        .unwrap();
+        assert_eq!(output, "name: first\nsecond\nnil\n");
+    }
+
+    #[test]
+    fn input_rejects_non_string_prompt() {
+        assert_eq!(
+            run_with_input("input(1);", "").err().unwrap(),
+            "input() prompt must be a string, got number"
+        );
+        assert_eq!(
+            run_with_input("input(\"a\", \"b\");", "").err().unwrap(),
+            "Expected 0 to 1 arguments, got 2"
+        );
+    }
+
+    // Output that notes how much had been written at each flush.
+    #[derive(Clone, Default)]
+    struct Flushes(Output, Rc<RefCell<Vec<usize>>>);
+
+    impl Write for Flushes {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.1.borrow_mut().push(self.0.contents().len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_leaves_the_line_open_and_flushes() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let output = Flushes::default();
+            let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+            interpreter.backend = backend;
+            interpreter
+                .interpret(parse("write(\"a\"); write(\"b\"); print \"\";").unwrap())
+                .unwrap();
+            assert_eq!(output.0.contents(), "ab\n");
+            assert_eq!(*output.1.borrow(), [1, 2]);
+        }
+
+        let output = Flushes::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        interpreter
+            .interpret(
+                parse("var x = write(\"x\", 1, 2.5, nil, [true, \"s\"]); write(); print x;")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(output.0.contents(), "x12.5nil[true, s]nil\n");
+        assert_eq!(*output.1.borrow(), [17, 17]);
+    }
+
+    #[test]
+    fn args_lists_script_arguments() {
+        let source = "print len(args()); print args();";
+        assert_eq!(output(source).unwrap(), "0\n[]\n");
+
+        let (mut interpreter, output) = interpreter();
+        interpreter.args = vec!["input.txt".to_string(), "3".to_string()];
+        interpreter
+            .interpret(
+                parse(&format!(
+                    "{} var [file, count] = args(); print file; print num(count) + 1;",
+                    source
+                ))
+                .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(output.contents(), "2\n[input.txt, 3]\ninput.txt\n4\n");
+    }
+
+    #[test]
+    fn typeof_names_every_value_kind() {
+        let interpreter = run("class Point {}
+             fun f() {}
+             var types = [typeof(1), typeof(\"s\"), typeof(true), typeof(false), typeof(nil),
+                          typeof([]), typeof({}), typeof(f), typeof(clock), typeof(Point),
+                          typeof(Point())];")
+        .unwrap();
+        assert_eq!(
+            get(&interpreter, "types").to_string(),
+            "[number, string, boolean, boolean, nil, list, map, function, function, class, instance]"
+        );
+    }
+
+    #[test]
+    fn type_errors_use_type_names() {
+        assert_eq!(
+            run("print nil - [];").err().unwrap(),
+            "Minus cannot be evaluated for nil and list"
+        );
+    }
+
+    #[test]
+    fn str_uses_print_formatting() {
+        let interpreter = run(
+            "var values = [str(42), str(2.5), str(nil), str(true), str(false), str(\"s\")];
+             var same = str(42) == \"42\";",
+        )
+        .unwrap();
+        assert_eq!(
+            get(&interpreter, "values").to_string(),
+            "[42, 2.5, nil, true, false, s]"
+        );
+        assert_eq!(get(&interpreter, "same"), LiteralValue::True);
+    }
+
+    #[test]
+    fn num_parses_whole_strings_only() {
+        let interpreter = run(
+            "var values = [num(\"42\"), num(\"3.5\"), num(\" 7 \"), num(true), num(false), num(4)];
+             var invalid = [num(\"abc\"), num(\"\"), num(\"42abc\"), num(\"inf\"), num(nil)];",
+        )
+        .unwrap();
+        assert_eq!(
+            get(&interpreter, "values").to_string(),
+            "[42, 3.5, 7, 1, 0, 4]"
+        );
+        assert_eq!(
+            get(&interpreter, "invalid").to_string(),
+            "[nil, nil, nil, nil, nil]"
+        );
+        assert_eq!(
+            run("num([]);").err().unwrap(),
+            "Cannot convert list to a number"
+        );
+    }
+
+    #[test]
+    fn len_counts_characters_and_elements() {
+        let interpreter = run(
+            "var lengths = [len(\"hello\"), len(\"héllo\"), len(\"\"), len([1, 2, 3]), len({1: 2})];",
+        )
+        .unwrap();
+        assert_eq!(get(&interpreter, "lengths").to_string(), "[5, 5, 0, 3, 1]");
+        assert_eq!(
+            run("len(5);").err().unwrap(),
+            "Cannot take the length of number"
+        );
+    }
+
+    fn host_add(arguments: &[LiteralValue]) -> Result<LiteralValue, String> {
+        match arguments {
+            [LiteralValue::Number(a), LiteralValue::Number(b)] => Ok(LiteralValue::Number(a + b)),
+            _ => Err("add() expects two numbers".to_string()),
+        }
+    }
+
+    #[test]
+    fn host_natives_are_callable() {
+        let (mut interpreter, _) = interpreter();
+        interpreter.define_native("add", 2, host_add);
+        interpreter
+            .interpret(parse("var sum = add(2, 3);").unwrap())
+            .unwrap();
+        assert_eq!(get(&interpreter, "sum"), LiteralValue::Number(5.0));
+    }
+
+    #[test]
+    fn host_native_errors_report_call_site() {
+        let (mut interpreter, _) = interpreter();
+        interpreter.define_native("add", 2, host_add);
+
+        let err = interpreter
+            .interpret(parse("var a = 1;\nadd(a, \"b\");").unwrap())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "[line 2] RuntimeError: add() expects two numbers"
+        );
+
+        let err = runtime(
+            interpreter
+                .interpret(parse("add(1);").unwrap())
+                .unwrap_err(),
+        );
+        assert_eq!(err.message, "Expected 2 arguments, got 1");
+    }
+
+    #[test]
+    fn unbounded_recursion_errors() {
+        let (mut interpreter, _) = interpreter();
+        interpreter.max_call_depth = 50;
+        let err = runtime(
+            interpreter
+                .interpret(parse("fun f() { f(); }\nf();").unwrap())
+                .unwrap_err(),
+        );
+        assert_eq!(err.message, "maximum call depth (50) exceeded");
+        assert_eq!(err.line, 1);
+
+        // The depth is unwound after the error.
+        interpreter
+            .interpret(parse("fun g() { return 1; } var one = g();").unwrap())
+            .unwrap();
+        assert_eq!(get(&interpreter, "one"), LiteralValue::Number(1.0));
+    }
+
+    #[test]
+    fn recursion_within_limit_succeeds() {
+        let depth = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(|| {
+                let interpreter = run(
+                    "fun down(n) { if (n > 1) return down(n - 1) + 1; return 1; }
+                     var depth = down(900);",
+                )
+                .unwrap();
+                get(&interpreter, "depth").to_string()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(depth, "900");
+    }
+
+    #[test]
+    fn blocks_have_their_own_scope() {
+        let interpreter = run("var a = \"outer\"; var b = \"outer\";
+             { var a = \"inner\"; b = a; var c = 1; }")
+        .unwrap();
+        assert_eq!(get(&interpreter, "a"), LiteralValue::String("outer".into()));
+        assert_eq!(get(&interpreter, "b"), LiteralValue::String("inner".into()));
+        assert!(interpreter.environment.borrow().lookup("c").is_err());
+    }
+
+    #[test]
+    fn functions_assign_to_enclosing_variables() {
+        let interpreter = run("var count = 0;
+             fun increment() { count = count + 1; }
+             increment(); increment();")
+        .unwrap();
+        assert_eq!(get(&interpreter, "count"), LiteralValue::Number(2.0));
+    }
+
+    #[test]
+    fn assigning_undeclared_variable_errors() {
+        let err = runtime_error("var x = 1;\ny = 3;");
+        assert_eq!(err.message, "cannot assign to undeclared variable 'y'");
+        assert_eq!(err.line, 2);
+
+        let interpreter = run("var y; y = 3;").unwrap();
+        assert_eq!(get(&interpreter, "y"), LiteralValue::Number(3.0));
+    }
+
+    #[test]
+    fn closures_capture_binding_at_declaration() {
+        let printed = output(
+            "var a = \"global\";
+             {
+                 fun show() { print a; }
+                 show();
+                 var a = \"block\";
+                 show();
+             }",
+        )
+        .unwrap();
+        assert_eq!(printed, "global\nglobal\n");
+    }
+
+    #[test]
+    fn closures_share_enclosing_locals() {
+        let interpreter = run("fun counter() {
+                 var count = 0;
+                 fun increment() { count = count + 1; return count; }
+                 return increment;
+             }
+             var next = counter();
+             next();
+             var second = next();")
+        .unwrap();
+        assert_eq!(get(&interpreter, "second"), LiteralValue::Number(2.0));
+    }
+
+    #[test]
+    fn redeclared_locals_shadow_earlier_ones() {
+        let interpreter = run("var seen = nil;
+             fun f(x) {
+                 var a = x;
+                 fun first() { return a; }
+                 var b = a + 1;
+                 var a = b;
+                 { var a = 100; a = a + 1; }
+                 return [first(), a];
+             }
+             seen = f(1);")
+        .unwrap();
+        assert_eq!(get(&interpreter, "seen").to_string(), "[1, 2]");
+    }
+
+    #[test]
+    fn undefined_variable_in_function_reports_usage_line() {
+        let err = runtime_error("fun f() {\n    var a = 1;\n    return a + b;\n}\n\nf();");
+        assert_eq!(err.line, 3);
+        assert_eq!(err.message, "undefined variable 'b'");
+    }
+
+    #[test]
+    fn reading_uninitialized_variable_errors() {
+        let err = runtime_error("var x;\nprint x;");
+        assert_eq!(err.message, "variable 'x' used before being assigned");
+        assert_eq!(err.line, 2);
+
+        let err = runtime_error("fun f() {\n var y;\n return y;\n}\nf();");
+        assert_eq!(err.message, "variable 'y' used before being assigned");
+        assert_eq!(err.line, 3);
+    }
+
+    #[test]
+    fn uninitialized_variable_can_be_assigned_then_read() {
+        let interpreter =
+            run("var x; x = 1; var y = x; fun f() { var z; z = 2; return z; } var w = f();")
+                .unwrap();
+        assert_eq!(get(&interpreter, "y"), LiteralValue::Number(1.0));
+        assert_eq!(get(&interpreter, "w"), LiteralValue::Number(2.0));
+    }
+
+    #[test]
+    fn explicit_nil_initializer_reads_nil() {
+        let interpreter = run("var x = nil; var y = x;").unwrap();
+        assert_eq!(get(&interpreter, "y"), LiteralValue::Nil);
+    }
+
+    #[test]
+    fn catches_thrown_values() {
+        let interpreter = run("var caught = nil;
+             fun fail() { throw [1, \"two\"]; }
+             try { fail(); caught = \"not thrown\"; } catch (e) { caught = e; }")
+        .unwrap();
+        assert_eq!(get(&interpreter, "caught").to_string(), "[1, two]");
+    }
+
+    #[test]
+    fn catches_built_in_errors_as_messages() {
+        let interpreter = run("var caught = nil;
+             try { print 1 / 0; } catch (e) { caught = e; }")
+        .unwrap();
+        assert_eq!(
+            get(&interpreter, "caught"),
+            LiteralValue::String("Division by zero".into())
+        );
+    }
+
+    #[test]
+    fn rethrows_from_catch() {
+        let interpreter = run("var caught = nil;
+             try {
+                 try { throw \"inner\"; } catch (e) { throw e + \" again\"; }
+             } catch (e) {
+                 caught = e;
+             }")
+        .unwrap();
+        assert_eq!(
+            get(&interpreter, "caught"),
+            LiteralValue::String("inner again".into())
+        );
+    }
+
+    #[test]
+    fn uncaught_throw_reports_its_line() {
+        let err = runtime_error("var x = 1;\nfun f() {\n  throw \"boom\";\n}\nf();");
+        assert_eq!(
+            err.to_string(),
+            "[line 3] RuntimeError: boom\n  at f (line 5)"
+        );
+        assert_eq!(err.thrown, Some(LiteralValue::String("boom".into())));
+    }
+
+    #[test]
+    fn exit_stops_the_program_with_its_status() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            for (source, code) in [
+                ("print 1; exit(3); print 2;", 3),
+                ("print 1; exit(); print 2;", 0),
+                (
+                    "print 1; try { exit(4); } catch (e) { print e; } print 2;",
+                    4,
+                ),
+                ("print 1; fun f(x) { exit(5); } map([1, 2], f); print 2;", 5),
+                (
+                    "print 1; fun f(x) { while (true) { exit(6); } } f(1); print 2;",
+                    6,
+                ),
+            ] {
+                let (mut interpreter, output) = interpreter();
+                interpreter.backend = backend;
+                assert_eq!(
+                    interpreter.interpret(parse(source).unwrap()),
+                    Err(KadomError::Exit(code)),
+                    "{}",
+                    source
+                );
+                assert_eq!(output.contents(), "1\n", "{}", source);
+                assert!(interpreter.frames.is_empty());
+            }
+        }
+
+        for (source, expected) in [
+            ("exit(256);", "exit status must be from 0 to 255, got 256"),
+            ("exit(-1);", "exit status must be from 0 to 255, got -1"),
+            ("exit(1.5);", "exit expects an integer, got 1.5"),
+            ("exit(\"1\");", "exit expects an integer, got string"),
+        ] {
+            assert_eq!(run(source).err(), Some(expected.to_string()), "{}", source);
+        }
+        // A bad status is an ordinary error, so it can be caught.
+        assert!(run("try { exit(300); } catch (e) {}").is_ok());
+    }
+
+    #[test]
+    fn eval_runs_source_at_the_top_level() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (mut interpreter, output) = interpreter();
+            interpreter.backend = backend;
+            interpreter
+                .interpret(
+                    parse(
+                        "var x = 4;
+                         print eval(\"1 + 2 * x\");
+                         print eval(\"var made = x + 1;\");
+                         print made;
+                         print eval(\"var a = 2; a * 3 // six\");
+                         var code = \"x\";
+                         print eval(\"eval(code)\");",
+                    )
+                    .unwrap(),
+                )
+                .unwrap();
+            assert_eq!(output.contents(), "9\nnil\n5\n6\n4\n");
+        }
+    }
+
+    #[test]
+    fn eval_sees_the_locals_where_it_is_called() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (mut interpreter, output) = interpreter();
+            interpreter.backend = backend;
+            interpreter
+                .interpret(
+                    parse(
+                        "var x = 4;
+                         fun f() { var x = 10; return eval(\"x\"); }
+                         print f();
+                         fun sum(n) {
+                           var total = 0;
+                           var i = 0;
+                           while (i < n) { eval(\"total = total + i\"); i = i + 1; }
+                           return total;
+                         }
+                         print sum(4);
+                         fun outer() {
+                           var a = 1;
+                           fun inner() { var b = 2; return eval(\"var c = 3; a + b + c\"); }
+                           return inner();
+                         }
+                         print outer();
+                         fun own() { eval(\"var y = 1;\"); var z = 2; return z; }
+                         print own();
+                         class Counter {
+                           init() { this.count = 5; }
+                           read() { var code = \"this.count + 1\"; return eval(code); }
+                         }
+                         print Counter().read();
+                         { var x = 7; var inner = \"x\"; print eval(\"eval(inner)\"); }
+                         print x;",
+                    )
+                    .unwrap(),
+                )
+                .unwrap();
+            assert_eq!(output.contents(), "10\n6\n6\n2\n6\n7\n4\n");
+        }
+    }
+
+    #[test]
+    fn calls_to_another_function_named_eval_are_ordinary_calls() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (mut interpreter, output) = interpreter();
+            interpreter.backend = backend;
+            interpreter
+                .interpret(
+                    parse(
+                        "fun local(eval) { var x = 1; return eval(\"x\"); }
+                         print local(str);
+                         fun eval(source) { return \"mine: \" + source; }
+                         fun global() { var x = 1; return eval(\"x\"); }
+                         print global();",
+                    )
+                    .unwrap(),
+                )
+                .unwrap();
+            assert_eq!(output.contents(), "x\nmine: x\n");
+        }
+    }
+
+    #[test]
+    fn eval_refuses_to_run_in_a_scope_it_cannot_see() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (mut interpreter, _) = interpreter();
+            interpreter.backend = backend;
+            let err = runtime(
+                interpreter
+                    .interpret(
+                        parse("var run = eval; fun f() { var x = 1; return run(\"x\"); } f();")
+                            .unwrap(),
+                    )
+                    .unwrap_err(),
+            );
+            assert_eq!(
+                err.message,
+                "eval must be called by its own name inside a function or block"
+            );
+        }
+    }
+
+    #[test]
+    fn eval_errors_name_the_line_in_the_source() {
+        let err = runtime_error("var a = 1;\neval(\"1 +\");");
+        assert_eq!(err.message, "in eval: Expected expression on line 1");
+        assert_eq!((err.line, err.column), (2, 11));
+
+        let err = runtime_error("fun f() {\n  eval(\"var q = 1;\nq + nil;\");\n}\nf();");
+        assert_eq!(
+            err.message,
+            "in eval: Plus cannot be evaluated for number and nil on line 2"
+        );
+        // The call's closing parenthesis, after the string's line break.
+        assert_eq!(err.line, 3);
+
+        assert_eq!(
+            output("try { eval(\"throw 7;\"); } catch (e) { print e; }"),
+            Ok("7\n".to_string())
+        );
+        assert_eq!(
+            run("eval(1);").err(),
+            Some("eval expects a string, got number".to_string())
+        );
+    }
+
+    #[test]
+    fn eval_counts_towards_the_run_limits() {
+        let (mut recursive, _) = interpreter();
+        recursive.max_call_depth = 50;
+        let err = runtime(
+            recursive
+                .interpret(parse("fun f() { eval(\"f();\"); }\nf();").unwrap())
+                .unwrap_err(),
+        );
+        assert_eq!(
+            err.message,
+            "in eval: maximum call depth (50) exceeded on line 1"
+        );
+        assert!(recursive.frames.is_empty());
+
+        let mut budgeted = Interpreter::with_step_limit(100);
+        let err = runtime(
+            budgeted
+                .interpret(parse("eval(\"while (true) {}\");").unwrap())
+                .unwrap_err(),
+        );
+        assert_eq!(
+            err.message,
+            "in eval: execution budget exceeded (100 steps) on line 1"
+        );
+
+        let (mut sandboxed, _) = interpreter();
+        sandboxed.host_access = false;
+        assert_eq!(
+            sandboxed
+                .interpret(parse("eval(\"1\");").unwrap())
+                .map_err(message),
+            Err("eval is not allowed: host access is turned off".to_string())
+        );
+    }
+
+    #[test]
+    fn operators_call_methods_on_instances() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (mut interpreter, output) = interpreter();
+            interpreter.backend = backend;
+            interpreter
+                .interpret(
+                    parse(
+                        "class Vector {
+                           init(x, y) { this.x = x; this.y = y; }
+                           plus(other) { return Vector(this.x + other.x, this.y + other.y); }
+                           minus(other) { return Vector(this.x - other.x, this.y - other.y); }
+                           times(k) { return Vector(this.x * k, this.y * k); }
+                           div(k) { return Vector(this.x / k, this.y / k); }
+                           equals(other) {
+                             if (this.x != other.x) return false;
+                             return this.y == other.y;
+                           }
+                           less(other) { return this.x * this.x + this.y * this.y < other.x * other.x + other.y * other.y; }
+                         }
+                         var a = Vector(1, 2);
+                         var b = Vector(3, 4);
+                         var sum = a + b;
+                         print [sum.x, sum.y];
+                         var difference = b - a;
+                         print [difference.x, difference.y];
+                         var scaled = a * 3 / 2;
+                         print [scaled.x, scaled.y];
+                         print [a == Vector(1, 2), a == b, a != b];
+                         print [a < b, a <= b, a > b, a >= b];
+                         print [b < b, b <= Vector(3, 4), b > b, b >= b];",
+                    )
+                    .unwrap(),
+                )
+                .unwrap();
+            assert_eq!(
+                output.contents(),
+                "[4, 6]\n[2, 2]\n[1.5, 3]\n[true, false, true]\n\
+                 [true, true, false, false]\n[false, true, false, true]\n"
+            );
+        }
+    }
+
+    #[test]
+    fn instances_without_equals_compare_by_identity() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (mut interpreter, output) = interpreter();
+            interpreter.backend = backend;
+            interpreter
+                .interpret(
+                    parse(
+                        "class Point {}
+                         var p = Point();
+                         print [p == p, p == Point(), p != Point(), p == nil, 1 == p];",
+                    )
+                    .unwrap(),
+                )
+                .unwrap();
+            assert_eq!(output.contents(), "[true, false, true, false, false]\n");
+        }
+    }
+
+    #[test]
+    fn missing_operator_methods_name_the_method() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let error = |source: &str| {
+                let (mut interpreter, _) = interpreter();
+                interpreter.backend = backend;
+                runtime(interpreter.interpret(parse(source).unwrap()).unwrap_err()).message
+            };
+            assert_eq!(
+                error("class Point {} Point() + 1;"),
+                "Plus cannot be evaluated for instance and number; \
+                 define a 'plus' method on Point to support +"
+            );
+            assert_eq!(
+                error("class Point {} Point() >= Point();"),
+                "GreaterEqual cannot be evaluated for instance and instance; \
+                 define a 'less' method on Point to support >="
+            );
+            assert_eq!(
+                error("class Point { times(k) { return k; } } 2 * Point();"),
+                "Star cannot be evaluated for number and instance; \
+                 only the left operand's 'times' method is used, so put the Point instance first"
+            );
+        }
+    }
+
+    #[test]
+    fn errors_include_call_stack_trace() {
+        let (mut interpreter, _) = interpreter();
+        let err = interpreter
+            .interpret(
+                parse(
+                    "fun inner() {\n  return 1 / 0;\n}\nfun middle() {\n  return inner();\n}\n\
+                     fun outer() {\n  return middle();\n}\nouter();",
+                )
+                .unwrap(),
+            )
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "[line 2] RuntimeError: Division by zero\n  at inner (line 5)\n  \
+             at middle (line 8)\n  at outer (line 10)"
+        );
+        assert!(interpreter.frames.is_empty());
+
+        let err = runtime(
+            interpreter
+                .interpret(parse("fun ok() { return 1; }\nok();\nprint nil - 1;").unwrap())
+                .unwrap_err(),
+        );
+        assert_eq!(err.trace, Vec::new());
+        assert_eq!(err.line, 3);
+    }
+
+    #[test]
+    fn do_while_runs_body_once_when_condition_false() {
+        let interpreter = run("var i = 0; do { i = i + 1; } while (false);").unwrap();
+        assert_eq!(get(&interpreter, "i"), LiteralValue::Number(1.0));
+    }
+
+    #[test]
+    fn do_while_loops_until_condition_false() {
+        assert_eq!(
+            output("var i = 0; do { i = i + 1; print i; } while (i < 3);").unwrap(),
+            "1\n2\n3\n"
+        );
+    }
+
+    #[test]
+    fn do_while_break_and_continue() {
+        let interpreter = run("
+            var i = 0;
+            do { i = i + 1; break; i = 100; } while (true);
+        ")
+        .unwrap();
+        assert_eq!(get(&interpreter, "i"), LiteralValue::Number(1.0));
+
+        let interpreter = run("
+            var i = 0;
+            var after = 0;
+            do { i = i + 1; continue; after = 1; } while (i < 3);
+        ")
+        .unwrap();
+        assert_eq!(get(&interpreter, "i"), LiteralValue::Number(3.0));
+        assert_eq!(get(&interpreter, "after"), LiteralValue::Number(0.0));
+    }
+
+    #[test]
+    fn do_while_missing_while_is_parse_error() {
+        let err = parse("var i = 0;\ndo { i = i + 1; }\nprint i;")
+            .err()
+            .unwrap();
+        assert!(err.contains("Expected 'while' after do-while body on line 3"));
+    }
+
+    fn switch_on(value: &str) -> LiteralValue {
+        let source = format!(
+            "
+            var result = \"untouched\";
+            switch ({value}) {{
+                case 1: result = \"one\";
+                case 2:
+                    result = \"two\";
+                case \"x\": result = \"ex\";
+                default: result = \"other\";
+            }}
+            "
+        );
+        get(&run(&source).unwrap(), "result")
+    }
+
+    #[test]
+    fn switch_matches_number() {
+        assert_eq!(switch_on("1 + 1"), LiteralValue::String("two".into()));
+    }
+
+    #[test]
+    fn switch_matches_string() {
+        assert_eq!(switch_on("\"x\""), LiteralValue::String("ex".into()));
+    }
+
+    #[test]
+    fn switch_falls_to_default() {
+        assert_eq!(switch_on("nil"), LiteralValue::String("other".into()));
+    }
+
+    #[test]
+    fn switch_without_match_or_default_does_nothing() {
+        let interpreter = run("
+            var result = 0;
+            switch (3) {
+                case 1: result = 1;
+                case 2: result = 2;
+            }
+        ")
+        .unwrap();
+        assert_eq!(get(&interpreter, "result"), LiteralValue::Number(0.0));
+    }
+
+    #[test]
+    fn destructure_exact_length_list() {
+        let interpreter = run("var [a, b, c] = [1, 2, 3];").unwrap();
+        assert_eq!(get(&interpreter, "a"), LiteralValue::Number(1.0));
+        assert_eq!(get(&interpreter, "b"), LiteralValue::Number(2.0));
+        assert_eq!(get(&interpreter, "c"), LiteralValue::Number(3.0));
+    }
+
+    #[test]
+    fn destructure_ignores_extra_elements() {
+        let interpreter = run("var [a, b] = [1, 2, 3];").unwrap();
+        assert_eq!(get(&interpreter, "a"), LiteralValue::Number(1.0));
+        assert_eq!(get(&interpreter, "b"), LiteralValue::Number(2.0));
+    }
+
+    #[test]
+    fn destructure_short_list_errors() {
+        let err = run("\nvar [a, b, c] = [1, 2];").err().unwrap();
+        assert_eq!(err, "Cannot destructure 3 values from a list of length 2");
+    }
+
+    #[test]
+    fn destructure_non_list_errors() {
+        let err = run("var [a] = \"abc\";").err().unwrap();
+        assert!(err.ends_with("expected a list"));
+    }
+
+    #[test]
+    fn destructure_underscore_skips_slot() {
+        let interpreter = run("var [_, b] = [1, 2];").unwrap();
+        assert_eq!(get(&interpreter, "b"), LiteralValue::Number(2.0));
+        assert!(interpreter.environment.borrow().lookup("_").is_err());
+    }
+
+    const GREET: &str = "
+        fun greet(name, greeting = \"hello\") {
+            return greeting + \" \" + name;
+        }
+    ";
+
+    #[test]
+    fn default_parameter_used_when_omitted() {
+        let interpreter = run(&format!("{GREET} var result = greet(\"bob\");")).unwrap();
+        assert_eq!(
+            get(&interpreter, "result"),
+            LiteralValue::String("hello bob".into())
+        );
+    }
+
+    #[test]
+    fn default_parameter_overridden_when_provided() {
+        let interpreter = run(&format!("{GREET} var result = greet(\"bob\", \"hi\");")).unwrap();
+        assert_eq!(
+            get(&interpreter, "result"),
+            LiteralValue::String("hi bob".into())
+        );
+    }
+
+    #[test]
+    fn default_parameter_can_reference_earlier_parameter() {
+        let interpreter = run("
+            fun f(a, b = a * 2) { return a + b; }
+            var result = f(3);
+        ")
+        .unwrap();
+        assert_eq!(get(&interpreter, "result"), LiteralValue::Number(9.0));
+    }
+
+    #[test]
+    fn arity_error_reports_range() {
+        let err = run(&format!("{GREET} greet(\"a\", \"b\", \"c\");"))
+            .err()
+            .unwrap();
+        assert!(err.contains("Expected 1 to 2 arguments, got 3"));
+
+        let err = run("fun f(a) {} f();").err().unwrap();
+        assert!(err.contains("Expected 1 argument, got 0"));
+    }
+
+    const COLLECT: &str = "fun collect(first, ...rest) { return [first, rest]; }";
+
+    #[test]
+    fn rest_parameter_collects_surplus_arguments() {
+        let interpreter = run("
+            fun all(...nums) { return nums; }
+            var none = all();
+            var one = all(1);
+            var five = all(1, 2, 3, 4, 5);
+        ")
+        .unwrap();
+        assert_eq!(get(&interpreter, "none").to_string(), "[]");
+        assert_eq!(get(&interpreter, "one").to_string(), "[1]");
+        assert_eq!(get(&interpreter, "five").to_string(), "[1, 2, 3, 4, 5]");
+    }
+
+    #[test]
+    fn rest_parameter_after_normal_parameters() {
+        let interpreter = run(&format!(
+            "{COLLECT} var a = collect(1); var b = collect(1, 2, 3);"
+        ))
+        .unwrap();
+        assert_eq!(get(&interpreter, "a").to_string(), "[1, []]");
+        assert_eq!(get(&interpreter, "b").to_string(), "[1, [2, 3]]");
+    }
+
+    #[test]
+    fn rest_parameter_arity_is_a_minimum() {
+        let err = run(&format!("{COLLECT} collect();")).err().unwrap();
+        assert!(err.contains("Expected at least 1 argument, got 0"));
+    }
+
+    const POINT: &str = "
+        class Point {
+            init(x, y) {
+                this.x = x;
+                this.y = y;
+            }
+            sum() { return this.x + this.y; }
+        }
+        var p = Point(1, 2);
+        var none = nil;
+    ";
+
+    #[test]
+    fn optional_access_on_nil_is_nil() {
+        let interpreter = run(&format!("{POINT} var result = none?.x;")).unwrap();
+        assert_eq!(get(&interpreter, "result"), LiteralValue::Nil);
+
+        let err = run(&format!("{POINT} var result = none.x;")).err().unwrap();
+        assert!(err.contains("Cannot read property 'x' of nil"));
+    }
+
+    #[test]
+    fn optional_access_chains() {
+        let interpreter = run(&format!(
+            "{POINT} p.next = nil; var a = p?.next?.x; var b = none?.next.x;"
+        ))
+        .unwrap();
+        assert_eq!(get(&interpreter, "a"), LiteralValue::Nil);
+        assert_eq!(get(&interpreter, "b"), LiteralValue::Nil);
+    }
+
+    #[test]
+    fn optional_access_short_circuits_call() {
+        let interpreter = run(&format!("{POINT} var result = none?.sum();")).unwrap();
+        assert_eq!(get(&interpreter, "result"), LiteralValue::Nil);
+    }
+
+    #[test]
+    fn optional_access_on_instance_matches_dot() {
+        let interpreter = run(&format!(
+            "{POINT} var x = p?.x; var sum = p?.sum(); var same = p?.x == p.x;"
+        ))
+        .unwrap();
+        assert_eq!(get(&interpreter, "x"), LiteralValue::Number(1.0));
+        assert_eq!(get(&interpreter, "sum"), LiteralValue::Number(3.0));
+        assert_eq!(get(&interpreter, "same"), LiteralValue::True);
+    }
+
+    #[test]
+    fn optional_access_on_non_instance_errors() {
+        let err = run("var n = 1; var x = n?.x;").err().unwrap();
+        assert!(err.contains("Only instances have properties"));
+    }
+
+    #[test]
+    fn in_operator_membership() {
+        let interpreter = run("
+            var map = {\"key\": 1, 2: \"two\"};
+            var list = [1, \"a\", nil];
+            var in_map = \"key\" in map;
+            var not_in_map = \"other\" in map;
+            var in_list = nil in list;
+            var not_in_list = 3 in list;
+            var in_string = \"ab\" in \"cabbage\";
+            var not_in_string = \"bed\" in \"cabbage\";
+        ")
+        .unwrap();
+
+        assert_eq!(get(&interpreter, "in_map"), LiteralValue::True);
+        assert_eq!(get(&interpreter, "not_in_map"), LiteralValue::False);
+        assert_eq!(get(&interpreter, "in_list"), LiteralValue::True);
+        assert_eq!(get(&interpreter, "not_in_list"), LiteralValue::False);
+        assert_eq!(get(&interpreter, "in_string"), LiteralValue::True);
+        assert_eq!(get(&interpreter, "not_in_string"), LiteralValue::False);
+    }
+
+    #[test]
+    fn in_operator_type_error() {
+        let err = run("var a = 1;\nvar b = 1 in 2;").err().unwrap();
+        assert_eq!(err, "Cannot test membership of number in number");
+
+        let err = run("var b = 1 in \"123\";").err().unwrap();
+        assert!(err.contains("of number in string"));
+    }
+
+    #[test]
+    fn assert_passes_without_evaluating_message() {
+        // The message refers to an undeclared variable, so evaluating it would fail.
+        assert!(run("assert 1 == 1, undeclared;").is_ok());
+    }
+
+    #[test]
+    fn assert_failure_default_message() {
+        let err = run("var x = 2;\nassert x == 1;").err().unwrap();
+        assert_eq!(err, "assertion failed at line 2: (== var x 1)");
+    }
+
+    #[test]
+    fn assert_failure_custom_message() {
+        let err = run("assert false, \"x should be \" + \"one\";")
+            .err()
+            .unwrap();
+        assert_eq!(err, "assertion failed at line 1: x should be one");
+    }
+
+    fn limited(limit: u64, backend: Backend, source: &str) -> Result<(), RuntimeError> {
+        let mut interpreter = Interpreter::with_step_limit(limit);
+        interpreter.output = Box::new(std::io::sink());
+        interpreter.backend = backend;
+        interpreter
+            .interpret(parse(source).unwrap())
+            .map_err(runtime)
+    }
+
+    #[test]
+    fn step_limit_stops_runaway_programs() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let bounded = "var i = 0; while (i < 100) { i = i + 1; } print i;";
+            assert_eq!(limited(100_000, backend, bounded), Ok(()));
+
+            for source in [
+                "while (true) {}",
+                "fun f(n) { return f(n + 1) + 1; } f(0);",
+                "while (true) { try { while (true) {} } catch (e) {} }",
+            ] {
+                let err = limited(50, backend, source).unwrap_err();
+                assert_eq!(err.message, "execution budget exceeded (50 steps)");
+            }
+
+            let err = limited(1000, backend, "var i = 0;\nwhile (i >= 0) i = i + 1;").unwrap_err();
+            assert_eq!(err.line, 2);
+        }
+    }
+
+    #[test]
+    fn step_budget_is_per_run() {
+        let mut interpreter = Interpreter::with_step_limit(1000);
+        let program = "var i = 0; while (i < 70) i = i + 1;";
+        for _ in 0..5 {
+            interpreter.interpret(parse(program).unwrap()).unwrap();
+        }
+        assert_eq!(
+            interpreter.evaluate_expression("1 + 2"),
+            Ok(LiteralValue::Number(3.0))
+        );
+    }
+
+    fn timed(
+        timeout: Duration,
+        check_interval: u64,
+        backend: Backend,
+        source: &str,
+    ) -> Result<(), RuntimeError> {
+        let mut interpreter = Interpreter::with_timeout(timeout);
+        interpreter.timeout_check_interval = check_interval;
+        interpreter.output = Box::new(std::io::sink());
+        interpreter.backend = backend;
+        interpreter
+            .interpret(parse(source).unwrap())
+            .map_err(runtime)
+    }
+
+    #[test]
+    fn timeout_stops_long_running_programs() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            for check_interval in [1, TIMEOUT_CHECK_INTERVAL] {
+                let started = Instant::now();
+                let err = timed(
+                    Duration::from_millis(100),
+                    check_interval,
+                    backend,
+                    "while (true) { try { while (true) {} } catch (e) {} }",
+                )
+                .unwrap_err();
+                assert!(started.elapsed() < Duration::from_secs(2));
+                assert!(err.message.starts_with("execution timed out after "));
+                assert!(err.message.ends_with("ms (limit 100ms)"));
+            }
+
+            let fast = "var i = 0; while (i < 100) { i = i + 1; } print i;";
+            assert_eq!(timed(Duration::from_secs(10), 1, backend, fast), Ok(()));
+        }
+    }
+
+    #[test]
+    fn trace_writes_each_statement_before_it_runs() {
+        let source = "var i = 0;\nwhile (i < 2) {\n    print i;\n    i = i + 1;\n}\nfun f() {\n    return 3;\n}\nprint f();";
+        let (mut traced, output) = interpreter();
+        let trace = Output::default();
+        traced.trace = true;
+        traced.trace_output = Box::new(trace.clone());
+        traced.interpret(parse(source).unwrap()).unwrap();
+
+        assert_eq!(output.contents(), "0\n1\n3\n");
+        assert_eq!(
+            trace.contents(),
+            "TRACE [line 1] var i = 0;
+TRACE [line 2] while (i < 2) {
+TRACE [line 2] {
+TRACE [line 3] print i;
+TRACE [line 4] i = i + 1;
+TRACE [line 2] {
+TRACE [line 3] print i;
+TRACE [line 4] i = i + 1;
+TRACE [line 6] fun f() {
+TRACE [line 9] print f();
+TRACE [line 7] return 3;
+"
+        );
+
+        // Sent to the same place, the trace of a statement comes before anything it prints.
+        let (mut interpreter, output) = interpreter();
+        interpreter.trace = true;
+        interpreter.trace_output = Box::new(output.clone());
+        interpreter
+            .interpret(parse("print 1;\nprint 2;").unwrap())
+            .unwrap();
+        assert_eq!(
+            output.contents(),
+            "TRACE [line 1] print 1;\n1\nTRACE [line 2] print 2;\n2\n"
+        );
+    }
+
+    #[test]
+    fn failed_assert_stops_execution() {
+        let (mut interpreter, _) = interpreter();
+        let result = interpreter.interpret(parse("var a = 1; assert false; a = 2;").unwrap());
+
+        assert!(result.is_err());
+        assert_eq!(get(&interpreter, "a"), LiteralValue::Number(1.0));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("kadom-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(directory.join("lib")).unwrap();
+        directory
+    }
+
+    fn run_script(directory: &Path, source: &str) -> Result<Interpreter, String> {
+        let main = directory.join("main.kdm");
+        std::fs::write(&main, source).unwrap();
+
+        let (mut interpreter, _) = interpreter();
+        interpreter.set_script_path(&main);
+        interpreter.interpret(parse(source)?).map_err(message)?;
+        Ok(interpreter)
+    }
+
+    #[test]
+    fn import_defines_globals_for_importer() {
+        let directory = temp_dir("import");
+        std::fs::write(
+            directory.join("lib/helpers.kdm"),
+            "var greeting = \"hello\"; count = count + 1;",
+        )
+        .unwrap();
+
+        let interpreter = run_script(
+            &directory,
+            "var count = 0; import \"lib/helpers.kdm\"; import \"lib/helpers.kdm\"; var result = greeting;",
+        )
+        .unwrap();
+        assert_eq!(
+            get(&interpreter, "result"),
+            LiteralValue::String("hello".into())
+        );
+        assert_eq!(get(&interpreter, "count"), LiteralValue::Number(1.0));
+    }
+
+    #[test]
+    fn failed_import_runs_again_when_retried() {
+        let directory = temp_dir("import-retry");
+        std::fs::write(directory.join("lib/bad.kdm"), "print x + nil;").unwrap();
+
+        let err = run_script(
+            &directory,
+            "try { import \"lib/bad.kdm\"; } catch (e) {} import \"lib/bad.kdm\"; var after = 1;",
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err, "lib/bad.kdm:1: undefined variable 'x'");
+    }
+
+    #[test]
+    fn import_missing_file_errors() {
+        let directory = temp_dir("import-missing");
+        let err = run_script(&directory, "import \"lib/missing.kdm\";")
+            .err()
+            .unwrap();
+        assert!(err.starts_with("Failed to import 'lib/missing.kdm': "));
+    }
+
+    #[test]
+    fn import_circular_errors() {
+        let directory = temp_dir("import-circular");
+        std::fs::write(directory.join("lib/a.kdm"), "import \"b.kdm\";").unwrap();
+        std::fs::write(directory.join("lib/b.kdm"), "import \"a.kdm\";").unwrap();
+
+        let err = run_script(&directory, "import \"lib/a.kdm\";")
+            .err()
+            .unwrap();
+        assert_eq!(err, "lib/a.kdm:1: b.kdm:1: Circular import of 'a.kdm'");
+    }
+
+    #[test]
+    fn import_errors_are_prefixed_with_filename() {
+        let directory = temp_dir("import-error");
+        std::fs::write(directory.join("lib/broken.kdm"), "\nprint undefined;").unwrap();
+
+        let err = run_script(&directory, "import \"lib/broken.kdm\";")
+            .err()
+            .unwrap();
+        assert_eq!(err, "lib/broken.kdm:2: undefined variable 'undefined'");
+
+        std::fs::write(directory.join("lib/broken.kdm"), "print (;").unwrap();
+        let err = run_script(&directory, "import \"lib/broken.kdm\";")
+            .err()
+            .unwrap();
+        assert!(
+            err.starts_with("lib/broken.kdm: Expected expression"),
+            "{}",
+            err
+        );
+    }
 }
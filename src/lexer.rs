@@ -1,5 +1,8 @@
-use std::collections::HashMap;
+use crate::diagnostics::Diagnostic;
+use crate::error::KadomError;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self};
+use std::rc::Rc;
 use LiteralValue::*;
 use TokenType::*;
 
@@ -11,34 +14,60 @@ fn is_alphanumeric(c: char) -> bool {
     is_alpha(c) || c.is_ascii_digit()
 }
 
+// Every reserved word and the token it scans to.
+pub const KEYWORDS: [(&str, TokenType); 28] = [
+    ("and", And),
+    ("assert", Assert),
+    ("break", Break),
+    ("case", Case),
+    ("catch", Catch),
+    ("class", Class),
+    ("continue", Continue),
+    ("default", Default),
+    ("do", Do),
+    ("else", Else),
+    ("false", False),
+    ("for", For),
+    ("fun", Fun),
+    ("if", If),
+    ("import", Import),
+    ("in", In),
+    ("nil", Nil),
+    ("or", Or),
+    ("print", Print),
+    ("return", Return),
+    ("super", Super),
+    ("switch", Switch),
+    ("this", This),
+    ("throw", Throw),
+    ("true", True),
+    ("try", Try),
+    ("var", Var),
+    ("while", While),
+];
+
 pub struct Scanner {
     source: String,
     tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: u64,
+    // Where the current line starts in the source, for working out columns.
+    line_start: usize,
+    // Position of the token being scanned, which can span lines if it's a string.
+    start_line: u64,
+    start_column: usize,
     keywords: HashMap<String, TokenType>,
+    // Every distinct lexeme and string value scanned so far. Repeats share one allocation.
+    interned: HashSet<Rc<str>>,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
-        let mut keywords: HashMap<String, TokenType> = HashMap::new();
-        keywords.insert("and".to_string(), And);
-        keywords.insert("class".to_string(), Class);
-        keywords.insert("else".to_string(), Else);
-        keywords.insert("false".to_string(), False);
-        keywords.insert("for".to_string(), For);
-        keywords.insert("fun".to_string(), Fun);
-        keywords.insert("if".to_string(), If);
-        keywords.insert("nil".to_string(), Nil);
-        keywords.insert("or".to_string(), Or);
-        keywords.insert("print".to_string(), Print);
-        keywords.insert("return".to_string(), Return);
-        keywords.insert("super".to_string(), Super);
-        keywords.insert("this".to_string(), This);
-        keywords.insert("true".to_string(), True);
-        keywords.insert("var".to_string(), Var);
-        keywords.insert("while".to_string(), While);
+        let keywords = KEYWORDS
+            .iter()
+            .map(|(keyword, token_type)| (keyword.to_string(), *token_type))
+            .collect();
 
         Self {
             source,
@@ -46,32 +75,54 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
             keywords,
+            interned: HashSet::new(),
+        }
+    }
+
+    // Scans source that continues a longer input, so lines are numbered from `line` rather than 1.
+    pub fn new_with_start_line(source: String, line: u64) -> Self {
+        Self {
+            line,
+            start_line: line,
+            ..Self::new(source)
+        }
+    }
+
+    pub fn scan_tokens(self) -> Result<Vec<Token>, KadomError> {
+        let (tokens, errors) = self.scan_all();
+        match errors.is_empty() {
+            true => Ok(tokens),
+            false => Err(KadomError::from_diagnostics(errors, KadomError::scan)),
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, String> {
+    // Scans the whole source, returning the tokens alongside every error rather than failing.
+    // Consumes the scanner so the tokens are handed over rather than copied.
+    pub fn scan_all(mut self) -> (Vec<Token>, Vec<Diagnostic>) {
         let mut errors = Vec::new();
 
         while !self.is_at_end() {
             self.start = self.current;
-            let _ = self.scan_token().map_err(|e| errors.push(e));
+            self.start_line = self.line;
+            self.start_column = self.start - self.line_start + 1;
+            if let Err(message) = self.scan_token() {
+                errors.push(Diagnostic::new(
+                    self.start_line,
+                    Some(self.start_column),
+                    message,
+                ));
+            }
         }
 
+        let column = self.current - self.line_start + 1;
         self.tokens
-            .push(Token::new(Eof, "".into(), None, self.line));
-
-        if !errors.is_empty() {
-            let mut joined = "".to_string();
-            errors.iter().for_each(|msg| {
-                joined.push_str(msg);
-                joined.push('\n');
-            });
-
-            return Err(joined);
-        }
+            .push(Token::new(Eof, "", None, self.line, column));
 
-        Ok(self.tokens.clone())
+        (self.tokens, errors)
     }
 
     fn is_at_end(&self) -> bool {
@@ -86,12 +137,25 @@ impl Scanner {
             ')' => self.add_token_null_literal(RightParent),
             '{' => self.add_token_null_literal(LeftBrace),
             '}' => self.add_token_null_literal(RightBrace),
+            '[' => self.add_token_null_literal(LeftBracket),
+            ']' => self.add_token_null_literal(RightBracket),
             ',' => self.add_token_null_literal(Comma),
-            '.' => self.add_token_null_literal(Dot),
+            '.' => {
+                let token = if self.peek() == '.' && self.peek_next() == '.' {
+                    self.current += 2;
+                    DotDotDot
+                } else {
+                    Dot
+                };
+
+                self.add_token_null_literal(token)
+            }
             '-' => self.add_token_null_literal(Minus),
             '+' => self.add_token_null_literal(Plus),
             ';' => self.add_token_null_literal(Semicolon),
+            ':' => self.add_token_null_literal(Colon),
             '*' => self.add_token_null_literal(Star),
+            '%' => self.add_token_null_literal(Percent),
 
             // Operators
             '=' => {
@@ -133,20 +197,28 @@ impl Scanner {
             // Comments or Division
             '/' => {
                 if self.match_char('/') {
-                    loop {
-                        if self.peek() == '\n' || !self.is_at_end() {
-                            break;
-                        }
+                    // The newline is left for the main loop, which keeps track of lines.
+                    while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    Ok(())
+                } else {
+                    self.add_token_null_literal(Slash)
+                }
+            }
+
+            '?' => {
+                if self.match_char('.') {
+                    self.add_token_null_literal(QuestionDot)
+                } else {
+                    Err("Expected \'.\' after \'?\'".to_string())
                 }
-                self.add_token_null_literal(Slash)
             }
 
             // Whitespace
             ' ' | '\r' | '\t' => Ok(()),
             '\n' => {
-                self.line += 1;
+                self.new_line();
                 Ok(())
             }
 
@@ -160,15 +232,22 @@ impl Scanner {
                 } else if is_alpha(c) {
                     self.identifier()
                 } else {
-                    Err(format!(
-                        "Oopsie, character not recognised: {} at line {}",
-                        c, self.line
-                    ))
+                    Err(format!("Oopsie, character not recognised: {}", c))
                 }
             }
         }
     }
 
+    // Call after consuming a newline.
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    fn previous(&self) -> char {
+        self.source.as_bytes()[self.current - 1] as char
+    }
+
     fn source_char_at_current(&self) -> char {
         let c = self.source.as_bytes()[self.current];
         c as char
@@ -207,20 +286,19 @@ impl Scanner {
             if self.peek() == '"' || self.is_at_end() {
                 break;
             }
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
             self.advance();
+            if self.previous() == '\n' {
+                self.new_line();
+            }
         }
 
         if self.is_at_end() {
-            return Err(format!("unterminated string lol :/ on line {}", self.line));
+            return Err("unterminated string lol :/".to_string());
         }
 
         self.advance();
-        let value_as_str = &self.source[self.start + 1..self.current - 1];
-        let value = StringVal(value_as_str.into());
-        self.add_token(StringLiteral, Some(value))?;
+        let value = self.intern(self.start + 1..self.current - 1);
+        self.add_token(StringLiteral, Some(StringVal(value)))?;
         Ok(())
     }
 
@@ -257,41 +335,58 @@ impl Scanner {
             self.advance();
         }
 
-        let text = self.source[self.start..self.current].to_string();
-        let token_type = self.keywords.get(&text).unwrap_or(&Identifier).clone();
+        let text = &self.source[self.start..self.current];
+        let token_type = self.keywords.get(text).copied().unwrap_or(Identifier);
         self.add_token_null_literal(token_type)
     }
 
+    fn intern(&mut self, range: std::ops::Range<usize>) -> Rc<str> {
+        let text = &self.source[range];
+        if let Some(interned) = self.interned.get(text) {
+            return interned.clone();
+        }
+        let interned: Rc<str> = text.into();
+        self.interned.insert(interned.clone());
+        interned
+    }
+
     fn add_token(
         &mut self,
         token_type: TokenType,
         literal_option: Option<LiteralValue>,
     ) -> Result<(), String> {
-        let text = &self.source[self.start..self.current];
+        let text = self.intern(self.start..self.current);
         self.tokens.push(Token::new(
             token_type,
-            text.into(),
+            text,
             literal_option,
-            self.line,
+            self.start_line,
+            self.start_column,
         ));
         Ok(())
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenType {
     // Single-character tokens.
     LeftParent,
     RightParent,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    DotDotDot,
     Minus,
     Plus,
     Semicolon,
+    Colon,
     Slash,
     Star,
+    Percent,
 
     // One or two character tokens.
     Bang,
@@ -302,6 +397,7 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    QuestionDot,
 
     // Literals.
     Identifier,
@@ -310,63 +406,231 @@ pub enum TokenType {
 
     // Keywords.
     And,
+    Assert,
+    Break,
+    Case,
+    Catch,
     Class,
+    Continue,
+    Default,
+    Do,
     Else,
     False,
     Fun,
     For,
     If,
+    Import,
+    In,
     Nil,
     Or,
     Print,
     Return,
     Super,
+    Switch,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
     Eof,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LiteralValue {
-    IntVal(i64),
     FVal(f64),
-    StringVal(String),
-    IdentifierVal(String),
+    StringVal(Rc<str>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
-    pub token_type: TokenType,
-    pub lexeme: String,
-    pub literal_option: Option<LiteralValue>,
-    pub line_number: u64,
+    token_type: TokenType,
+    lexeme: Rc<str>,
+    literal_option: Option<LiteralValue>,
+    line_number: u64,
+    // 1-based byte offset of the token's first character within its line.
+    column: usize,
 }
 
 impl Token {
+    /// Makes a token, as the scanner does for each one it finds.
+    ///
+    /// ```
+    /// use kadom::lexer::{LiteralValue, Token, TokenType};
+    ///
+    /// let token = Token::new(
+    ///     TokenType::Number,
+    ///     "1.5".to_string(),
+    ///     Some(LiteralValue::FVal(1.5)),
+    ///     3,
+    ///     9,
+    /// );
+    /// assert_eq!(token.token_type(), &TokenType::Number);
+    /// assert_eq!(token.lexeme(), "1.5");
+    /// assert_eq!(token.literal(), Some(&LiteralValue::FVal(1.5)));
+    /// assert_eq!((token.line(), token.column()), (3, 9));
+    /// ```
     pub fn new(
         token_type: TokenType,
-        lexeme: String,
+        lexeme: impl Into<Rc<str>>,
         literal_option: Option<LiteralValue>,
         line_number: u64,
+        column: usize,
     ) -> Self {
         Self {
             token_type,
-            lexeme,
+            lexeme: lexeme.into(),
             literal_option,
             line_number,
+            column,
         }
     }
+
+    pub fn token_type(&self) -> &TokenType {
+        &self.token_type
+    }
+
+    // The source text the token was scanned from.
+    pub fn lexeme(&self) -> &str {
+        &self.lexeme
+    }
+
+    // The value of a number or string literal; None for every other token.
+    pub fn literal(&self) -> Option<&LiteralValue> {
+        self.literal_option.as_ref()
+    }
+
+    pub fn line(&self) -> u64 {
+        self.line_number
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
 }
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{:?} {} {:?}",
-            self.token_type, self.lexeme, self.literal_option
+            "{:?} {} {:?} line {}:{}",
+            self.token_type, self.lexeme, self.literal_option, self.line_number, self.column
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(source: &str, start_line: u64) -> Vec<u64> {
+        let tokens = Scanner::new_with_start_line(source.to_string(), start_line)
+            .scan_tokens()
+            .unwrap();
+        tokens.iter().map(|token| token.line_number).collect()
+    }
+
+    #[test]
+    fn keywords_scan_to_their_token_types() {
+        for (keyword, token_type) in KEYWORDS {
+            let tokens = Scanner::new(keyword.to_string()).scan_tokens().unwrap();
+            assert_eq!(*tokens[0].token_type(), token_type, "{}", keyword);
+        }
+        let tokens = Scanner::new("While".to_string()).scan_tokens().unwrap();
+        assert_eq!(*tokens[0].token_type(), Identifier);
+    }
+
+    #[test]
+    fn numbers_lines_from_the_start_line() {
+        assert_eq!(
+            lines("var a = 1;\nprint a;", 1),
+            [1, 1, 1, 1, 1, 2, 2, 2, 2]
+        );
+        assert_eq!(
+            lines("var a = 1;\nprint a;", 5),
+            [5, 5, 5, 5, 5, 6, 6, 6, 6]
+        );
+    }
+
+    #[test]
+    fn comments_run_to_the_end_of_the_line() {
+        assert_eq!(
+            lines("print 1; // a / comment\nprint 2 / 1;//", 1),
+            [1, 1, 1, 2, 2, 2, 2, 2, 2]
+        );
+    }
+
+    #[test]
+    fn scans_a_representative_program() {
+        let tokens = Scanner::new("var s = \"a b\"; // note\nprint s.n + 1.5 >= !nil;".to_string())
+            .scan_tokens()
+            .unwrap();
+        let dump: Vec<String> = tokens.iter().map(|token| token.to_string()).collect();
+        assert_eq!(
+            dump,
+            [
+                "Var var None line 1:1",
+                "Identifier s None line 1:5",
+                "Equal = None line 1:7",
+                "StringLiteral \"a b\" Some(StringVal(\"a b\")) line 1:9",
+                "Semicolon ; None line 1:14",
+                "Print print None line 2:1",
+                "Identifier s None line 2:7",
+                "Dot . None line 2:8",
+                "Identifier n None line 2:9",
+                "Plus + None line 2:11",
+                "Number 1.5 Some(FVal(1.5)) line 2:13",
+                "GreaterEqual >= None line 2:17",
+                "Bang ! None line 2:20",
+                "Nil nil None line 2:21",
+                "Semicolon ; None line 2:24",
+                "Eof  None line 2:25",
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_lexemes_share_one_allocation() {
+        let tokens = Scanner::new("total = total + \"s\" + \"s\";".to_string())
+            .scan_tokens()
+            .unwrap();
+        assert_eq!(tokens[0].lexeme().as_ptr(), tokens[2].lexeme().as_ptr());
+        assert_eq!(tokens[3].lexeme().as_ptr(), tokens[5].lexeme().as_ptr());
+        match (tokens[4].literal(), tokens[6].literal()) {
+            (Some(StringVal(a)), Some(StringVal(b))) => assert!(Rc::ptr_eq(a, b)),
+            other => panic!("expected two string literals, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tokens_read_back_through_accessors() {
+        let token = Token::new(StringLiteral, "\"hi\"".to_string(), None, 4, 2);
+        assert_eq!(token.token_type(), &StringLiteral);
+        assert_eq!(token.lexeme(), "\"hi\"");
+        assert_eq!(token.literal(), None);
+        assert_eq!((token.line(), token.column()), (4, 2));
+
+        let tokens = Scanner::new("print \"hi\";".to_string())
+            .scan_tokens()
+            .unwrap();
+        assert_eq!(tokens[1].lexeme(), "\"hi\"");
+        assert_eq!(tokens[1].literal(), Some(&StringVal("hi".into())));
+        assert_eq!(tokens[1].column(), 7);
+    }
+
+    #[test]
+    fn errors_report_the_offset_line() {
+        let error = Scanner::new_with_start_line("print 1;\nprint @;".to_string(), 5)
+            .scan_tokens()
+            .unwrap_err();
+        assert!(
+            matches!(error, KadomError::Scan { line: 6, .. }),
+            "{}",
+            error
+        );
+        assert!(error.to_string().contains("line 6"), "{}", error);
+    }
+}
@@ -0,0 +1,132 @@
+pub mod callable;
+mod compiler;
+pub mod coverage;
+pub mod diagnostics;
+pub mod environment;
+pub mod error;
+pub mod expr;
+pub mod interpreter;
+pub mod lexer;
+mod natives;
+pub mod optimizer;
+pub mod parser;
+pub mod printer;
+pub mod profiler;
+pub mod resolver;
+pub mod stmt;
+pub mod style;
+mod vm;
+pub mod walk;
+
+use diagnostics::Diagnostic;
+use error::KadomError;
+use interpreter::Interpreter;
+use lexer::Scanner;
+use parser::Parser;
+use resolver::Resolver;
+use stmt::Stmt;
+
+// Scans, parses and resolves a program, ready to be interpreted.
+pub fn compile(source: &str) -> Result<Vec<Stmt>, KadomError> {
+    let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+    let statements = Parser::new(tokens).parse()?;
+    Resolver::new().resolve(&statements)?;
+    Ok(statements)
+}
+
+// Scans, parses and resolves a program, returning every error found along the way. Parsing is
+// only attempted when scanning succeeded, since tokens missing from a failed scan would only cause
+// follow-on errors.
+pub fn compile_checked(source: &str) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    compile_checked_from(source, 1)
+}
+
+// As compile_checked, for source whose first line is line `first_line` of a longer input.
+pub fn compile_checked_from(source: &str, first_line: u64) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    let (tokens, errors) = Scanner::new_with_start_line(source.to_string(), first_line).scan_all();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let (statements, mut errors) = Parser::new(tokens).parse_all();
+    errors.extend(Resolver::new().resolve_all(&statements));
+    match errors.is_empty() {
+        true => Ok(statements),
+        false => Err(errors),
+    }
+}
+
+// Every static error in a program, without running any of it. A program without errors gets its
+// warnings instead; with errors, some of it went unparsed and warnings could be wrong.
+pub fn check_source(source: &str) -> Vec<Diagnostic> {
+    match compile_checked(source) {
+        Ok(statements) => Resolver::new().warnings(&statements),
+        Err(errors) => errors,
+    }
+}
+
+// Compiles and runs a program.
+pub fn run_source(interpreter: &mut Interpreter, source: &str) -> Result<(), KadomError> {
+    interpreter.interpret(compile(source)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> Vec<String> {
+        check_source(source)
+            .into_iter()
+            .map(|diagnostic| format!("{}: {}", diagnostic.line, diagnostic.message))
+            .collect()
+    }
+
+    #[test]
+    fn clean_source_has_no_diagnostics() {
+        assert!(check_source("var a = 1;\nprint a + missing;").is_empty());
+    }
+
+    #[test]
+    fn reports_a_single_error() {
+        assert_eq!(messages("print 1 +;"), vec!["1: Expected expression"]);
+    }
+
+    #[test]
+    fn reports_parse_and_resolve_errors_together() {
+        assert_eq!(
+            messages("var a = (;\nprint 1 +;\nreturn 2;"),
+            vec![
+                "1: Expected expression",
+                "2: Expected expression",
+                "3: Cannot return from top-level code",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_scan_error() {
+        assert_eq!(
+            messages("var a = #;\nvar b = @;"),
+            vec![
+                "1: Oopsie, character not recognised: #",
+                "2: Oopsie, character not recognised: @",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_warnings_only_without_errors() {
+        let diagnostics = check_source("var unused = 1;");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, diagnostics::Severity::Warning);
+        assert_eq!(
+            messages("var unused = 1;\nprint 1 +;"),
+            vec!["2: Expected expression"]
+        );
+    }
+
+    #[test]
+    fn checking_never_runs_the_program() {
+        assert!(check_source("print nil - 1; throw \"boom\";").is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+use kadom::{environment::Environment, lexer::KEYWORDS};
+use std::cell::RefCell;
+use std::io::{stdin, stdout, Write};
+use std::rc::Rc;
+
+// Where the REPL gets its input from, one line at a time.
+pub trait LineReader {
+    // Shows the prompt and reads a line, keeping its trailing newline. None means end of input.
+    fn read_line(&mut self, prompt: &str) -> Result<Option<String>, String>;
+
+    // Sets the environment whose names Tab completes. Readers without completion ignore it.
+    fn complete_from(&mut self, _environment: Rc<RefCell<Environment>>) {}
+}
+
+// Reads straight from stdin, for pipes and builds without line editing.
+pub struct StdinReader;
+
+impl LineReader for StdinReader {
+    fn read_line(&mut self, prompt: &str) -> Result<Option<String>, String> {
+        print!("{}", prompt);
+        stdout()
+            .flush()
+            .map_err(|err| format!("Flush error <lol> : {}", err))?;
+
+        let mut line = String::new();
+        let read = stdin()
+            .read_line(&mut line)
+            .map_err(|err| format!("Failed to read line: {}", err))?;
+        Ok(if read == 0 { None } else { Some(line) })
+    }
+}
+
+// Whether a line belongs in history: blank lines and repeats of the previous entry are left out.
+#[cfg_attr(not(feature = "readline"), allow(dead_code))]
+pub fn records_in_history(previous: Option<&str>, line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && previous.map(str::trim) != Some(line)
+}
+
+// Keywords and global names, natives included, that start with `prefix`. Matching is case
+// sensitive, and the result is sorted with each name once.
+#[cfg_attr(not(feature = "readline"), allow(dead_code))]
+pub fn complete(prefix: &str, env: &Environment) -> Vec<String> {
+    let keywords = KEYWORDS.iter().map(|(keyword, _)| keyword.to_string());
+    let mut candidates: Vec<String> = keywords
+        .chain(env.names())
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+// Where the identifier ending at `pos` starts, if there's one to complete. Properties after a dot
+// aren't globals, so they're left alone.
+#[cfg_attr(not(feature = "readline"), allow(dead_code))]
+pub fn completion_start(line: &str, pos: usize) -> Option<usize> {
+    let before = &line[..pos];
+    let start = before
+        .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .map_or(0, |i| i + 1);
+    let word = &before[start..];
+    let after_dot = before[..start].ends_with('.');
+    let starts_well = word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+    (starts_well && !after_dot).then_some(start)
+}
+
+// The text a sole match completes to. Keywords that need something after them get a space.
+#[cfg_attr(not(feature = "readline"), allow(dead_code))]
+pub fn completed(name: &str) -> String {
+    let complete_alone = ["break", "continue", "false", "nil", "super", "this", "true"];
+    let keyword = KEYWORDS.iter().any(|(keyword, _)| *keyword == name);
+    if keyword && !complete_alone.contains(&name) {
+        format!("{} ", name)
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(feature = "readline")]
+pub use editor::EditorReader;
+
+#[cfg(feature = "readline")]
+mod editor {
+    use super::{complete, completed, completion_start, records_in_history, LineReader};
+    use kadom::environment::Environment;
+    use rustyline::{
+        completion::{Completer, Pair},
+        config::{CompletionType, Config},
+        error::ReadlineError,
+        highlight::Highlighter,
+        hint::Hinter,
+        history::DefaultHistory,
+        validate::Validator,
+        Context, Editor, Helper,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    // Terminal input with cursor movement, in-session history and Tab completion.
+    pub struct EditorReader {
+        editor: Editor<Completions, DefaultHistory>,
+        previous: Option<String>,
+    }
+
+    impl EditorReader {
+        pub fn new() -> Result<Self, String> {
+            // Tab completes as far as every match agrees; a second Tab lists them.
+            let config = Config::builder()
+                .completion_type(CompletionType::List)
+                .build();
+            let mut editor = Editor::with_config(config)
+                .map_err(|err| format!("Failed to set up line editing: {}", err))?;
+            editor.set_helper(Some(Completions { environment: None }));
+            Ok(Self {
+                editor,
+                previous: None,
+            })
+        }
+    }
+
+    // Completes names from the REPL's environment. Nothing matching leaves the line as it is, and
+    // rustyline rings the bell.
+    struct Completions {
+        environment: Option<Rc<RefCell<Environment>>>,
+    }
+
+    impl Completer for Completions {
+        type Candidate = Pair;
+
+        fn complete(
+            &self,
+            line: &str,
+            pos: usize,
+            _: &Context<'_>,
+        ) -> rustyline::Result<(usize, Vec<Pair>)> {
+            let (Some(environment), Some(start)) = (&self.environment, completion_start(line, pos))
+            else {
+                return Ok((pos, Vec::new()));
+            };
+            let names = complete(&line[start..pos], &environment.borrow());
+            let only = names.len() == 1;
+            let candidates = names
+                .into_iter()
+                .map(|name| Pair {
+                    replacement: if only { completed(&name) } else { name.clone() },
+                    display: name,
+                })
+                .collect();
+            Ok((start, candidates))
+        }
+    }
+
+    impl Hinter for Completions {
+        type Hint = String;
+    }
+
+    impl Highlighter for Completions {}
+
+    impl Validator for Completions {}
+
+    impl Helper for Completions {}
+
+    impl LineReader for EditorReader {
+        fn complete_from(&mut self, environment: Rc<RefCell<Environment>>) {
+            if let Some(completions) = self.editor.helper_mut() {
+                completions.environment = Some(environment);
+            }
+        }
+
+        fn read_line(&mut self, prompt: &str) -> Result<Option<String>, String> {
+            match self.editor.readline(prompt) {
+                Ok(line) => {
+                    if records_in_history(self.previous.as_deref(), &line) {
+                        self.editor
+                            .add_history_entry(line.as_str())
+                            .map_err(|err| format!("Failed to record history: {}", err))?;
+                        self.previous = Some(line.clone());
+                    }
+                    Ok(Some(line + "\n"))
+                }
+                // Ctrl-C drops whatever is being typed, the same as entering a blank line.
+                Err(ReadlineError::Interrupted) => Ok(Some("\n".to_string())),
+                Err(ReadlineError::Eof) => Ok(None),
+                Err(err) => Err(format!("Failed to read line: {}", err)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kadom::expr::LiteralValue;
+
+    #[test]
+    fn blank_lines_are_not_recorded() {
+        assert!(!records_in_history(None, ""));
+        assert!(!records_in_history(Some("print 1;"), "   "));
+    }
+
+    #[test]
+    fn consecutive_duplicates_are_not_recorded() {
+        assert!(records_in_history(None, "print 1;"));
+        assert!(!records_in_history(Some("print 1;"), "print 1;"));
+        assert!(!records_in_history(Some("print 1;"), "print 1;  "));
+        assert!(records_in_history(Some("print 1;"), "print 2;"));
+    }
+
+    #[test]
+    fn completes_keywords_and_globals_by_prefix() {
+        let mut env = Environment::new();
+        env.define("count".to_string(), LiteralValue::Nil);
+        env.define("Counter".to_string(), LiteralValue::Nil);
+        env.define("clock".to_string(), LiteralValue::Nil);
+
+        assert_eq!(
+            complete("c", &env),
+            ["case", "catch", "class", "clock", "continue", "count"]
+        );
+        assert_eq!(complete("Co", &env), ["Counter"]);
+        assert_eq!(complete("wh", &env), ["while"]);
+        assert!(complete("z", &env).is_empty());
+    }
+
+    #[test]
+    fn shadowed_globals_complete_once() {
+        let mut env = Environment::new();
+        env.define("clock".to_string(), LiteralValue::Nil);
+        env.define("clock".to_string(), LiteralValue::Number(1.0));
+
+        assert_eq!(complete("clo", &env), ["clock"]);
+    }
+
+    #[test]
+    fn completes_the_identifier_before_the_cursor() {
+        assert_eq!(completion_start("print cou", 9), Some(6));
+        assert_eq!(completion_start("x = f(a, _b", 11), Some(9));
+        assert_eq!(completion_start("wh", 2), Some(0));
+        assert_eq!(completion_start("print ", 6), None);
+        assert_eq!(completion_start("point.x", 7), None);
+        assert_eq!(completion_start("print 12", 8), None);
+    }
+
+    #[test]
+    fn sole_keywords_complete_with_a_space() {
+        assert_eq!(completed("while"), "while ");
+        assert_eq!(completed("true"), "true");
+        assert_eq!(completed("count"), "count");
+    }
+}
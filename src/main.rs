@@ -1,72 +1,1367 @@
-mod environment;
-mod expr;
-mod interpreter;
-mod lexer;
-mod parser;
-mod stmt;
-
-use interpreter::*;
-use lexer::*;
-use parser::*;
+mod line_reader;
+mod watch;
+
+use kadom::{
+    check_source, compile_checked_from, coverage,
+    diagnostics::{Diagnostic, Severity},
+    error::KadomError,
+    interpreter::*,
+    lexer::*,
+    parser::*,
+    printer::format_program,
+    profiler::Profiler,
+    style::Style,
+};
+use line_reader::*;
 use std::env::args;
 use std::fs::read_to_string;
-use std::io::{stdin, stdout, Write};
+use std::io::{stderr, stdin, stdout, IsTerminal, Read, Write};
+use std::path::Path;
 use std::process::exit;
+use std::thread;
+use std::time::Duration;
+
+// Why running kadom failed, which decides the exit status.
+#[derive(Debug, PartialEq)]
+enum Failure {
+    // Reading the program or talking to the terminal failed.
+    Io(String),
+    // The source didn't scan, parse or resolve, so none of it ran.
+    Static(String),
+    Runtime(String),
+    // The program called exit() with this status. There's nothing to report.
+    Exit(i32),
+}
+
+impl Failure {
+    // Exit statuses from sysexits.h, or the one the program chose.
+    fn exit_code(&self) -> i32 {
+        match self {
+            Self::Io(_) => 74,
+            Self::Static(_) => 65,
+            Self::Runtime(_) => 70,
+            Self::Exit(code) => *code,
+        }
+    }
+}
+
+// Errors from the library, shown as they display.
+impl From<KadomError> for Failure {
+    fn from(error: KadomError) -> Self {
+        if let KadomError::Exit(code) = error {
+            return Self::Exit(code);
+        }
+        match error.is_static() {
+            true => Self::Static(error.to_string()),
+            false => Self::Runtime(error.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(msg) | Self::Static(msg) | Self::Runtime(msg) => write!(f, "{}", msg),
+            Self::Exit(code) => write!(f, "exited with status {}", code),
+        }
+    }
+}
+
+// Lines listed under the expression counts in a --profile report.
+const PROFILE_TOP_LINES: usize = 10;
+
+fn run_file(path: &str, mut interpreter: Interpreter, style: Style) -> Result<(), Failure> {
+    interpreter.set_script_path(Path::new(path));
+    let source = read_file(path)?;
+    let result = run(&mut interpreter, &source, style);
+    print_profile(&interpreter);
+    print_coverage(&interpreter, &source);
+    result
+}
 
-fn run_file(path: &String) -> Result<(), String> {
-    let mut interpreter = Interpreter::new();
-    let file_content =
-        read_to_string(path).map_err(|err| format!("Failed to read file to string: {}", err))?;
-    run(&mut interpreter, file_content)
+// Runs a whole program read from stdin, as given by "-" or piped in without arguments.
+fn run_stdin(mut interpreter: Interpreter, style: Style) -> Result<(), Failure> {
+    let source = read_program(stdin().lock())?;
+    let result = run(&mut interpreter, &source, style);
+    print_profile(&interpreter);
+    print_coverage(&interpreter, &source);
+    result
+}
+
+// Shows what --profile collected, whether or not the program finished.
+fn print_profile(interpreter: &Interpreter) {
+    if let Some(profiler) = &interpreter.profile {
+        eprint!("{}", profiler.report(PROFILE_TOP_LINES));
+    }
+}
+
+// Shows which lines of the program --coverage saw run.
+fn print_coverage(interpreter: &Interpreter, source: &str) {
+    if let Some(counts) = &interpreter.coverage {
+        eprint!("{}", coverage::report(source, counts));
+    }
+}
+
+fn read_program(mut reader: impl Read) -> Result<String, Failure> {
+    let mut source = String::new();
+    reader
+        .read_to_string(&mut source)
+        .map_err(|err| Failure::Io(format!("Failed to read program from stdin: {}", err)))?;
+    Ok(source)
+}
+
+fn read_file(path: &str) -> Result<String, Failure> {
+    if path == "-" {
+        return read_program(stdin().lock());
+    }
+    read_to_string(path)
+        .map_err(|err| Failure::Io(format!("Failed to read file to string: {}", err)))
 }
 
-fn run_prompt() -> Result<(), String> {
-    let mut interpreter = Interpreter::new();
+// Runs each snippet in order, sharing one interpreter, and stops at the first failure.
+fn run_eval(
+    sources: &[String],
+    interpreter: &mut Interpreter,
+    style: Style,
+) -> Result<(), Failure> {
+    let result = sources
+        .iter()
+        .try_for_each(|source| run(interpreter, source, style));
+    print_profile(interpreter);
+    result
+}
+
+const REPL_HELP: &str = "\
+:help           show this list
+:env            print every global variable
+:clear          forget everything defined so far
+:tokens <code>  show the tokens code scans to
+:ast <code>     show the statements code parses to
+:quit, :q       exit the REPL";
+
+// Ends with the status the program passed to exit(), if it called it.
+fn run_prompt(interpreter: Interpreter, style: Style) -> Result<(), Failure> {
+    let mut reader = line_reader().map_err(Failure::Io)?;
+    let mut repl = Repl::new(interpreter, style);
+    let mut action = ReplAction::Read;
     loop {
-        print!("> ");
+        let prompt = if action == ReplAction::ReadMore {
+            "... "
+        } else {
+            "> "
+        };
+        reader.complete_from(repl.interpreter.environment.clone());
+        let line = reader
+            .read_line(prompt)
+            .map_err(Failure::Io)?
+            .unwrap_or_default();
+
+        action = repl.step(&line);
+        match action {
+            ReplAction::Quit => {
+                print_profile(&repl.interpreter);
+                return Ok(());
+            }
+            ReplAction::Exit(code) => {
+                print_profile(&repl.interpreter);
+                return Err(Failure::Exit(code));
+            }
+            _ => (),
+        }
         stdout()
             .flush()
-            .map_err(|err| format!("Flush error <lol> : {}", err))?;
+            .map_err(|err| Failure::Io(format!("Flush error <lol> : {}", err)))?;
+    }
+}
 
-        let mut prompt = String::new();
-        stdin()
-            .read_line(&mut prompt)
-            .map_err(|err| format!("Failed to read line: {}", err))?;
+// Line editing only makes sense on a terminal; piped input is read as is.
+fn line_reader() -> Result<Box<dyn LineReader>, String> {
+    #[cfg(feature = "readline")]
+    if stdin().is_terminal() {
+        return Ok(Box::new(EditorReader::new()?));
+    }
 
-        let prompt = prompt.trim();
+    Ok(Box::new(StdinReader))
+}
+
+// What the REPL loop should do after a line has been handled.
+#[derive(Debug, PartialEq)]
+enum ReplAction {
+    Read,
+    // Input so far is incomplete, so show the continuation prompt.
+    ReadMore,
+    Quit,
+    // The program called exit() with this status.
+    Exit(i32),
+}
+
+struct Repl {
+    interpreter: Interpreter,
+    // Lines read so far for input that isn't complete yet.
+    pending: String,
+    // Every line read so far, so errors give line numbers counted across the whole session.
+    lines_read: u64,
+    // Where error messages go, so they stay out of redirected program output.
+    errors: Box<dyn Write>,
+    style: Style,
+}
+
+impl Repl {
+    fn new(interpreter: Interpreter, style: Style) -> Self {
+        Self {
+            interpreter,
+            pending: String::new(),
+            lines_read: 0,
+            errors: Box::new(stderr()),
+            style,
+        }
+    }
+
+    fn report(&mut self, msg: impl std::fmt::Display) {
+        // Nowhere left to report a failure to write an error.
+        let _ = writeln!(self.errors, "{}", msg);
+    }
+
+    // Handles one line as returned by read_line, where an empty string means end of input.
+    fn step(&mut self, line: &str) -> ReplAction {
+        if line.is_empty() {
+            println!();
+            return ReplAction::Quit;
+        }
+        self.lines_read += 1;
+
+        if self.pending.is_empty() {
+            if line.trim().is_empty() {
+                return ReplAction::Read;
+            }
+            if let Some(command) = line.trim().strip_prefix(':') {
+                return self.command(command);
+            }
+        }
+
+        // A blank line abandons input still waiting on closing delimiters.
+        if line.trim().is_empty() {
+            self.pending.clear();
+            return ReplAction::Read;
+        }
+
+        self.pending.push_str(line);
+        if needs_continuation(&self.pending) {
+            return ReplAction::ReadMore;
+        }
+
+        let first_line = self.lines_read + 1 - self.pending.lines().count() as u64;
+        let source = self.pending.trim().to_string();
+        self.pending.clear();
+        match run_from(&mut self.interpreter, &source, first_line, self.style) {
+            Err(Failure::Exit(code)) => ReplAction::Exit(code),
+            Err(failure) => {
+                self.report(failure);
+                ReplAction::Read
+            }
+            Ok(()) => ReplAction::Read,
+        }
+    }
 
-        match run(&mut interpreter, prompt.to_string()) {
-            Ok(_) => (),
-            Err(msg) => println!("{}", msg),
+    fn command(&mut self, command: &str) -> ReplAction {
+        match command {
+            "quit" | "q" => return ReplAction::Quit,
+            "help" => println!("{}", REPL_HELP),
+            "env" => {
+                for (name, value) in self.interpreter.environment.borrow().variables() {
+                    match value {
+                        Some(value) => println!("{} = {}", name, value),
+                        None => println!("{} (uninitialized)", name),
+                    }
+                }
+            }
+            "clear" => self.interpreter.reset(),
+            _ => {
+                // Commands that inspect a snippet take it as the rest of the line.
+                let (name, source) = command.split_once(' ').unwrap_or((command, ""));
+                let dump = match name {
+                    "tokens" => dump_tokens,
+                    "ast" => dump_ast,
+                    _ => {
+                        self.report("unknown command, try :help");
+                        return ReplAction::Read;
+                    }
+                };
+                match dump(source) {
+                    Ok(output) => print!("{}", output),
+                    Err(msg) => self.report(msg),
+                }
+            }
         }
+        ReplAction::Read
     }
 }
 
-fn run(interpreter: &mut Interpreter, source: String) -> Result<(), String> {
-    let mut scanner = Scanner::new(source);
-    let tokens: Vec<Token> = scanner.scan_tokens()?;
-    let mut parser = Parser::new(tokens);
-    let statements = parser.parse()?;
-    interpreter.interpret(statements)?;
-    Ok(())
+// Every token the source scans to, one per line.
+fn dump_tokens(source: &str) -> Result<String, KadomError> {
+    let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+    Ok(tokens.iter().map(|token| format!("{}\n", token)).collect())
+}
+
+// The parsed statements in S-expression form, one per line.
+fn dump_ast(source: &str) -> Result<String, KadomError> {
+    let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+    let statements = Parser::new(tokens).parse()?;
+    Ok(statements
+        .iter()
+        .map(|statement| format!("{}\n", statement))
+        .collect())
+}
+
+// The program printed back as canonically formatted source.
+fn dump_formatted(source: &str) -> Result<String, KadomError> {
+    let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+    let statements = Parser::new(tokens).parse()?;
+    Ok(format_program(&statements))
+}
+
+// The parsed statements as pretty-printed JSON.
+#[cfg(feature = "serde")]
+fn dump_ast_json(source: &str) -> Result<String, Failure> {
+    let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+    let statements = Parser::new(tokens).parse()?;
+    let json = serde_json::to_string_pretty(&statements)
+        .map_err(|err| Failure::Static(format!("Failed to serialize the syntax tree: {}", err)))?;
+    Ok(json + "\n")
+}
+
+// Whether the REPL should keep reading lines before running the input: a bracket is still open
+// or a string hasn't been closed.
+fn needs_continuation(source: &str) -> bool {
+    let mut depth = 0i64;
+    let mut in_string = false;
+    for c in source.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            _ if in_string => (),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            _ => (),
+        }
+    }
+
+    in_string || depth > 0
+}
+
+// Runs the source, keeping compile and runtime failures apart. Errors are rendered against the
+// source so they show the offending line.
+fn run(interpreter: &mut Interpreter, source: &str, style: Style) -> Result<(), Failure> {
+    run_from(interpreter, source, 1, style)
+}
+
+// As run, for source whose first line is line `first_line` of a longer input.
+fn run_from(
+    interpreter: &mut Interpreter,
+    source: &str,
+    first_line: u64,
+    style: Style,
+) -> Result<(), Failure> {
+    let statements = compile_checked_from(source, first_line)
+        .map_err(|errors| Failure::Static(render(&errors, source, first_line, style)))?;
+    interpreter.interpret(statements).map_err(|err| match err {
+        KadomError::Runtime(err) => {
+            let mut rendered = Diagnostic::from(&err).render_styled(source, first_line, style);
+            for frame in &err.trace {
+                rendered.push_str(&format!("\n  {}", frame));
+            }
+            Failure::Runtime(rendered)
+        }
+        other => Failure::from(other),
+    })
+}
+
+fn render(diagnostics: &[Diagnostic], source: &str, first_line: u64, style: Style) -> String {
+    let rendered: Vec<String> = diagnostics
+        .iter()
+        .map(|d| d.render_styled(source, first_line, style))
+        .collect();
+    rendered.join("\n\n")
 }
 
 fn main() {
-    let args: Vec<String> = args().collect();
-    let run_result = match args.len() {
-        1 => run_prompt(),
-        2 => run_file(&args[1]),
-        _ => {
-            println!("Usage: kadom [script]");
-            exit(64);
+    // Run on a thread with a larger stack, since deep kadom recursion recurses on the host stack.
+    let cli = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(cli)
+        .expect("Failed to spawn the interpreter thread");
+
+    if cli.join().is_err() {
+        exit(1);
+    }
+}
+
+// Command line arguments: flags first, then an optional script path.
+#[derive(Debug, PartialEq)]
+struct Options {
+    mode: Mode,
+    script: Option<String>,
+    // Arguments after the script path, readable by the script through args().
+    script_args: Vec<String>,
+    // Snippets given with -e, run in order instead of a script.
+    eval: Vec<String>,
+    // How often --watch checks the script for changes, if not the default.
+    interval: Option<Duration>,
+    backend: Backend,
+    // Fold constant expressions before running.
+    optimize: bool,
+    // How long each run may take before it's stopped.
+    timeout: Option<Duration>,
+    // What random() and randomInt() are seeded with, instead of the clock.
+    seed: Option<u64>,
+    // Print each statement to stderr before it runs.
+    trace: bool,
+    // Print per-line hit counts and timings to stderr once the program ends.
+    profile: bool,
+    // Print the script with how many times each line ran to stderr once it ends.
+    coverage: bool,
+    // Never colour diagnostics, even on a terminal.
+    no_color: bool,
+}
+
+impl Options {
+    // A fresh interpreter set up as the flags ask, with the script's arguments.
+    fn interpreter(&self) -> Interpreter {
+        let mut interpreter = Interpreter::with_backend(self.backend);
+        interpreter.optimize = self.optimize;
+        interpreter.timeout = self.timeout;
+        if let Some(seed) = self.seed {
+            interpreter.seed_rng(seed);
+        }
+        interpreter.trace = self.trace;
+        if self.profile {
+            interpreter.profile = Some(Profiler::new());
+        }
+        if self.coverage {
+            interpreter.coverage = Some(coverage::LineCounts::new());
+        }
+        interpreter.args = self.script_args.clone();
+        interpreter
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Run,
+    // Print the script's tokens instead of running it.
+    Tokens,
+    // Print the parsed statements instead of running them.
+    Ast,
+    // Report every error that can be found without running the script.
+    Check,
+    // Print the script back as formatted source.
+    Fmt,
+    // Rerun the script whenever it changes.
+    Watch,
+    // Print the parsed statements as JSON.
+    #[cfg(feature = "serde")]
+    AstJson,
+}
+
+// None when the arguments don't make sense, in which case the usage should be shown.
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        mode: Mode::Run,
+        script: None,
+        script_args: Vec::new(),
+        eval: Vec::new(),
+        interval: None,
+        backend: Backend::TreeWalker,
+        optimize: false,
+        timeout: None,
+        seed: None,
+        trace: false,
+        profile: false,
+        coverage: false,
+        no_color: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // Everything after the script path belongs to the script.
+        if options.script.is_some() {
+            options.script_args.push(arg.clone());
+            continue;
+        }
+
+        let mode = match arg.as_str() {
+            "-e" | "--eval" => {
+                options.eval.push(args.next()?.clone());
+                continue;
+            }
+            "--tokens" => Mode::Tokens,
+            "--ast" => Mode::Ast,
+            "--check" => Mode::Check,
+            "--fmt" => Mode::Fmt,
+            "--watch" => Mode::Watch,
+            "--interval" => {
+                options.interval = Some(Duration::from_millis(args.next()?.parse().ok()?));
+                continue;
+            }
+            "--vm" => {
+                options.backend = Backend::Vm;
+                continue;
+            }
+            "--opt" => {
+                options.optimize = true;
+                continue;
+            }
+            "--trace" => {
+                options.trace = true;
+                continue;
+            }
+            "--profile" => {
+                options.profile = true;
+                continue;
+            }
+            "--coverage" => {
+                options.coverage = true;
+                continue;
+            }
+            "--no-color" => {
+                options.no_color = true;
+                continue;
+            }
+            "--timeout" => {
+                let seconds: f64 = args.next()?.parse().ok()?;
+                options.timeout = Some(Duration::try_from_secs_f64(seconds).ok()?);
+                continue;
+            }
+            "--seed" => {
+                options.seed = Some(args.next()?.parse().ok()?);
+                continue;
+            }
+            #[cfg(feature = "serde")]
+            "--ast-json" => Mode::AstJson,
+            flag if flag.starts_with("--") => return None,
+            path => {
+                options.script = Some(path.to_string());
+                continue;
+            }
+        };
+        if options.mode != Mode::Run {
+            return None;
         }
+        options.mode = mode;
+    }
+
+    if !options.eval.is_empty() && (options.mode != Mode::Run || options.script.is_some()) {
+        return None;
+    }
+    if options.interval.is_some() && options.mode != Mode::Watch {
+        return None;
+    }
+    // Only modes that run the program have a backend to choose, code to optimize, a timeout, a
+    // seed or statements to trace and profile.
+    let runs = matches!(options.mode, Mode::Run | Mode::Watch);
+    let watches_statements = options.trace || options.profile || options.coverage;
+    let configures_run = options.backend != Backend::TreeWalker
+        || options.optimize
+        || options.timeout.is_some()
+        || options.seed.is_some()
+        || watches_statements;
+    if configures_run && !runs {
+        return None;
+    }
+    // The VM runs bytecode, not statements, so there's nothing for it to trace, profile or cover.
+    if watches_statements && options.backend == Backend::Vm {
+        return None;
+    }
+    // Coverage is reported against the lines of a single script.
+    if options.coverage && options.script.is_none() {
+        return None;
+    }
+    if options.mode != Mode::Run && options.script.is_none() {
+        return None;
+    }
+    Some(options)
+}
+
+fn cli() {
+    let args: Vec<String> = args().skip(1).collect();
+    let Some(options) = parse_args(&args) else {
+        eprintln!("Usage: kadom [-e source]... | [--tokens | --ast | --ast-json | --check | --fmt | --watch [--interval ms]] [--vm] [--opt] [--timeout secs] [--seed n] [--trace] [--profile] [--coverage] [--no-color] [script]");
+        eprintln!("       arguments after the script are passed to it, see args()");
+        exit(64);
     };
+    let style = Style::for_stderr(options.no_color);
 
-    match run_result {
-        Ok(_) => (),
-        Err(msg) => {
-            println!("{}", msg);
-            exit(1);
+    let run_result = match (&options.script, &options.mode) {
+        (None, _) if !options.eval.is_empty() => {
+            run_eval(&options.eval, &mut options.interpreter(), style)
         }
+        (None, _) if !stdin().is_terminal() => run_stdin(options.interpreter(), style),
+        (None, _) => run_prompt(options.interpreter(), style),
+        (Some(path), Mode::Run) if path == "-" => run_stdin(options.interpreter(), style),
+        (Some(path), Mode::Run) => run_file(path, options.interpreter(), style),
+        (Some(path), Mode::Tokens) => print_dump(path, dump_tokens),
+        (Some(path), Mode::Ast) => print_dump(path, dump_ast),
+        (Some(path), Mode::Check) => check_file(path, style),
+        (Some(path), Mode::Fmt) => print_dump(path, dump_formatted),
+        (Some(path), Mode::Watch) => watch::watch(
+            path,
+            options.interval.unwrap_or(watch::DEFAULT_INTERVAL),
+            || {
+                // Failures are reported and the watcher carries on.
+                if let Err(failure) = run_file(path, options.interpreter(), style) {
+                    eprintln!("{}", failure);
+                }
+            },
+        ),
+        #[cfg(feature = "serde")]
+        (Some(path), Mode::AstJson) => print_dump(path, dump_ast_json),
+    };
+
+    if let Err(failure) = run_result {
+        if !matches!(failure, Failure::Exit(_)) {
+            eprintln!("{}", failure);
+        }
+        exit(failure.exit_code());
+    }
+}
+
+fn check_file(path: &str, style: Style) -> Result<(), Failure> {
+    let source = read_file(path)?;
+    let diagnostics = check_source(&source);
+    if diagnostics.is_empty() {
+        return Ok(());
+    }
+
+    // Warnings are shown but don't fail the check.
+    let rendered = render(&diagnostics, &source, 1, style);
+    if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        return Err(Failure::Static(rendered));
+    }
+    eprintln!("{}", rendered);
+    Ok(())
+}
+
+// Prints what the script scans or parses to.
+fn print_dump<E: Into<Failure>>(
+    path: &str,
+    dump: fn(&str) -> Result<String, E>,
+) -> Result<(), Failure> {
+    let output = dump(&read_file(path)?).map_err(Into::into)?;
+    print!("{}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn balanced_input_runs_immediately() {
+        assert!(!needs_continuation("print 1;"));
+        assert!(!needs_continuation("if (x) { print [1, 2]; }"));
+        assert!(!needs_continuation(""));
+    }
+
+    #[test]
+    fn open_brackets_continue() {
+        assert!(needs_continuation("if (x) {"));
+        assert!(needs_continuation(
+            "fun f() {\n  if (x) {\n    print 1;\n  }\n"
+        ));
+        assert!(needs_continuation("print f(1,"));
+        assert!(!needs_continuation("fun f() {\n  if (x) {\n  }\n}\n"));
+    }
+
+    #[test]
+    fn unterminated_string_continues() {
+        assert!(needs_continuation("print \"hello"));
+        assert!(!needs_continuation("print \"hello\nworld\";"));
+        assert!(!needs_continuation("print \"{\";"));
+    }
+
+    fn repl() -> Repl {
+        Repl {
+            interpreter: Interpreter::with_output(Box::new(std::io::sink())),
+            pending: String::new(),
+            lines_read: 0,
+            errors: Box::new(std::io::sink()),
+            style: Style::PLAIN,
+        }
+    }
+
+    // Feeds the lines to a REPL and returns what it printed to stdout and to stderr.
+    fn repl_session(lines: &[&str]) -> (String, String) {
+        styled_repl_session(lines, Style::PLAIN)
+    }
+
+    fn styled_repl_session(lines: &[&str], style: Style) -> (String, String) {
+        let (output, errors) = (Output::default(), Output::default());
+        let mut repl = Repl {
+            interpreter: Interpreter::with_output(Box::new(output.clone())),
+            pending: String::new(),
+            lines_read: 0,
+            errors: Box::new(errors.clone()),
+            style,
+        };
+        for line in lines {
+            repl.step(line);
+        }
+        (output.contents(), errors.contents())
+    }
+
+    #[test]
+    fn repl_line_numbers_count_across_inputs() {
+        let (_, errors) = repl_session(&["print 1;\n", "\n", "{\n", "  print 1 +;\n", "}\n"]);
+        assert!(errors.starts_with(
+            "error: Expected expression
+ --> line 4:12
+  |
+4 |   print 1 +;
+  |            ^"
+        ));
+    }
+
+    #[test]
+    fn colours_errors_but_not_program_output() {
+        let lines = ["print \"red\";\n", "print missing;\n"];
+        let (output, errors) = styled_repl_session(&lines, Style::COLOR);
+        assert_eq!(output, "red\n");
+        assert!(errors.starts_with("\x1b[1;31merror\x1b[0m: undefined variable 'missing'\n"));
+        assert!(errors.contains("\x1b[1;31m^\x1b[0m"));
+
+        let (_, errors) = styled_repl_session(&lines, Style::PLAIN);
+        assert!(!errors.contains('\x1b'));
+    }
+
+    #[test]
+    fn no_color_flag_works_in_every_mode() {
+        assert!(
+            parse_args(&args(&["--no-color", "a.kdm"]))
+                .unwrap()
+                .no_color
+        );
+        assert!(
+            parse_args(&args(&["--check", "--no-color", "a.kdm"]))
+                .unwrap()
+                .no_color
+        );
+        assert!(!parse_args(&args(&["a.kdm"])).unwrap().no_color);
+    }
+
+    #[test]
+    fn repl_errors_go_to_stderr() {
+        let (output, errors) = repl_session(&[
+            "print 1;\n",
+            "print missing;\n",
+            "print 1 +;\n",
+            ":nope\n",
+            "print 2;\n",
+        ]);
+        assert_eq!(output, "1\n2\n");
+        assert_eq!(
+            errors,
+            "error: undefined variable 'missing'
+ --> line 2:7
+  |
+2 | print missing;
+  |       ^
+error: Expected expression
+ --> line 3:10
+  |
+3 | print 1 +;
+  |          ^
+unknown command, try :help
+"
+        );
+    }
+
+    fn global(repl: &Repl, name: &str) -> Option<String> {
+        let value = repl.interpreter.environment.borrow().lookup(name);
+        value.ok().map(|value| value.to_string())
+    }
+
+    #[test]
+    fn end_of_input_quits() {
+        assert_eq!(repl().step(""), ReplAction::Quit);
+
+        let mut repl = repl();
+        assert_eq!(repl.step("if (true) {\n"), ReplAction::ReadMore);
+        assert_eq!(repl.step(""), ReplAction::Quit);
+    }
+
+    #[test]
+    fn blank_lines_are_skipped() {
+        let mut repl = repl();
+        assert_eq!(repl.step("\n"), ReplAction::Read);
+        assert_eq!(repl.step("   \t\n"), ReplAction::Read);
+        assert_eq!(repl.step("var x = 1;\n"), ReplAction::Read);
+        assert_eq!(global(&repl, "x"), Some("1".to_string()));
+    }
+
+    #[test]
+    fn blank_line_abandons_pending_input() {
+        let mut repl = repl();
+        assert_eq!(repl.step("var x = [1,\n"), ReplAction::ReadMore);
+        assert_eq!(repl.step("\n"), ReplAction::Read);
+        assert_eq!(repl.step("var y = 2;\n"), ReplAction::Read);
+        assert_eq!(global(&repl, "x"), None);
+        assert_eq!(global(&repl, "y"), Some("2".to_string()));
+    }
+
+    #[test]
+    fn meta_commands() {
+        let mut repl = repl();
+        repl.step("var x = 1;\n");
+        assert_eq!(repl.step(":clear\n"), ReplAction::Read);
+        assert_eq!(global(&repl, "x"), None);
+        assert_eq!(repl.step(":nope\n"), ReplAction::Read);
+        assert_eq!(repl.step(":q\n"), ReplAction::Quit);
+    }
+
+    #[test]
+    fn dumps_tokens() {
+        assert_eq!(
+            dump_tokens("1 + 2 * 3").unwrap(),
+            "Number 1 Some(FVal(1.0)) line 1:1
+Plus + None line 1:3
+Number 2 Some(FVal(2.0)) line 1:5
+Star * None line 1:7
+Number 3 Some(FVal(3.0)) line 1:9
+Eof  None line 1:10
+"
+        );
+    }
+
+    #[test]
+    fn dumps_ast() {
+        assert_eq!(dump_ast("1 + 2 * 3;").unwrap(), "(; (+ 1 (* 2 3)))\n");
+        assert_eq!(
+            dump_ast("fun f(a, b = 1, ...c) { if (a) return b; else print c; }").unwrap(),
+            "(fun f (a (= b 1) ...c) (if var a (return var b) (print var c)))\n"
+        );
+        assert!(dump_ast("1 +").is_err());
+    }
+
+    #[test]
+    fn dumps_formatted_source() {
+        assert_eq!(
+            dump_formatted("var x=1;if(x){print x+1;}").unwrap(),
+            "var x = 1;\nif (x) {\n    print x + 1;\n}\n"
+        );
+        assert!(dump_formatted("var x = ;").is_err());
+    }
+
+    #[test]
+    fn dumps_program_ast() {
+        assert_eq!(
+            dump_ast("var total = (1 + 2) * -3;\nprint total / 4 - 1;").unwrap(),
+            "(var total (* (group (+ 1 2)) (- 3)))\n(print (- (/ var total 4) 1))\n"
+        );
+    }
+
+    #[test]
+    fn dumps_every_token_category() {
+        let dump = dump_tokens("var s = \"hi\";\nif (a.b >= 1.5) {}").unwrap();
+        assert_eq!(
+            dump,
+            "Var var None line 1:1
+Identifier s None line 1:5
+Equal = None line 1:7
+StringLiteral \"hi\" Some(StringVal(\"hi\")) line 1:9
+Semicolon ; None line 1:13
+If if None line 2:1
+LeftParent ( None line 2:4
+Identifier a None line 2:5
+Dot . None line 2:6
+Identifier b None line 2:7
+GreaterEqual >= None line 2:9
+Number 1.5 Some(FVal(1.5)) line 2:12
+RightParent ) None line 2:15
+LeftBrace { None line 2:17
+RightBrace } None line 2:18
+Eof  None line 2:19
+"
+        );
+    }
+
+    #[test]
+    fn dump_tokens_reports_lexing_errors() {
+        assert_eq!(
+            dump_tokens("var a = 1;\nvar b = #;"),
+            Err(KadomError::Scan {
+                line: 2,
+                message: "Oopsie, character not recognised: #".to_string()
+            })
+        );
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_flags_before_the_script() {
+        assert_eq!(
+            parse_args(&args(&[])),
+            Some(Options {
+                mode: Mode::Run,
+                script: None,
+                script_args: Vec::new(),
+                eval: Vec::new(),
+                interval: None,
+                backend: Backend::TreeWalker,
+                optimize: false,
+                timeout: None,
+                seed: None,
+                trace: false,
+                profile: false,
+                coverage: false,
+                no_color: false,
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["--tokens", "a.kdm"])),
+            Some(Options {
+                mode: Mode::Tokens,
+                script: Some("a.kdm".to_string()),
+                script_args: Vec::new(),
+                eval: Vec::new(),
+                interval: None,
+                backend: Backend::TreeWalker,
+                optimize: false,
+                timeout: None,
+                seed: None,
+                trace: false,
+                profile: false,
+                coverage: false,
+                no_color: false,
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["--ast", "a.kdm"])),
+            Some(Options {
+                mode: Mode::Ast,
+                script: Some("a.kdm".to_string()),
+                script_args: Vec::new(),
+                eval: Vec::new(),
+                interval: None,
+                backend: Backend::TreeWalker,
+                optimize: false,
+                timeout: None,
+                seed: None,
+                trace: false,
+                profile: false,
+                coverage: false,
+                no_color: false,
+            })
+        );
+        assert_eq!(parse_args(&args(&["--ast", "--tokens", "a.kdm"])), None);
+        assert_eq!(
+            parse_args(&args(&["--watch", "--interval", "100", "a.kdm"])).unwrap(),
+            Options {
+                mode: Mode::Watch,
+                script: Some("a.kdm".to_string()),
+                script_args: Vec::new(),
+                eval: Vec::new(),
+                interval: Some(Duration::from_millis(100)),
+                backend: Backend::TreeWalker,
+                optimize: false,
+                timeout: None,
+                seed: None,
+                trace: false,
+                profile: false,
+                coverage: false,
+                no_color: false,
+            }
+        );
+        assert_eq!(parse_args(&args(&["--interval", "100", "a.kdm"])), None);
+        assert_eq!(
+            parse_args(&args(&["--watch", "--interval", "x", "a.kdm"])),
+            None
+        );
+        assert_eq!(
+            parse_args(&args(&["--check", "a.kdm"])).unwrap().mode,
+            Mode::Check
+        );
+
+        assert_eq!(parse_args(&args(&["--tokens"])), None);
+        assert_eq!(parse_args(&args(&["--nope", "a.kdm"])), None);
+    }
+
+    #[test]
+    fn vm_flag_selects_the_backend_for_running_modes() {
+        assert_eq!(
+            parse_args(&args(&["--vm", "a.kdm"])).unwrap().backend,
+            Backend::Vm
+        );
+        assert_eq!(
+            parse_args(&args(&["--watch", "--vm", "a.kdm"]))
+                .unwrap()
+                .backend,
+            Backend::Vm
+        );
+        assert_eq!(
+            parse_args(&args(&["--vm", "-e", "print 1;"]))
+                .unwrap()
+                .backend,
+            Backend::Vm
+        );
+        assert_eq!(parse_args(&args(&["--vm"])).unwrap().backend, Backend::Vm);
+        assert_eq!(parse_args(&args(&["--ast", "--vm", "a.kdm"])), None);
+    }
+
+    #[test]
+    fn opt_flag_enables_folding_for_running_modes() {
+        let options = parse_args(&args(&["--opt", "--vm", "a.kdm", "x"])).unwrap();
+        assert!(options.optimize);
+        let interpreter = options.interpreter();
+        assert!(interpreter.optimize);
+        assert_eq!(interpreter.backend, Backend::Vm);
+        assert_eq!(interpreter.args, args(&["x"]));
+
+        assert!(!parse_args(&args(&["a.kdm"])).unwrap().optimize);
+        assert_eq!(parse_args(&args(&["--fmt", "--opt", "a.kdm"])), None);
+    }
+
+    #[test]
+    fn timeout_flag_limits_running_modes() {
+        let options = parse_args(&args(&["--timeout", "0.5", "a.kdm"])).unwrap();
+        assert_eq!(options.timeout, Some(Duration::from_millis(500)));
+        assert_eq!(
+            options.interpreter().timeout,
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            parse_args(&args(&["--timeout", "2", "-e", "print 1;"]))
+                .unwrap()
+                .timeout,
+            Some(Duration::from_secs(2))
+        );
+
+        assert_eq!(parse_args(&args(&["a.kdm"])).unwrap().timeout, None);
+        assert_eq!(
+            parse_args(&args(&["--check", "--timeout", "1", "a.kdm"])),
+            None
+        );
+        assert_eq!(parse_args(&args(&["--timeout", "-1", "a.kdm"])), None);
+        assert_eq!(parse_args(&args(&["--timeout", "soon", "a.kdm"])), None);
+    }
+
+    #[test]
+    fn seed_flag_makes_runs_repeatable() {
+        let options = parse_args(&args(&["--seed", "42", "a.kdm"])).unwrap();
+        assert_eq!(options.seed, Some(42));
+        let draw = |options: &Options| {
+            let mut interpreter = options.interpreter();
+            interpreter
+                .interpret(kadom::compile("var x = [random(), randomInt(1, 1000)];").unwrap())
+                .unwrap();
+            let x = interpreter.environment.borrow().lookup("x").unwrap();
+            x.to_string()
+        };
+        assert_eq!(draw(&options), draw(&options));
+
+        assert_eq!(parse_args(&args(&["a.kdm"])).unwrap().seed, None);
+        assert_eq!(parse_args(&args(&["--fmt", "--seed", "1", "a.kdm"])), None);
+        assert_eq!(parse_args(&args(&["--seed", "-1", "a.kdm"])), None);
+        assert_eq!(parse_args(&args(&["--seed", "a.kdm"])), None);
+    }
+
+    #[test]
+    fn trace_flag_is_for_the_tree_walker_when_running() {
+        let options = parse_args(&args(&["--trace", "a.kdm"])).unwrap();
+        assert!(options.trace);
+        assert!(options.interpreter().trace);
+        assert!(
+            parse_args(&args(&["--trace", "-e", "print 1;"]))
+                .unwrap()
+                .trace
+        );
+
+        assert!(!parse_args(&args(&["a.kdm"])).unwrap().trace);
+        assert_eq!(parse_args(&args(&["--ast", "--trace", "a.kdm"])), None);
+        assert_eq!(parse_args(&args(&["--trace", "--vm", "a.kdm"])), None);
+    }
+
+    #[test]
+    fn profile_flag_is_for_the_tree_walker_when_running() {
+        let options = parse_args(&args(&["--profile", "a.kdm"])).unwrap();
+        assert!(options.profile);
+        assert!(options.interpreter().profile.is_some());
+        assert!(parse_args(&args(&["a.kdm"]))
+            .unwrap()
+            .interpreter()
+            .profile
+            .is_none());
+
+        assert_eq!(parse_args(&args(&["--fmt", "--profile", "a.kdm"])), None);
+        assert_eq!(parse_args(&args(&["--profile", "--vm", "a.kdm"])), None);
+    }
+
+    #[test]
+    fn coverage_flag_needs_a_script_to_run() {
+        let options = parse_args(&args(&["--coverage", "a.kdm"])).unwrap();
+        assert!(options.coverage);
+        assert!(options.interpreter().coverage.is_some());
+        assert!(parse_args(&args(&["--coverage", "-"])).unwrap().coverage);
+        assert!(parse_args(&args(&["a.kdm"]))
+            .unwrap()
+            .interpreter()
+            .coverage
+            .is_none());
+
+        assert_eq!(parse_args(&args(&["--coverage", "-e", "print 1;"])), None);
+        assert_eq!(parse_args(&args(&["--coverage"])), None);
+        assert_eq!(parse_args(&args(&["--check", "--coverage", "a.kdm"])), None);
+        assert_eq!(parse_args(&args(&["--coverage", "--vm", "a.kdm"])), None);
+    }
+
+    #[test]
+    fn passes_arguments_after_the_script_to_it() {
+        assert_eq!(
+            parse_args(&args(&["--ast", "a.kdm", "--tokens", "b.kdm", "-e"])),
+            Some(Options {
+                mode: Mode::Ast,
+                script: Some("a.kdm".to_string()),
+                script_args: args(&["--tokens", "b.kdm", "-e"]),
+                eval: Vec::new(),
+                interval: None,
+                backend: Backend::TreeWalker,
+                optimize: false,
+                timeout: None,
+                seed: None,
+                trace: false,
+                profile: false,
+                coverage: false,
+                no_color: false,
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["-", "3"])).unwrap().script_args,
+            args(&["3"])
+        );
+    }
+
+    #[test]
+    fn parses_repeated_eval_flags() {
+        assert_eq!(
+            parse_args(&args(&["-e", "var x = 1;", "--eval", "print x;"])),
+            Some(Options {
+                mode: Mode::Run,
+                script: None,
+                script_args: Vec::new(),
+                eval: args(&["var x = 1;", "print x;"]),
+                interval: None,
+                backend: Backend::TreeWalker,
+                optimize: false,
+                timeout: None,
+                seed: None,
+                trace: false,
+                profile: false,
+                coverage: false,
+                no_color: false,
+            })
+        );
+        assert_eq!(parse_args(&args(&["-e"])), None);
+        assert_eq!(parse_args(&args(&["-e", "print 1;", "a.kdm"])), None);
+        assert_eq!(parse_args(&args(&["--ast", "-e", "print 1;"])), None);
+    }
+
+    // Output sink that can still be read after an interpreter takes it.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    fn eval(sources: &[&str]) -> (Result<(), Failure>, String) {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let result = run_eval(&args(sources), &mut interpreter, Style::PLAIN);
+        (result, output.contents())
+    }
+
+    #[test]
+    fn eval_runs_snippets_in_one_interpreter() {
+        assert_eq!(eval(&["print 1 + 2;"]), (Ok(()), "3\n".to_string()));
+        assert_eq!(eval(&["var x=1;", "print x;"]), (Ok(()), "1\n".to_string()));
+    }
+
+    #[test]
+    fn eval_failures_set_the_exit_code() {
+        let (result, printed) = eval(&["print 1;", "print (;", "print 2;"]);
+        assert_eq!(result.unwrap_err().exit_code(), 65);
+        assert_eq!(printed, "1\n");
+
+        let (result, _) = eval(&["print nil - 1;"]);
+        assert_eq!(result.unwrap_err().exit_code(), 70);
+    }
+
+    #[test]
+    fn compile_and_runtime_failures_are_distinguished() {
+        let mut interpreter = Interpreter::with_output(Box::new(std::io::sink()));
+        assert_eq!(
+            run(&mut interpreter, "print 1 +;", Style::PLAIN),
+            Err(Failure::Static(
+                "error: Expected expression
+ --> line 1:10
+  |
+1 | print 1 +;
+  |          ^"
+                    .to_string()
+            ))
+        );
+        assert_eq!(
+            run(&mut interpreter, "var a = \"unterminated;", Style::PLAIN),
+            Err(Failure::Static(
+                "error: unterminated string lol :/
+ --> line 1:9
+  |
+1 | var a = \"unterminated;
+  |         ^"
+                    .to_string()
+            ))
+        );
+        assert_eq!(
+            run(&mut interpreter, "print missing;", Style::PLAIN),
+            Err(Failure::Runtime(
+                "error: undefined variable 'missing'
+ --> line 1:7
+  |
+1 | print missing;
+  |       ^"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn errors_in_an_imported_file_point_at_the_import() {
+        let directory =
+            std::env::temp_dir().join(format!("kadom-main-import-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("lib")).unwrap();
+        std::fs::write(directory.join("lib/bad.kdm"), "var y = 1;\nprint x + nil;").unwrap();
+        let script = directory.join("e.kdm");
+
+        let mut interpreter = Interpreter::with_output(Box::new(std::io::sink()));
+        interpreter.set_script_path(&script);
+        assert_eq!(
+            run(
+                &mut interpreter,
+                "import \"lib/bad.kdm\";\nvar unrelated = \"this line is not the error\";",
+                Style::PLAIN
+            ),
+            Err(Failure::Runtime(
+                "error: lib/bad.kdm:2: undefined variable 'x'
+ --> line 1:8
+  |
+1 | import \"lib/bad.kdm\";
+  |        ^"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn exit_ends_with_the_programs_status() {
+        let (result, printed) = eval(&["print 1;", "exit(3); print 2;", "print 3;"]);
+        let failure = result.unwrap_err();
+        assert_eq!(failure, Failure::Exit(3));
+        assert_eq!(failure.exit_code(), 3);
+        assert_eq!(printed, "1\n");
+    }
+
+    #[test]
+    fn repl_quits_on_exit() {
+        let mut repl = repl();
+        assert_eq!(repl.step("var a = 1;\n"), ReplAction::Read);
+        assert_eq!(repl.step("exit(a + 1);\n"), ReplAction::Exit(2));
+
+        let (output, errors) = repl_session(&["print 1; exit(); print 2;\n"]);
+        assert_eq!((output.as_str(), errors.as_str()), ("1\n", ""));
+    }
+
+    #[test]
+    fn reads_whole_program_from_reader() {
+        let source = "var a = 1;\nprint a;\n";
+        assert_eq!(read_program(source.as_bytes()), Ok(source.to_string()));
+        assert!(matches!(
+            read_program(&[0xff, 0xfe][..]),
+            Err(Failure::Io(_))
+        ));
+    }
+
+    #[test]
+    fn runs_program_read_from_reader() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let source = read_program("var a = 1;\n{\n  print a + 1;\n}\n".as_bytes()).unwrap();
+        run(&mut interpreter, &source, Style::PLAIN).unwrap();
+        assert_eq!(output.contents(), "2\n");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn dumps_ast_as_json() {
+        assert_eq!(
+            dump_ast_json("print \"hi\";").unwrap(),
+            r#"[
+  {
+    "Print": {
+      "keyword": {
+        "token_type": "Print",
+        "lexeme": "print",
+        "literal_option": null,
+        "line_number": 1,
+        "column": 1
+      },
+      "expression": {
+        "Literal": {
+          "value": {
+            "String": "hi"
+          }
+        }
+      }
+    }
+  }
+]
+"#
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_ast_deserializes_to_an_equal_tree() {
+        let source = "fun add(a, b = 1, ...rest) { return a + b; }
+             class Point { init(x) { this.x = x; } }
+             var [p, _] = [Point(1), nil];
+             if (p.x >= 1) print add(p.x); else { print -1; }
+             try { throw \"boom\"; } catch (e) { print e; }";
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+
+        let json = dump_ast_json(source).unwrap();
+        let parsed: Vec<kadom::stmt::Stmt> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, statements);
+    }
+
+    #[test]
+    fn extra_closers_are_left_to_the_parser() {
+        assert!(!needs_continuation("}"));
     }
 }
@@ -0,0 +1,350 @@
+mod assert;
+mod host;
+mod json;
+mod list;
+mod map;
+mod math;
+mod random;
+mod regex;
+mod string;
+mod time;
+
+pub(crate) use random::Rng;
+
+use crate::{
+    environment::Environment,
+    error::RuntimeError,
+    expr::{LiteralValue, NativeFunction},
+    interpreter::Interpreter,
+    lexer::Token,
+};
+use std::{
+    cell::RefCell,
+    io::{stdin, BufRead, Write},
+    ops::{Range, RangeInclusive},
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Whether a global is one of the built-in constants.
+pub(crate) fn is_constant(name: &str) -> bool {
+    math::CONSTANTS
+        .iter()
+        .any(|(constant, _)| *constant == name)
+}
+
+// Natives that run code where they're called, so calls to them from inside a scope are given the
+// names of the locals around the call.
+const SEES_LOCALS: [&str; 1] = ["eval"];
+
+// Whether a global is a native that needs the locals of its caller.
+pub(crate) fn sees_locals(name: &str) -> bool {
+    SEES_LOCALS.contains(&name)
+}
+
+type NativeResult = Result<LiteralValue, String>;
+
+// Defines every native function in the given (global) environment.
+pub fn define(globals: &mut Environment) {
+    define_native(globals, "clock", 0..=0, clock);
+    define_native(globals, "input", 0..=1, input);
+    define_native(globals, "write", 0..=usize::MAX, write);
+    define_native(globals, "typeof", 1..=1, type_of);
+    define_native(globals, "str", 1..=1, str);
+    define_native(globals, "num", 1..=1, num);
+    define_native(globals, "len", 1..=1, len);
+    define_calling(globals, "contains", 2..=2, contains);
+    define_calling(globals, "indexOf", 2..=2, index_of);
+    define_native(globals, "args", 0..=0, args);
+    define_calling(globals, "exit", 0..=1, exit);
+    assert::define(globals);
+    host::define(globals);
+    json::define(globals);
+    list::define(globals);
+    map::define(globals);
+    math::define(globals);
+    random::define(globals);
+    regex::define(globals);
+    string::define(globals);
+    time::define(globals);
+}
+
+// Errors the function returns are reported at the call site.
+fn define_native(
+    globals: &mut Environment,
+    name: &str,
+    arity: RangeInclusive<usize>,
+    function: impl Fn(&mut Interpreter, Vec<LiteralValue>) -> NativeResult + 'static,
+) {
+    define_calling(
+        globals,
+        name,
+        arity,
+        move |interpreter, arguments, paren| {
+            function(interpreter, arguments).map_err(|message| RuntimeError::new(paren, message))
+        },
+    );
+}
+
+// For natives that call kadom functions, whose errors come from elsewhere and are passed on as
+// they are.
+fn define_calling(
+    globals: &mut Environment,
+    name: &str,
+    arity: RangeInclusive<usize>,
+    function: impl Fn(&mut Interpreter, Vec<LiteralValue>, &Token) -> Result<LiteralValue, RuntimeError>
+        + 'static,
+) {
+    globals.define(
+        name.to_string(),
+        LiteralValue::Native(Rc::new(NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+            sees_locals: sees_locals(name),
+        })),
+    );
+}
+
+// Integers beyond 2^53 can't all be told apart as numbers.
+const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+// A whole number argument, for natives that count or index.
+fn integer(function: &str, value: &LiteralValue) -> Result<i64, String> {
+    match value {
+        LiteralValue::Number(x) if x.fract() == 0.0 && x.abs() <= MAX_EXACT_INTEGER => {
+            Ok(*x as i64)
+        }
+        other => Err(format!(
+            "{} expects an integer, got {}",
+            function,
+            match other {
+                LiteralValue::Number(_) => other.to_string(),
+                _ => other.type_name().to_string(),
+            }
+        )),
+    }
+}
+
+// The characters or items from a start index up to an optional end, which defaults to the length.
+// Indices outside 0..=length, or a start after the end, are errors naming the length of the
+// `kind` of value being sliced.
+fn slice_range(
+    function: &str,
+    kind: &str,
+    length: usize,
+    start: &LiteralValue,
+    end: Option<&LiteralValue>,
+) -> Result<Range<usize>, String> {
+    let length = length as i64;
+    let start = integer(function, start)?;
+    let end = match end {
+        Some(end) => integer(function, end)?,
+        None => length,
+    };
+
+    if let Some(index) = [start, end]
+        .into_iter()
+        .find(|index| !(0..=length).contains(index))
+    {
+        return Err(format!(
+            "{} index {} is out of range for a {} of length {}",
+            function, index, kind, length
+        ));
+    }
+    if start > end {
+        return Err(format!(
+            "{} start {} is after end {} in a {} of length {}",
+            function, start, end, kind, length
+        ));
+    }
+    Ok(start as usize..end as usize)
+}
+
+// Seconds since the Unix epoch, with sub-second precision.
+fn clock(_: &mut Interpreter, _: Vec<LiteralValue>) -> NativeResult {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| format!("System clock is before the Unix epoch: {}", err))?;
+
+    Ok(LiteralValue::Number(elapsed.as_secs_f64()))
+}
+
+// Ends the program with a status from 0 to 255, 0 when none is given. It stops the run as an
+// error that nothing catches, which the caller sees as KadomError::Exit.
+fn exit(
+    _: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let code = match arguments.first() {
+        Some(code) => integer("exit", code).map_err(|message| RuntimeError::new(paren, message))?,
+        None => 0,
+    };
+    match u8::try_from(code) {
+        Ok(code) => Err(RuntimeError::exit(paren, code.into())),
+        Err(_) => Err(RuntimeError::new(
+            paren,
+            format!("exit status must be from 0 to 255, got {}", code),
+        )),
+    }
+}
+
+// The command line arguments given after the script path, as a list of strings.
+fn args(interpreter: &mut Interpreter, _: Vec<LiteralValue>) -> NativeResult {
+    let args = interpreter
+        .args
+        .iter()
+        .map(|arg| LiteralValue::String(arg.as_str().into()))
+        .collect();
+    Ok(LiteralValue::List(Rc::new(RefCell::new(args))))
+}
+
+fn type_of(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    Ok(LiteralValue::String(arguments[0].type_name().into()))
+}
+
+fn str(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    Ok(LiteralValue::String(arguments[0].to_string().into()))
+}
+
+// Converts to a number. Strings that aren't entirely a finite number (ignoring surrounding
+// whitespace) give nil, as does nil itself.
+fn num(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    match &arguments[0] {
+        LiteralValue::Number(x) => Ok(LiteralValue::Number(*x)),
+        LiteralValue::String(text) => {
+            Ok(string::parse_number(text, true).map_or(LiteralValue::Nil, LiteralValue::Number))
+        }
+        LiteralValue::True => Ok(LiteralValue::Number(1.0)),
+        LiteralValue::False => Ok(LiteralValue::Number(0.0)),
+        LiteralValue::Nil => Ok(LiteralValue::Nil),
+        other => Err(format!("Cannot convert {} to a number", other.type_name())),
+    }
+}
+
+// Strings are measured in characters, not bytes.
+fn len(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let length = match &arguments[0] {
+        LiteralValue::String(string) => string.chars().count(),
+        LiteralValue::List(items) => items.borrow().len(),
+        LiteralValue::Map(entries) => entries.borrow().len(),
+        other => return Err(format!("Cannot take the length of {}", other.type_name())),
+    };
+
+    Ok(LiteralValue::Number(length as f64))
+}
+
+// Strings are searched for a substring, lists for an item.
+fn contains(
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    match &arguments[0] {
+        LiteralValue::String(_) => string::contains(interpreter, arguments)
+            .map_err(|message| RuntimeError::new(paren, message)),
+        LiteralValue::List(_) => list::contains(interpreter, arguments, paren),
+        other => Err(RuntimeError::new(
+            paren,
+            format!(
+                "contains expects a string or a list, got {}",
+                other.type_name()
+            ),
+        )),
+    }
+}
+
+fn index_of(
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    match &arguments[0] {
+        LiteralValue::String(_) => string::index_of(interpreter, arguments)
+            .map_err(|message| RuntimeError::new(paren, message)),
+        LiteralValue::List(_) => list::index_of(interpreter, arguments, paren),
+        other => Err(RuntimeError::new(
+            paren,
+            format!(
+                "indexOf expects a string or a list, got {}",
+                other.type_name()
+            ),
+        )),
+    }
+}
+
+// Writes each value as print shows it, with nothing between them and no newline after. The output
+// is flushed so a prompt or partial line shows straight away.
+fn write(interpreter: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    arguments
+        .iter()
+        .try_for_each(|value| write!(interpreter.output, "{}", value))
+        .and_then(|_| interpreter.output.flush())
+        .map_err(|err| format!("Failed to write output: {}", err))?;
+    Ok(LiteralValue::Nil)
+}
+
+// Reads a line from the interpreter's input, writing the optional prompt first. Returns nil at
+// end of input.
+fn input(interpreter: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    match arguments.first() {
+        Some(LiteralValue::String(prompt)) => {
+            write!(interpreter.output, "{}", prompt)
+                .and_then(|_| interpreter.output.flush())
+                .map_err(|err| format!("Failed to write prompt: {}", err))?;
+        }
+        Some(other) => {
+            return Err(format!(
+                "input() prompt must be a string, got {}",
+                other.type_name()
+            ))
+        }
+        None => (),
+    }
+
+    let mut line = String::new();
+    let read = match &mut interpreter.input {
+        Some(input) => input.read_line(&mut line),
+        None => stdin().lock().read_line(&mut line),
+    }
+    .map_err(|err| format!("Failed to read input: {}", err))?;
+    if read == 0 {
+        return Ok(LiteralValue::Nil);
+    }
+
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    Ok(LiteralValue::String(line.into()))
+}
+
+// Helpers for the tests of the native modules, which mostly run a snippet and look at what it left
+// in x.
+#[cfg(test)]
+mod testing {
+    use crate::{compile, error::KadomError, interpreter::Interpreter};
+
+    // Runs the source, reporting a failure by its message.
+    pub(super) fn run(interpreter: &mut Interpreter, source: &str) -> Result<(), String> {
+        interpreter
+            .interpret(compile(source).unwrap())
+            .map_err(|err| match err {
+                KadomError::Runtime(err) => err.message,
+                other => other.to_string(),
+            })
+    }
+
+    // Runs the source and shows the value it left in x, or the message it failed with.
+    pub(super) fn evaluate_in(
+        interpreter: &mut Interpreter,
+        source: &str,
+    ) -> Result<String, String> {
+        run(interpreter, source)?;
+        let value = interpreter.environment.borrow().lookup("x").unwrap();
+        Ok(value.to_string())
+    }
+
+    pub(super) fn evaluate(source: &str) -> Result<String, String> {
+        evaluate_in(&mut Interpreter::new(), source)
+    }
+}
@@ -0,0 +1,168 @@
+use super::{define_calling, define_native, NativeResult};
+use crate::{
+    environment::Environment,
+    error::RuntimeError,
+    expr::{self, LiteralValue},
+};
+
+// Defines the assertion functions in the given (global) environment. A failed assertion is a
+// runtime error, like a failed assert statement, showing values as print would.
+pub fn define(globals: &mut Environment) {
+    define_native(globals, "assertTrue", 1..=2, |_, arguments| {
+        let condition = &arguments[0];
+        check(
+            "assertTrue",
+            condition.is_truthy(),
+            format!("expected a truthy value, got {}", condition),
+            arguments.get(1),
+        )
+    });
+    // Compares with ==, so values of different types are never equal and an instance uses its
+    // class's equals method if it has one.
+    define_calling(
+        globals,
+        "assertEqual",
+        2..=3,
+        |interpreter, arguments, paren| {
+            let (actual, expected) = (&arguments[0], &arguments[1]);
+            let passed = expr::equal(interpreter, actual, expected, paren)?;
+            check(
+                "assertEqual",
+                passed,
+                format!("expected {}, got {}", expected, actual),
+                arguments.get(2),
+            )
+            .map_err(|message| RuntimeError::new(paren, message))
+        },
+    );
+}
+
+fn check(
+    function: &str,
+    passed: bool,
+    failure: String,
+    message: Option<&LiteralValue>,
+) -> NativeResult {
+    if passed {
+        return Ok(LiteralValue::Nil);
+    }
+    Err(match message {
+        Some(message) => format!("{} failed: {} — {}", function, failure, message),
+        None => format!("{} failed: {}", function, failure),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{interpreter::Interpreter, natives::testing};
+    use std::{cell::RefCell, io::Write, rc::Rc};
+
+    // Runs the source and returns what it printed, or the message of the error that stopped it.
+    fn run(source: &str) -> Result<String, String> {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::with_output(Box::new(Output(output.clone())));
+        testing::run(&mut interpreter, source)?;
+        let printed = output.borrow().clone();
+        Ok(String::from_utf8(printed).unwrap())
+    }
+
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn passing_assertions_return_nil() {
+        assert_eq!(
+            run("print assertTrue(1); print assertTrue(\"\", \"why\"); print assertEqual(3, 1 + 2);
+                 assertEqual(\"ab\", \"a\" + \"b\"); assertEqual(nil, nil); assertEqual([1, [2]], [1, [2]]);
+                 assertEqual({\"a\": 1}, {\"a\": 1}, \"maps\");"),
+            Ok("nil\nnil\nnil\n".to_string())
+        );
+    }
+
+    #[test]
+    fn failures_show_the_values_and_message() {
+        for (source, message) in [
+            (
+                "assertTrue(nil);",
+                "assertTrue failed: expected a truthy value, got nil",
+            ),
+            (
+                "assertTrue(false, \"flag\");",
+                "assertTrue failed: expected a truthy value, got false — flag",
+            ),
+            (
+                "assertEqual(1 + 3, 3);",
+                "assertEqual failed: expected 3, got 4",
+            ),
+            (
+                "assertEqual(1 + 3, 3, \"sum\");",
+                "assertEqual failed: expected 3, got 4 — sum",
+            ),
+            (
+                "assertEqual([1, \"a\"], [1, \"b\"], 7);",
+                "assertEqual failed: expected [1, b], got [1, a] — 7",
+            ),
+            // Like ==, nothing converts between types.
+            (
+                "assertEqual(\"1\", 1);",
+                "assertEqual failed: expected 1, got 1",
+            ),
+            (
+                "assertEqual(false, nil);",
+                "assertEqual failed: expected nil, got false",
+            ),
+            (
+                "assertEqual(0, false);",
+                "assertEqual failed: expected false, got 0",
+            ),
+            (
+                "assertEqual(NAN, NAN);",
+                "assertEqual failed: expected NaN, got NaN",
+            ),
+        ] {
+            assert_eq!(run(source), Err(message.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn a_failure_stops_the_program() {
+        assert_eq!(
+            run("print 1; assertEqual(1, 2); print 2;"),
+            Err("assertEqual failed: expected 2, got 1".to_string())
+        );
+        assert_eq!(
+            run("try { assertTrue(false); print 1; } catch (e) { print e; } print 2;"),
+            Ok("assertTrue failed: expected a truthy value, got false\n2\n".to_string())
+        );
+    }
+
+    #[test]
+    fn instances_compare_with_their_equals_method() {
+        let classes = "class P { init(x) { this.x = x; } equals(o) { return this.x == o.x; } }
+                       class Q {}";
+        assert_eq!(
+            run(&format!(
+                "{} print P(1) == P(1); assertEqual(P(1), P(1)); var q = Q(); assertEqual(q, q);",
+                classes
+            )),
+            Ok("true\n".to_string())
+        );
+        assert_eq!(
+            run(&format!("{} assertEqual(P(1), P(2));", classes)),
+            Err("assertEqual failed: expected <P instance>, got <P instance>".to_string())
+        );
+        assert_eq!(
+            run(&format!("{} assertEqual(Q(), Q());", classes)),
+            Err("assertEqual failed: expected <Q instance>, got <Q instance>".to_string())
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use super::{define_calling, define_native, string::string, NativeResult};
+use crate::{
+    environment::Environment, error::RuntimeError, expr::LiteralValue, interpreter::Interpreter,
+    lexer::Token,
+};
+use std::env;
+
+// Defines the functions an embedder running untrusted code may want to withhold in the given
+// (global) environment: those that reach outside the interpreter, and eval. Each fails when the
+// interpreter's host access is turned off.
+pub fn define(globals: &mut Environment) {
+    define_native(globals, "getenv", 1..=1, getenv);
+    define_native(globals, "setenv", 2..=2, setenv);
+    define_calling(globals, "eval", 1..=1, eval);
+}
+
+fn check_access(function: &str, interpreter: &Interpreter) -> Result<(), String> {
+    match interpreter.host_access {
+        true => Ok(()),
+        false => Err(format!(
+            "{} is not allowed: host access is turned off",
+            function
+        )),
+    }
+}
+
+// Runs kadom source where it is called and returns the value of its last expression statement.
+// Inside a function or block it has to be called by the name eval, since only those calls are
+// given the locals; called through another name there, it's an error.
+fn eval(
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let source = check_access("eval", interpreter)
+        .and_then(|_| string("eval", &arguments[0]))
+        .map_err(|message| RuntimeError::new(paren, message))?;
+    interpreter.eval(source, paren)
+}
+
+// The value of an environment variable, or nil when it isn't set. Bytes that aren't valid UTF-8
+// are replaced.
+fn getenv(interpreter: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    check_access("getenv", interpreter)?;
+    let name = string("getenv", &arguments[0])?;
+    Ok(env::var_os(name).map_or(LiteralValue::Nil, |value| {
+        LiteralValue::String(value.to_string_lossy().into())
+    }))
+}
+
+// Sets an environment variable for this process and any it starts.
+fn setenv(interpreter: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    check_access("setenv", interpreter)?;
+    let name = string("setenv", &arguments[0])?;
+    let value = string("setenv", &arguments[1])?;
+    // The names and values the operating system can't hold, which set_var would panic on.
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(format!(
+            "setenv name must be non-empty and without '=' or NUL, got \"{}\"",
+            name.escape_debug()
+        ));
+    }
+    if value.contains('\0') {
+        return Err("setenv value must not contain NUL".to_string());
+    }
+    env::set_var(name, value);
+    Ok(LiteralValue::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{interpreter::Interpreter, natives::testing::evaluate_in};
+
+    // Names no other test or process uses, so nothing depends on the machine's environment.
+    fn unique_name(purpose: &str) -> String {
+        format!("KADOM_TEST_{}_{}", purpose, std::process::id())
+    }
+
+    #[test]
+    fn sets_and_reads_variables() {
+        let mut interpreter = Interpreter::new();
+        let name = unique_name("SET");
+        let source = format!(
+            "var before = getenv(\"{0}\"); var result = setenv(\"{0}\", \"on\");
+             var x = [before, result, getenv(\"{0}\"), typeof(getenv(\"{0}\"))];",
+            name
+        );
+        assert_eq!(
+            evaluate_in(&mut interpreter, &source),
+            Ok("[nil, nil, on, string]".to_string())
+        );
+        assert_eq!(std::env::var(&name).as_deref(), Ok("on"));
+
+        let unset = format!("var x = getenv(\"{}\");", unique_name("UNSET"));
+        assert_eq!(evaluate_in(&mut interpreter, &unset), Ok("nil".to_string()));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        let mut interpreter = Interpreter::new();
+        for (source, message) in [
+            ("var x = getenv(1);", "getenv expects a string, got number"),
+            (
+                "var x = setenv(\"A\", nil);",
+                "setenv expects a string, got nil",
+            ),
+            (
+                "var x = setenv(\"\", \"v\");",
+                "setenv name must be non-empty and without '=' or NUL, got \"\"",
+            ),
+            (
+                "var x = setenv(\"A=B\", \"v\");",
+                "setenv name must be non-empty and without '=' or NUL, got \"A=B\"",
+            ),
+        ] {
+            assert_eq!(
+                evaluate_in(&mut interpreter, source),
+                Err(message.to_string()),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn host_access_can_be_turned_off() {
+        let mut interpreter = Interpreter::new();
+        interpreter.host_access = false;
+        let name = unique_name("DENIED");
+        for (source, message) in [
+            (
+                format!("var x = getenv(\"{}\");", name),
+                "getenv is not allowed: host access is turned off",
+            ),
+            (
+                format!("var x = setenv(\"{}\", \"v\");", name),
+                "setenv is not allowed: host access is turned off",
+            ),
+        ] {
+            assert_eq!(
+                evaluate_in(&mut interpreter, &source),
+                Err(message.to_string()),
+                "{}",
+                source
+            );
+        }
+        assert!(std::env::var_os(&name).is_none());
+    }
+}
@@ -0,0 +1,608 @@
+use super::{define_native, NativeResult};
+use crate::{
+    environment::Environment,
+    expr::{self, LiteralValue},
+    interpreter::Interpreter,
+};
+use std::{cell::RefCell, rc::Rc};
+
+// Defines the JSON functions in the given (global) environment.
+pub fn define(globals: &mut Environment) {
+    define_native(
+        globals,
+        "jsonParse",
+        1..=1,
+        |_, arguments| match &arguments[0] {
+            LiteralValue::String(text) => parse(text),
+            other => Err(format!(
+                "jsonParse expects a string, got {}",
+                other.type_name()
+            )),
+        },
+    );
+    define_native(globals, "jsonStringify", 1..=2, stringify_native);
+}
+
+// Arrays and objects nested deeper than this are refused rather than risking the host stack.
+const MAX_DEPTH: usize = 512;
+
+// Reads a JSON document: objects become maps, arrays lists, null nil. An object that repeats a
+// key keeps the last value, as a map literal does.
+fn parse(text: &str) -> NativeResult {
+    let mut parser = Parser {
+        text,
+        position: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.position == text.len() {
+        true => Ok(value),
+        false => Err(parser.error("expected the end of the document")),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    // Byte offset of the next character.
+    position: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    // The message, with the line and column of the next character.
+    fn error(&self, message: &str) -> String {
+        let before = &self.text[..self.position];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        let column = before[line_start..].chars().count() + 1;
+        format!(
+            "invalid JSON at line {}, column {}: {}",
+            line, column, message
+        )
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    // Consumes the character if it's next.
+    fn eat(&mut self, expected: char) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.position += expected.len_utf8();
+        }
+        found
+    }
+
+    fn value(&mut self) -> NativeResult {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.nested(Self::object),
+            Some('[') => self.nested(Self::array),
+            Some('"') => Ok(LiteralValue::String(self.string()?.into())),
+            Some('-' | '0'..='9') => self.number(),
+            Some('t') => self.word("true", LiteralValue::True),
+            Some('f') => self.word("false", LiteralValue::False),
+            Some('n') => self.word("null", LiteralValue::Nil),
+            Some(other) => Err(self.error(&format!("unexpected character '{}'", other))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> NativeResult) -> NativeResult {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(&format!("nested deeper than {} levels", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> NativeResult {
+        self.eat('{');
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if !self.eat('}') {
+            loop {
+                self.skip_whitespace();
+                if self.peek() != Some('"') {
+                    return Err(self.error("expected a string key"));
+                }
+                let key = LiteralValue::String(self.string()?.into());
+                self.skip_whitespace();
+                if !self.eat(':') {
+                    return Err(self.error("expected ':' after an object key"));
+                }
+                entries.push((key, self.value()?));
+                self.skip_whitespace();
+                if self.eat('}') {
+                    break;
+                }
+                if !self.eat(',') {
+                    return Err(self.error("expected ',' or '}' in an object"));
+                }
+            }
+        }
+        Ok(expr::map(entries))
+    }
+
+    fn array(&mut self) -> NativeResult {
+        self.eat('[');
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if !self.eat(']') {
+            loop {
+                items.push(self.value()?);
+                self.skip_whitespace();
+                if self.eat(']') {
+                    break;
+                }
+                if !self.eat(',') {
+                    return Err(self.error("expected ',' or ']' in an array"));
+                }
+            }
+        }
+        Ok(LiteralValue::List(Rc::new(RefCell::new(items))))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.eat('"');
+        let mut string = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some('"') => {
+                    self.position += 1;
+                    return Ok(string);
+                }
+                Some('\\') => {
+                    self.position += 1;
+                    string.push(self.escape()?);
+                }
+                Some(c) if c < ' ' => {
+                    return Err(self.error("control characters must be escaped in strings"))
+                }
+                Some(c) => {
+                    self.position += c.len_utf8();
+                    string.push(c);
+                }
+            }
+        }
+    }
+
+    // The character an escape after a backslash stands for.
+    fn escape(&mut self) -> Result<char, String> {
+        let escaped = match self.peek() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                self.position += 1;
+                return self.unicode_escape();
+            }
+            _ => return Err(self.error("invalid escape in string")),
+        };
+        self.position += 1;
+        Ok(escaped)
+    }
+
+    // A \u escape, past the u. Characters outside the Basic Multilingual Plane take two, as a
+    // UTF-16 surrogate pair.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex_code()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("unpaired surrogate in string"));
+        }
+        if !(self.eat('\\') && self.eat('u')) {
+            return Err(self.error("unpaired surrogate in string"));
+        }
+        let low = self.hex_code()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(self.error("unpaired surrogate in string"));
+        }
+        let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        char::from_u32(code).ok_or_else(|| self.error("invalid \\u escape"))
+    }
+
+    fn hex_code(&mut self) -> Result<u32, String> {
+        let digits = self
+            .text
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("expected four hex digits after \\u"))?;
+        self.position += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap_or_default())
+    }
+
+    fn digits(&mut self) -> usize {
+        let count = self.text[self.position..]
+            .bytes()
+            .take_while(u8::is_ascii_digit)
+            .count();
+        self.position += count;
+        count
+    }
+
+    fn number(&mut self) -> NativeResult {
+        let start = self.position;
+        self.eat('-');
+        // No leading zeros, so a 0 is the whole integer part.
+        if !self.eat('0') && self.digits() == 0 {
+            return Err(self.error("expected a digit"));
+        }
+        if self.eat('.') && self.digits() == 0 {
+            return Err(self.error("expected a digit after the decimal point"));
+        }
+        if self.eat('e') || self.eat('E') {
+            let _ = self.eat('+') || self.eat('-');
+            if self.digits() == 0 {
+                return Err(self.error("expected a digit in the exponent"));
+            }
+        }
+        match self.text[start..self.position].parse::<f64>() {
+            Ok(x) if x.is_finite() => Ok(LiteralValue::Number(x)),
+            _ => {
+                self.position = start;
+                Err(self.error("number is too large"))
+            }
+        }
+    }
+
+    fn word(&mut self, word: &str, value: LiteralValue) -> NativeResult {
+        match self.text[self.position..].starts_with(word) {
+            true => {
+                self.position += word.len();
+                Ok(value)
+            }
+            false => Err(self.error(&format!(
+                "unexpected character '{}'",
+                self.peek().unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+fn stringify_native(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let pretty = match arguments.get(1) {
+        None | Some(LiteralValue::False) => false,
+        Some(LiteralValue::True) => true,
+        Some(other) => {
+            return Err(format!(
+                "jsonStringify expects a boolean for pretty, got {}",
+                other.type_name()
+            ))
+        }
+    };
+    Ok(LiteralValue::String(
+        stringify(&arguments[0], pretty)?.into(),
+    ))
+}
+
+// Writes a value as compact JSON, or indented by two spaces a level when pretty. Only nil,
+// booleans, finite numbers, strings, lists and maps with string keys have a JSON form.
+fn stringify(value: &LiteralValue, pretty: bool) -> Result<String, String> {
+    let mut writer = Writer {
+        json: String::new(),
+        pretty,
+        open: Vec::new(),
+    };
+    writer.value(value)?;
+    Ok(writer.json)
+}
+
+struct Writer {
+    json: String,
+    pretty: bool,
+    // The lists and maps being written, outermost first. Meeting one again means a cycle.
+    open: Vec<*const ()>,
+}
+
+impl Writer {
+    fn value(&mut self, value: &LiteralValue) -> Result<(), String> {
+        match value {
+            LiteralValue::Nil => self.json.push_str("null"),
+            LiteralValue::True => self.json.push_str("true"),
+            LiteralValue::False => self.json.push_str("false"),
+            LiteralValue::Number(x) if x.is_finite() => self.json.push_str(&value.to_string()),
+            LiteralValue::Number(_) => {
+                return Err(format!("jsonStringify cannot represent {} in JSON", value))
+            }
+            LiteralValue::String(string) => self.string(string),
+            LiteralValue::List(items) => {
+                self.enter(Rc::as_ptr(items) as *const ())?;
+                let items = items.borrow();
+                self.json.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    self.separate(index);
+                    self.value(item)?;
+                }
+                self.leave(items.is_empty());
+                self.json.push(']');
+            }
+            LiteralValue::Map(entries) => {
+                self.enter(Rc::as_ptr(entries) as *const ())?;
+                let entries = entries.borrow();
+                self.json.push('{');
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    let LiteralValue::String(key) = key else {
+                        return Err(format!(
+                            "jsonStringify needs string keys, got {}",
+                            key.type_name()
+                        ));
+                    };
+                    self.separate(index);
+                    self.string(key);
+                    self.json.push_str(if self.pretty { ": " } else { ":" });
+                    self.value(value)?;
+                }
+                self.leave(entries.is_empty());
+                self.json.push('}');
+            }
+            other => {
+                return Err(format!(
+                    "jsonStringify cannot convert {} to JSON",
+                    other.type_name()
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, container: *const ()) -> Result<(), String> {
+        if self.open.contains(&container) {
+            return Err(
+                "jsonStringify cannot convert a list or map that contains itself".to_string(),
+            );
+        }
+        self.open.push(container);
+        Ok(())
+    }
+
+    // Closes the innermost list or map, putting its closing bracket on a line of its own when
+    // pretty and it had anything in it.
+    fn leave(&mut self, empty: bool) {
+        self.open.pop();
+        if !empty {
+            self.newline();
+        }
+    }
+
+    // Starts the item at `index` of the innermost list or map.
+    fn separate(&mut self, index: usize) {
+        if index > 0 {
+            self.json.push(',');
+        }
+        self.newline();
+    }
+
+    fn newline(&mut self) {
+        if self.pretty {
+            self.json.push('\n');
+            self.json.push_str(&"  ".repeat(self.open.len()));
+        }
+    }
+
+    fn string(&mut self, string: &str) {
+        self.json.push('"');
+        for c in string.chars() {
+            match c {
+                '"' => self.json.push_str("\\\""),
+                '\\' => self.json.push_str("\\\\"),
+                '\n' => self.json.push_str("\\n"),
+                '\r' => self.json.push_str("\\r"),
+                '\t' => self.json.push_str("\\t"),
+                '\u{8}' => self.json.push_str("\\b"),
+                '\u{c}' => self.json.push_str("\\f"),
+                c if c < ' ' => self.json.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.json.push(c),
+            }
+        }
+        self.json.push('"');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::natives::testing::evaluate_in;
+
+    // Runs the source with the global `text` set, and shows the value it left in x.
+    fn evaluate(text: &str, source: &str) -> Result<String, String> {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .environment
+            .borrow_mut()
+            .define("text".to_string(), LiteralValue::String(text.into()));
+        evaluate_in(&mut interpreter, source)
+    }
+
+    fn round_trip(text: &str) -> String {
+        stringify(&parse(text).unwrap(), false).unwrap()
+    }
+
+    #[test]
+    fn parses_into_kadom_values() {
+        assert_eq!(
+            evaluate(
+                r#" {"name": "kadom", "tags": ["a", "b"], "size": -2.5e2, "ok": true, "none": null} "#,
+                "var v = jsonParse(text); var x = [v, typeof(v), keys(v)];"
+            ),
+            Ok("[{name: kadom, tags: [a, b], size: -250, ok: true, none: nil}, map, [name, tags, size, ok, none]]".to_string())
+        );
+        assert_eq!(
+            evaluate("[[], {}, [[0]], false]", "var x = jsonParse(text);"),
+            Ok("[[], {}, [[0]], false]".to_string())
+        );
+        assert_eq!(
+            evaluate(r#"{"a": 1, "a": 2}"#, "var x = jsonParse(text);"),
+            Ok("{a: 2}".to_string())
+        );
+    }
+
+    #[test]
+    fn round_trips_documents() {
+        for text in [
+            r#"{"a":[1,2.5,-3,true,false,null],"b":{"c":"d","e":{}},"f":[]}"#,
+            r#"[[[1]],[{"x":[{}]}]]"#,
+            r#""plain""#,
+            "0",
+            "null",
+            r#"{"":""}"#,
+        ] {
+            assert_eq!(round_trip(text), text);
+        }
+        assert_eq!(round_trip(" [ 1 ,\n 2 ] "), "[1,2]");
+        assert_eq!(round_trip("1E3"), "1000");
+    }
+
+    #[test]
+    fn handles_escapes() {
+        let parsed = parse(r#""q\" b\\ s\/ n\n t\t ué 😀""#).unwrap();
+        assert_eq!(parsed.to_string(), "q\" b\\ s/ n\n t\t ué 😀");
+        assert_eq!(
+            stringify(&parsed, false).unwrap(),
+            r#""q\" b\\ s/ n\n t\t ué 😀""#
+        );
+        assert_eq!(
+            stringify(&LiteralValue::String("\u{1}\r\u{8}".into()), false).unwrap(),
+            r#""\u0001\r\b""#
+        );
+    }
+
+    #[test]
+    fn pretty_prints_with_two_spaces() {
+        assert_eq!(
+            evaluate(
+                r#"{"a":[1,{"b":null}],"c":[],"d":{}}"#,
+                "var x = jsonStringify(jsonParse(text), true);"
+            ),
+            Ok("{\n  \"a\": [\n    1,\n    {\n      \"b\": null\n    }\n  ],\n  \"c\": [],\n  \"d\": {}\n}".to_string())
+        );
+        assert_eq!(
+            evaluate("", "var x = jsonStringify([1, \"two\"], false);"),
+            Ok("[1,\"two\"]".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_where_malformed_input_goes_wrong() {
+        for (text, message) in [
+            ("", "line 1, column 1: unexpected end of input"),
+            (
+                r#"{"a" 1}"#,
+                "line 1, column 6: expected ':' after an object key",
+            ),
+            ("[1,]", "line 1, column 4: unexpected character ']'"),
+            ("[1 2]", "line 1, column 4: expected ',' or ']' in an array"),
+            ("{1: 2}", "line 1, column 2: expected a string key"),
+            (
+                "[1] x",
+                "line 1, column 5: expected the end of the document",
+            ),
+            ("01", "line 1, column 2: expected the end of the document"),
+            ("-", "line 1, column 2: expected a digit"),
+            (
+                "1.",
+                "line 1, column 3: expected a digit after the decimal point",
+            ),
+            ("1e+", "line 1, column 4: expected a digit in the exponent"),
+            ("1e999", "line 1, column 1: number is too large"),
+            (r#""abc"#, "line 1, column 5: unterminated string"),
+            (r#""\x""#, "line 1, column 3: invalid escape in string"),
+            (
+                r#""\u12""#,
+                "line 1, column 4: expected four hex digits after \\u",
+            ),
+            (
+                r#""\udc00""#,
+                "line 1, column 8: unpaired surrogate in string",
+            ),
+            (
+                "\"a\tb\"",
+                "line 1, column 3: control characters must be escaped in strings",
+            ),
+            (
+                "{\n  \"a\": tru\n}",
+                "line 2, column 8: unexpected character 't'",
+            ),
+            ("[\"é\", nul]", "line 1, column 7: unexpected character 'n'"),
+        ] {
+            assert_eq!(
+                parse(text),
+                Err(format!("invalid JSON at {}", message)),
+                "{}",
+                text
+            );
+        }
+        let deep = "[".repeat(100_000);
+        assert_eq!(
+            parse(&deep),
+            Err("invalid JSON at line 1, column 513: nested deeper than 512 levels".to_string())
+        );
+        assert_eq!(
+            evaluate("", "var x = jsonParse(1);"),
+            Err("jsonParse expects a string, got number".to_string())
+        );
+    }
+
+    #[test]
+    fn refuses_values_without_a_json_form() {
+        for (source, message) in [
+            (
+                "var x = jsonStringify([clock]);",
+                "jsonStringify cannot convert function to JSON",
+            ),
+            (
+                "class A {} var x = jsonStringify({\"a\": A});",
+                "jsonStringify cannot convert class to JSON",
+            ),
+            (
+                "var x = jsonStringify({1: 2});",
+                "jsonStringify needs string keys, got number",
+            ),
+            (
+                "var x = jsonStringify(INFINITY);",
+                "jsonStringify cannot represent inf in JSON",
+            ),
+            (
+                "var x = jsonStringify(nil, 1);",
+                "jsonStringify expects a boolean for pretty, got number",
+            ),
+        ] {
+            assert_eq!(evaluate("", source), Err(message.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn refuses_cycles_but_not_shared_values() {
+        let shared = LiteralValue::List(Rc::new(RefCell::new(vec![LiteralValue::Number(1.0)])));
+        let twice = LiteralValue::List(Rc::new(RefCell::new(vec![shared.clone(), shared])));
+        assert_eq!(stringify(&twice, false), Ok("[[1],[1]]".to_string()));
+
+        let items = Rc::new(RefCell::new(Vec::new()));
+        let cyclic = expr::map(vec![(
+            LiteralValue::String("self".into()),
+            LiteralValue::List(items.clone()),
+        )]);
+        items.borrow_mut().push(cyclic.clone());
+        assert_eq!(
+            stringify(&cyclic, false),
+            Err("jsonStringify cannot convert a list or map that contains itself".to_string())
+        );
+        // Break the cycle so the test doesn't leak it.
+        items.borrow_mut().clear();
+    }
+}
@@ -0,0 +1,604 @@
+use super::{define_calling, define_native, slice_range, NativeResult};
+use crate::{
+    environment::Environment,
+    error::RuntimeError,
+    expr::{self, LiteralValue},
+    interpreter::Interpreter,
+    lexer::Token,
+};
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+// Defines the list functions in the given (global) environment.
+pub fn define(globals: &mut Environment) {
+    define_calling(globals, "sort", 1..=2, sort);
+    define_native(globals, "slice", 2..=3, slice);
+    define_native(globals, "concat", 2..=2, concat);
+    define_native(globals, "reverse", 1..=1, reverse);
+    define_calling(globals, "map", 2..=2, map);
+    define_calling(globals, "filter", 2..=2, filter);
+    define_calling(globals, "reduce", 2..=3, reduce);
+}
+
+fn list(function: &str, value: &LiteralValue) -> Result<Rc<RefCell<Vec<LiteralValue>>>, String> {
+    match value {
+        LiteralValue::List(list) => Ok(list.clone()),
+        other => Err(format!(
+            "{} expects a list, got {}",
+            function,
+            other.type_name()
+        )),
+    }
+}
+
+// The items of a list and the function to call with each. The items are a copy, so the function
+// can change the list without affecting which items it's called with.
+fn list_and_callback<'a>(
+    function: &str,
+    arguments: &'a [LiteralValue],
+    paren: &Token,
+) -> Result<(Vec<LiteralValue>, &'a LiteralValue), RuntimeError> {
+    let error = |message: String| RuntimeError::new(paren, message);
+    let items = list(function, &arguments[0])
+        .map_err(error)?
+        .borrow()
+        .clone();
+    let callback = &arguments[1];
+    if callback.as_callable().is_none() {
+        return Err(error(format!(
+            "{} expects a function, got {}",
+            function,
+            callback.type_name()
+        )));
+    }
+    Ok((items, callback))
+}
+
+fn new_list(items: Vec<LiteralValue>) -> LiteralValue {
+    LiteralValue::List(Rc::new(RefCell::new(items)))
+}
+
+// Whether any item is equal to the value, as == would say. That compares lists item by item, so
+// a nested list is found by its contents, NaN is never found, and an instance item uses its
+// class's equals method if it has one.
+pub(super) fn contains(
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let index = position("contains", interpreter, &arguments, paren)?;
+    Ok(LiteralValue::from_bool(index.is_some()))
+}
+
+// The index of the first item equal to the value, or -1 if there is none.
+pub(super) fn index_of(
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let index = position("indexOf", interpreter, &arguments, paren)?;
+    Ok(LiteralValue::Number(
+        index.map_or(-1.0, |index| index as f64),
+    ))
+}
+
+// An equals method can change the list, so it's searched through a copy.
+fn position(
+    function: &str,
+    interpreter: &mut Interpreter,
+    arguments: &[LiteralValue],
+    paren: &Token,
+) -> Result<Option<usize>, RuntimeError> {
+    let items = list(function, &arguments[0])
+        .map_err(|message| RuntimeError::new(paren, message))?
+        .borrow()
+        .clone();
+    for (index, item) in items.iter().enumerate() {
+        if expr::equal(interpreter, item, &arguments[1], paren)? {
+            return Ok(Some(index));
+        }
+    }
+    Ok(None)
+}
+
+// A new list of the items from start up to but not including end, which defaults to the end of
+// the list. Like substring, negative indices are errors rather than counting from the end.
+fn slice(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let list = list("slice", &arguments[0])?;
+    let items = list.borrow();
+    let range = slice_range(
+        "slice",
+        "list",
+        items.len(),
+        &arguments[1],
+        arguments.get(2),
+    )?;
+    Ok(new_list(items[range].to_vec()))
+}
+
+// A new list of the items of both, leaving them as they were.
+fn concat(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let first = list("concat", &arguments[0])?;
+    let second = list("concat", &arguments[1])?;
+    let mut items = first.borrow().clone();
+    items.extend(second.borrow().iter().cloned());
+    Ok(new_list(items))
+}
+
+// Reverses a list in place and returns it, as sort does.
+fn reverse(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    list("reverse", &arguments[0])?.borrow_mut().reverse();
+    Ok(arguments[0].clone())
+}
+
+// A new list of the function's result for each item.
+fn map(
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let (items, callback) = list_and_callback("map", &arguments, paren)?;
+    let mapped = items
+        .into_iter()
+        .map(|item| expr::call_value(interpreter, callback.clone(), vec![item], paren))
+        .collect::<Result<_, _>>()?;
+    Ok(new_list(mapped))
+}
+
+// A new list of the items the function returns a truthy value for.
+fn filter(
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let (items, callback) = list_and_callback("filter", &arguments, paren)?;
+    let mut kept = Vec::new();
+    for item in items {
+        if expr::call_value(interpreter, callback.clone(), vec![item.clone()], paren)?.is_truthy() {
+            kept.push(item);
+        }
+    }
+    Ok(new_list(kept))
+}
+
+// Folds the items from the left: the function is called with the result so far and the next
+// item. Without an initial value the first item starts it, so the list can't be empty.
+fn reduce(
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let (items, callback) = list_and_callback("reduce", &arguments, paren)?;
+    let mut items = items.into_iter();
+    let Some(mut result) = arguments.get(2).cloned().or_else(|| items.next()) else {
+        return Err(RuntimeError::new(
+            paren,
+            "reduce of an empty list with no initial value".to_string(),
+        ));
+    };
+    for item in items {
+        result = expr::call_value(interpreter, callback.clone(), vec![result, item], paren)?;
+    }
+    Ok(result)
+}
+
+// Sorts a list in place and returns it. Without a comparator the list must be all numbers,
+// sorted ascending, or all strings, sorted by character. A comparator is called with two items
+// and returns a number: negative if the first goes first, positive if the second does, zero to
+// keep their order. Either way the sort is stable.
+fn sort(
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let error = |message: String| RuntimeError::new(paren, message);
+    let list = list("sort", &arguments[0]).map_err(error)?;
+    // The comparator may change the list, so it's sorted as a copy and put back at the end.
+    let items = list.borrow().clone();
+
+    let sorted = match arguments.get(1) {
+        None => {
+            check_comparable(&items).map_err(error)?;
+            merge_sort(items, &mut |a, b| Ok(compare(a, b)))?
+        }
+        Some(comparator) => {
+            if comparator.as_callable().is_none() {
+                return Err(error(format!(
+                    "sort comparator must be a function, got {}",
+                    comparator.type_name()
+                )));
+            }
+            merge_sort(items, &mut |a, b| {
+                let arguments = vec![a.clone(), b.clone()];
+                match expr::call_value(interpreter, comparator.clone(), arguments, paren)? {
+                    LiteralValue::Number(x) => Ok(x.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+                    other => Err(error(format!(
+                        "sort comparator must return a number, got {}",
+                        other.type_name()
+                    ))),
+                }
+            })?
+        }
+    };
+
+    *list.borrow_mut() = sorted;
+    Ok(arguments[0].clone())
+}
+
+// Numbers and strings can each be sorted on their own, but not together.
+fn check_comparable(items: &[LiteralValue]) -> Result<(), String> {
+    let Some(first) = items.first() else {
+        return Ok(());
+    };
+    for (index, item) in items.iter().enumerate() {
+        let comparable = match first {
+            LiteralValue::Number(_) | LiteralValue::String(_) => {
+                std::mem::discriminant(first) == std::mem::discriminant(item)
+            }
+            _ => false,
+        };
+        if !comparable {
+            return Err(format!(
+                "sort can only order all numbers or all strings without a comparator, got {} at index {} after {}",
+                item.type_name(),
+                index,
+                first.type_name()
+            ));
+        }
+    }
+    Ok(())
+}
+
+// NaN sorts after every other number.
+fn compare(a: &LiteralValue, b: &LiteralValue) -> Ordering {
+    match (a, b) {
+        (LiteralValue::Number(x), LiteralValue::Number(y)) => x
+            .partial_cmp(y)
+            .unwrap_or_else(|| x.is_nan().cmp(&y.is_nan())),
+        (LiteralValue::String(x), LiteralValue::String(y)) => x.cmp(y),
+        _ => Ordering::Equal,
+    }
+}
+
+// A stable merge sort that stops at the first error from `ordering`. Unlike the standard
+// library's sorts it never panics, however inconsistent the ordering is.
+fn merge_sort(
+    mut items: Vec<LiteralValue>,
+    ordering: &mut dyn FnMut(&LiteralValue, &LiteralValue) -> Result<Ordering, RuntimeError>,
+) -> Result<Vec<LiteralValue>, RuntimeError> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, ordering)?.into_iter().peekable();
+    let mut right = merge_sort(right, ordering)?.into_iter().peekable();
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Ties take from the left, which keeps equal items in their original order.
+        let next = match ordering(b, a)? {
+            Ordering::Less => right.next(),
+            _ => left.next(),
+        };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, error::KadomError, interpreter::Interpreter, natives::testing::evaluate};
+
+    #[test]
+    fn sorts_numbers_and_strings_in_place() {
+        for (source, expected) in [
+            (
+                "var x = [3, -1, 2.5, 0, 10]; sort(x);",
+                "[-1, 0, 2.5, 3, 10]",
+            ),
+            ("var x = sort([NAN, 2, -INFINITY, 1]);", "[-inf, 1, 2, NaN]"),
+            (
+                "var x = sort([\"pear\", \"Apple\", \"apple\", \"\"]);",
+                "[, Apple, apple, pear]",
+            ),
+            ("var x = sort([]);", "[]"),
+            ("var xs = [2, 1]; var x = sort(xs) == xs;", "true"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn sorts_with_a_comparator() {
+        assert_eq!(
+            evaluate("fun descending(a, b) { return b - a; } var x = sort([1, 3, 2], descending);"),
+            Ok("[3, 2, 1]".to_string())
+        );
+        // Sorting by the leading digit alone, equal keys keep their order in both directions.
+        let keyed = "var items = [\"1a\", \"0b\", \"1c\", \"0d\", \"1e\"];
+                     fun key(item) { return num(substring(item, 0, 1)); }
+                     fun up(a, b) { return key(a) - key(b); }
+                     fun down(a, b) { return key(b) - key(a); }";
+        assert_eq!(
+            evaluate(&format!("{} var x = sort(items, up);", keyed)),
+            Ok("[0b, 0d, 1a, 1c, 1e]".to_string())
+        );
+        assert_eq!(
+            evaluate(&format!("{} var x = sort(items, down);", keyed)),
+            Ok("[1a, 1c, 1e, 0b, 0d]".to_string())
+        );
+        // An inconsistent comparator gives some order rather than failing.
+        assert!(evaluate(
+            "fun whatever(a, b) { return 1; } var x = sort([5, 4, 3, 2, 1, 0], whatever);"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn slices_into_a_new_list() {
+        for (source, expected) in [
+            ("var x = slice([1, 2, 3, 4], 1, 3);", "[2, 3]"),
+            ("var x = slice([1, 2, 3, 4], 0);", "[1, 2, 3, 4]"),
+            ("var x = slice([1, 2, 3, 4], 2);", "[3, 4]"),
+            ("var x = slice([1, 2, 3], 3);", "[]"),
+            ("var x = slice([1, 2, 3], 1, 1);", "[]"),
+            ("var x = slice([], 0, 0);", "[]"),
+            (
+                "var xs = [1, 2]; var ys = slice(xs, 0); reverse(ys); var x = [xs, ys];",
+                "[[1, 2], [2, 1]]",
+            ),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn concat_leaves_its_inputs_alone() {
+        for (source, expected) in [
+            ("var x = concat([1, 2], [3]);", "[1, 2, 3]"),
+            ("var x = concat([], [1]);", "[1]"),
+            ("var x = concat([1], []);", "[1]"),
+            ("var x = concat([], []);", "[]"),
+            (
+                "var a = [1]; var b = [2]; var c = concat(a, b); reverse(c); var x = [a, b, c];",
+                "[[1], [2], [2, 1]]",
+            ),
+            ("var a = [1]; var x = concat(a, a) == a;", "false"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn reverses_in_place() {
+        for (source, expected) in [
+            ("var x = reverse([1, 2, 3]);", "[3, 2, 1]"),
+            ("var x = reverse([1, 2, 3, 4]);", "[4, 3, 2, 1]"),
+            ("var x = reverse([]);", "[]"),
+            ("var x = [1, 2]; var y = x; reverse(y);", "[2, 1]"),
+            ("var xs = [1]; var x = reverse(xs) == xs;", "true"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_slices() {
+        for (source, message) in [
+            (
+                "var x = slice([1, 2, 3], 1, 4);",
+                "slice index 4 is out of range for a list of length 3",
+            ),
+            (
+                "var x = slice([1, 2, 3], -1);",
+                "slice index -1 is out of range for a list of length 3",
+            ),
+            (
+                "var x = slice([1, 2, 3], 2, 1);",
+                "slice start 2 is after end 1 in a list of length 3",
+            ),
+            (
+                "var x = slice([1], 0.5);",
+                "slice expects an integer, got 0.5",
+            ),
+            (
+                "var x = slice(\"abc\", 0);",
+                "slice expects a list, got string",
+            ),
+            (
+                "var x = concat([1], 2);",
+                "concat expects a list, got number",
+            ),
+            ("var x = reverse(nil);", "reverse expects a list, got nil"),
+        ] {
+            assert_eq!(evaluate(source), Err(message.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn maps_filters_and_reduces() {
+        let functions = "fun double(n) { return n * 2; }
+                         fun even(n) { return n % 2 == 0; }
+                         fun add(total, n) { return total + n; }";
+        for (source, expected) in [
+            ("var x = map([1, 2, 3], double);", "[2, 4, 6]"),
+            ("var x = map([], double);", "[]"),
+            ("var x = map([1, true, nil], str);", "[1, true, nil]"),
+            ("var x = filter([1, 2, 3, 4, 5, 6], even);", "[2, 4, 6]"),
+            (
+                "var x = filter([0, \"\", nil, false, 1], typeof);",
+                "[0, , nil, false, 1]",
+            ),
+            (
+                "fun id(v) { return v; } var x = filter([0, \"\", nil, false, 1], id);",
+                "[0, , 1]",
+            ),
+            ("var x = reduce([1, 2, 3, 4], add);", "10"),
+            ("var x = reduce([1, 2, 3, 4], add, 100);", "110"),
+            ("var x = reduce([], add, 0);", "0"),
+            ("var x = reduce([7], add);", "7"),
+            (
+                "fun cat(a, b) { return a + b; } var x = reduce([\"a\", \"b\", \"c\"], cat, \"\");",
+                "abc",
+            ),
+            (
+                "var xs = [1, 2]; var ys = map(xs, double); var x = [xs, ys];",
+                "[[1, 2], [2, 4]]",
+            ),
+        ] {
+            let source = format!("{} {}", functions, source);
+            assert_eq!(evaluate(&source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn callbacks_close_over_variables() {
+        assert_eq!(
+            evaluate(
+                "fun scaler(factor) { fun scale(n) { return n * factor; } return scale; }
+                 var seen = 0;
+                 fun count(n) { seen = seen + 1; return n > 1; }
+                 var x = [map([1, 2], scaler(10)), filter([1, 2, 3], count), seen];"
+            ),
+            Ok("[[10, 20], [2, 3], 3]".to_string())
+        );
+    }
+
+    #[test]
+    fn callback_errors_propagate() {
+        for (source, message) in [
+            (
+                "fun zero() { return 0; } var x = map([1], zero);",
+                "Expected 0 arguments, got 1",
+            ),
+            ("var x = map([1], 2);", "map expects a function, got number"),
+            (
+                "var x = filter(nil, str);",
+                "filter expects a list, got nil",
+            ),
+            (
+                "fun add(a, b) { return a + b; } var x = reduce([], add);",
+                "reduce of an empty list with no initial value",
+            ),
+        ] {
+            assert_eq!(evaluate(source), Err(message.to_string()), "{}", source);
+        }
+
+        // An error inside the callback is reported where it happened, with the call to the
+        // callback in its trace.
+        let mut interpreter = Interpreter::new();
+        let program = compile("fun bad(n) {\n  return n + nil;\n}\nmap([1], bad);").unwrap();
+        let Err(KadomError::Runtime(err)) = interpreter.interpret(program) else {
+            panic!("expected a runtime error");
+        };
+        assert_eq!(err.line, 2);
+        assert_eq!(err.trace[0].function, "bad");
+        assert_eq!(err.trace[0].line, 4);
+    }
+
+    #[test]
+    fn finds_items_by_equality() {
+        for (source, expected) in [
+            (
+                "var x = [contains([1, 2, 3], 2), indexOf([1, 2, 3], 2)];",
+                "[true, 1]",
+            ),
+            (
+                "var x = [contains([1, 2, 3], 4), indexOf([1, 2, 3], 4)];",
+                "[false, -1]",
+            ),
+            (
+                "var x = [contains([\"a\", \"b\"], \"b\"), indexOf([\"a\", \"b\"], \"b\")];",
+                "[true, 1]",
+            ),
+            (
+                "var x = [contains([\"ab\"], \"a\"), indexOf([\"ab\"], \"a\")];",
+                "[false, -1]",
+            ),
+            (
+                "var x = [contains([1, nil], nil), indexOf([1, nil, nil], nil)];",
+                "[true, 1]",
+            ),
+            (
+                "var x = [contains([1, \"1\", true], \"1\"), indexOf([1, \"1\", true], true)];",
+                "[true, 2]",
+            ),
+            (
+                "var x = [contains([1], \"1\"), contains([0], false), contains([nil], false)];",
+                "[false, false, false]",
+            ),
+            (
+                "var x = [contains([[1, 2], [3]], [3]), indexOf([[1], [1, 2]], [1, 2])];",
+                "[true, 1]",
+            ),
+            (
+                "var x = [contains([[1, [2]]], [1, [2]]), contains([[1, 2]], [2, 1])];",
+                "[true, false]",
+            ),
+            (
+                "var x = [contains([], nil), indexOf([], 1)];",
+                "[false, -1]",
+            ),
+            (
+                "var x = [contains([NAN], NAN), indexOf([NAN], NAN)];",
+                "[false, -1]",
+            ),
+            (
+                "var x = [indexOf([5, 5], 5), contains(\"kadom\", \"ado\")];",
+                "[0, true]",
+            ),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+        assert_eq!(
+            evaluate("var x = indexOf(nil, 1);"),
+            Err("indexOf expects a string or a list, got nil".to_string())
+        );
+    }
+
+    #[test]
+    fn finds_instances_with_their_equals_method() {
+        let source = "class P { init(x) { this.x = x; } equals(o) { return this.x == o.x; } }
+                      class Q {}
+                      var q = Q();
+                      var x = [contains([P(1), P(2)], P(2)), indexOf([P(1), P(2)], P(2)),
+                               contains([Q()], Q()), indexOf([1, q], q)];";
+        assert_eq!(evaluate(source), Ok("[true, 1, false, 1]".to_string()));
+    }
+
+    #[test]
+    fn comparator_errors_propagate() {
+        // A thrown value reaches the catch around sort unchanged.
+        assert_eq!(
+            evaluate("fun no(a, b) { throw \"no\"; } var x; try { sort([1, 2], no); } catch (e) { x = e; }"),
+            Ok("no".to_string())
+        );
+        for (source, message) in [
+            (
+                "fun size(a, b) { return a.size; } var x = sort([1, 2], size);",
+                "Only instances have properties, got Number(2.0)",
+            ),
+            (
+                "fun text(a, b) { return \"1\"; } var x = sort([1, 2], text);",
+                "sort comparator must return a number, got string",
+            ),
+            (
+                "fun one(a) { return 0; } var x = sort([1, 2], one);",
+                "Expected 1 argument, got 2",
+            ),
+            ("var x = sort([1, 2], 3);", "sort comparator must be a function, got number"),
+            (
+                "var x = sort([1, \"2\"]);",
+                "sort can only order all numbers or all strings without a comparator, got string at index 1 after number",
+            ),
+            (
+                "var x = sort([nil]);",
+                "sort can only order all numbers or all strings without a comparator, got nil at index 0 after nil",
+            ),
+            ("var x = sort(\"ba\");", "sort expects a list, got string"),
+        ] {
+            assert_eq!(evaluate(source), Err(message.to_string()), "{}", source);
+        }
+    }
+}
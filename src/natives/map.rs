@@ -0,0 +1,126 @@
+use super::{define_native, NativeResult};
+use crate::{environment::Environment, expr::LiteralValue, interpreter::Interpreter};
+use std::{cell::RefCell, rc::Rc};
+
+type Entries = Rc<RefCell<Vec<(LiteralValue, LiteralValue)>>>;
+
+// Defines the map functions in the given (global) environment. Maps keep their entries in the
+// order the keys were first added, and these functions follow that order.
+pub fn define(globals: &mut Environment) {
+    define_native(globals, "keys", 1..=1, |_, arguments| {
+        let entries = map("keys", &arguments[0])?;
+        let keys = entries
+            .borrow()
+            .iter()
+            .map(|(key, _)| key.clone())
+            .collect();
+        Ok(LiteralValue::List(Rc::new(RefCell::new(keys))))
+    });
+    define_native(globals, "values", 1..=1, |_, arguments| {
+        let entries = map("values", &arguments[0])?;
+        let values = entries
+            .borrow()
+            .iter()
+            .map(|(_, value)| value.clone())
+            .collect();
+        Ok(LiteralValue::List(Rc::new(RefCell::new(values))))
+    });
+    define_native(globals, "has", 2..=2, has);
+    define_native(globals, "remove", 2..=2, remove);
+}
+
+fn map(function: &str, value: &LiteralValue) -> Result<Entries, String> {
+    match value {
+        LiteralValue::Map(entries) => Ok(entries.clone()),
+        other => Err(format!(
+            "{} expects a map, got {}",
+            function,
+            other.type_name()
+        )),
+    }
+}
+
+// Whether the key is in the map, even when its value is nil.
+fn has(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let entries = map("has", &arguments[0])?;
+    let found = entries.borrow().iter().any(|(key, _)| *key == arguments[1]);
+    Ok(LiteralValue::from_bool(found))
+}
+
+// Deletes the key's entry, returning its value, or nil if the key wasn't there. The other
+// entries keep their order.
+fn remove(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let entries = map("remove", &arguments[0])?;
+    let mut entries = entries.borrow_mut();
+    let position = entries.iter().position(|(key, _)| *key == arguments[1]);
+    Ok(position.map_or(LiteralValue::Nil, |position| entries.remove(position).1))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::natives::testing::evaluate;
+
+    #[test]
+    fn lists_keys_and_values_in_insertion_order() {
+        for (source, expected) in [
+            (
+                "var m = {\"b\": 1, \"a\": 2, 3: nil}; var x = [keys(m), values(m)];",
+                "[[b, a, 3], [1, 2, nil]]",
+            ),
+            (
+                "var m = {\"b\": 1, \"a\": 2}; var x = [keys(m) == keys(m), values(m) == values(m)];",
+                "[true, true]",
+            ),
+            ("var x = [keys({}), values({})];", "[[], []]"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn has_tells_a_nil_value_from_a_missing_key() {
+        assert_eq!(
+            evaluate(
+                "var m = {\"set\": 1, \"empty\": nil, [1]: 2};
+                 var x = [has(m, \"set\"), has(m, \"empty\"), has(m, \"missing\"), has(m, [1])];"
+            ),
+            Ok("[true, true, false, true]".to_string())
+        );
+    }
+
+    #[test]
+    fn remove_returns_the_old_value() {
+        assert_eq!(
+            evaluate(
+                "var m = {\"a\": 1, \"b\": 2, \"c\": 3};
+                 var removed = remove(m, \"b\");
+                 var x = [removed, len(m), m, has(m, \"b\")];"
+            ),
+            Ok("[2, 2, {a: 1, c: 3}, false]".to_string())
+        );
+        assert_eq!(
+            evaluate("var m = {\"a\": 1}; var x = [remove(m, \"z\"), len(m), remove({}, 1)];"),
+            Ok("[nil, 1, nil]".to_string())
+        );
+        // The map is changed wherever it's referred to.
+        assert_eq!(
+            evaluate("var m = {1: 2}; var n = m; remove(n, 1); var x = m;"),
+            Ok("{}".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_maps() {
+        for (source, message) in [
+            ("var x = keys([1]);", "keys expects a map, got list"),
+            ("var x = values(nil);", "values expects a map, got nil"),
+            (
+                "var x = has(\"a\", \"a\");",
+                "has expects a map, got string",
+            ),
+            ("var x = remove(1, 1);", "remove expects a map, got number"),
+        ] {
+            assert_eq!(evaluate(source), Err(message.to_string()), "{}", source);
+        }
+    }
+}
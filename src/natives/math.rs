@@ -0,0 +1,534 @@
+use super::{define_native, integer, NativeResult};
+use crate::{environment::Environment, expr::LiteralValue, interpreter::Interpreter};
+
+// Read-only globals. Locals may shadow them, but nothing can assign to them.
+pub const CONSTANTS: [(&str, f64); 4] = [
+    ("PI", std::f64::consts::PI),
+    ("E", std::f64::consts::E),
+    ("INFINITY", f64::INFINITY),
+    ("NAN", f64::NAN),
+];
+
+// Defines the math constants and functions in the given (global) environment.
+pub fn define(globals: &mut Environment) {
+    for (name, value) in CONSTANTS {
+        globals.define_constant(name.to_string(), LiteralValue::Number(value));
+    }
+    define_native(globals, "sqrt", 1..=1, sqrt);
+    define_native(globals, "abs", 1..=1, |_, arguments| {
+        unary("abs", arguments, f64::abs)
+    });
+    define_native(globals, "floor", 1..=1, |_, arguments| {
+        unary("floor", arguments, f64::floor)
+    });
+    define_native(globals, "ceil", 1..=1, |_, arguments| {
+        unary("ceil", arguments, f64::ceil)
+    });
+    // Halves round away from zero, so round(2.5) is 3 and round(-2.5) is -3.
+    define_native(globals, "round", 1..=1, |_, arguments| {
+        unary("round", arguments, f64::round)
+    });
+    define_native(globals, "pow", 2..=2, pow);
+    define_native(globals, "min", 1..=usize::MAX, |_, arguments| {
+        extreme("min", arguments, f64::min)
+    });
+    define_native(globals, "max", 1..=usize::MAX, |_, arguments| {
+        extreme("max", arguments, f64::max)
+    });
+    define_native(globals, "clamp", 3..=3, clamp);
+
+    define_native(globals, "sin", 1..=1, |_, arguments| {
+        unary("sin", arguments, f64::sin)
+    });
+    define_native(globals, "cos", 1..=1, |_, arguments| {
+        unary("cos", arguments, f64::cos)
+    });
+    define_native(globals, "tan", 1..=1, |_, arguments| {
+        unary("tan", arguments, f64::tan)
+    });
+    define_native(globals, "asin", 1..=1, |interpreter, arguments| {
+        partial(interpreter, "asin", arguments, f64::asin, Domain::UNIT)
+    });
+    define_native(globals, "acos", 1..=1, |interpreter, arguments| {
+        partial(interpreter, "acos", arguments, f64::acos, Domain::UNIT)
+    });
+    define_native(globals, "atan", 1..=1, |_, arguments| {
+        unary("atan", arguments, f64::atan)
+    });
+    define_native(globals, "atan2", 2..=2, atan2);
+    define_native(globals, "log", 1..=1, |interpreter, arguments| {
+        partial(interpreter, "log", arguments, f64::ln, Domain::POSITIVE)
+    });
+    define_native(globals, "log10", 1..=1, |interpreter, arguments| {
+        partial(
+            interpreter,
+            "log10",
+            arguments,
+            f64::log10,
+            Domain::POSITIVE,
+        )
+    });
+    define_native(globals, "exp", 1..=1, |_, arguments| {
+        unary("exp", arguments, f64::exp)
+    });
+    define_native(globals, "toFixed", 2..=2, to_fixed);
+}
+
+// The arguments a function has a real result for.
+struct Domain {
+    description: &'static str,
+    contains: fn(f64) -> bool,
+}
+
+impl Domain {
+    const POSITIVE: Self = Self::new("a positive number", |x| x > 0.0);
+    const NON_NEGATIVE: Self = Self::new("a non-negative number", |x| x >= 0.0);
+    const UNIT: Self = Self::new("a number between -1 and 1", |x| (-1.0..=1.0).contains(&x));
+
+    const fn new(description: &'static str, contains: fn(f64) -> bool) -> Self {
+        Self {
+            description,
+            contains,
+        }
+    }
+}
+
+fn number(function: &str, value: &LiteralValue) -> Result<f64, String> {
+    match value {
+        LiteralValue::Number(x) => Ok(*x),
+        other => Err(format!(
+            "{} expects a number, got {}",
+            function,
+            other.type_name()
+        )),
+    }
+}
+
+// A number that can be ordered. NaN compares false with everything, so it would be silently
+// dropped or let through.
+fn comparable(function: &str, value: &LiteralValue) -> Result<f64, String> {
+    match number(function, value)? {
+        x if x.is_nan() => Err(format!("{} expects a number, got NaN", function)),
+        x => Ok(x),
+    }
+}
+
+fn unary(function: &str, arguments: Vec<LiteralValue>, op: fn(f64) -> f64) -> NativeResult {
+    Ok(LiteralValue::Number(op(number(function, &arguments[0])?)))
+}
+
+// As unary, for a function without a real result outside `domain`. Like division by zero, that's
+// an error unless the interpreter follows IEEE 754, in which case the result is NaN or infinite.
+fn partial(
+    interpreter: &mut Interpreter,
+    function: &str,
+    arguments: Vec<LiteralValue>,
+    op: fn(f64) -> f64,
+    domain: Domain,
+) -> NativeResult {
+    let x = number(function, &arguments[0])?;
+    if !(domain.contains)(x) && !interpreter.ieee_division {
+        return Err(format!(
+            "{} expects {}, got {}",
+            function, domain.description, arguments[0]
+        ));
+    }
+    Ok(LiteralValue::Number(op(x)))
+}
+
+fn sqrt(interpreter: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    partial(
+        interpreter,
+        "sqrt",
+        arguments,
+        f64::sqrt,
+        Domain::NON_NEGATIVE,
+    )
+}
+
+fn pow(interpreter: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let base = number("pow", &arguments[0])?;
+    let exponent = number("pow", &arguments[1])?;
+    if !interpreter.ieee_division {
+        if base == 0.0 && exponent < 0.0 {
+            return Err("pow of zero to a negative power".to_string());
+        }
+        if base < 0.0 && exponent.fract() != 0.0 {
+            return Err("pow of a negative number to a fractional power".to_string());
+        }
+    }
+    Ok(LiteralValue::Number(base.powf(exponent)))
+}
+
+// The smallest or largest of two or more numbers, or of the numbers in a single list. Strings
+// aren't compared, so min and max take numbers only.
+fn extreme(
+    function: &str,
+    arguments: Vec<LiteralValue>,
+    pick: fn(f64, f64) -> f64,
+) -> NativeResult {
+    match arguments.as_slice() {
+        [LiteralValue::List(items)] => fold(function, &items.borrow(), pick),
+        [other] => Err(format!(
+            "{} expects two or more numbers or a list, got {}",
+            function,
+            other.type_name()
+        )),
+        values => fold(function, values, pick),
+    }
+}
+
+fn fold(function: &str, values: &[LiteralValue], pick: fn(f64, f64) -> f64) -> NativeResult {
+    let Some((first, rest)) = values.split_first() else {
+        return Err(format!("{} of an empty list", function));
+    };
+    let mut result = comparable(function, first)?;
+    for value in rest {
+        result = pick(result, comparable(function, value)?);
+    }
+    Ok(LiteralValue::Number(result))
+}
+
+// x limited to the range from lo to hi.
+fn clamp(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let x = comparable("clamp", &arguments[0])?;
+    let lo = comparable("clamp", &arguments[1])?;
+    let hi = comparable("clamp", &arguments[2])?;
+    if lo > hi {
+        return Err(format!(
+            "clamp lower bound {} is greater than upper bound {}",
+            arguments[1], arguments[2]
+        ));
+    }
+    Ok(LiteralValue::Number(x.max(lo).min(hi)))
+}
+
+// The angle of the point (x, y) from the positive x axis, in radians.
+fn atan2(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let y = number("atan2", &arguments[0])?;
+    let x = number("atan2", &arguments[1])?;
+    Ok(LiteralValue::Number(y.atan2(x)))
+}
+
+const MAX_FIXED_DIGITS: i64 = 20;
+
+// Enough decimal places to write out any f64 exactly.
+const EXACT_DIGITS: usize = 1074;
+
+// The number as a string with exactly `digits` decimal places, rounded half away from zero:
+// 0.125 to two places is "0.13" and -2.5 to none is "-3". It's the number's exact binary value
+// that's rounded, so 1.005, stored as a little less, becomes "1.00". A result that rounds to
+// zero has no sign.
+fn to_fixed(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let x = number("toFixed", &arguments[0])?;
+    let digits = integer("toFixed", &arguments[1])?;
+    if !(0..=MAX_FIXED_DIGITS).contains(&digits) {
+        return Err(format!(
+            "toFixed digits must be from 0 to {}, got {}",
+            MAX_FIXED_DIGITS, digits
+        ));
+    }
+    if !x.is_finite() {
+        return Ok(LiteralValue::String(arguments[0].to_string().into()));
+    }
+
+    let exact = format!("{:.*}", EXACT_DIGITS, x.abs());
+    let cut = exact.find('.').unwrap_or(exact.len()) + 1 + digits as usize;
+    let mut kept: Vec<u8> = exact.as_bytes()[..cut]
+        .iter()
+        .copied()
+        .filter(|byte| *byte != b'.')
+        .collect();
+    // What's cut off is at least half of the last kept place.
+    if exact.as_bytes()[cut] >= b'5' {
+        let carried = kept
+            .iter()
+            .rev()
+            .take_while(|digit| **digit == b'9')
+            .count();
+        let end = kept.len();
+        kept[end - carried..].fill(b'0');
+        match end.checked_sub(carried + 1) {
+            Some(last) => kept[last] += 1,
+            None => kept.insert(0, b'1'),
+        }
+    }
+
+    let whole = kept.len() - digits as usize;
+    let mut fixed = String::new();
+    if x < 0.0 && kept.iter().any(|digit| *digit != b'0') {
+        fixed.push('-');
+    }
+    fixed.extend(kept[..whole].iter().map(|digit| char::from(*digit)));
+    if digits > 0 {
+        fixed.push('.');
+        fixed.extend(kept[whole..].iter().map(|digit| char::from(*digit)));
+    }
+    Ok(LiteralValue::String(fixed.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        interpreter::Interpreter,
+        natives::testing::{evaluate, run},
+    };
+
+    #[test]
+    fn computes_typical_values() {
+        for (source, expected) in [
+            ("var x = sqrt(16);", "4"),
+            ("var x = sqrt(2);", "1.4142135623730951"),
+            ("var x = abs(-3.5);", "3.5"),
+            ("var x = floor(-1.5);", "-2"),
+            ("var x = ceil(1.2);", "2"),
+            ("var x = pow(2, 10);", "1024"),
+            ("var x = pow(4, 0.5);", "2"),
+            ("var x = pow(-2, 3);", "-8"),
+        ] {
+            assert_eq!(evaluate(source).as_deref(), Ok(expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn rounds_halves_away_from_zero() {
+        assert_eq!(
+            evaluate("var x = [round(0.5), round(1.5), round(2.5), round(-2.5), round(2.4)];"),
+            Ok("[1, 2, 3, -3, 2]".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_results_with_no_real_value() {
+        assert_eq!(
+            evaluate("var x = sqrt(-4);"),
+            Err("sqrt expects a non-negative number, got -4".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = pow(0, -1);"),
+            Err("pow of zero to a negative power".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = pow(-8, 1 / 3);"),
+            Err("pow of a negative number to a fractional power".to_string())
+        );
+
+        let mut interpreter = Interpreter::new();
+        interpreter.ieee_division = true;
+        run(&mut interpreter, "var x = sqrt(-4); var y = pow(0, -1);").unwrap();
+        let globals = interpreter.environment.borrow();
+        assert_eq!(globals.lookup("x").unwrap().to_string(), "NaN");
+        assert_eq!(globals.lookup("y").unwrap().to_string(), "inf");
+    }
+
+    #[test]
+    fn checks_arguments() {
+        assert_eq!(
+            evaluate("var x = sqrt(\"9\");"),
+            Err("sqrt expects a number, got string".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = pow(2, nil);"),
+            Err("pow expects a number, got nil".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = floor(1, 2);"),
+            Err("Expected 1 argument, got 2".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = pow(2);"),
+            Err("Expected 2 arguments, got 1".to_string())
+        );
+    }
+
+    fn number(source: &str) -> f64 {
+        evaluate(source).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn trigonometry_and_logarithms_meet_known_identities() {
+        for (source, expected) in [
+            ("var x = sin(0);", 0.0),
+            ("var x = cos(0);", 1.0),
+            ("var x = tan(0);", 0.0),
+            ("var x = sin(asin(0.5));", 0.5),
+            ("var x = acos(-1);", std::f64::consts::PI),
+            ("var x = 4 * atan(1);", std::f64::consts::PI),
+            ("var x = atan2(1, -1);", 3.0 * std::f64::consts::FRAC_PI_4),
+            ("var x = log(exp(1));", 1.0),
+            ("var x = log10(1000);", 3.0),
+            ("var x = exp(0);", 1.0),
+            ("var x = pow(sin(0.3), 2) + pow(cos(0.3), 2);", 1.0),
+        ] {
+            assert!((number(source) - expected).abs() < 1e-12, "{}", source);
+        }
+    }
+
+    #[test]
+    fn rejects_arguments_outside_the_domain() {
+        for (source, message) in [
+            (
+                "var x = asin(2);",
+                "asin expects a number between -1 and 1, got 2",
+            ),
+            (
+                "var x = acos(-1.5);",
+                "acos expects a number between -1 and 1, got -1.5",
+            ),
+            ("var x = log(0);", "log expects a positive number, got 0"),
+            ("var x = log(-1);", "log expects a positive number, got -1"),
+            (
+                "var x = log10(0);",
+                "log10 expects a positive number, got 0",
+            ),
+            ("var x = sin(\"0\");", "sin expects a number, got string"),
+            ("var x = atan2(1);", "Expected 2 arguments, got 1"),
+        ] {
+            assert_eq!(evaluate(source), Err(message.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn min_and_max_take_numbers_or_a_list() {
+        for (source, expected) in [
+            ("var x = min(3, 1);", "1"),
+            ("var x = max(3, 1);", "3"),
+            ("var x = min(4, -2, 7.5, 0, 3);", "-2"),
+            ("var x = max(4, -2, 7.5, 0, 3);", "7.5"),
+            ("var x = min([5, 2, 8]);", "2"),
+            ("var x = max([5]);", "5"),
+        ] {
+            assert_eq!(evaluate(source).as_deref(), Ok(expected), "{}", source);
+        }
+
+        for (source, message) in [
+            ("var x = min(1, \"a\");", "min expects a number, got string"),
+            ("var x = max([1, nil]);", "max expects a number, got nil"),
+            (
+                "var x = max(\"b\", \"a\");",
+                "max expects a number, got string",
+            ),
+            (
+                "var x = min(1);",
+                "min expects two or more numbers or a list, got number",
+            ),
+            ("var x = max([]);", "max of an empty list"),
+            ("var x = max(1, NAN);", "max expects a number, got NaN"),
+            ("var x = min([NAN, 1]);", "min expects a number, got NaN"),
+            ("var x = min();", "Expected at least 1 argument, got 0"),
+        ] {
+            assert_eq!(evaluate(source), Err(message.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn clamp_keeps_a_number_within_bounds() {
+        assert_eq!(
+            evaluate(
+                "var x = [clamp(5, 0, 10), clamp(-5, 0, 10), clamp(15, 0, 10), clamp(3, 3, 3)];"
+            ),
+            Ok("[5, 0, 10, 3]".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = clamp(1, 10, 0);"),
+            Err("clamp lower bound 10 is greater than upper bound 0".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = clamp(1, \"0\", 2);"),
+            Err("clamp expects a number, got string".to_string())
+        );
+        for source in ["var x = clamp(NAN, 0, 1);", "var x = clamp(5, NAN, 1);"] {
+            assert_eq!(
+                evaluate(source),
+                Err("clamp expects a number, got NaN".to_string()),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn constants_work_in_arithmetic() {
+        assert_eq!(
+            number("var r = 2; var x = 2 * PI * r;"),
+            4.0 * std::f64::consts::PI
+        );
+        assert_eq!(number("var x = log(E);"), 1.0);
+        // Infinities and NaN print the way any other number would.
+        assert_eq!(
+            evaluate("var x = [INFINITY, -INFINITY, NAN, NAN == NAN, 1 / INFINITY];"),
+            Ok("[inf, -inf, NaN, false, 0]".to_string())
+        );
+    }
+
+    #[test]
+    fn constants_cannot_be_reassigned() {
+        assert_eq!(
+            evaluate("PI = 3;"),
+            Err("cannot reassign built-in constant 'PI'".to_string())
+        );
+        assert_eq!(
+            evaluate("fun f() { E = 2; } f();"),
+            Err("cannot reassign built-in constant 'E'".to_string())
+        );
+        // A local of the same name is a different variable.
+        assert_eq!(
+            evaluate("{ var PI = 3; PI = 4; print PI; } var x = PI;"),
+            Ok(std::f64::consts::PI.to_string())
+        );
+    }
+
+    #[test]
+    fn to_fixed_rounds_half_away_from_zero() {
+        for (source, expected) in [
+            ("var x = toFixed(3.14159, 2);", "3.14"),
+            ("var x = toFixed(2.675, 2);", "2.67"),
+            ("var x = toFixed(0.125, 2);", "0.13"),
+            ("var x = toFixed(0.375, 2);", "0.38"),
+            ("var x = toFixed(0.124, 2);", "0.12"),
+            ("var x = toFixed(-0.125, 2);", "-0.13"),
+            ("var x = toFixed(2.5, 0);", "3"),
+            ("var x = toFixed(-2.5, 0);", "-3"),
+            ("var x = toFixed(1.4, 0);", "1"),
+            ("var x = toFixed(9.995, 2);", "9.99"),
+            ("var x = toFixed(9.9951, 2);", "10.00"),
+            ("var x = toFixed(99.5, 0);", "100"),
+            ("var x = toFixed(7, 3);", "7.000"),
+            ("var x = toFixed(-1234.5678, 1);", "-1234.6"),
+            ("var x = toFixed(-0.001, 2);", "0.00"),
+            (
+                "var x = toFixed(1000000000000000000000, 2);",
+                "1000000000000000000000.00",
+            ),
+            ("var x = toFixed(0.1, 20);", "0.10000000000000000555"),
+            ("var x = toFixed(INFINITY, 2);", "inf"),
+            ("var x = typeof(toFixed(1, 0));", "string"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn to_fixed_checks_the_digit_count() {
+        for (source, message) in [
+            (
+                "var x = toFixed(1, 21);",
+                "toFixed digits must be from 0 to 20, got 21",
+            ),
+            (
+                "var x = toFixed(1, -1);",
+                "toFixed digits must be from 0 to 20, got -1",
+            ),
+            (
+                "var x = toFixed(1, 1.5);",
+                "toFixed expects an integer, got 1.5",
+            ),
+            (
+                "var x = toFixed(\"1\", 2);",
+                "toFixed expects a number, got string",
+            ),
+        ] {
+            assert_eq!(evaluate(source), Err(message.to_string()), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use super::{define_native, integer, NativeResult};
+use crate::{environment::Environment, expr::LiteralValue, interpreter::Interpreter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Defines the random number functions in the given (global) environment.
+pub fn define(globals: &mut Environment) {
+    define_native(globals, "random", 0..=0, |interpreter, _| {
+        Ok(LiteralValue::Number(interpreter.rng.next_f64()))
+    });
+    define_native(globals, "randomInt", 2..=2, random_int);
+    define_native(globals, "seedRandom", 1..=1, |interpreter, arguments| {
+        let seed = integer("seedRandom", &arguments[0])?;
+        interpreter.seed_rng(seed as u64);
+        Ok(LiteralValue::Nil)
+    });
+}
+
+// SplitMix64: small, fast and good enough for scripts, though not for anything that needs to be
+// unpredictable. Every seed, zero included, gives a full-period sequence.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    // Seeded from the system clock, so each run differs.
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Self::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1), from the top 53 bits so every value is exactly representable.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in lo..=hi. Draws that would favour the low end of the range are thrown away.
+    pub fn next_in(&mut self, lo: i64, hi: i64) -> i64 {
+        let span = (hi.wrapping_sub(lo) as u64).wrapping_add(1);
+        // The whole of i64.
+        if span == 0 {
+            return self.next_u64() as i64;
+        }
+        let limit = u64::MAX - u64::MAX % span;
+        loop {
+            let draw = self.next_u64();
+            if draw < limit {
+                return lo.wrapping_add((draw % span) as i64);
+            }
+        }
+    }
+}
+
+// A whole number from lo to hi, both included.
+fn random_int(interpreter: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let lo = integer("randomInt", &arguments[0])?;
+    let hi = integer("randomInt", &arguments[1])?;
+    if hi < lo {
+        return Err(format!(
+            "randomInt upper bound {} is less than lower bound {}",
+            hi, lo
+        ));
+    }
+    Ok(LiteralValue::Number(interpreter.rng.next_in(lo, hi) as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::natives::testing::evaluate_in;
+
+    #[test]
+    fn a_seed_gives_the_same_sequence() {
+        let mut first = Rng::new(42);
+        let mut second = Rng::new(42);
+        let drawn: Vec<u64> = (0..5).map(|_| first.next_u64()).collect();
+        assert_eq!(drawn, (0..5).map(|_| second.next_u64()).collect::<Vec<_>>());
+        assert_ne!(
+            drawn,
+            (0..5).map(|_| Rng::new(43).next_u64()).collect::<Vec<_>>()
+        );
+
+        let source = "var x = [random(), randomInt(1, 100), random()];";
+        let mut interpreter = Interpreter::new();
+        interpreter.seed_rng(7);
+        let seeded = evaluate_in(&mut interpreter, source).unwrap();
+        assert_eq!(
+            evaluate_in(
+                &mut Interpreter::new(),
+                &format!("seedRandom(7); {}", source)
+            ),
+            Ok(seeded.clone())
+        );
+
+        interpreter.seed_rng(7);
+        assert_eq!(evaluate_in(&mut interpreter, source), Ok(seeded));
+    }
+
+    #[test]
+    fn values_stay_in_bounds() {
+        let mut rng = Rng::new(1);
+        let mut seen = [false; 7];
+        for _ in 0..10_000 {
+            let x = rng.next_f64();
+            assert!((0.0..1.0).contains(&x));
+
+            let n = rng.next_in(-3, 3);
+            assert!((-3..=3).contains(&n));
+            seen[(n + 3) as usize] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+        assert_eq!(rng.next_in(5, 5), 5);
+        assert!((i64::MIN..=i64::MAX).contains(&rng.next_in(i64::MIN, i64::MAX)));
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        let mut interpreter = Interpreter::new();
+        for (source, message) in [
+            (
+                "var x = randomInt(5, 1);",
+                "randomInt upper bound 1 is less than lower bound 5",
+            ),
+            (
+                "var x = randomInt(1.5, 2);",
+                "randomInt expects an integer, got 1.5",
+            ),
+            (
+                "var x = randomInt(\"1\", 2);",
+                "randomInt expects an integer, got string",
+            ),
+            ("seedRandom(nil);", "seedRandom expects an integer, got nil"),
+        ] {
+            assert_eq!(
+                evaluate_in(&mut interpreter, source),
+                Err(message.to_string()),
+                "{}",
+                source
+            );
+        }
+    }
+}
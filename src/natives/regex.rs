@@ -0,0 +1,610 @@
+use super::{define_native, string::string};
+use crate::{environment::Environment, expr::LiteralValue};
+use std::{cell::RefCell, rc::Rc};
+
+// Defines the pattern matching functions in the given (global) environment.
+pub fn define(globals: &mut Environment) {
+    define_native(globals, "match", 2..=2, |_, arguments| {
+        let text: Vec<char> = string("match", &arguments[0])?.chars().collect();
+        let regex = Regex::new(string("match", &arguments[1])?)?;
+        Ok(match regex.find(&text, 0) {
+            Some((start, end)) => substring(&text, start, end),
+            None => LiteralValue::Nil,
+        })
+    });
+    define_native(globals, "matchAll", 2..=2, |_, arguments| {
+        let text: Vec<char> = string("matchAll", &arguments[0])?.chars().collect();
+        let regex = Regex::new(string("matchAll", &arguments[1])?)?;
+        let matches = regex
+            .find_all(&text)
+            .into_iter()
+            .map(|(start, end)| substring(&text, start, end))
+            .collect();
+        Ok(LiteralValue::List(Rc::new(RefCell::new(matches))))
+    });
+    define_native(globals, "regexReplace", 3..=3, |_, arguments| {
+        let text: Vec<char> = string("regexReplace", &arguments[0])?.chars().collect();
+        let regex = Regex::new(string("regexReplace", &arguments[1])?)?;
+        let replacement = string("regexReplace", &arguments[2])?;
+        Ok(LiteralValue::String(
+            regex.replace_all(&text, replacement).into(),
+        ))
+    });
+}
+
+fn substring(text: &[char], start: usize, end: usize) -> LiteralValue {
+    LiteralValue::String(text[start..end].iter().collect::<String>().into())
+}
+
+// Inclusive ranges of code points. A negated class matches what none of its ranges do.
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    negated: bool,
+    ranges: Vec<(u32, u32)>,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        let c = c as u32;
+        let found = self.ranges.iter().any(|&(low, high)| low <= c && c <= high);
+        found != self.negated
+    }
+
+    // The class for \d, \w or \s, or their negations \D, \W and \S.
+    fn shorthand(letter: char) -> Option<Self> {
+        let ranges = match letter.to_ascii_lowercase() {
+            'd' => vec![(0x30, 0x39)],
+            'w' => vec![(0x30, 0x39), (0x41, 0x5a), (0x5f, 0x5f), (0x61, 0x7a)],
+            's' => vec![(0x09, 0x0d), (0x20, 0x20)],
+            _ => return None,
+        };
+        Some(Self {
+            negated: letter.is_ascii_uppercase(),
+            ranges,
+        })
+    }
+
+    // The same class as ranges alone, so it can sit inside another.
+    fn flattened(self) -> Vec<(u32, u32)> {
+        if !self.negated {
+            return self.ranges;
+        }
+        let mut ranges = self.ranges;
+        ranges.sort_unstable();
+        let mut complement = Vec::new();
+        let mut next = 0;
+        for (low, high) in ranges {
+            if low > next {
+                complement.push((next, low - 1));
+            }
+            next = next.max(high + 1);
+        }
+        if next <= char::MAX as u32 {
+            complement.push((next, char::MAX as u32));
+        }
+        complement
+    }
+}
+
+// One step of a compiled pattern. Split tries its first target before its second, which is what
+// makes repetition greedy and alternation prefer its left side.
+#[derive(Debug, Clone, PartialEq)]
+enum Instruction {
+    Char(char),
+    // Any character but a newline.
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+// A pattern of literals, `.`, classes in brackets or as \d, \w and \s, the `*`, `+` and `?`
+// repetitions, `|`, grouping and the `^` and `$` anchors. A backslash makes any other symbol
+// literal.
+//
+// Matching runs every possible path through the pattern at once, a character at a time, so it
+// takes time proportional to the length of the text times the length of the pattern. Patterns
+// that make a backtracking matcher explode, like `(a+)+$`, can't make this one hang.
+#[derive(Debug)]
+struct Regex {
+    program: Vec<Instruction>,
+}
+
+impl Regex {
+    fn new(pattern: &str) -> Result<Self, String> {
+        let mut compiler = Compiler {
+            pattern: pattern.chars().collect(),
+            position: 0,
+            program: Vec::new(),
+        };
+        compiler.alternation()?;
+        if compiler.position < compiler.pattern.len() {
+            // Alternation only stops early at a ')'.
+            return Err(compiler.error("unmatched ')'"));
+        }
+        compiler.program.push(Instruction::Match);
+        Ok(Self {
+            program: compiler.program,
+        })
+    }
+
+    // The start and end of the leftmost match at or after `from`, as character indices.
+    fn find(&self, text: &[char], from: usize) -> Option<(usize, usize)> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut found = None;
+        for position in from..=text.len() {
+            // Threads carried from earlier started earlier, so a new one comes last.
+            if found.is_none() {
+                self.add(&mut current, 0, position, position, text.len());
+            }
+            if current.list.is_empty() && found.is_some() {
+                break;
+            }
+            for &(pc, start) in &current.list {
+                let advances = match &self.program[pc] {
+                    Instruction::Char(c) => text.get(position) == Some(c),
+                    Instruction::Any => text.get(position).is_some_and(|&c| c != '\n'),
+                    Instruction::Class(class) => {
+                        text.get(position).is_some_and(|&c| class.matches(c))
+                    }
+                    Instruction::Match => {
+                        // Every thread after this one is a worse match.
+                        found = Some((start, position));
+                        break;
+                    }
+                    _ => false,
+                };
+                if advances {
+                    self.add(&mut next, pc + 1, start, position + 1, text.len());
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        found
+    }
+
+    // Every match, left to right and not overlapping. An empty match moves the search on by a
+    // character so it isn't found again.
+    fn find_all(&self, text: &[char]) -> Vec<(usize, usize)> {
+        let mut matches = Vec::new();
+        let mut from = 0;
+        while from <= text.len() {
+            let Some((start, end)) = self.find(text, from) else {
+                break;
+            };
+            matches.push((start, end));
+            from = if end == start { end + 1 } else { end };
+        }
+        matches
+    }
+
+    fn replace_all(&self, text: &[char], replacement: &str) -> String {
+        let mut replaced = String::new();
+        let mut copied = 0;
+        for (start, end) in self.find_all(text) {
+            replaced.extend(&text[copied..start]);
+            replaced.push_str(replacement);
+            copied = end;
+        }
+        replaced.extend(&text[copied..]);
+        replaced
+    }
+
+    // Adds the thread at `pc`, following jumps, splits and anchors through to the instructions
+    // that read a character.
+    fn add(&self, threads: &mut Threads, pc: usize, start: usize, position: usize, length: usize) {
+        let mut pending = vec![pc];
+        while let Some(pc) = pending.pop() {
+            if !threads.insert(pc) {
+                continue;
+            }
+            match self.program[pc] {
+                Instruction::Jump(target) => pending.push(target),
+                // Popped last-in first-out, so the preferred target goes on top.
+                Instruction::Split(first, second) => pending.extend([second, first]),
+                Instruction::Start if position == 0 => pending.push(pc + 1),
+                Instruction::End if position == length => pending.push(pc + 1),
+                Instruction::Start | Instruction::End => (),
+                _ => threads.list.push((pc, start)),
+            }
+        }
+    }
+}
+
+// The threads at one position in the text, in order of preference, with where each one's match
+// started. A thread reaching an instruction another has already reached is dropped, since the
+// earlier one is preferred and will do the same from there.
+struct Threads {
+    list: Vec<(usize, usize)>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Self {
+        Self {
+            list: Vec::new(),
+            seen: vec![false; size],
+        }
+    }
+
+    fn insert(&mut self, pc: usize) -> bool {
+        !std::mem::replace(&mut self.seen[pc], true)
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.fill(false);
+    }
+}
+
+struct Compiler {
+    pattern: Vec<char>,
+    position: usize,
+    program: Vec<Instruction>,
+}
+
+impl Compiler {
+    fn error(&self, message: &str) -> String {
+        self.error_at(self.position, message)
+    }
+
+    fn error_at(&self, position: usize, message: &str) -> String {
+        format!("invalid pattern at column {}: {}", position + 1, message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.pattern.get(self.position).copied()
+    }
+
+    fn alternation(&mut self) -> Result<(), String> {
+        let start = self.program.len();
+        self.sequence()?;
+        if self.peek() != Some('|') {
+            return Ok(());
+        }
+        self.position += 1;
+        // Try the left side first, and skip over the right side once it has matched.
+        self.program.insert(start, Instruction::Split(start + 1, 0));
+        self.shift(start + 1, 1);
+        let jump = self.program.len();
+        self.program.push(Instruction::Jump(0));
+        self.program[start] = Instruction::Split(start + 1, jump + 1);
+        self.alternation()?;
+        self.program[jump] = Instruction::Jump(self.program.len());
+        Ok(())
+    }
+
+    fn sequence(&mut self) -> Result<(), String> {
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            self.repetition()?;
+        }
+        Ok(())
+    }
+
+    fn repetition(&mut self) -> Result<(), String> {
+        let start = self.program.len();
+        let repeatable = self.atom()?;
+        let Some(operator @ ('*' | '+' | '?')) = self.peek() else {
+            return Ok(());
+        };
+        if !repeatable {
+            return Err(self.error(&format!("nothing for '{}' to repeat", operator)));
+        }
+        self.position += 1;
+        match operator {
+            '+' => {
+                let after = self.program.len() + 1;
+                self.program.push(Instruction::Split(start, after));
+            }
+            '*' => {
+                self.program.insert(start, Instruction::Split(start + 1, 0));
+                self.shift(start + 1, 1);
+                self.program.push(Instruction::Jump(start));
+                self.program[start] = Instruction::Split(start + 1, self.program.len());
+            }
+            _ => {
+                self.program.insert(start, Instruction::Split(start + 1, 0));
+                self.shift(start + 1, 1);
+                self.program[start] = Instruction::Split(start + 1, self.program.len());
+            }
+        }
+        if let Some(operator @ ('*' | '+' | '?')) = self.peek() {
+            return Err(self.error(&format!("nothing for '{}' to repeat", operator)));
+        }
+        Ok(())
+    }
+
+    // Moves the targets of the jumps and splits from `from` on, after inserting instructions
+    // before them.
+    fn shift(&mut self, from: usize, by: usize) {
+        for instruction in &mut self.program[from..] {
+            match instruction {
+                Instruction::Jump(target) => *target += by,
+                Instruction::Split(first, second) => {
+                    *first += by;
+                    *second += by;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Compiles one item of a sequence, and says whether it can be repeated.
+    fn atom(&mut self) -> Result<bool, String> {
+        let start = self.position;
+        let Some(c) = self.peek() else {
+            return Ok(false);
+        };
+        self.position += 1;
+        let instruction = match c {
+            '(' => {
+                self.alternation()?;
+                if self.peek() != Some(')') {
+                    return Err(self.error_at(start, "unclosed '('"));
+                }
+                self.position += 1;
+                return Ok(true);
+            }
+            '*' | '+' | '?' => {
+                return Err(self.error_at(start, &format!("nothing for '{}' to repeat", c)))
+            }
+            '[' => Instruction::Class(self.class(start)?),
+            '.' => Instruction::Any,
+            '^' => Instruction::Start,
+            '$' => Instruction::End,
+            '\\' => match self.escape()? {
+                Ok(c) => Instruction::Char(c),
+                Err(class) => Instruction::Class(class),
+            },
+            c => Instruction::Char(c),
+        };
+        let repeatable = !matches!(instruction, Instruction::Start | Instruction::End);
+        self.program.push(instruction);
+        Ok(repeatable)
+    }
+
+    // The character or shorthand class a backslash escapes, past the backslash.
+    fn escape(&mut self) -> Result<Result<char, Class>, String> {
+        let Some(c) = self.peek() else {
+            return Err(self.error_at(self.position - 1, "trailing '\\'"));
+        };
+        self.position += 1;
+        if let Some(class) = Class::shorthand(c) {
+            return Ok(Err(class));
+        }
+        match c.is_alphanumeric() {
+            true => Err(self.error_at(self.position - 2, &format!("unknown escape '\\{}'", c))),
+            false => Ok(Ok(c)),
+        }
+    }
+
+    // A class in brackets, past the '['. A ']' straight after the opening bracket or '^', or a
+    // '-' at either end, stands for itself.
+    fn class(&mut self, start: usize) -> Result<Class, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.position += 1;
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let item_start = self.position;
+            let low = match self.peek() {
+                None => return Err(self.error_at(start, "unclosed '['")),
+                Some(']') if !first => break,
+                Some('\\') => {
+                    self.position += 1;
+                    match self.escape()? {
+                        Ok(c) => c,
+                        Err(class) => {
+                            ranges.extend(class.flattened());
+                            first = false;
+                            continue;
+                        }
+                    }
+                }
+                Some(c) => {
+                    self.position += 1;
+                    c
+                }
+            };
+            first = false;
+            let high = match self.pattern.get(self.position..self.position + 2) {
+                Some(&['-', '\\']) => {
+                    self.position += 2;
+                    self.escape()?
+                        .map_err(|_| self.error_at(item_start, "a class can't end a range"))?
+                }
+                Some(&['-', c]) if c != ']' => {
+                    self.position += 2;
+                    c
+                }
+                _ => {
+                    ranges.push((low as u32, low as u32));
+                    continue;
+                }
+            };
+            if low > high {
+                return Err(self.error_at(
+                    item_start,
+                    &format!("range '{}-{}' is out of order", low, high),
+                ));
+            }
+            ranges.push((low as u32, high as u32));
+        }
+        self.position += 1;
+        Ok(Class { negated, ranges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+    use crate::natives::testing::evaluate;
+    use std::time::{Duration, Instant};
+
+    fn first(pattern: &str, text: &str) -> Option<String> {
+        let text: Vec<char> = text.chars().collect();
+        let (start, end) = Regex::new(pattern).unwrap().find(&text, 0)?;
+        Some(text[start..end].iter().collect())
+    }
+
+    fn all(pattern: &str, text: &str) -> Vec<String> {
+        let text: Vec<char> = text.chars().collect();
+        Regex::new(pattern)
+            .unwrap()
+            .find_all(&text)
+            .into_iter()
+            .map(|(start, end)| text[start..end].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn matches_each_metacharacter() {
+        for (pattern, text, expected) in [
+            ("cat", "concatenate", Some("cat")),
+            ("c.t", "a cut", Some("cut")),
+            ("a.b", "a\nb", None),
+            ("[aeiou]+", "rhythm and blues", Some("a")),
+            ("[^a-z ]", "abc dEf", Some("E")),
+            ("[a-c]*d", "xabcabd", Some("abcabd")),
+            ("[]a]+", "x]a]", Some("]a]")),
+            ("[a-]+", "b-a-", Some("-a-")),
+            ("\\d+", "room 101", Some("101")),
+            ("\\w+", "  hello_1 there", Some("hello_1")),
+            ("\\s\\S", "a b", Some(" b")),
+            ("[\\d.]+", "v1.25", Some("1.25")),
+            ("[\\D]+", "12ab3", Some("ab")),
+            ("ab*", "abbbc", Some("abbb")),
+            ("ab*", "ac", Some("a")),
+            ("ab+", "ac abb", Some("abb")),
+            ("colou?r", "my color", Some("color")),
+            ("colou?r", "my colour", Some("colour")),
+            ("cat|dog", "hotdog", Some("dog")),
+            ("a|ab", "ab", Some("a")),
+            ("(ab)+", "abababa", Some("ababab")),
+            ("(a|b)c", "xbc", Some("bc")),
+            ("^ab", "abab", Some("ab")),
+            ("^b", "ab", None),
+            ("b$", "abab", Some("b")),
+            ("a$", "ab", None),
+            ("^$", "", Some("")),
+            ("$", "ab", Some("")),
+            ("\\.\\*\\(", "a.*(b", Some(".*(")),
+            ("é+", "caféé!", Some("éé")),
+            ("x*", "abc", Some("")),
+            ("(a*)*b", "aab", Some("aab")),
+        ] {
+            assert_eq!(
+                first(pattern, text).as_deref(),
+                expected,
+                "{} in {:?}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn finds_every_match_without_overlap() {
+        assert_eq!(all("\\d+", "1 22 333"), ["1", "22", "333"]);
+        assert_eq!(all("aa", "aaaaa"), ["aa", "aa"]);
+        assert_eq!(all("a*", "baaa"), ["", "aaa", ""]);
+        assert!(all("z", "abc").is_empty());
+        assert_eq!(
+            evaluate("var x = matchAll(\"a1b22c\", \"[0-9]+\");"),
+            Ok("[1, 22]".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = matchAll(\"abc\", \"z\");"),
+            Ok("[]".to_string())
+        );
+    }
+
+    #[test]
+    fn match_returns_nil_without_a_match() {
+        assert_eq!(
+            evaluate("var x = match(\"kadom 2024\", \"[0-9]+\");"),
+            Ok("2024".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = match(\"kadom\", \"[0-9]+\");"),
+            Ok("nil".to_string())
+        );
+    }
+
+    #[test]
+    fn replaces_every_match() {
+        for (source, expected) in [
+            (
+                "var x = regexReplace(\"a1b22c333\", \"[0-9]+\", \"#\");",
+                "a#b#c#",
+            ),
+            (
+                "var x = regexReplace(\"  too   many  \", \" +\", \" \");",
+                " too many ",
+            ),
+            ("var x = regexReplace(\"abc\", \"x*\", \"-\");", "-a-b-c-"),
+            ("var x = regexReplace(\"abc\", \"z\", \"-\");", "abc"),
+            (
+                "var x = regexReplace(\"cat dog\", \"^|$\", \"|\");",
+                "|cat dog|",
+            ),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn invalid_patterns_point_at_the_problem() {
+        for (pattern, message) in [
+            ("a(b", "2: unclosed '('"),
+            ("ab)", "3: unmatched ')'"),
+            ("*a", "1: nothing for '*' to repeat"),
+            ("a|+", "3: nothing for '+' to repeat"),
+            ("a**", "3: nothing for '*' to repeat"),
+            ("^?", "2: nothing for '?' to repeat"),
+            ("x[ab", "2: unclosed '['"),
+            ("[z-a]", "2: range 'z-a' is out of order"),
+            ("[a-\\d]", "2: a class can't end a range"),
+            ("ab\\", "3: trailing '\\'"),
+            ("a\\q", "2: unknown escape '\\q'"),
+        ] {
+            assert_eq!(
+                Regex::new(pattern).unwrap_err(),
+                format!("invalid pattern at column {}", message),
+                "{}",
+                pattern
+            );
+        }
+        assert_eq!(
+            evaluate("var x = match(\"abc\", \"(a\");"),
+            Err("invalid pattern at column 1: unclosed '('".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = matchAll(\"abc\", 1);"),
+            Err("matchAll expects a string, got number".to_string())
+        );
+    }
+
+    #[test]
+    fn pathological_patterns_finish_quickly() {
+        let text = format!("{}b", "a".repeat(20_000));
+        let started = Instant::now();
+        assert_eq!(first("(a+)+$", &text), None);
+        assert_eq!(first("(a|aa)*c", &text), None);
+        assert_eq!(
+            first("(a*)*b", &text).map(|found| found.len()),
+            Some(20_001)
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,597 @@
+use super::{define_native, slice_range, NativeResult};
+use crate::{environment::Environment, expr::LiteralValue, interpreter::Interpreter};
+use std::{cell::RefCell, rc::Rc};
+
+// Defines the string functions in the given (global) environment. Like len, they count
+// characters rather than bytes.
+pub fn define(globals: &mut Environment) {
+    define_native(globals, "substring", 2..=3, substring);
+    define_native(globals, "split", 2..=2, split);
+    define_native(globals, "join", 2..=2, join);
+    define_native(globals, "upper", 1..=1, |_, arguments| {
+        convert("upper", arguments, str::to_uppercase)
+    });
+    define_native(globals, "lower", 1..=1, |_, arguments| {
+        convert("lower", arguments, str::to_lowercase)
+    });
+    define_native(globals, "trim", 1..=1, |_, arguments| {
+        convert("trim", arguments, |s| s.trim().to_string())
+    });
+    define_native(globals, "trimStart", 1..=1, |_, arguments| {
+        convert("trimStart", arguments, |s| s.trim_start().to_string())
+    });
+    define_native(globals, "trimEnd", 1..=1, |_, arguments| {
+        convert("trimEnd", arguments, |s| s.trim_end().to_string())
+    });
+    define_native(globals, "replace", 3..=3, replace);
+    define_native(globals, "parseNumber", 1..=2, parse_number_native);
+    define_native(globals, "startsWith", 2..=2, |_, arguments| {
+        test("startsWith", arguments, |s, prefix| s.starts_with(prefix))
+    });
+    define_native(globals, "endsWith", 2..=2, |_, arguments| {
+        test("endsWith", arguments, |s, suffix| s.ends_with(suffix))
+    });
+    define_native(globals, "format", 1..=usize::MAX, format_string);
+}
+
+pub(super) fn string<'a>(function: &str, value: &'a LiteralValue) -> Result<&'a str, String> {
+    match value {
+        LiteralValue::String(string) => Ok(string),
+        other => Err(format!(
+            "{} expects a string, got {}",
+            function,
+            other.type_name()
+        )),
+    }
+}
+
+// A new string made from the one argument. Strings are immutable, so the argument is untouched.
+fn convert(function: &str, arguments: Vec<LiteralValue>, op: fn(&str) -> String) -> NativeResult {
+    Ok(LiteralValue::String(
+        op(string(function, &arguments[0])?).into(),
+    ))
+}
+
+// Whether the first string relates to the second as op says. Every string contains, starts and
+// ends with the empty string.
+fn test(function: &str, arguments: Vec<LiteralValue>, op: fn(&str, &str) -> bool) -> NativeResult {
+    let text = string(function, &arguments[0])?;
+    let pattern = string(function, &arguments[1])?;
+    Ok(LiteralValue::from_bool(op(text, pattern)))
+}
+
+pub(super) fn contains(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    test("contains", arguments, |s, sub| s.contains(sub))
+}
+
+// Replaces every occurrence of from, scanning left to right. Occurrences that overlap one already
+// replaced are skipped, so replacing "aa" in "aaa" gives one replacement, not two.
+fn replace(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let text = string("replace", &arguments[0])?;
+    let from = string("replace", &arguments[1])?;
+    let to = string("replace", &arguments[2])?;
+    if from.is_empty() {
+        return Err("replace cannot replace an empty string".to_string());
+    }
+    Ok(LiteralValue::String(text.replace(from, to).into()))
+}
+
+// Fills each {} in the format with the next argument as print shows it, or each {n} with argument
+// n counting from 0. {{ and }} stand for literal braces. Every argument has to be used, so a
+// count that doesn't match the placeholders is an error.
+fn format_string(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let format = string("format", &arguments[0])?;
+    let values = &arguments[1..];
+    let mut used = vec![false; values.len()];
+    let (mut next, mut indexed) = (0, false);
+    let mut formatted = String::new();
+
+    let mut chars = format.chars().enumerate().peekable();
+    while let Some((column, c)) = chars.next() {
+        let column = column + 1;
+        match c {
+            '{' if chars.next_if(|&(_, c)| c == '{').is_some() => formatted.push('{'),
+            '}' if chars.next_if(|&(_, c)| c == '}').is_some() => formatted.push('}'),
+            '}' => return Err(format!("format has an unmatched '}}' at column {}", column)),
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '}')) => break,
+                        Some((_, c)) => placeholder.push(c),
+                        None => {
+                            return Err(format!(
+                                "format has an unmatched '{{' at column {}",
+                                column
+                            ))
+                        }
+                    }
+                }
+                let index = match placeholder.as_str() {
+                    "" => {
+                        next += 1;
+                        next - 1
+                    }
+                    digits if digits.bytes().all(|byte| byte.is_ascii_digit()) => {
+                        indexed = true;
+                        digits.parse().unwrap_or(usize::MAX)
+                    }
+                    _ => {
+                        return Err(format!(
+                            "format has an invalid placeholder '{{{}}}' at column {}",
+                            placeholder, column
+                        ))
+                    }
+                };
+                if index >= values.len() && !placeholder.is_empty() {
+                    return Err(format!(
+                        "format placeholder '{{{}}}' at column {} is out of range for {}",
+                        placeholder,
+                        column,
+                        counted(values.len(), "argument")
+                    ));
+                }
+                if let Some(value) = values.get(index) {
+                    formatted.push_str(&value.to_string());
+                    used[index] = true;
+                }
+            }
+            c => formatted.push(c),
+        }
+    }
+
+    let unused = used.iter().position(|used| !used);
+    if next > values.len() || (unused.is_some() && !indexed) {
+        return Err(format!(
+            "format has {} but got {}",
+            counted(next, "placeholder"),
+            counted(values.len(), "argument")
+        ));
+    }
+    if let Some(index) = unused {
+        return Err(format!("format never uses argument {}", index));
+    }
+    Ok(LiteralValue::String(formatted.into()))
+}
+
+// "1 argument", "2 arguments".
+fn counted(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        count => format!("{} {}s", count, noun),
+    }
+}
+
+// Reads a decimal number, allowing surrounding whitespace, a sign, a fraction and an exponent.
+// Strict parsing wants nothing else in the string. Otherwise the longest number at its start is
+// taken and the rest ignored, so "12px" is 12. None when there's no number or it's too large to
+// be finite.
+pub(super) fn parse_number(text: &str, strict: bool) -> Option<f64> {
+    let text = text.trim();
+    let bytes = text.as_bytes();
+    let digits_from = |start: usize| {
+        bytes[start..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count()
+    };
+
+    let mut end = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let whole = digits_from(end);
+    end += whole;
+    let mut fraction = 0;
+    if bytes.get(end) == Some(&b'.') {
+        fraction = digits_from(end + 1);
+        // A lone point isn't a number, but "5." and ".5" are.
+        if whole + fraction > 0 {
+            end += 1 + fraction;
+        }
+    }
+    if whole + fraction == 0 {
+        return None;
+    }
+    // The exponent only counts once it has digits.
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exponent = digits_from(end + 1 + sign);
+        if exponent > 0 {
+            end += 1 + sign + exponent;
+        }
+    }
+
+    if strict && end != text.len() {
+        return None;
+    }
+    text[..end].parse().ok().filter(|x: &f64| x.is_finite())
+}
+
+fn parse_number_native(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let text = string("parseNumber", &arguments[0])?;
+    let strict = match arguments.get(1) {
+        None | Some(LiteralValue::False) => false,
+        Some(LiteralValue::True) => true,
+        Some(other) => {
+            return Err(format!(
+                "parseNumber expects a boolean for strict, got {}",
+                other.type_name()
+            ))
+        }
+    };
+    Ok(parse_number(text, strict).map_or(LiteralValue::Nil, LiteralValue::Number))
+}
+
+// The characters from start up to but not including end, which defaults to the end of the string.
+fn substring(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let string = string("substring", &arguments[0])?;
+    let range = slice_range(
+        "substring",
+        "string",
+        string.chars().count(),
+        &arguments[1],
+        arguments.get(2),
+    )?;
+
+    let slice: String = string.chars().skip(range.start).take(range.len()).collect();
+    Ok(LiteralValue::String(slice.into()))
+}
+
+// The character index of the first occurrence of needle, or -1 if there is none. An empty needle
+// is found at 0.
+pub(super) fn index_of(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let haystack = string("indexOf", &arguments[0])?;
+    let needle = string("indexOf", &arguments[1])?;
+    let index = match haystack.find(needle) {
+        Some(byte) => haystack[..byte].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(LiteralValue::Number(index))
+}
+
+// The pieces between each separator, keeping empty ones. An empty separator splits the string
+// into its characters.
+fn split(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let text = string("split", &arguments[0])?;
+    let separator = string("split", &arguments[1])?;
+    let pieces: Vec<LiteralValue> = match separator {
+        "" => text
+            .chars()
+            .map(|c| LiteralValue::String(c.to_string().into()))
+            .collect(),
+        _ => text
+            .split(separator)
+            .map(|piece| LiteralValue::String(piece.into()))
+            .collect(),
+    };
+    Ok(LiteralValue::List(Rc::new(RefCell::new(pieces))))
+}
+
+// Joins a list of strings with a separator between each. Anything else in the list is an error
+// rather than being converted, as str() would.
+fn join(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let LiteralValue::List(items) = &arguments[0] else {
+        return Err(format!(
+            "join expects a list, got {}",
+            arguments[0].type_name()
+        ));
+    };
+    let separator = string("join", &arguments[1])?;
+
+    let mut joined = String::new();
+    for (index, item) in items.borrow().iter().enumerate() {
+        let LiteralValue::String(item) = item else {
+            return Err(format!(
+                "join expects a list of strings, got {} at index {}",
+                item.type_name(),
+                index
+            ));
+        };
+        if index > 0 {
+            joined.push_str(separator);
+        }
+        joined.push_str(item);
+    }
+    Ok(LiteralValue::String(joined.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_number;
+    use crate::natives::testing::evaluate;
+
+    #[test]
+    fn substring_counts_characters() {
+        for (source, expected) in [
+            ("var x = substring(\"kadom\", 1, 3);", "ad"),
+            ("var x = substring(\"kadom\", 2);", "dom"),
+            ("var x = substring(\"kadom\", 5);", ""),
+            ("var x = substring(\"kadom\", 0, 0);", ""),
+            ("var x = substring(\"crème brûlée\", 2, 5);", "ème"),
+            ("var x = substring(\"crème brûlée\", 6);", "brûlée"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn index_of_finds_the_first_occurrence() {
+        for (source, expected) in [
+            ("var x = indexOf(\"banana\", \"an\");", "1"),
+            ("var x = indexOf(\"banana\", \"x\");", "-1"),
+            ("var x = indexOf(\"banana\", \"\");", "0"),
+            ("var x = indexOf(\"\", \"a\");", "-1"),
+            ("var x = indexOf(\"crème brûlée\", \"brû\");", "6"),
+            ("var x = indexOf(\"éé\", \"é\");", "0"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn split_keeps_empty_fields() {
+        for (source, expected) in [
+            ("var x = split(\"a,b,,c\", \",\");", "[a, b, , c]"),
+            ("var x = len(split(\",a,\", \",\"));", "3"),
+            ("var x = split(\"a->b\", \"->\");", "[a, b]"),
+            ("var x = split(\"abc\", \";\");", "[abc]"),
+            ("var x = split(\"héé\", \"\");", "[h, é, é]"),
+            ("var x = len(split(\"\", \"\"));", "0"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn join_puts_the_separator_between_strings() {
+        for (source, expected) in [
+            ("var x = join([\"a\", \"b\"], \"-\");", "a-b"),
+            ("var x = join([], \"-\");", ""),
+            ("var x = join([\"only\"], \", \");", "only"),
+            ("var x = join(split(\"a,b,,c\", \",\"), \",\");", "a,b,,c"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn changes_case_and_trims() {
+        for (source, expected) in [
+            ("var x = upper(\"Kadom 1\");", "KADOM 1"),
+            ("var x = lower(\"Kadom 1\");", "kadom 1"),
+            ("var x = upper(\"crème\");", "CRÈME"),
+            ("var x = lower(\"ÉCOLE\");", "école"),
+            ("var x = upper(\"straße\");", "STRASSE"),
+            ("var x = \"[\" + trim(\"  a b \t\n\") + \"]\";", "[a b]"),
+            ("var x = \"[\" + trimStart(\"  a \") + \"]\";", "[a ]"),
+            ("var x = \"[\" + trimEnd(\"  a \") + \"]\";", "[  a]"),
+            ("var x = len(trim(\" \t \n \"));", "0"),
+            ("var s = \" Ab \"; var t = upper(s); var x = s;", " Ab "),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn format_fills_placeholders() {
+        for (source, expected) in [
+            (
+                "var x = format(\"hello {}, you are {} years old\", \"ada\", 36);",
+                "hello ada, you are 36 years old",
+            ),
+            (
+                "var x = format(\"{}{}{}\", nil, [1, \"a\"], 2.5);",
+                "nil[1, a]2.5",
+            ),
+            ("var x = format(\"no placeholders\");", "no placeholders"),
+            ("var x = format(\"{{}} {{{}}} }}{{\", 1);", "{} {1} }{"),
+            ("var x = format(\"{1} {0} {1}\", \"a\", \"b\");", "b a b"),
+            ("var x = format(\"{0}-{0}\", true);", "true-true"),
+            ("var x = format(\"{} {0}\", \"é\");", "é é"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn format_rejects_mismatches_and_bad_placeholders() {
+        for (source, message) in [
+            (
+                "var x = format(\"{} and {}\", 1);",
+                "format has 2 placeholders but got 1 argument",
+            ),
+            (
+                "var x = format(\"{}\", 1, 2);",
+                "format has 1 placeholder but got 2 arguments",
+            ),
+            (
+                "var x = format(\"{}\");",
+                "format has 1 placeholder but got 0 arguments",
+            ),
+            (
+                "var x = format(\"{1}\", 1);",
+                "format placeholder '{1}' at column 1 is out of range for 1 argument",
+            ),
+            (
+                "var x = format(\"{1}\", 1, 2);",
+                "format never uses argument 0",
+            ),
+            (
+                "var x = format(\"{2}\", 1, 2);",
+                "format placeholder '{2}' at column 1 is out of range for 2 arguments",
+            ),
+            (
+                "var x = format(\"ab {\", 1);",
+                "format has an unmatched '{' at column 4",
+            ),
+            (
+                "var x = format(\"é } {}\", 1);",
+                "format has an unmatched '}' at column 3",
+            ),
+            (
+                "var x = format(\"a {x}\", 1);",
+                "format has an invalid placeholder '{x}' at column 3",
+            ),
+            (
+                "var x = format(1, 2);",
+                "format expects a string, got number",
+            ),
+            ("var x = format();", "Expected at least 1 argument, got 0"),
+        ] {
+            assert_eq!(evaluate(source), Err(message.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn replaces_every_occurrence() {
+        for (source, expected) in [
+            ("var x = replace(\"a-b-c\", \"-\", \"+\");", "a+b+c"),
+            ("var x = replace(\"banana\", \"an\", \"\");", "ba"),
+            ("var x = replace(\"abc\", \"x\", \"y\");", "abc"),
+            ("var x = replace(\"aaa\", \"aa\", \"b\");", "ba"),
+            ("var x = replace(\"aaaa\", \"aa\", \"b\");", "bb"),
+            ("var x = replace(\"café\", \"é\", \"e\");", "cafe"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn tests_for_substrings() {
+        for (source, expected) in [
+            ("var x = contains(\"kadom\", \"do\");", "true"),
+            ("var x = contains(\"kadom\", \"od\");", "false"),
+            ("var x = contains(\"kadom\", \"\");", "true"),
+            ("var x = contains(\"\", \"\");", "true"),
+            ("var x = startsWith(\"kadom\", \"ka\");", "true"),
+            ("var x = startsWith(\"kadom\", \"om\");", "false"),
+            ("var x = startsWith(\"kadom\", \"kadom\");", "true"),
+            ("var x = endsWith(\"kadom\", \"om\");", "true"),
+            ("var x = endsWith(\"kadom\", \"kadom\");", "true"),
+            ("var x = endsWith(\"om\", \"kadom\");", "false"),
+        ] {
+            assert_eq!(evaluate(source), Ok(expected.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn parses_numbers_leniently_or_strictly() {
+        let long = "9".repeat(10_000);
+        let long_fraction = format!("0.{}", "1".repeat(10_000));
+        // Input, then the lenient and strict results.
+        for (input, lenient, strict) in [
+            ("42", Some(42.0), Some(42.0)),
+            ("  -2.5  ", Some(-2.5), Some(-2.5)),
+            ("+7", Some(7.0), Some(7.0)),
+            ("1e3", Some(1000.0), Some(1000.0)),
+            ("2.5E-2", Some(0.025), Some(0.025)),
+            (".5", Some(0.5), Some(0.5)),
+            ("5.", Some(5.0), Some(5.0)),
+            ("12px", Some(12.0), None),
+            ("1e", Some(1.0), None),
+            ("1e+", Some(1.0), None),
+            ("1.5.2", Some(1.5), None),
+            ("0x10", Some(0.0), None),
+            ("1 2", Some(1.0), None),
+            (".", None, None),
+            ("-", None, None),
+            ("--3", None, None),
+            ("", None, None),
+            ("   ", None, None),
+            ("abc", None, None),
+            ("inf", None, None),
+            ("NaN", None, None),
+            ("é1", None, None),
+            ("1e999", None, None),
+            (long.as_str(), None, None),
+            (
+                long_fraction.as_str(),
+                Some(0.1111111111111111),
+                Some(0.1111111111111111),
+            ),
+        ] {
+            assert_eq!(parse_number(input, false), lenient, "lenient {:.20}", input);
+            assert_eq!(parse_number(input, true), strict, "strict {:.20}", input);
+        }
+    }
+
+    #[test]
+    fn parse_number_native_is_lenient_unless_asked() {
+        assert_eq!(
+            evaluate("var x = [parseNumber(\"8 bits\"), parseNumber(\"8 bits\", true)];"),
+            Ok("[8, nil]".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = parseNumber(\" 1e2 \", false);"),
+            Ok("100".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = parseNumber(\"1\", 1);"),
+            Err("parseNumber expects a boolean for strict, got number".to_string())
+        );
+        assert_eq!(
+            evaluate("var x = parseNumber(1);"),
+            Err("parseNumber expects a string, got number".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_bad_ranges_and_arguments() {
+        for (source, message) in [
+            (
+                "var x = substring(\"héllo\", 2, 6);",
+                "substring index 6 is out of range for a string of length 5",
+            ),
+            (
+                "var x = substring(\"héllo\", -1);",
+                "substring index -1 is out of range for a string of length 5",
+            ),
+            (
+                "var x = substring(\"héllo\", 3, 1);",
+                "substring start 3 is after end 1 in a string of length 5",
+            ),
+            (
+                "var x = substring(\"héllo\", 0.5);",
+                "substring expects an integer, got 0.5",
+            ),
+            (
+                "var x = substring(1, 0);",
+                "substring expects a string, got number",
+            ),
+            (
+                "var x = indexOf(\"a\", nil);",
+                "indexOf expects a string, got nil",
+            ),
+            (
+                "var x = split(\"a\", 1);",
+                "split expects a string, got number",
+            ),
+            ("var x = upper(1);", "upper expects a string, got number"),
+            ("var x = trimEnd([]);", "trimEnd expects a string, got list"),
+            (
+                "var x = replace(\"abc\", \"\", \"-\");",
+                "replace cannot replace an empty string",
+            ),
+            (
+                "var x = replace(\"abc\", \"a\", nil);",
+                "replace expects a string, got nil",
+            ),
+            (
+                "var x = contains(1, \"1\");",
+                "contains expects a string or a list, got number",
+            ),
+            (
+                "var x = endsWith(\"a\", true);",
+                "endsWith expects a string, got boolean",
+            ),
+            (
+                "var x = join(\"ab\", \"\");",
+                "join expects a list, got string",
+            ),
+            (
+                "var x = join([\"a\", 1], \"\");",
+                "join expects a list of strings, got number at index 1",
+            ),
+        ] {
+            assert_eq!(evaluate(source), Err(message.to_string()), "{}", source);
+        }
+    }
+}
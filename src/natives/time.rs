@@ -0,0 +1,481 @@
+use super::{define_calling, define_native, integer, string::string, NativeResult};
+use crate::{
+    environment::Environment, error::RuntimeError, expr::LiteralValue, interpreter::Interpreter,
+    lexer::Token,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Defines the date and time functions in the given (global) environment. Times are whole
+// milliseconds since the Unix epoch, and every date and time is read and written in UTC.
+pub fn define(globals: &mut Environment) {
+    define_native(globals, "now", 0..=0, now);
+    define_native(globals, "formatTime", 2..=2, format_time);
+    define_native(globals, "parseTime", 2..=2, parse_time);
+    define_calling(globals, "sleep", 1..=1, sleep);
+}
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+fn now(_: &mut Interpreter, _: Vec<LiteralValue>) -> NativeResult {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| format!("System clock is before the Unix epoch: {}", err))?;
+
+    Ok(LiteralValue::Number(elapsed.as_millis() as f64))
+}
+
+// Waits for a number of milliseconds on the interpreter's clock, then returns nil.
+fn sleep(
+    interpreter: &mut Interpreter,
+    arguments: Vec<LiteralValue>,
+    paren: &Token,
+) -> Result<LiteralValue, RuntimeError> {
+    let duration = match &arguments[0] {
+        LiteralValue::Number(millis) => Duration::try_from_secs_f64(millis / 1000.0).ok(),
+        _ => None,
+    };
+    let Some(duration) = duration else {
+        let got = match &arguments[0] {
+            number @ LiteralValue::Number(_) => number.to_string(),
+            other => other.type_name().to_string(),
+        };
+        return Err(RuntimeError::new(
+            paren,
+            format!(
+                "sleep expects a non-negative number of milliseconds, got {}",
+                got
+            ),
+        ));
+    };
+    interpreter.sleep(duration, paren)?;
+    Ok(LiteralValue::Nil)
+}
+
+// The parts of a format: %Y is a four digit year, %m, %d, %H, %M and %S two digit months, days,
+// hours, minutes and seconds, and %% a percent sign. Anything else stands for itself.
+#[derive(Clone, Copy)]
+enum Piece {
+    Literal(char),
+    Field(Field),
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl Field {
+    fn width(self) -> usize {
+        match self {
+            Field::Year => 4,
+            _ => 2,
+        }
+    }
+}
+
+fn pieces(function: &str, format: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            pieces.push(Piece::Literal(c));
+            continue;
+        }
+        let field = match chars.next() {
+            Some('Y') => Field::Year,
+            Some('m') => Field::Month,
+            Some('d') => Field::Day,
+            Some('H') => Field::Hour,
+            Some('M') => Field::Minute,
+            Some('S') => Field::Second,
+            Some('%') => {
+                pieces.push(Piece::Literal('%'));
+                continue;
+            }
+            Some(other) => {
+                return Err(format!(
+                    "{} format has an unknown specifier '%{}'",
+                    function, other
+                ))
+            }
+            None => return Err(format!("{} format ends with a lone '%'", function)),
+        };
+        pieces.push(Piece::Field(field));
+    }
+    Ok(pieces)
+}
+
+// A UTC date and time to the second, within the years 0 to 9999 so the year always fits %Y.
+#[derive(Debug, PartialEq)]
+struct DateTime {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+}
+
+impl DateTime {
+    // The date and time a number of milliseconds after the epoch falls in.
+    fn from_millis(millis: i64) -> Option<Self> {
+        let days = millis.div_euclid(MILLIS_PER_DAY);
+        let seconds = millis.rem_euclid(MILLIS_PER_DAY) / 1000;
+        let (year, month, day) = civil_from_days(days);
+        (0..=9999).contains(&year).then_some(Self {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+        })
+    }
+
+    // None when a field is out of range, like a 30th of February or a 24th hour.
+    fn to_millis(&self) -> Option<i64> {
+        let valid = (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && (0..24).contains(&self.hour)
+            && (0..60).contains(&self.minute)
+            && (0..60).contains(&self.second);
+        let seconds = self.hour * 3600 + self.minute * 60 + self.second;
+        valid.then(|| {
+            days_from_civil(self.year, self.month, self.day) * MILLIS_PER_DAY + seconds * 1000
+        })
+    }
+
+    fn field(&mut self, field: Field) -> &mut i64 {
+        match field {
+            Field::Year => &mut self.year,
+            Field::Month => &mut self.month,
+            Field::Day => &mut self.day,
+            Field::Hour => &mut self.hour,
+            Field::Minute => &mut self.minute,
+            Field::Second => &mut self.second,
+        }
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days from 1970-01-01 to the given date in the proleptic Gregorian calendar. Years are counted
+// from March, putting the leap day last, and grouped into 400 year eras that all have the same
+// number of days.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// The inverse of days_from_civil, as a year, month and day.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn format_time(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let millis = integer("formatTime", &arguments[0])?;
+    let pieces = pieces("formatTime", string("formatTime", &arguments[1])?)?;
+    let mut time = DateTime::from_millis(millis)
+        .ok_or_else(|| format!("formatTime time {} is outside the years 0 to 9999", millis))?;
+
+    let mut formatted = String::new();
+    for piece in pieces {
+        match piece {
+            Piece::Literal(c) => formatted.push(c),
+            Piece::Field(field) => {
+                let width = field.width();
+                formatted.push_str(&format!("{:0width$}", time.field(field)));
+            }
+        }
+    }
+    Ok(LiteralValue::String(formatted.into()))
+}
+
+// The time the text shows, or nil when it doesn't match the format or names a date or time that
+// doesn't exist. Fields must have exactly the digits formatTime writes, and fields missing from
+// the format are taken from 1970-01-01 00:00:00.
+fn parse_time(_: &mut Interpreter, arguments: Vec<LiteralValue>) -> NativeResult {
+    let text = string("parseTime", &arguments[0])?;
+    let pieces = pieces("parseTime", string("parseTime", &arguments[1])?)?;
+    let parsed = read_time(text, &pieces).and_then(|time| time.to_millis());
+    Ok(parsed.map_or(LiteralValue::Nil, |millis| {
+        LiteralValue::Number(millis as f64)
+    }))
+}
+
+fn read_time(text: &str, pieces: &[Piece]) -> Option<DateTime> {
+    let mut time = DateTime {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+    let mut rest = text;
+    for &piece in pieces {
+        match piece {
+            Piece::Literal(c) => rest = rest.strip_prefix(c)?,
+            Piece::Field(field) => {
+                let digits = rest.get(..field.width())?;
+                if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+                    return None;
+                }
+                *time.field(field) = digits.parse().ok()?;
+                rest = &rest[field.width()..];
+            }
+        }
+    }
+    rest.is_empty().then_some(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interpreter::Clock,
+        natives::testing::{evaluate, run},
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+    fn format(millis: i64) -> Result<String, String> {
+        evaluate(&format!("var x = formatTime({}, \"{}\");", millis, FORMAT))
+    }
+
+    fn parse(text: &str) -> Result<String, String> {
+        evaluate(&format!("var x = parseTime(\"{}\", \"{}\");", text, FORMAT))
+    }
+
+    #[test]
+    fn formats_fixed_times_in_utc() {
+        for (millis, expected) in [
+            (0, "1970-01-01 00:00:00"),
+            (999, "1970-01-01 00:00:00"),
+            (-1, "1969-12-31 23:59:59"),
+            (951_782_400_000, "2000-02-29 00:00:00"),
+            (951_868_799_000, "2000-02-29 23:59:59"),
+            (951_868_800_000, "2000-03-01 00:00:00"),
+            (1_709_251_199_000, "2024-02-29 23:59:59"),
+            (4_107_542_399_000, "2100-02-28 23:59:59"),
+            (4_107_542_400_000, "2100-03-01 00:00:00"),
+            (1_704_067_199_000, "2023-12-31 23:59:59"),
+            (1_704_067_200_000, "2024-01-01 00:00:00"),
+            (1_714_521_600_000, "2024-05-01 00:00:00"),
+            (-62_167_219_200_000, "0000-01-01 00:00:00"),
+            (253_402_300_799_000, "9999-12-31 23:59:59"),
+        ] {
+            assert_eq!(format(millis), Ok(expected.to_string()), "{}", millis);
+        }
+        assert_eq!(
+            evaluate("var x = formatTime(1700000000000, \"%d/%m/%Y at %H.%M, 100%%\");"),
+            Ok("14/11/2023 at 22.13, 100%".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_what_it_formats() {
+        for millis in [
+            0,
+            -86_400_000,
+            951_782_400_000,
+            1_709_251_199_000,
+            1_700_000_000_000,
+            -62_167_219_200_000,
+            253_402_300_799_000,
+        ] {
+            let text = format(millis).unwrap();
+            assert_eq!(parse(&text), Ok(millis.to_string()), "{}", text);
+        }
+        assert_eq!(
+            evaluate("var x = parseTime(\"12:30\", \"%H:%M\");"),
+            Ok("45000000".to_string())
+        );
+        // Every day from 1900 to 2100 survives the trip through a date.
+        for days in -25_567..47_482 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+            assert!((1..=days_in_month(year, month)).contains(&day));
+        }
+    }
+
+    #[test]
+    fn parse_returns_nil_on_mismatch() {
+        for text in [
+            "2024-02-30 00:00:00",
+            "2023-02-29 00:00:00",
+            "2100-02-29 00:00:00",
+            "2024-13-01 00:00:00",
+            "2024-04-31 00:00:00",
+            "2024-01-01 24:00:00",
+            "2024-01-01 00:60:00",
+            "2024-1-01 00:00:00",
+            "2024-01-01T00:00:00",
+            "2024-01-01 00:00:00 ",
+            "2024-01-01",
+            "2024-01-01 +0:00:00",
+        ] {
+            assert_eq!(parse(text), Ok("nil".to_string()), "{}", text);
+        }
+        assert_eq!(
+            parse("2024-02-29 12:00:00"),
+            Ok("1709208000000".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        for (source, message) in [
+            (
+                "var x = formatTime(1.5, \"%Y\");",
+                "formatTime expects an integer, got 1.5",
+            ),
+            (
+                "var x = formatTime(0, \"%y\");",
+                "formatTime format has an unknown specifier '%y'",
+            ),
+            (
+                "var x = parseTime(\"1\", \"%Y%\");",
+                "parseTime format ends with a lone '%'",
+            ),
+            (
+                "var x = parseTime(1, \"%Y\");",
+                "parseTime expects a string, got number",
+            ),
+            (
+                "var x = formatTime(253402300800000, \"%Y\");",
+                "formatTime time 253402300800000 is outside the years 0 to 9999",
+            ),
+        ] {
+            assert_eq!(evaluate(source), Err(message.to_string()), "{}", source);
+        }
+    }
+
+    // An interpreter whose clock only moves when it sleeps, recording each sleep.
+    fn recording() -> (Interpreter, Rc<RefCell<Vec<Duration>>>) {
+        let slept = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        let (now, record) = (slept.clone(), slept.clone());
+        interpreter.clock = Clock {
+            now: Box::new(move || now.borrow().iter().sum()),
+            sleep: Box::new(move |duration| {
+                record.borrow_mut().push(duration);
+                Ok(())
+            }),
+        };
+        (interpreter, slept)
+    }
+
+    #[test]
+    fn sleep_waits_on_the_interpreters_clock() {
+        let (mut interpreter, slept) = recording();
+        run(
+            &mut interpreter,
+            "var x = [sleep(250), sleep(0), sleep(1.5)];",
+        )
+        .unwrap();
+        let value = interpreter.environment.borrow().lookup("x").unwrap();
+        assert_eq!(value.to_string(), "[nil, nil, nil]");
+        assert_eq!(
+            *slept.borrow(),
+            [
+                Duration::from_millis(250),
+                Duration::ZERO,
+                Duration::from_micros(1500)
+            ]
+        );
+
+        interpreter.clock.sleep = Box::new(|_| Err("sleep is not allowed here".to_string()));
+        assert_eq!(
+            run(&mut interpreter, "sleep(1);"),
+            Err("sleep is not allowed here".to_string())
+        );
+    }
+
+    #[test]
+    fn sleep_rejects_bad_durations() {
+        for (source, message) in [
+            (
+                "sleep(-1);",
+                "sleep expects a non-negative number of milliseconds, got -1",
+            ),
+            (
+                "sleep(NAN);",
+                "sleep expects a non-negative number of milliseconds, got NaN",
+            ),
+            (
+                "sleep(\"1\");",
+                "sleep expects a non-negative number of milliseconds, got string",
+            ),
+        ] {
+            let (mut interpreter, slept) = recording();
+            assert_eq!(run(&mut interpreter, source), Err(message.to_string()));
+            assert!(slept.borrow().is_empty());
+        }
+    }
+
+    #[test]
+    fn sleep_counts_against_the_timeout() {
+        let (mut interpreter, slept) = recording();
+        interpreter.timeout = Some(Duration::from_millis(100));
+        interpreter.output = Box::new(std::io::sink());
+        assert_eq!(
+            run(
+                &mut interpreter,
+                "sleep(60);\ntry { sleep(60); } catch (e) {}\nprint 1;"
+            ),
+            Err("execution timed out after 100ms (limit 100ms)".to_string())
+        );
+        // The second sleep is cut short at the timeout.
+        assert_eq!(
+            *slept.borrow(),
+            [Duration::from_millis(60), Duration::from_millis(40)]
+        );
+
+        // Each run gets the whole budget again.
+        slept.borrow_mut().clear();
+        assert_eq!(run(&mut interpreter, "sleep(99); sleep(1);"), Ok(()));
+    }
+
+    #[test]
+    fn now_is_whole_milliseconds_after_the_epoch() {
+        let now = evaluate("var x = now();").unwrap();
+        let millis: i64 = now.parse().unwrap();
+        assert!(millis > 1_700_000_000_000);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::{
+    expr::{self, Expr, LiteralValue},
+    stmt::{FunctionDeclaration, Stmt},
+};
+use std::rc::Rc;
+
+// Folds constant expressions in a program before it runs: a unary or binary operator on literals
+// becomes the literal it evaluates to, and parentheses around a literal go away. Operators that
+// would fail at run time, like a division by zero, are left alone so the error still happens when
+// and where it would have.
+pub fn fold_constants(statements: &mut [Stmt], ieee_division: bool) {
+    let folder = Folder { ieee_division };
+    for statement in statements {
+        folder.statement(statement);
+    }
+}
+
+pub fn fold_expression(expression: &mut Expr, ieee_division: bool) {
+    Folder { ieee_division }.expression(expression);
+}
+
+struct Folder {
+    ieee_division: bool,
+}
+
+impl Folder {
+    fn statements(&self, statements: &mut [Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&self, statement: &mut Stmt) {
+        match statement {
+            // Without a message the failure shows the expression as written, so it stays.
+            Stmt::Assert {
+                expression,
+                message: Some(message),
+                ..
+            } => {
+                self.expression(expression);
+                self.expression(message);
+            }
+            Stmt::Assert { message: None, .. } => (),
+            Stmt::Block { statements, .. } => self.statements(statements),
+            Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Import { .. } => (),
+            Stmt::Class { methods, .. } => {
+                for method in methods {
+                    self.function(method);
+                }
+            }
+            Stmt::DoWhile {
+                body, condition, ..
+            } => {
+                self.statement(body);
+                self.expression(condition);
+            }
+            Stmt::Expression { expression, .. } | Stmt::Print { expression, .. } => {
+                self.expression(expression)
+            }
+            Stmt::Function { declaration, .. } => self.function(declaration),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+                ..
+            } => {
+                self.expression(subject);
+                for (value, body) in cases {
+                    self.expression(value);
+                    self.statements(body);
+                }
+                if let Some(default) = default {
+                    self.statements(default);
+                }
+            }
+            Stmt::Throw { value, .. } => self.expression(value),
+            Stmt::Try { body, handler, .. } => {
+                self.statements(body);
+                self.statements(handler);
+            }
+            Stmt::Var { initialiser, .. } => {
+                if let Some(initialiser) = initialiser {
+                    self.expression(initialiser);
+                }
+            }
+            Stmt::VarDestructure { initialiser, .. } => self.expression(initialiser),
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.expression(condition);
+                self.statement(body);
+            }
+        }
+    }
+
+    // A declaration straight from the parser has no other owner. One that's already shared may be
+    // running, so it's left as it is.
+    fn function(&self, declaration: &mut Rc<FunctionDeclaration>) {
+        let Some(declaration) = Rc::get_mut(declaration) else {
+            return;
+        };
+        for param in &mut declaration.params {
+            if let Some(default) = &mut param.default {
+                self.expression(default);
+            }
+        }
+        self.statements(&mut declaration.body);
+    }
+
+    // Folds the children first, so nested constants collapse from the inside out.
+    fn expression(&self, expression: &mut Expr) {
+        let folded = match expression {
+            Expr::Assign { value, .. } => {
+                self.expression(value);
+                None
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.expression(right);
+                match (literal(left), literal(right)) {
+                    (Some(left), Some(right)) => {
+                        expr::binary(left, operator, right, self.ieee_division).ok()
+                    }
+                    _ => None,
+                }
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                None
+            }
+            Expr::Get { object, .. } => {
+                self.expression(object);
+                None
+            }
+            Expr::Grouping { expression } => {
+                self.expression(expression);
+                literal(expression)
+            }
+            Expr::List { elements } => {
+                for element in elements {
+                    self.expression(element);
+                }
+                None
+            }
+            Expr::Literal { .. } | Expr::This { .. } | Expr::Variable { .. } => None,
+            Expr::Map { entries } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                None
+            }
+            Expr::Set { object, value, .. } => {
+                self.expression(object);
+                self.expression(value);
+                None
+            }
+            Expr::Unary { operator, right } => {
+                self.expression(right);
+                literal(right).and_then(|right| expr::unary(operator, right).ok())
+            }
+        };
+
+        if let Some(value) = folded {
+            *expression = Expr::Literal { value };
+        }
+    }
+}
+
+fn literal(expression: &Expr) -> Option<LiteralValue> {
+    match expression {
+        Expr::Literal { value } => Some(value.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, interpreter::Interpreter};
+    use std::{cell::RefCell, io::Write};
+
+    fn folded(source: &str) -> Vec<String> {
+        let mut statements = compile(source).unwrap();
+        fold_constants(&mut statements, false);
+        statements.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn folds_operators_on_literals() {
+        assert_eq!(
+            folded(
+                "print 60 * 60 * 24;
+                 print \"a\" + \"b\" + 1;
+                 print -(2 + 3) < 0 == !nil;
+                 print x * (60 * 60);
+                 print \"ab\" in (\"c\" + \"ab\");"
+            ),
+            [
+                "(print 86400)",
+                "(print ab1)",
+                "(print true)",
+                "(print (* var x 3600))",
+                "(print true)",
+            ]
+        );
+    }
+
+    #[test]
+    fn folds_inside_functions_and_control_flow() {
+        assert_eq!(
+            folded(
+                "fun f(a = 1 + 1) { while (a < 2 * 5) a = a + (3 - 2); return [(4), {(\"k\"): 2 * 2}]; }
+                 class C { m() { return (1) + (2); } }"
+            ),
+            [
+                "(fun f ((= a 2)) (while (< var a 10) (; (= a (+ var a 1)))) (return (list 4 (map (k 4)))))",
+                "(class C (fun m () (return 3)))",
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_failing_operators_alone() {
+        assert_eq!(
+            folded(
+                "print 1 / 0; print 2 % (1 - 1); print -\"s\"; print \"s\" - 1; assert 1 + 1 == 3;"
+            ),
+            [
+                "(print (/ 1 0))",
+                "(print (% 2 0))",
+                "(print (- s))",
+                "(print (- s 1))",
+                "(assert (== (+ 1 1) 3))",
+            ]
+        );
+
+        let mut statements = compile("print 1 / 0;").unwrap();
+        fold_constants(&mut statements, true);
+        assert_eq!(statements[0].to_string(), "(print inf)");
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run(source: &str, optimize: bool) -> (Vec<u8>, Option<String>) {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        interpreter.optimize = optimize;
+        let error = interpreter
+            .interpret(compile(source).unwrap())
+            .err()
+            .map(|err| err.to_string());
+        let printed = output.0.borrow().clone();
+        (printed, error)
+    }
+
+    #[test]
+    fn programs_behave_the_same_when_folded() {
+        for source in [
+            "var day = 60 * 60 * 24; var i = 0; while (i < 3) { print day * i + (\"n\" + \"=\") + i; i = i + 1; }",
+            "print 0.1 + 0.2; print \"ab\" * (1 + 2); print [1 + 1, -(-3)] == [2, 3];",
+            "print 1;\nprint 10 / (5 - 5);\nprint 2;",
+            "print 1;\nassert 2 * 2 == 5;",
+        ] {
+            assert_eq!(run(source, true), run(source, false), "{}", source);
+        }
+    }
+}
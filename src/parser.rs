@@ -1,20 +1,37 @@
+use crate::diagnostics::Diagnostic;
+use crate::error::KadomError;
 use crate::expr::{Expr, Expr::*, LiteralValue};
 use crate::lexer::{Token, TokenType, TokenType::*};
-use crate::stmt::Stmt;
+use crate::stmt::{FunctionDeclaration, Parameter, Stmt};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
 
 #[derive(Debug)]
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    loop_depth: usize,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+    // Tokens normally come from a Scanner, which ends them with Eof. Any other stream gets one
+    // added, so the parser always has a token to look at.
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        if tokens.last().is_none_or(|token| *token.token_type() != Eof) {
+            let line = tokens.last().map_or(1, |token| token.line());
+            tokens.push(Token::new(Eof, String::new(), None, line, 1));
+        }
+        Self {
+            tokens,
+            current: 0,
+            loop_depth: 0,
+        }
     }
 
     fn match_token(&mut self, types: &[TokenType]) -> bool {
-        for token_type in types {
+        for &token_type in types {
             if self.check(token_type) {
                 self.advance();
                 return true;
@@ -24,15 +41,15 @@ impl Parser {
         false
     }
 
-    fn check(&self, token_type: &TokenType) -> bool {
+    fn check(&self, token_type: TokenType) -> bool {
         if self.is_at_end() {
             return false;
         }
 
-        self.peek().token_type == *token_type
+        *self.peek().token_type() == token_type
     }
 
-    fn advance(&mut self) -> Token {
+    fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
         }
@@ -41,20 +58,32 @@ impl Parser {
     }
 
     fn is_at_end(&self) -> bool {
-        self.peek().token_type == Eof
+        *self.peek().token_type() == Eof
+    }
+
+    // Only called after advancing, but falls back to the first token rather than underflowing.
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current.saturating_sub(1)]
     }
 
-    fn previous(&self) -> Token {
-        self.tokens[self.current - 1].clone()
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
     }
 
-    fn peek(&self) -> Token {
-        self.tokens[self.current].clone()
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, KadomError> {
+        let (statements, errors) = self.parse_all();
+
+        match errors.is_empty() {
+            true => Ok(statements),
+            false => Err(KadomError::from_diagnostics(errors, KadomError::parse)),
+        }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, String> {
+    // Parses every statement it can, returning them alongside the errors for the rest.
+    // Each error is located at the token the parser had reached when it gave up.
+    pub fn parse_all(&mut self) -> (Vec<Stmt>, Vec<Diagnostic>) {
         let mut statements: Vec<Stmt> = Vec::new();
-        let mut errors: Vec<String> = Vec::new();
+        let mut errors: Vec<Diagnostic> = Vec::new();
 
         while !self.is_at_end() {
             match self.declaration() {
@@ -62,71 +91,471 @@ impl Parser {
                     statements.push(statement);
                 }
                 Err(msg) => {
+                    let token = self.peek();
+                    errors.push(Diagnostic::new(token.line(), Some(token.column()), msg));
                     self.synchronise();
-                    errors.push(msg);
                 }
             }
         }
 
-        if errors.is_empty() {
-            Ok(statements)
-        } else {
-            Err(errors.join("\n"))
+        (statements, errors)
+    }
+
+    // Parses source holding a single expression, with an optional trailing semicolon.
+    pub fn parse_expression(&mut self) -> Result<Expr, KadomError> {
+        self.standalone_expression()
+            .map_err(|message| KadomError::Parse {
+                line: self.peek().line(),
+                message,
+            })
+    }
+
+    fn standalone_expression(&mut self) -> Result<Expr, String> {
+        if self.check(Var) || self.check(Fun) || self.check(Class) {
+            let keyword = self.peek();
+            return Err(format!(
+                "Expected an expression but found a \'{}\' declaration; run declarations with interpret",
+                keyword.lexeme()
+            ));
+        }
+
+        let expression = self.expression()?;
+        self.match_token(&[Semicolon]);
+        if !self.is_at_end() {
+            return Err("Expected end of expression".to_string());
         }
+
+        Ok(expression)
     }
 
     fn declaration(&mut self) -> Result<Stmt, String> {
         if self.match_token(&[Var]) {
             self.var_declaration()
+        } else if self.match_token(&[Fun]) {
+            let line = self.previous().line();
+            Ok(Stmt::Function {
+                declaration: self.function("function")?,
+                line,
+            })
+        } else if self.match_token(&[Class]) {
+            self.class_declaration()
         } else {
             self.statement()
         }
     }
 
+    fn class_declaration(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line();
+        let name = self.consume(Identifier, "Expected class name")?.clone();
+        self.consume(LeftBrace, "Expected \'{\' before class body")?;
+
+        let mut methods = Vec::new();
+        while !self.check(RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+
+        self.consume(RightBrace, "Expected \'}\' after class body")?;
+        Ok(Stmt::Class {
+            name,
+            methods,
+            line,
+        })
+    }
+
+    fn function(&mut self, kind: &str) -> Result<Rc<FunctionDeclaration>, String> {
+        let name = self
+            .consume(Identifier, &format!("Expected {} name", kind))?
+            .clone();
+        self.consume(LeftParent, &format!("Expected \'(\' after {} name", kind))?;
+
+        let mut params: Vec<Parameter> = Vec::new();
+        let mut rest = None;
+        if !self.check(RightParent) {
+            loop {
+                if self.match_token(&[DotDotDot]) {
+                    rest = Some(
+                        self.consume(Identifier, "Expected rest parameter name")?
+                            .clone(),
+                    );
+                    if self.check(Comma) {
+                        return Err("Rest parameter must be the last parameter".to_string());
+                    }
+                    break;
+                }
+
+                let name = self.consume(Identifier, "Expected parameter name")?.clone();
+
+                let default = if self.match_token(&[Equal]) {
+                    Some(self.expression()?)
+                } else if params.iter().any(|param| param.default.is_some()) {
+                    return Err(format!(
+                        "Parameter \'{}\' without a default cannot follow one with a default",
+                        name.lexeme()
+                    ));
+                } else {
+                    None
+                };
+
+                params.push(Parameter { name, default });
+                if !self.match_token(&[Comma]) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(RightParent, "Expected \')\' after parameters")?;
+        self.consume(LeftBrace, &format!("Expected \'{{\' before {} body", kind))?;
+
+        // Loops don't extend into the function body, so break/continue there are errors.
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        let body = self.block();
+        self.loop_depth = loop_depth;
+
+        Ok(Rc::new(FunctionDeclaration {
+            name,
+            params,
+            rest,
+            body: body?,
+        }))
+    }
+
     fn var_declaration(&mut self) -> Result<Stmt, String> {
-        let name = self.consume(Identifier, "Expected variable name")?;
+        let line = self.previous().line();
+        if self.match_token(&[LeftBracket]) {
+            return self.var_destructure(line);
+        }
+
+        let name = self.consume(Identifier, "Expected variable name")?.clone();
 
-        let mut initialiser = Expr::Literal {
-            value: LiteralValue::Nil,
+        let initialiser = if self.match_token(&[Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
         };
 
-        if self.match_token(&[Equal]) {
-            initialiser = self.expression()?;
+        self.consume(Semicolon, "Expected \';\' after statement")?;
+        Ok(Stmt::Var {
+            name,
+            initialiser,
+            line,
+        })
+    }
+
+    fn var_destructure(&mut self, line: u64) -> Result<Stmt, String> {
+        let bracket = self.previous().clone();
+        let mut names = Vec::new();
+
+        loop {
+            names.push(
+                self.consume(Identifier, "Expected variable name in list pattern")?
+                    .clone(),
+            );
+            if !self.match_token(&[Comma]) {
+                break;
+            }
         }
 
+        self.consume(RightBracket, "Expected \']\' after list pattern")?;
+        self.consume(Equal, "Expected \'=\' after list pattern")?;
+        let initialiser = self.expression()?;
         self.consume(Semicolon, "Expected \';\' after statement")?;
-        Ok(Stmt::Var { name, initialiser })
+
+        Ok(Stmt::VarDestructure {
+            bracket,
+            names,
+            initialiser,
+            line,
+        })
     }
 
     fn statement(&mut self) -> Result<Stmt, String> {
         if self.match_token(&[Print]) {
             self.print_statement()
+        } else if self.match_token(&[Assert]) {
+            self.assert_statement()
+        } else if self.match_token(&[Import]) {
+            self.import_statement()
+        } else if self.match_token(&[LeftBrace]) {
+            let line = self.previous().line();
+            Ok(Stmt::Block {
+                statements: self.block()?,
+                line,
+            })
+        } else if self.match_token(&[If]) {
+            self.if_statement()
+        } else if self.match_token(&[While]) {
+            self.while_statement()
+        } else if self.match_token(&[Do]) {
+            self.do_while_statement()
+        } else if self.match_token(&[Switch]) {
+            self.switch_statement()
+        } else if self.match_token(&[Break, Continue]) {
+            self.loop_jump_statement()
+        } else if self.match_token(&[Return]) {
+            self.return_statement()
+        } else if self.match_token(&[Throw]) {
+            self.throw_statement()
+        } else if self.match_token(&[Try]) {
+            self.try_statement()
         } else {
             self.expression_statement()
         }
     }
 
+    fn block(&mut self) -> Result<Vec<Stmt>, String> {
+        let mut statements = Vec::new();
+
+        while !self.check(RightBrace) && !self.is_at_end() {
+            statements.push(self.declaration()?);
+        }
+
+        self.consume(RightBrace, "Expected \'}\' after block")?;
+        Ok(statements)
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line();
+        self.consume(LeftParent, "Expected \'(\' after \'if\'")?;
+        let condition = self.expression()?;
+        self.consume(RightParent, "Expected \')\' after if condition")?;
+
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.match_token(&[Else]) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            line,
+        })
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line();
+        self.consume(LeftParent, "Expected \'(\' after \'while\'")?;
+        let condition = self.expression()?;
+        self.consume(RightParent, "Expected \')\' after condition")?;
+        let body = self.loop_body()?;
+
+        Ok(Stmt::While {
+            condition,
+            body: Box::new(body),
+            line,
+        })
+    }
+
+    fn do_while_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line();
+        let body = self.loop_body()?;
+
+        self.consume(While, "Expected \'while\' after do-while body")?;
+        self.consume(LeftParent, "Expected \'(\' after \'while\'")?;
+        let condition = self.expression()?;
+        self.consume(RightParent, "Expected \')\' after condition")?;
+        self.consume(Semicolon, "Expected \';\' after do-while condition")?;
+
+        Ok(Stmt::DoWhile {
+            body: Box::new(body),
+            condition,
+            line,
+        })
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, String> {
+        let keyword = self.previous().clone();
+        let value = if self.check(Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+
+        self.consume(Semicolon, "Expected \';\' after return value")?;
+        Ok(Stmt::Return { keyword, value })
+    }
+
+    fn switch_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line();
+        self.consume(LeftParent, "Expected \'(\' after \'switch\'")?;
+        let subject = self.expression()?;
+        self.consume(RightParent, "Expected \')\' after switch subject")?;
+        self.consume(LeftBrace, "Expected \'{\' before switch cases")?;
+
+        let mut cases = Vec::new();
+        let mut default = None;
+
+        while !self.check(RightBrace) && !self.is_at_end() {
+            if self.match_token(&[Case]) {
+                let value = self.expression()?;
+                self.consume(Colon, "Expected \':\' after case value")?;
+                cases.push((value, self.switch_arm()?));
+            } else if self.match_token(&[Default]) {
+                if default.is_some() {
+                    return Err("Duplicate \'default\' in switch".to_string());
+                }
+                self.consume(Colon, "Expected \':\' after \'default\'")?;
+                default = Some(self.switch_arm()?);
+            } else {
+                return Err("Expected \'case\' or \'default\'".to_string());
+            }
+        }
+
+        self.consume(RightBrace, "Expected \'}\' after switch cases")?;
+        Ok(Stmt::Switch {
+            subject,
+            cases,
+            default,
+            line,
+        })
+    }
+
+    fn switch_arm(&mut self) -> Result<Vec<Stmt>, String> {
+        let mut statements = Vec::new();
+
+        while !self.check(Case)
+            && !self.check(Default)
+            && !self.check(RightBrace)
+            && !self.is_at_end()
+        {
+            statements.push(self.declaration()?);
+        }
+
+        Ok(statements)
+    }
+
+    fn loop_body(&mut self) -> Result<Stmt, String> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    fn loop_jump_statement(&mut self) -> Result<Stmt, String> {
+        let keyword = self.previous().clone();
+        if self.loop_depth == 0 {
+            return Err(format!("\'{}\' used outside of a loop", keyword.lexeme()));
+        }
+
+        self.consume(Semicolon, "Expected \';\' after statement")?;
+        let line = keyword.line();
+        match keyword.token_type() {
+            Break => Ok(Stmt::Break { line }),
+            _ => Ok(Stmt::Continue { line }),
+        }
+    }
+
     fn print_statement(&mut self) -> Result<Stmt, String> {
+        let keyword = self.previous().clone();
+        let expression = self.expression()?;
+        self.consume(Semicolon, "Expected \';\' after statement")?;
+        Ok(Stmt::Print {
+            keyword,
+            expression,
+        })
+    }
+
+    fn assert_statement(&mut self) -> Result<Stmt, String> {
+        let keyword = self.previous().clone();
         let expression = self.expression()?;
+
+        let message = if self.match_token(&[Comma]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
         self.consume(Semicolon, "Expected \';\' after statement")?;
-        Ok(Stmt::Print { expression })
+        Ok(Stmt::Assert {
+            keyword,
+            expression,
+            message,
+        })
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, String> {
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
+        self.consume(Semicolon, "Expected \';\' after thrown value")?;
+        Ok(Stmt::Throw { keyword, value })
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line();
+        self.consume(LeftBrace, "Expected \'{\' after \'try\'")?;
+        let body = self.block()?;
+
+        self.consume(Catch, "Expected \'catch\' after try block")?;
+        self.consume(LeftParent, "Expected \'(\' after \'catch\'")?;
+        let name = self
+            .consume(Identifier, "Expected name of caught value")?
+            .clone();
+        self.consume(RightParent, "Expected \')\' after caught value name")?;
+        self.consume(LeftBrace, "Expected \'{\' before catch block")?;
+        let handler = self.block()?;
+
+        Ok(Stmt::Try {
+            body,
+            name,
+            handler,
+            line,
+        })
+    }
+
+    fn import_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line();
+        let path = self
+            .consume(StringLiteral, "Expected file path after \'import\'")?
+            .clone();
+        self.consume(Semicolon, "Expected \';\' after statement")?;
+        Ok(Stmt::Import { path, line })
     }
 
     fn expression_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.peek().line();
         let expression = self.expression()?;
-        println!("{expression}");
         self.consume(Semicolon, "Expected \';\' after statement")?;
-        Ok(Stmt::Expression { expression })
+        Ok(Stmt::Expression { expression, line })
     }
 
     fn expression(&mut self) -> Result<Expr, String> {
-        self.equality()
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Result<Expr, String> {
+        let expr = self.equality()?;
+
+        if self.match_token(&[Equal]) {
+            let value = self.assignment()?;
+
+            return match expr {
+                Variable { name, .. } => Ok(Assign {
+                    name,
+                    value: Box::new(value),
+                    local: Cell::new(None),
+                }),
+                Get {
+                    object,
+                    name,
+                    optional: false,
+                } => Ok(Set {
+                    object,
+                    name,
+                    value: Box::new(value),
+                }),
+                _ => Err("Invalid assignment target".to_string()),
+            };
+        }
+
+        Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expr, String> {
         let mut expr = self.comparison()?;
         while self.match_token(&[BangEqual, EqualEqual]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
             let rhs = self.comparison()?;
 
             expr = Binary {
@@ -141,8 +570,8 @@ impl Parser {
     fn comparison(&mut self) -> Result<Expr, String> {
         let mut expr = self.term()?;
 
-        while self.match_token(&[Greater, GreaterEqual, Less, LessEqual]) {
-            let operator = self.previous();
+        while self.match_token(&[Greater, GreaterEqual, Less, LessEqual, In]) {
+            let operator = self.previous().clone();
             let rhs = self.term()?;
 
             expr = Binary {
@@ -159,7 +588,7 @@ impl Parser {
         let mut expr = self.factor()?;
 
         while self.match_token(&[Plus, Minus]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
             let rhs = self.factor()?;
 
             expr = Binary {
@@ -175,8 +604,8 @@ impl Parser {
     fn factor(&mut self) -> Result<Expr, String> {
         let mut expr = self.unary()?;
 
-        while self.match_token(&[Star, Slash]) {
-            let operator = self.previous();
+        while self.match_token(&[Star, Slash, Percent]) {
+            let operator = self.previous().clone();
             let rhs = self.unary()?;
 
             expr = Binary {
@@ -191,7 +620,7 @@ impl Parser {
 
     fn unary(&mut self) -> Result<Expr, String> {
         if self.match_token(&[Bang, Minus]) {
-            let operator = self.previous();
+            let operator = self.previous().clone();
             let rhs = self.unary()?;
 
             return Ok(Unary {
@@ -200,7 +629,59 @@ impl Parser {
             });
         }
 
-        self.primary()
+        self.call()
+    }
+
+    fn call(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.match_token(&[LeftParent]) {
+                let arguments = self.expression_list(RightParent)?;
+                let paren = self
+                    .consume(RightParent, "Expected \')\' after arguments")?
+                    .clone();
+
+                expr = Call {
+                    callee: Box::new(expr),
+                    paren,
+                    arguments,
+                    scopes: RefCell::new(None),
+                }
+            } else if self.match_token(&[Dot, QuestionDot]) {
+                let optional = *self.previous().token_type() == QuestionDot;
+                let name = self
+                    .consume(Identifier, "Expected property name after \'.\'")?
+                    .clone();
+
+                expr = Get {
+                    object: Box::new(expr),
+                    name,
+                    optional,
+                }
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    // Comma separated expressions up to (but not including) the terminator. A single trailing
+    // comma before the terminator is allowed.
+    fn expression_list(&mut self, terminator: TokenType) -> Result<Vec<Expr>, String> {
+        let mut expressions = Vec::new();
+
+        if !self.check(terminator) {
+            loop {
+                expressions.push(self.expression()?);
+                if !self.match_token(&[Comma]) || self.check(terminator) {
+                    break;
+                }
+            }
+        }
+
+        Ok(expressions)
     }
 
     fn primary(&mut self) -> Result<Expr, String> {
@@ -210,24 +691,52 @@ impl Parser {
             Ok(Grouping {
                 expression: Box::new(expr),
             })
+        } else if self.match_token(&[LeftBracket]) {
+            let elements = self.expression_list(RightBracket)?;
+            self.consume(RightBracket, "Expected \']\' after list elements")?;
+            Ok(List { elements })
+        } else if self.match_token(&[LeftBrace]) {
+            self.map_literal()
+        } else if self.match_token(&[This]) {
+            Ok(Expr::This {
+                keyword: self.previous().clone(),
+                local: Cell::new(None),
+            })
         } else if self.match_token(&[Identifier]) {
             Ok(Variable {
-                name: self.previous(),
+                name: self.previous().clone(),
+                local: Cell::new(None),
             })
         } else if self.match_token(&[False, True, StringLiteral, Number, Nil]) {
             Ok(Literal {
                 value: LiteralValue::from_token(self.previous())?,
             })
         } else {
-            Err(format!(
-                "Expected expression on line {}",
-                self.peek().line_number
-            ))
+            Err("Expected expression".to_string())
         }
     }
 
-    fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<Token, String> {
-        if self.check(&token_type) {
+    fn map_literal(&mut self) -> Result<Expr, String> {
+        let mut entries = Vec::new();
+
+        while !self.check(RightBrace) {
+            let key = self.expression()?;
+            self.consume(Colon, "Expected \':\' after map key")?;
+            let value = self.expression()?;
+            entries.push((key, value));
+
+            if !self.match_token(&[Comma]) {
+                break;
+            }
+        }
+
+        self.consume(RightBrace, "Expected \'}\' after map entries")?;
+        Ok(Map { entries })
+    }
+
+    // Tokens kept in the tree are cloned by the caller; the rest are only checked and skipped.
+    fn consume(&mut self, token_type: TokenType, msg: &str) -> Result<&Token, String> {
+        if self.check(token_type) {
             Ok(self.advance())
         } else {
             Err(msg.to_string())
@@ -238,10 +747,10 @@ impl Parser {
         self.advance();
 
         while !self.is_at_end() {
-            if self.previous().token_type == Semicolon {
+            if *self.previous().token_type() == Semicolon {
                 return;
             }
-            match self.previous().token_type {
+            match self.previous().token_type() {
                 Class | Fun | Var | For | If | While | Print | Return => return,
                 _ => (),
             };
@@ -250,3 +759,171 @@ impl Parser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Scanner;
+
+    fn parse_expression(source: &str) -> Result<Expr, KadomError> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+        let mut statements = Parser::new(tokens).parse()?;
+        match statements.remove(0) {
+            Stmt::Expression { expression, .. } => Ok(expression),
+            _ => panic!("expected an expression statement"),
+        }
+    }
+
+    #[test]
+    fn trailing_comma_accepted() {
+        let call = parse_expression("f(\n  1,\n  2,\n);").unwrap();
+        assert_eq!(call.to_string(), "(call var f 1 2)");
+
+        let list = parse_expression("[1, 2, 3,];").unwrap();
+        assert_eq!(list.to_string(), "(list 1 2 3)");
+    }
+
+    #[test]
+    fn double_comma_rejected() {
+        assert!(parse_expression("f(1,,2);").is_err());
+        assert!(parse_expression("[1,,2];").is_err());
+    }
+
+    #[test]
+    fn lone_comma_rejected() {
+        assert!(parse_expression("f(,);").is_err());
+        assert!(parse_expression("[,];").is_err());
+    }
+
+    #[test]
+    fn duplicate_switch_default_rejected() {
+        let tokens =
+            Scanner::new("switch (1) {\n default: print 1;\n default: print 2;\n}".to_string())
+                .scan_tokens()
+                .unwrap();
+        let err = Parser::new(tokens).parse().err().unwrap().to_string();
+        assert!(err.contains("Duplicate 'default' in switch"));
+    }
+
+    #[test]
+    fn required_parameter_after_default_rejected() {
+        let tokens = Scanner::new("fun f(a, b = 1, c) {}".to_string())
+            .scan_tokens()
+            .unwrap();
+        let err = Parser::new(tokens).parse().err().unwrap().to_string();
+        assert!(err.contains("Parameter 'c' without a default cannot follow one with a default"));
+    }
+
+    #[test]
+    fn rest_parameter_must_be_last() {
+        let tokens = Scanner::new("fun f(...rest, a) {}".to_string())
+            .scan_tokens()
+            .unwrap();
+        let err = Parser::new(tokens).parse().err().unwrap().to_string();
+        assert!(err.contains("Rest parameter must be the last parameter"));
+    }
+
+    #[test]
+    fn optional_property_access() {
+        assert_eq!(
+            parse_expression("a?.b.c();").unwrap().to_string(),
+            "(call (. (?. var a b) c))"
+        );
+        assert!(parse_expression("a?.b = 1;").is_err());
+    }
+
+    #[test]
+    fn empty_call_and_list() {
+        assert_eq!(
+            parse_expression("f();").unwrap().to_string(),
+            "(call var f)"
+        );
+        assert_eq!(parse_expression("[];").unwrap().to_string(), "(list)");
+    }
+
+    #[test]
+    fn standalone_expression_must_span_the_source() {
+        let parse = |source: &str| {
+            let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+            Parser::new(tokens).parse_expression()
+        };
+
+        assert_eq!(parse("1 + 2").unwrap().to_string(), "(+ 1 2)");
+        assert_eq!(parse("1 + 2;").unwrap().to_string(), "(+ 1 2)");
+        assert_eq!(
+            parse("1; 2").err().unwrap(),
+            KadomError::Parse {
+                line: 1,
+                message: "Expected end of expression".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn empty_token_stream_parses_to_nothing() {
+        assert_eq!(Parser::new(Vec::new()).parse(), Ok(Vec::new()));
+        assert!(Parser::new(Vec::new()).parse_expression().is_err());
+    }
+
+    #[test]
+    fn token_stream_without_eof_is_an_error_not_a_panic() {
+        let tokens = vec![
+            Token::new(Print, "print".to_string(), None, 1, 1),
+            Token::new(Minus, "-".to_string(), None, 1, 7),
+        ];
+        assert_eq!(
+            Parser::new(tokens).parse(),
+            Err(KadomError::Parse {
+                line: 1,
+                message: "Expected expression".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn literal_from_a_non_literal_token_is_an_error() {
+        let token = Token::new(Plus, "+".to_string(), None, 3, 1);
+        assert_eq!(
+            LiteralValue::from_token(&token),
+            Err("Cannot make a literal from a Plus token".to_string())
+        );
+
+        // A number token the scanner didn't give a value.
+        let token = Token::new(Number, "1".to_string(), None, 1, 1);
+        assert!(LiteralValue::from_token(&token).is_err());
+    }
+
+    // Feeds pseudo-random input through the scanner and parser. Errors are expected; panics
+    // aren't.
+    #[test]
+    fn random_input_never_panics() {
+        const PIECES: &[&str] = &[
+            "var", "fun", "class", "(", ")", "{", "}", "[", "]", ",", ".", "?.", "...", "=", "==",
+            "!", "-", "+", "*", "/", ";", ":", "\"", "1", "2.5", "x", "this", "return", "if",
+            "else", "while", "do", "break", "switch", "case", "default", "try", "catch", "throw",
+            "import", "assert", "print", "\n", " ", "//", "é", "\0",
+        ];
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            // xorshift64, so the inputs are the same on every run.
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..500 {
+            let length = next() % 40;
+            let source: String = (0..length)
+                .map(|_| match next() % 4 {
+                    0 => char::from((next() % 128) as u8).to_string(),
+                    _ => PIECES[(next() % PIECES.len() as u64) as usize].to_string(),
+                })
+                .collect();
+
+            let (tokens, _) = Scanner::new(source.clone()).scan_all();
+            let _ = Parser::new(tokens.clone()).parse_all();
+            let _ = Parser::new(tokens).parse_expression();
+        }
+    }
+}
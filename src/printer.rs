@@ -0,0 +1,443 @@
+use crate::{
+    expr::{Expr, ExprVisitor, LiteralValue},
+    lexer::Token,
+    stmt::{FunctionDeclaration, Stmt, StmtVisitor},
+};
+use std::rc::Rc;
+
+const INDENT: &str = "    ";
+
+// Prints a program back as kadom source in a canonical layout: one statement per line, blocks
+// indented by four spaces and single spaces around binary operators. Comments aren't part of the
+// tree, so they don't survive. Parsing the output gives the same statements back, which makes
+// formatting idempotent.
+pub fn format_program(statements: &[Stmt]) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        depth: 0,
+    };
+    for statement in statements {
+        printer.line(statement);
+    }
+    printer.out
+}
+
+struct Printer {
+    out: String,
+    // How many blocks deep the statement being printed is.
+    depth: usize,
+}
+
+impl Printer {
+    fn write(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    // Writes a statement on its own line at the current depth.
+    fn line(&mut self, statement: &Stmt) {
+        self.indent();
+        statement.accept(self);
+        self.write("\n");
+    }
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    // Writes braces around the statements, each on its own line one level deeper. An empty body
+    // stays on one line.
+    fn block(&mut self, statements: &[Stmt]) {
+        if statements.is_empty() {
+            return self.write("{}");
+        }
+
+        self.write("{\n");
+        self.depth += 1;
+        for statement in statements {
+            self.line(statement);
+        }
+        self.depth -= 1;
+        self.indent();
+        self.write("}");
+    }
+
+    // Writes a function's name, parameters and body, as shared by functions and methods.
+    fn function(&mut self, declaration: &FunctionDeclaration) {
+        self.write(declaration.name.lexeme());
+        self.write("(");
+        for (i, param) in declaration.params.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.write(param.name.lexeme());
+            if let Some(default) = &param.default {
+                self.write(" = ");
+                default.accept(self);
+            }
+        }
+        if let Some(rest) = &declaration.rest {
+            if !declaration.params.is_empty() {
+                self.write(", ");
+            }
+            self.write("...");
+            self.write(rest.lexeme());
+        }
+        self.write(") ");
+        self.block(&declaration.body);
+    }
+
+    fn expressions(&mut self, expressions: &[Expr]) {
+        for (i, expression) in expressions.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            expression.accept(self);
+        }
+    }
+}
+
+impl StmtVisitor<()> for Printer {
+    fn visit_assert(&mut self, _: &Token, expression: &Expr, message: Option<&Expr>) {
+        self.write("assert ");
+        expression.accept(self);
+        if let Some(message) = message {
+            self.write(", ");
+            message.accept(self);
+        }
+        self.write(";");
+    }
+
+    fn visit_block(&mut self, statements: &[Stmt]) {
+        self.block(statements);
+    }
+
+    fn visit_break(&mut self) {
+        self.write("break;");
+    }
+
+    fn visit_class(&mut self, name: &Token, methods: &[Rc<FunctionDeclaration>]) {
+        self.write("class ");
+        self.write(name.lexeme());
+        if methods.is_empty() {
+            return self.write(" {}");
+        }
+
+        self.write(" {\n");
+        self.depth += 1;
+        for method in methods {
+            self.indent();
+            self.function(method);
+            self.write("\n");
+        }
+        self.depth -= 1;
+        self.indent();
+        self.write("}");
+    }
+
+    fn visit_continue(&mut self) {
+        self.write("continue;");
+    }
+
+    fn visit_do_while(&mut self, body: &Stmt, condition: &Expr) {
+        self.write("do ");
+        body.accept(self);
+        self.write(" while (");
+        condition.accept(self);
+        self.write(");");
+    }
+
+    fn visit_expression(&mut self, expression: &Expr) {
+        expression.accept(self);
+        self.write(";");
+    }
+
+    fn visit_function(&mut self, declaration: &Rc<FunctionDeclaration>) {
+        self.write("fun ");
+        self.function(declaration);
+    }
+
+    fn visit_if(&mut self, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) {
+        self.write("if (");
+        condition.accept(self);
+        self.write(") ");
+        then_branch.accept(self);
+        if let Some(else_branch) = else_branch {
+            self.write(" else ");
+            else_branch.accept(self);
+        }
+    }
+
+    fn visit_import(&mut self, path: &Token) {
+        self.write("import ");
+        self.write(path.lexeme());
+        self.write(";");
+    }
+
+    fn visit_print(&mut self, _: &Token, expression: &Expr) {
+        self.write("print ");
+        expression.accept(self);
+        self.write(";");
+    }
+
+    fn visit_return(&mut self, _: &Token, value: Option<&Expr>) {
+        self.write("return");
+        if let Some(value) = value {
+            self.write(" ");
+            value.accept(self);
+        }
+        self.write(";");
+    }
+
+    fn visit_switch(
+        &mut self,
+        subject: &Expr,
+        cases: &[(Expr, Vec<Stmt>)],
+        default: Option<&[Stmt]>,
+    ) {
+        self.write("switch (");
+        subject.accept(self);
+        self.write(") {\n");
+        self.depth += 1;
+        let arms = cases
+            .iter()
+            .map(|(value, body)| (Some(value), body.as_slice()))
+            .chain(default.map(|body| (None, body)));
+        for (value, body) in arms {
+            self.indent();
+            match value {
+                Some(value) => {
+                    self.write("case ");
+                    value.accept(self);
+                    self.write(":\n");
+                }
+                None => self.write("default:\n"),
+            }
+            self.depth += 1;
+            for statement in body {
+                self.line(statement);
+            }
+            self.depth -= 1;
+        }
+        self.depth -= 1;
+        self.indent();
+        self.write("}");
+    }
+
+    fn visit_throw(&mut self, _: &Token, value: &Expr) {
+        self.write("throw ");
+        value.accept(self);
+        self.write(";");
+    }
+
+    fn visit_try(&mut self, body: &[Stmt], name: &Token, handler: &[Stmt]) {
+        self.write("try ");
+        self.block(body);
+        self.write(" catch (");
+        self.write(name.lexeme());
+        self.write(") ");
+        self.block(handler);
+    }
+
+    fn visit_var(&mut self, name: &Token, initialiser: Option<&Expr>) {
+        self.write("var ");
+        self.write(name.lexeme());
+        if let Some(initialiser) = initialiser {
+            self.write(" = ");
+            initialiser.accept(self);
+        }
+        self.write(";");
+    }
+
+    fn visit_var_destructure(&mut self, _: &Token, names: &[Token], initialiser: &Expr) {
+        let names: Vec<&str> = names.iter().map(|name| name.lexeme()).collect();
+        self.write(&format!("var [{}] = ", names.join(", ")));
+        initialiser.accept(self);
+        self.write(";");
+    }
+
+    fn visit_while(&mut self, condition: &Expr, body: &Stmt) {
+        self.write("while (");
+        condition.accept(self);
+        self.write(") ");
+        body.accept(self);
+    }
+}
+
+impl ExprVisitor<()> for Printer {
+    fn visit_assign(&mut self, name: &Token, value: &Expr) {
+        self.write(name.lexeme());
+        self.write(" = ");
+        value.accept(self);
+    }
+
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) {
+        left.accept(self);
+        self.write(&format!(" {} ", operator.lexeme()));
+        right.accept(self);
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _: &Token, arguments: &[Expr]) {
+        callee.accept(self);
+        self.write("(");
+        self.expressions(arguments);
+        self.write(")");
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Token, optional: bool) {
+        object.accept(self);
+        self.write(if optional { "?." } else { "." });
+        self.write(name.lexeme());
+    }
+
+    fn visit_grouping(&mut self, expression: &Expr) {
+        self.write("(");
+        expression.accept(self);
+        self.write(")");
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) {
+        self.write("[");
+        self.expressions(elements);
+        self.write("]");
+    }
+
+    fn visit_literal(&mut self, value: &LiteralValue) {
+        match value {
+            // Strings have no escapes, so the contents go back between quotes as they are.
+            LiteralValue::String(string) => self.write(&format!("\"{}\"", string)),
+            value => self.write(&value.to_string()),
+        }
+    }
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) {
+        self.write("{");
+        for (i, (key, value)) in entries.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            key.accept(self);
+            self.write(": ");
+            value.accept(self);
+        }
+        self.write("}");
+    }
+
+    fn visit_set(&mut self, object: &Expr, name: &Token, value: &Expr) {
+        object.accept(self);
+        self.write(".");
+        self.write(name.lexeme());
+        self.write(" = ");
+        value.accept(self);
+    }
+
+    fn visit_this(&mut self, _: &Token) {
+        self.write("this");
+    }
+
+    fn visit_unary(&mut self, operator: &Token, right: &Expr) {
+        self.write(operator.lexeme());
+        right.accept(self);
+    }
+
+    fn visit_variable(&mut self, name: &Token) {
+        self.write(name.lexeme());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Scanner, parser::Parser};
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    fn format(source: &str) -> String {
+        format_program(&parse(source))
+    }
+
+    // The statements in S-expression form, which leaves out token positions.
+    fn shape(statements: &[Stmt]) -> Vec<String> {
+        statements.iter().map(|s| s.to_string()).collect()
+    }
+
+    // Formatting keeps the program the same and formatting again changes nothing.
+    fn assert_round_trips(source: &str) {
+        let formatted = format(source);
+        assert_eq!(shape(&parse(&formatted)), shape(&parse(source)));
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn cleans_up_messy_spacing() {
+        let source = "var   x=1 ;fun add(a,b=2 , ...rest){return a+b*( x-1 );}
+            if(x>=1)print \"big\";else{print -x;}
+              while (x<3) { x=x+1 ; }";
+        assert_eq!(
+            format(source),
+            "var x = 1;
+fun add(a, b = 2, ...rest) {
+    return a + b * (x - 1);
+}
+if (x >= 1) print \"big\"; else {
+    print -x;
+}
+while (x < 3) {
+    x = x + 1;
+}
+"
+        );
+        assert_round_trips(source);
+    }
+
+    #[test]
+    fn formats_classes_switches_and_handlers() {
+        let source = "class Point{init(x){this.x=x;} empty(){}}
+            switch(p.x){case 1: print 1; print 2; default: print nil;}
+            try{throw {\"k\": [1,2,]};}catch(e){print e?.k;}
+            do { x = x - 1; } while (!done);
+            var [a,b]=[1,2]; assert a in b, \"no\"; import \"lib.kdm\";";
+        assert_eq!(
+            format(source),
+            "class Point {
+    init(x) {
+        this.x = x;
+    }
+    empty() {}
+}
+switch (p.x) {
+    case 1:
+        print 1;
+        print 2;
+    default:
+        print nil;
+}
+try {
+    throw {\"k\": [1, 2]};
+} catch (e) {
+    print e?.k;
+}
+do {
+    x = x - 1;
+} while (!done);
+var [a, b] = [1, 2];
+assert a in b, \"no\";
+import \"lib.kdm\";
+"
+        );
+        assert_round_trips(source);
+    }
+
+    #[test]
+    fn round_trips_nested_control_flow() {
+        assert_round_trips(
+            "fun f(n) { while (true) { if (n > 1) if (n > 2) break; else continue; n = n - 1; } }
+             print f(3)(4).g.h = {}; print [] ; {} { var y; }",
+        );
+        assert_round_trips("print 1.5 * -(-2) / 100000000000000000000 % 3 != 0 == false;");
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+// What the profiler saw for one source line.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LineProfile {
+    // Statements on the line that were executed.
+    pub hits: u64,
+    // Time spent executing them, including any statements and calls they ran.
+    pub time: Duration,
+    // Expressions evaluated while a statement on the line was the innermost one running.
+    pub expressions: u64,
+}
+
+// Records per-line hit counts and timings as the tree-walker executes statements. Lines are only
+// tracked within one file, so an imported module's lines add to the script's lines of the same
+// number.
+pub struct Profiler {
+    // Time since some fixed start, read as each statement starts and ends.
+    clock: Box<dyn FnMut() -> Duration>,
+    lines: BTreeMap<u64, LineProfile>,
+    // Lines of the statements being executed, innermost last.
+    running: Vec<u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let start = Instant::now();
+        Self::with_clock(move || start.elapsed())
+    }
+
+    // A profiler reading time from `clock` instead of the system clock.
+    pub fn with_clock(clock: impl FnMut() -> Duration + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+            lines: BTreeMap::new(),
+            running: Vec::new(),
+        }
+    }
+
+    pub fn lines(&self) -> &BTreeMap<u64, LineProfile> {
+        &self.lines
+    }
+
+    // Counts a statement on `line` starting, returning the time to hand to exit when it's done.
+    pub(crate) fn enter(&mut self, line: u64) -> Duration {
+        self.lines.entry(line).or_default().hits += 1;
+        self.running.push(line);
+        (self.clock)()
+    }
+
+    // Adds the time since `started` to the line of the innermost running statement. A line
+    // that's still running further out, as in a recursive call, gets the time when that
+    // outer statement finishes instead, so it isn't counted twice.
+    pub(crate) fn exit(&mut self, started: Duration) {
+        let elapsed = (self.clock)().saturating_sub(started);
+        let Some(line) = self.running.pop() else {
+            return;
+        };
+        if !self.running.contains(&line) {
+            self.lines.entry(line).or_default().time += elapsed;
+        }
+    }
+
+    pub(crate) fn expression(&mut self) {
+        if let Some(line) = self.running.last() {
+            self.lines.entry(*line).or_default().expressions += 1;
+        }
+    }
+
+    // A table of every line that ran, slowest first, followed by the `top` lines that evaluated
+    // the most expressions.
+    pub fn report(&self, top: usize) -> String {
+        let mut by_time: Vec<(&u64, &LineProfile)> = self.lines.iter().collect();
+        by_time.sort_by(|(a_line, a), (b_line, b)| {
+            b.time
+                .cmp(&a.time)
+                .then(b.hits.cmp(&a.hits))
+                .then(a_line.cmp(b_line))
+        });
+
+        let mut report = format!("{:>6} {:>10} {:>12}\n", "line", "hits", "total ms");
+        for (line, profile) in &by_time {
+            report.push_str(&format!(
+                "{:>6} {:>10} {:>12.3}\n",
+                line,
+                profile.hits,
+                profile.time.as_secs_f64() * 1000.0
+            ));
+        }
+
+        let mut by_expressions: Vec<(&u64, &LineProfile)> = self
+            .lines
+            .iter()
+            .filter(|(_, profile)| profile.expressions > 0)
+            .collect();
+        by_expressions.sort_by(|(a_line, a), (b_line, b)| {
+            b.expressions.cmp(&a.expressions).then(a_line.cmp(b_line))
+        });
+
+        report.push_str(&format!("\n{:>6} {:>11}\n", "line", "expressions"));
+        for (line, profile) in by_expressions.into_iter().take(top) {
+            report.push_str(&format!("{:>6} {:>11}\n", line, profile.expressions));
+        }
+        report
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, interpreter::Interpreter};
+    use std::{cell::Cell, rc::Rc};
+
+    // A clock that moves on a millisecond every time it's read.
+    fn ticking_clock() -> impl FnMut() -> Duration {
+        let now = Rc::new(Cell::new(0));
+        move || {
+            now.set(now.get() + 1);
+            Duration::from_millis(now.get())
+        }
+    }
+
+    fn profile(source: &str) -> Profiler {
+        let mut interpreter = Interpreter::with_output(Box::new(std::io::sink()));
+        interpreter.profile = Some(Profiler::with_clock(ticking_clock()));
+        interpreter.interpret(compile(source).unwrap()).unwrap();
+        interpreter.profile.unwrap()
+    }
+
+    #[test]
+    fn counts_each_execution_of_a_line() {
+        let profiler = profile(
+            "var i = 0;
+             while (i < 25) {
+                 i = i + 1;
+             }
+             fun f(n) { if (n > 0) f(n - 1); }
+             f(3);",
+        );
+        let lines = profiler.lines();
+
+        assert_eq!(lines[&1].hits, 1);
+        assert_eq!(lines[&2].hits, 1 + 25);
+        assert_eq!(lines[&3].hits, 25);
+        assert_eq!(lines[&3].expressions, 25 * 4);
+        // The declaration, an if for each of the four calls and the three calls it makes.
+        assert_eq!(lines[&5].hits, 1 + 4 + 3);
+    }
+
+    #[test]
+    fn recursion_is_timed_once() {
+        let profiler = profile("fun f(n) {\nif (n > 0) f(n - 1);\n}\nf(2);");
+        let lines = profiler.lines();
+
+        assert_eq!(lines[&2].hits, 3 + 2);
+        // The outermost if on line 2 starts at 4ms and finishes at 13ms. The statements nested
+        // inside it on the same line add nothing of their own.
+        assert_eq!(lines[&2].time, Duration::from_millis(9));
+        assert_eq!(lines[&4].time, Duration::from_millis(11));
+    }
+
+    #[test]
+    fn renders_a_stable_report() {
+        let profiler = profile("var a = 1;\nprint a + a * a;\nvar b = [a, a];");
+        assert_eq!(
+            profiler.report(2),
+            "  line       hits     total ms
+     1          1        1.000
+     2          1        1.000
+     3          1        1.000
+
+  line expressions
+     2           5
+     3           3
+"
+        );
+    }
+}
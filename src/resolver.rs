@@ -0,0 +1,611 @@
+use crate::{
+    diagnostics::Diagnostic,
+    error::KadomError,
+    expr::{Expr, Local, ScopeNames},
+    lexer::Token,
+    natives,
+    stmt::{FunctionDeclaration, Stmt},
+};
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+}
+
+struct Binding {
+    slot: usize,
+    defined: bool,
+    read: bool,
+    // Where a `var` declared it. Only these are warned about when never read.
+    declaration: Option<Token>,
+}
+
+// Redeclaring a name takes a new slot, since the interpreter appends every local it defines.
+#[derive(Default)]
+struct Scope {
+    bindings: HashMap<String, Binding>,
+    slots: usize,
+}
+
+// Walks a parsed program before it runs, recording on each variable use how many scopes out its
+// binding lives and reporting errors that can be found without running anything.
+pub struct Resolver {
+    // Local scopes, innermost last. Globals aren't tracked, so anything not found here is left
+    // unresolved.
+    scopes: Vec<Scope>,
+    function: FunctionType,
+    in_class: bool,
+    errors: Vec<Diagnostic>,
+    warnings: Vec<Diagnostic>,
+    // Top-level `var` declarations, and every name read that didn't resolve to a local. Globals
+    // can be read before they're declared, so unused ones are only known at the end.
+    globals: Vec<Token>,
+    global_reads: HashSet<String>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: Vec::new(),
+            function: FunctionType::None,
+            in_class: false,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            globals: Vec::new(),
+            global_reads: HashSet::new(),
+        }
+    }
+
+    // A resolver for source that eval() runs inside the given scopes. The source gets a scope of
+    // its own on top, so its declarations don't disturb the slots of the caller's.
+    pub fn with_scopes(scopes: &ScopeNames) -> Self {
+        let mut resolver = Self::new();
+        for names in scopes {
+            let bindings = names
+                .iter()
+                .map(|(name, slot)| {
+                    let binding = Binding {
+                        slot: *slot,
+                        defined: true,
+                        read: true,
+                        declaration: None,
+                    };
+                    (name.clone(), binding)
+                })
+                .collect();
+            let slots = names.values().max().map_or(0, |slot| slot + 1);
+            resolver.scopes.push(Scope { bindings, slots });
+        }
+        resolver.in_class = scopes.iter().any(|names| names.contains_key("this"));
+        resolver.begin_scope();
+        resolver
+    }
+
+    pub fn resolve(self, statements: &[Stmt]) -> Result<(), KadomError> {
+        join(self.resolve_all(statements))
+    }
+
+    // Resolves the statements and returns every error found.
+    pub fn resolve_all(mut self, statements: &[Stmt]) -> Vec<Diagnostic> {
+        self.resolve_statements(statements);
+        self.errors
+    }
+
+    // Resolves the statements and returns a warning for every variable declared with `var` whose
+    // value is never read. Names starting with `_` are exempt.
+    pub fn warnings(mut self, statements: &[Stmt]) -> Vec<Diagnostic> {
+        self.resolve_statements(statements);
+        for name in std::mem::take(&mut self.globals) {
+            if !self.global_reads.contains(name.lexeme()) {
+                self.unused(&name);
+            }
+        }
+
+        self.warnings
+            .sort_by_key(|warning| (warning.line, warning.column));
+        self.warnings
+    }
+
+    // Resolves an expression evaluated on its own, outside of any statement.
+    pub fn resolve_standalone(mut self, expression: &Expr) -> Result<(), KadomError> {
+        self.resolve_expression(expression);
+        join(self.errors)
+    }
+
+    fn error(&mut self, token: &Token, message: String) {
+        self.errors
+            .push(Diagnostic::new(token.line(), Some(token.column()), message));
+    }
+
+    fn unused(&mut self, name: &Token) {
+        if name.lexeme().starts_with('_') {
+            return;
+        }
+        self.warnings.push(Diagnostic::warning(
+            name.line(),
+            Some(name.column()),
+            format!("variable \'{}\' is never used", name.lexeme()),
+        ));
+    }
+
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Assert {
+                expression,
+                message,
+                ..
+            } => {
+                self.resolve_expression(expression);
+                if let Some(message) = message {
+                    self.resolve_expression(message);
+                }
+            }
+            Stmt::Block { statements, .. } => self.resolve_scoped(statements),
+            Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Import { .. } => (),
+            Stmt::Class { name, methods, .. } => {
+                self.declare(name);
+                self.define(name);
+
+                let in_class = std::mem::replace(&mut self.in_class, true);
+                self.begin_scope();
+                self.declare_name("this");
+                for method in methods {
+                    self.resolve_function(method, FunctionType::Method);
+                }
+                self.end_scope();
+                self.in_class = in_class;
+            }
+            Stmt::DoWhile {
+                body, condition, ..
+            } => {
+                self.resolve_statement(body);
+                self.resolve_expression(condition);
+            }
+            Stmt::Expression { expression, .. } | Stmt::Print { expression, .. } => {
+                self.resolve_expression(expression)
+            }
+            Stmt::Function { declaration, .. } => {
+                self.declare(&declaration.name);
+                self.define(&declaration.name);
+                self.resolve_function(declaration, FunctionType::Function);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            Stmt::Return { keyword, value } => {
+                if self.function == FunctionType::None {
+                    self.error(keyword, "Cannot return from top-level code".to_string());
+                }
+                if let Some(value) = value {
+                    self.resolve_expression(value);
+                }
+            }
+            Stmt::Switch {
+                subject,
+                cases,
+                default,
+                ..
+            } => {
+                self.resolve_expression(subject);
+                for (value, body) in cases {
+                    self.resolve_expression(value);
+                    self.resolve_scoped(body);
+                }
+                if let Some(body) = default {
+                    self.resolve_scoped(body);
+                }
+            }
+            Stmt::Throw { value, .. } => self.resolve_expression(value),
+            Stmt::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => {
+                self.resolve_scoped(body);
+
+                // The caught value and the handler's declarations share one scope.
+                self.begin_scope();
+                self.declare(name);
+                self.define(name);
+                self.resolve_statements(handler);
+                self.end_scope();
+            }
+            Stmt::Var {
+                name, initialiser, ..
+            } => {
+                self.declare_variable(name);
+                if let Some(initialiser) = initialiser {
+                    self.resolve_expression(initialiser);
+                }
+                self.define(name);
+            }
+            Stmt::VarDestructure {
+                names, initialiser, ..
+            } => {
+                self.resolve_expression(initialiser);
+                for name in names.iter().filter(|name| name.lexeme() != "_") {
+                    self.declare_variable(name);
+                    self.define(name);
+                }
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(body);
+            }
+        }
+    }
+
+    fn resolve_expression(&mut self, expression: &Expr) {
+        match expression {
+            Expr::Assign { name, value, local } => {
+                self.resolve_expression(value);
+                self.resolve_local(name, local, false);
+            }
+            Expr::Binary { left, right, .. } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            Expr::Call {
+                callee,
+                arguments,
+                scopes,
+                ..
+            } => {
+                self.resolve_expression(callee);
+                for argument in arguments {
+                    self.resolve_expression(argument);
+                }
+                // eval() runs its source where it's called, so it needs the names of the locals.
+                // Whether the global is still the native is only known when the call is made.
+                if let Expr::Variable { name, local } = callee.as_ref() {
+                    if local.get().is_none()
+                        && natives::sees_locals(name.lexeme())
+                        && !self.scopes.is_empty()
+                    {
+                        scopes.replace(Some(Rc::new(self.scope_names())));
+                    }
+                }
+            }
+            Expr::Get { object, .. } => self.resolve_expression(object),
+            Expr::Grouping { expression } => self.resolve_expression(expression),
+            Expr::List { elements } => {
+                for element in elements {
+                    self.resolve_expression(element);
+                }
+            }
+            Expr::Literal { .. } => (),
+            Expr::Map { entries } => {
+                for (key, value) in entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            }
+            Expr::Set { object, value, .. } => {
+                self.resolve_expression(value);
+                self.resolve_expression(object);
+            }
+            Expr::This { keyword, local } => {
+                if !self.in_class {
+                    self.error(
+                        keyword,
+                        "Cannot use \'this\' outside of a class".to_string(),
+                    );
+                    return;
+                }
+                self.resolve_local(keyword, local, true);
+            }
+            Expr::Unary { right, .. } => self.resolve_expression(right),
+            Expr::Variable { name, local } => {
+                let binding = self
+                    .scopes
+                    .last()
+                    .and_then(|scope| scope.bindings.get(name.lexeme()));
+                if let Some(Binding { defined: false, .. }) = binding {
+                    let message = format!(
+                        "Cannot read local variable \'{}\' in its own initialiser",
+                        name.lexeme()
+                    );
+                    self.error(name, message);
+                }
+                self.resolve_local(name, local, true);
+            }
+        }
+    }
+
+    // Parameters and the body share one scope, matching the environment a call runs in.
+    fn resolve_function(&mut self, declaration: &FunctionDeclaration, function: FunctionType) {
+        let enclosing = std::mem::replace(&mut self.function, function);
+        self.begin_scope();
+
+        for param in &declaration.params {
+            if let Some(default) = &param.default {
+                self.resolve_expression(default);
+            }
+            self.declare(&param.name);
+            self.define(&param.name);
+        }
+        if let Some(rest) = &declaration.rest {
+            self.declare(rest);
+            self.define(rest);
+        }
+        self.resolve_statements(&declaration.body);
+
+        self.end_scope();
+        self.function = enclosing;
+    }
+
+    fn resolve_scoped(&mut self, statements: &[Stmt]) {
+        self.begin_scope();
+        self.resolve_statements(statements);
+        self.end_scope();
+    }
+
+    // Assignments locate their variable without reading it.
+    fn resolve_local(&mut self, name: &Token, local: &Cell<Option<Local>>, read: bool) {
+        let found = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                let binding = scope.bindings.get_mut(name.lexeme())?;
+                binding.read |= read;
+                Some(Local {
+                    depth,
+                    slot: binding.slot,
+                })
+            });
+        if found.is_none() && read {
+            self.global_reads.insert(name.lexeme().to_string());
+        }
+        local.set(found);
+    }
+
+    fn scope_names(&self) -> ScopeNames {
+        self.scopes
+            .iter()
+            .map(|scope| {
+                scope
+                    .bindings
+                    .iter()
+                    .map(|(name, binding)| (name.clone(), binding.slot))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    fn end_scope(&mut self) {
+        if let Some(scope) = self.scopes.pop() {
+            for binding in scope.bindings.into_values() {
+                self.check_read(binding);
+            }
+        }
+    }
+
+    fn check_read(&mut self, binding: Binding) {
+        if let (Some(name), false) = (&binding.declaration, binding.read) {
+            self.unused(name);
+        }
+    }
+
+    fn declare(&mut self, name: &Token) {
+        self.check_global(name);
+        self.declare_name(name.lexeme());
+    }
+
+    // Declares a name from a `var`, which is warned about if it's never read.
+    fn declare_variable(&mut self, name: &Token) {
+        if self.scopes.is_empty() {
+            self.check_global(name);
+            return self.globals.push(name.clone());
+        }
+        self.declare_name(name.lexeme());
+        if let Some(binding) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.bindings.get_mut(name.lexeme()))
+        {
+            binding.declaration = Some(name.clone());
+        }
+    }
+
+    // A top-level declaration would replace a built-in constant. Locals can shadow them.
+    fn check_global(&mut self, name: &Token) {
+        if self.scopes.is_empty() && natives::is_constant(name.lexeme()) {
+            self.error(
+                name,
+                format!("Cannot redeclare built-in constant \'{}\'", name.lexeme()),
+            );
+        }
+    }
+
+    fn declare_name(&mut self, name: &str) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        let binding = Binding {
+            slot: scope.slots,
+            defined: false,
+            read: false,
+            declaration: None,
+        };
+        scope.slots += 1;
+        // A redeclaration hides the earlier variable for good.
+        if let Some(hidden) = scope.bindings.insert(name.to_string(), binding) {
+            self.check_read(hidden);
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(binding) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.bindings.get_mut(name.lexeme()))
+        {
+            binding.defined = true;
+        }
+    }
+}
+
+fn join(errors: Vec<Diagnostic>) -> Result<(), KadomError> {
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(KadomError::from_diagnostics(errors, KadomError::resolve)),
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Scanner, parser::Parser};
+
+    fn resolve(source: &str) -> Result<(), KadomError> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens()?;
+        let statements = Parser::new(tokens).parse()?;
+        Resolver::new().resolve(&statements)
+    }
+
+    #[test]
+    fn local_read_in_own_initialiser_errors() {
+        assert_eq!(
+            resolve("{\n var a = a; }"),
+            Err(KadomError::Resolve {
+                line: 2,
+                message: "Cannot read local variable 'a' in its own initialiser".to_string()
+            })
+        );
+        assert!(resolve("var a = 1; { var b = a; }").is_ok());
+    }
+
+    #[test]
+    fn constants_cannot_be_redeclared_globally() {
+        assert_eq!(
+            resolve("var PI = 3;"),
+            Err(KadomError::Resolve {
+                line: 1,
+                message: "Cannot redeclare built-in constant 'PI'".to_string()
+            })
+        );
+        assert!(resolve("fun INFINITY() {}").is_err());
+        assert!(resolve("{ var PI = 3; } fun f(E) { var NAN; }").is_ok());
+    }
+
+    #[test]
+    fn return_outside_function_errors() {
+        assert_eq!(
+            resolve("return 1;"),
+            Err(KadomError::Resolve {
+                line: 1,
+                message: "Cannot return from top-level code".to_string()
+            })
+        );
+        assert!(resolve("fun f() { return 1; }").is_ok());
+    }
+
+    #[test]
+    fn this_outside_class_errors() {
+        assert_eq!(
+            resolve("fun f() { return this; }"),
+            Err(KadomError::Resolve {
+                line: 1,
+                message: "Cannot use 'this' outside of a class".to_string()
+            })
+        );
+        assert!(resolve("class A { get() { return this; } }").is_ok());
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let Err(KadomError::Multiple(errors)) = resolve("return;\nprint this;") else {
+            panic!("expected both errors");
+        };
+        let lines: Vec<u64> = errors.iter().map(|err| err.line()).collect();
+        assert_eq!(lines, [1, 2]);
+    }
+
+    fn warnings(source: &str) -> Vec<String> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        Resolver::new()
+            .warnings(&statements)
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect()
+    }
+
+    #[test]
+    fn warns_about_unused_locals() {
+        assert_eq!(
+            warnings(
+                "fun f() {\n  var used = 1;\n  {\n    var temp = 2;\n  }\n  return used;\n}\nf();"
+            ),
+            ["variable 'temp' is never used"]
+        );
+        assert_eq!(
+            warnings("{ var a = 1; var a = 2; print a; }\n{ var [x, y] = [1, 2]; print x; }"),
+            ["variable 'a' is never used", "variable 'y' is never used",]
+        );
+    }
+
+    #[test]
+    fn warns_about_unused_globals() {
+        assert_eq!(
+            warnings("var total = 0;\nvar count = 1;\nprint count;"),
+            ["variable 'total' is never used"]
+        );
+        // A function may read a global declared after it.
+        assert!(warnings("fun show() { print later; }\nvar later = 1;\nshow();").is_empty());
+    }
+
+    #[test]
+    fn assignment_alone_is_not_a_use() {
+        assert_eq!(
+            warnings("var a = 1;\na = 2;\nfun f() { var b; b = 3; }"),
+            ["variable 'a' is never used", "variable 'b' is never used",]
+        );
+        assert!(warnings(
+            "fun counter() { var n = 0; fun next() { n = n + 1; } return next; }\nprint counter;"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn underscore_names_are_exempt() {
+        assert!(warnings("var _ignored = 1;\n{ var _temp = 2; }").is_empty());
+    }
+}
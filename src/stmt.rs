@@ -1,10 +1,408 @@
-use crate::{
-    expr::Expr,
-    lexer::{Token, TokenType},
-};
+use crate::{expr::Expr, lexer::Token};
+use std::{fmt, rc::Rc};
 
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parameter {
+    pub name: Token,
+    pub default: Option<Expr>,
+}
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionDeclaration {
+    pub name: Token,
+    pub params: Vec<Parameter>,
+    pub rest: Option<Token>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Stmt {
-    Expression { expression: Expr },
-    Print { expression: Expr },
-    Var { name: Token, initialiser: Expr },
+    Assert {
+        keyword: Token,
+        expression: Expr,
+        message: Option<Expr>,
+    },
+    Block {
+        statements: Vec<Stmt>,
+        line: u64,
+    },
+    Break {
+        line: u64,
+    },
+    Class {
+        name: Token,
+        methods: Vec<Rc<FunctionDeclaration>>,
+        line: u64,
+    },
+    Continue {
+        line: u64,
+    },
+    DoWhile {
+        body: Box<Stmt>,
+        condition: Expr,
+        line: u64,
+    },
+    Expression {
+        expression: Expr,
+        line: u64,
+    },
+    Function {
+        declaration: Rc<FunctionDeclaration>,
+        line: u64,
+    },
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+        line: u64,
+    },
+    Import {
+        path: Token,
+        line: u64,
+    },
+    Print {
+        keyword: Token,
+        expression: Expr,
+    },
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+    },
+    Switch {
+        subject: Expr,
+        cases: Vec<(Expr, Vec<Stmt>)>,
+        default: Option<Vec<Stmt>>,
+        line: u64,
+    },
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    Try {
+        body: Vec<Stmt>,
+        name: Token,
+        handler: Vec<Stmt>,
+        line: u64,
+    },
+    Var {
+        name: Token,
+        initialiser: Option<Expr>,
+        line: u64,
+    },
+    VarDestructure {
+        bracket: Token,
+        names: Vec<Token>,
+        initialiser: Expr,
+        line: u64,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+        line: u64,
+    },
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.default {
+            Some(default) => write!(f, "(= {} {})", self.name.lexeme(), default),
+            None => write!(f, "{}", self.name.lexeme()),
+        }
+    }
+}
+
+impl fmt::Display for FunctionDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(fun {} (", self.name.lexeme())?;
+        let rest = self.rest.iter().map(|rest| format!("...{}", rest.lexeme()));
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|p| p.to_string())
+            .chain(rest)
+            .collect();
+        write!(f, "{})", params.join(" "))?;
+        write_all(f, &self.body)?;
+        write!(f, ")")
+    }
+}
+
+// One method per Stmt variant, called by Stmt::accept with that variant's fields. Like
+// ExprVisitor there are no default methods, so a new statement kind has to be handled everywhere.
+pub trait StmtVisitor<R> {
+    fn visit_assert(&mut self, keyword: &Token, expression: &Expr, message: Option<&Expr>) -> R;
+    fn visit_block(&mut self, statements: &[Stmt]) -> R;
+    fn visit_break(&mut self) -> R;
+    fn visit_class(&mut self, name: &Token, methods: &[Rc<FunctionDeclaration>]) -> R;
+    fn visit_continue(&mut self) -> R;
+    fn visit_do_while(&mut self, body: &Stmt, condition: &Expr) -> R;
+    fn visit_expression(&mut self, expression: &Expr) -> R;
+    fn visit_function(&mut self, declaration: &Rc<FunctionDeclaration>) -> R;
+    fn visit_if(&mut self, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) -> R;
+    fn visit_import(&mut self, path: &Token) -> R;
+    fn visit_print(&mut self, keyword: &Token, expression: &Expr) -> R;
+    fn visit_return(&mut self, keyword: &Token, value: Option<&Expr>) -> R;
+    fn visit_switch(
+        &mut self,
+        subject: &Expr,
+        cases: &[(Expr, Vec<Stmt>)],
+        default: Option<&[Stmt]>,
+    ) -> R;
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> R;
+    fn visit_try(&mut self, body: &[Stmt], name: &Token, handler: &[Stmt]) -> R;
+    fn visit_var(&mut self, name: &Token, initialiser: Option<&Expr>) -> R;
+    fn visit_var_destructure(&mut self, bracket: &Token, names: &[Token], initialiser: &Expr) -> R;
+    fn visit_while(&mut self, condition: &Expr, body: &Stmt) -> R;
+}
+
+impl Stmt {
+    // The line the statement starts on. Statements that open with a keyword token keep that;
+    // the rest carry the line of their first token.
+    pub fn line(&self) -> u64 {
+        match self {
+            Self::Assert { keyword, .. }
+            | Self::Print { keyword, .. }
+            | Self::Return { keyword, .. }
+            | Self::Throw { keyword, .. } => keyword.line(),
+            Self::Block { line, .. }
+            | Self::Break { line }
+            | Self::Class { line, .. }
+            | Self::Continue { line }
+            | Self::DoWhile { line, .. }
+            | Self::Expression { line, .. }
+            | Self::Function { line, .. }
+            | Self::If { line, .. }
+            | Self::Import { line, .. }
+            | Self::Switch { line, .. }
+            | Self::Try { line, .. }
+            | Self::Var { line, .. }
+            | Self::VarDestructure { line, .. }
+            | Self::While { line, .. } => *line,
+        }
+    }
+
+    // The token that locates the statement, for the kinds that have one.
+    pub(crate) fn token(&self) -> Option<&Token> {
+        match self {
+            Self::Assert { keyword, .. }
+            | Self::Print { keyword, .. }
+            | Self::Return { keyword, .. }
+            | Self::Throw { keyword, .. } => Some(keyword),
+            Self::Class { name, .. } | Self::Try { name, .. } | Self::Var { name, .. } => {
+                Some(name)
+            }
+            Self::Function { declaration, .. } => Some(&declaration.name),
+            Self::Import { path, .. } => Some(path),
+            Self::VarDestructure { bracket, .. } => Some(bracket),
+            _ => None,
+        }
+    }
+
+    pub fn accept<R>(&self, visitor: &mut dyn StmtVisitor<R>) -> R {
+        match self {
+            Self::Assert {
+                keyword,
+                expression,
+                message,
+            } => visitor.visit_assert(keyword, expression, message.as_ref()),
+            Self::Block { statements, .. } => visitor.visit_block(statements),
+            Self::Break { .. } => visitor.visit_break(),
+            Self::Class { name, methods, .. } => visitor.visit_class(name, methods),
+            Self::Continue { .. } => visitor.visit_continue(),
+            Self::DoWhile {
+                body, condition, ..
+            } => visitor.visit_do_while(body, condition),
+            Self::Expression { expression, .. } => visitor.visit_expression(expression),
+            Self::Function { declaration, .. } => visitor.visit_function(declaration),
+            Self::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => visitor.visit_if(condition, then_branch, else_branch.as_deref()),
+            Self::Import { path, .. } => visitor.visit_import(path),
+            Self::Print {
+                keyword,
+                expression,
+            } => visitor.visit_print(keyword, expression),
+            Self::Return { keyword, value } => visitor.visit_return(keyword, value.as_ref()),
+            Self::Switch {
+                subject,
+                cases,
+                default,
+                ..
+            } => visitor.visit_switch(subject, cases, default.as_deref()),
+            Self::Throw { keyword, value } => visitor.visit_throw(keyword, value),
+            Self::Try {
+                body,
+                name,
+                handler,
+                ..
+            } => visitor.visit_try(body, name, handler),
+            Self::Var {
+                name, initialiser, ..
+            } => visitor.visit_var(name, initialiser.as_ref()),
+            Self::VarDestructure {
+                bracket,
+                names,
+                initialiser,
+                ..
+            } => visitor.visit_var_destructure(bracket, names, initialiser),
+            Self::While {
+                condition, body, ..
+            } => visitor.visit_while(condition, body),
+        }
+    }
+}
+
+// Writes a statement in S-expression form.
+struct StmtPrinter<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+}
+
+impl StmtVisitor<fmt::Result> for StmtPrinter<'_, '_> {
+    fn visit_assert(
+        &mut self,
+        _: &Token,
+        expression: &Expr,
+        message: Option<&Expr>,
+    ) -> fmt::Result {
+        match message {
+            Some(message) => write!(self.f, "(assert {} {})", expression, message),
+            None => write!(self.f, "(assert {})", expression),
+        }
+    }
+
+    fn visit_block(&mut self, statements: &[Stmt]) -> fmt::Result {
+        write!(self.f, "(block")?;
+        write_all(self.f, statements)?;
+        write!(self.f, ")")
+    }
+
+    fn visit_break(&mut self) -> fmt::Result {
+        write!(self.f, "(break)")
+    }
+
+    fn visit_class(&mut self, name: &Token, methods: &[Rc<FunctionDeclaration>]) -> fmt::Result {
+        write!(self.f, "(class {}", name.lexeme())?;
+        for method in methods {
+            write!(self.f, " {}", method)?;
+        }
+        write!(self.f, ")")
+    }
+
+    fn visit_continue(&mut self) -> fmt::Result {
+        write!(self.f, "(continue)")
+    }
+
+    fn visit_do_while(&mut self, body: &Stmt, condition: &Expr) -> fmt::Result {
+        write!(self.f, "(do {} {})", body, condition)
+    }
+
+    fn visit_expression(&mut self, expression: &Expr) -> fmt::Result {
+        write!(self.f, "(; {})", expression)
+    }
+
+    fn visit_function(&mut self, declaration: &Rc<FunctionDeclaration>) -> fmt::Result {
+        write!(self.f, "{}", declaration)
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> fmt::Result {
+        match else_branch {
+            Some(else_branch) => {
+                write!(self.f, "(if {} {} {})", condition, then_branch, else_branch)
+            }
+            None => write!(self.f, "(if {} {})", condition, then_branch),
+        }
+    }
+
+    fn visit_import(&mut self, path: &Token) -> fmt::Result {
+        write!(self.f, "(import {})", path.lexeme())
+    }
+
+    fn visit_print(&mut self, _: &Token, expression: &Expr) -> fmt::Result {
+        write!(self.f, "(print {})", expression)
+    }
+
+    fn visit_return(&mut self, _: &Token, value: Option<&Expr>) -> fmt::Result {
+        match value {
+            Some(value) => write!(self.f, "(return {})", value),
+            None => write!(self.f, "(return)"),
+        }
+    }
+
+    fn visit_switch(
+        &mut self,
+        subject: &Expr,
+        cases: &[(Expr, Vec<Stmt>)],
+        default: Option<&[Stmt]>,
+    ) -> fmt::Result {
+        write!(self.f, "(switch {}", subject)?;
+        for (value, body) in cases {
+            write!(self.f, " (case {}", value)?;
+            write_all(self.f, body)?;
+            write!(self.f, ")")?;
+        }
+        if let Some(default) = default {
+            write!(self.f, " (default")?;
+            write_all(self.f, default)?;
+            write!(self.f, ")")?;
+        }
+        write!(self.f, ")")
+    }
+
+    fn visit_throw(&mut self, _: &Token, value: &Expr) -> fmt::Result {
+        write!(self.f, "(throw {})", value)
+    }
+
+    fn visit_try(&mut self, body: &[Stmt], name: &Token, handler: &[Stmt]) -> fmt::Result {
+        write!(self.f, "(try (block")?;
+        write_all(self.f, body)?;
+        write!(self.f, ") (catch {}", name.lexeme())?;
+        write_all(self.f, handler)?;
+        write!(self.f, "))")
+    }
+
+    fn visit_var(&mut self, name: &Token, initialiser: Option<&Expr>) -> fmt::Result {
+        match initialiser {
+            Some(initialiser) => write!(self.f, "(var {} {})", name.lexeme(), initialiser),
+            None => write!(self.f, "(var {})", name.lexeme()),
+        }
+    }
+
+    fn visit_var_destructure(
+        &mut self,
+        _: &Token,
+        names: &[Token],
+        initialiser: &Expr,
+    ) -> fmt::Result {
+        let names: Vec<&str> = names.iter().map(|name| name.lexeme()).collect();
+        write!(self.f, "(var [{}] {})", names.join(" "), initialiser)
+    }
+
+    fn visit_while(&mut self, condition: &Expr, body: &Stmt) -> fmt::Result {
+        write!(self.f, "(while {} {})", condition, body)
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.accept(&mut StmtPrinter { f })
+    }
+}
+
+// Writes each statement preceded by a space, for the bodies of compound forms.
+fn write_all(f: &mut fmt::Formatter, statements: &[Stmt]) -> fmt::Result {
+    for statement in statements {
+        write!(f, " {}", statement)?;
+    }
+    Ok(())
 }
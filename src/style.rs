@@ -0,0 +1,76 @@
+use std::{
+    env,
+    io::{stderr, IsTerminal},
+};
+
+// How messages for people are dressed up: in ANSI colours for a terminal, or left as they are.
+// Only diagnostics go through this. Program output is never coloured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    color: bool,
+}
+
+impl Style {
+    pub const PLAIN: Self = Self { color: false };
+    pub const COLOR: Self = Self { color: true };
+
+    // Colour when stderr is a terminal, unless `no_color` is set or the NO_COLOR environment
+    // variable is (https://no-color.org).
+    pub fn for_stderr(no_color: bool) -> Self {
+        let no_color_env = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Self::detect(stderr().is_terminal(), no_color || no_color_env)
+    }
+
+    pub fn detect(is_terminal: bool, no_color: bool) -> Self {
+        Self {
+            color: is_terminal && !no_color,
+        }
+    }
+
+    pub fn is_color(self) -> bool {
+        self.color
+    }
+
+    // Bold red.
+    pub fn error(self, text: &str) -> String {
+        self.paint("1;31", text)
+    }
+
+    // Bold yellow.
+    pub fn warning(self, text: &str) -> String {
+        self.paint("1;33", text)
+    }
+
+    // Bold blue, for the frame around quoted source.
+    pub fn frame(self, text: &str) -> String {
+        self.paint("1;34", text)
+    }
+
+    fn paint(self, code: &str, text: &str) -> String {
+        match self.color {
+            true => format!("\x1b[{}m{}\x1b[0m", code, text),
+            false => text.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours_only_for_a_terminal_that_allows_it() {
+        assert!(Style::detect(true, false).is_color());
+        assert!(!Style::detect(false, false).is_color());
+        assert!(!Style::detect(true, true).is_color());
+        assert!(!Style::detect(false, true).is_color());
+    }
+
+    #[test]
+    fn plain_text_is_left_alone() {
+        assert_eq!(Style::PLAIN.error("error"), "error");
+        assert_eq!(Style::PLAIN.warning("warning"), "warning");
+        assert_eq!(Style::COLOR.error("error"), "\x1b[1;31merror\x1b[0m");
+        assert_eq!(Style::COLOR.warning("warning"), "\x1b[1;33mwarning\x1b[0m");
+    }
+}
@@ -0,0 +1,405 @@
+use crate::{
+    compiler::{Chunk, Op},
+    environment::Environment,
+    error::RuntimeError,
+    expr::{self, LiteralValue, LiteralValue::Number},
+    interpreter::{self, Interpreter},
+    lexer::Token,
+};
+use std::{cell::RefCell, rc::Rc};
+
+// Runs a chunk in the interpreter's current environment. Returns the value a `return` gave, or
+// None if the chunk ran to its end. The environment is the same afterwards, even on an error.
+pub fn run(
+    interpreter: &mut Interpreter,
+    chunk: &Chunk,
+) -> Result<Option<LiteralValue>, RuntimeError> {
+    let start = interpreter.environment.clone();
+    let mut vm = Vm {
+        stack: Vec::new(),
+        scopes: Vec::new(),
+        handlers: Vec::new(),
+    };
+    let result = vm.run(interpreter, chunk);
+    interpreter.environment = start;
+    result
+}
+
+// A `try` in progress: where its catch starts and the state to unwind to before running it.
+struct Handler {
+    catch: usize,
+    environment: Rc<RefCell<Environment>>,
+    scopes: usize,
+    stack: usize,
+}
+
+struct Vm {
+    stack: Vec<LiteralValue>,
+    // The environments that scopes entered by this chunk replaced, innermost last.
+    scopes: Vec<Rc<RefCell<Environment>>>,
+    handlers: Vec<Handler>,
+}
+
+impl Vm {
+    fn run(
+        &mut self,
+        interpreter: &mut Interpreter,
+        chunk: &Chunk,
+    ) -> Result<Option<LiteralValue>, RuntimeError> {
+        let mut ip = 0;
+        while let Some(&op) = chunk.code.get(ip) {
+            ip += 1;
+            if op == Op::Return {
+                return Ok(self.stack.pop());
+            }
+
+            if let Err(err) = self.execute(interpreter, chunk, op, &mut ip) {
+                let Some(handler) = self.handlers.pop().filter(|_| err.exit.is_none()) else {
+                    return Err(err);
+                };
+                interpreter.environment = handler.environment;
+                self.scopes.truncate(handler.scopes);
+                self.stack.truncate(handler.stack);
+                self.stack.push(err.value());
+                ip = handler.catch;
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn pop(&mut self) -> LiteralValue {
+        self.stack.pop().expect("the compiler balances the stack")
+    }
+
+    // Pops the top `count` values, oldest first.
+    fn pop_many(&mut self, count: usize) -> Vec<LiteralValue> {
+        self.stack.split_off(self.stack.len() - count)
+    }
+
+    #[inline(always)]
+    // Replaces the top two values with the result of a binary operator. Two numbers go to `numbers`
+    // first; anything it doesn't handle is left to the shared implementation.
+    fn binary(
+        &mut self,
+        interpreter: &mut Interpreter,
+        operator: &Token,
+        numbers: impl Fn(f64, f64) -> Option<LiteralValue>,
+    ) -> Result<(), RuntimeError> {
+        let right = self.pop();
+        let left = self.pop();
+        if let (Number(x), Number(y)) = (&left, &right) {
+            if let Some(value) = numbers(*x, *y) {
+                self.stack.push(value);
+                return Ok(());
+            }
+        }
+
+        self.stack
+            .push(expr::operate(interpreter, left, operator, right)?);
+        Ok(())
+    }
+
+    fn execute(
+        &mut self,
+        interpreter: &mut Interpreter,
+        chunk: &Chunk,
+        op: Op,
+        ip: &mut usize,
+    ) -> Result<(), RuntimeError> {
+        let tokens = &chunk.tokens;
+        if interpreter.is_limited() {
+            interpreter.step(op.token().map(|index| &tokens[index]))?;
+        }
+
+        match op {
+            Op::Constant(index) => self.stack.push(chunk.constants[index].clone()),
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Get { name, local } => {
+                let value = interpreter.look_up_variable(&tokens[name], local)?;
+                self.stack.push(value);
+            }
+            Op::Set { name, local } => {
+                let value = self.stack.last().cloned().unwrap_or(LiteralValue::Nil);
+                interpreter.assign_variable(&tokens[name], local, value)?;
+            }
+            Op::Define(name) => {
+                let value = self.pop();
+                interpreter
+                    .environment
+                    .borrow_mut()
+                    .define(tokens[name].lexeme().to_string(), value);
+            }
+            Op::DefineUninitialized(name) => interpreter
+                .environment
+                .borrow_mut()
+                .define_uninitialized(tokens[name].lexeme().to_string()),
+            Op::Destructure {
+                bracket,
+                names,
+                count,
+            } => {
+                let value = self.pop();
+                interpreter.destructure(&tokens[bracket], &tokens[names..names + count], value)?;
+            }
+            Op::Unary(operator) => {
+                let right = self.pop();
+                self.stack.push(expr::unary(&tokens[operator], right)?);
+            }
+            Op::Add(operator) => {
+                self.binary(interpreter, &tokens[operator], |x, y| Some(Number(x + y)))?
+            }
+            Op::Subtract(operator) => {
+                self.binary(interpreter, &tokens[operator], |x, y| Some(Number(x - y)))?
+            }
+            Op::Multiply(operator) => {
+                self.binary(interpreter, &tokens[operator], |x, y| Some(Number(x * y)))?
+            }
+            // A zero divisor leaves the error or IEEE result to the shared code.
+            Op::Divide(operator) => self.binary(interpreter, &tokens[operator], |x, y| {
+                (y != 0.0).then_some(Number(x / y))
+            })?,
+            Op::Remainder(operator) => self.binary(interpreter, &tokens[operator], |x, y| {
+                (y != 0.0).then_some(Number(x % y))
+            })?,
+            Op::Less(operator) => self.binary(interpreter, &tokens[operator], |x, y| {
+                Some(LiteralValue::from_bool(x < y))
+            })?,
+            Op::LessEqual(operator) => self.binary(interpreter, &tokens[operator], |x, y| {
+                Some(LiteralValue::from_bool(x <= y))
+            })?,
+            Op::Greater(operator) => self.binary(interpreter, &tokens[operator], |x, y| {
+                Some(LiteralValue::from_bool(x > y))
+            })?,
+            Op::GreaterEqual(operator) => self.binary(interpreter, &tokens[operator], |x, y| {
+                Some(LiteralValue::from_bool(x >= y))
+            })?,
+            Op::Equal(operator) => self.binary(interpreter, &tokens[operator], |x, y| {
+                Some(LiteralValue::from_bool(x == y))
+            })?,
+            Op::NotEqual(operator) => self.binary(interpreter, &tokens[operator], |x, y| {
+                Some(LiteralValue::from_bool(x != y))
+            })?,
+            Op::Binary(operator) => self.binary(interpreter, &tokens[operator], |_, _| None)?,
+            Op::List(count) => {
+                let items = self.pop_many(count);
+                self.stack
+                    .push(LiteralValue::List(Rc::new(RefCell::new(items))));
+            }
+            Op::Map(count) => {
+                let mut values = self.pop_many(count * 2).into_iter();
+                let mut entries = Vec::new();
+                while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                    entries.push((key, value));
+                }
+                self.stack.push(expr::map(entries));
+            }
+            Op::Call {
+                paren,
+                arguments,
+                scopes,
+            } => {
+                let arguments = self.pop_many(arguments);
+                let callee = self.pop();
+                let scopes = scopes.map(|index| chunk.scopes[index].clone());
+                let value =
+                    expr::call_expression(interpreter, callee, arguments, &tokens[paren], scopes)?;
+                self.stack.push(value);
+            }
+            Op::Property(name) => {
+                let object = self.pop();
+                self.stack.push(expr::get_property(object, &tokens[name])?);
+            }
+            Op::OptionalProperty { name, end } => match self.pop() {
+                LiteralValue::Nil => {
+                    self.stack.push(LiteralValue::Nil);
+                    *ip = end;
+                }
+                object => self.stack.push(expr::get_property(object, &tokens[name])?),
+            },
+            Op::SetTarget(name) => {
+                let object = self.pop();
+                let instance = expr::field_target(object, &tokens[name])?;
+                self.stack.push(LiteralValue::Instance(instance));
+            }
+            Op::SetProperty(name) => {
+                let value = self.pop();
+                let instance = expr::field_target(self.pop(), &tokens[name])?;
+                instance
+                    .borrow_mut()
+                    .fields
+                    .insert(tokens[name].lexeme().to_string(), value.clone());
+                self.stack.push(value);
+            }
+            Op::Jump(target) => *ip = target,
+            Op::JumpIfFalse(target) => {
+                if !self.pop().is_truthy() {
+                    *ip = target;
+                }
+            }
+            Op::JumpIfCase(target) => {
+                let value = self.pop();
+                if self.stack.last() == Some(&value) {
+                    self.pop();
+                    *ip = target;
+                }
+            }
+            Op::Print(keyword) => {
+                let value = self.pop();
+                interpreter.print(&tokens[keyword], &value)?;
+            }
+            Op::AssertFailed(keyword) => {
+                let message = self.pop().to_string();
+                return Err(interpreter::assertion_failed(&tokens[keyword], message));
+            }
+            Op::Throw(keyword) => {
+                return Err(RuntimeError::thrown(&tokens[keyword], self.pop()));
+            }
+            Op::PushScope => {
+                let environment = Environment::new_enclosed(interpreter.environment.clone());
+                let previous = std::mem::replace(
+                    &mut interpreter.environment,
+                    Rc::new(RefCell::new(environment)),
+                );
+                self.scopes.push(previous);
+            }
+            Op::PopScope => {
+                interpreter.environment = self.scopes.pop().expect("scopes are balanced");
+            }
+            Op::PushHandler(catch) => self.handlers.push(Handler {
+                catch,
+                environment: interpreter.environment.clone(),
+                scopes: self.scopes.len(),
+                stack: self.stack.len(),
+            }),
+            Op::PopHandler => {
+                self.handlers.pop();
+            }
+            Op::Function(index) => interpreter.define_function(&chunk.functions[index]),
+            Op::Class {
+                name,
+                methods,
+                count,
+            } => {
+                interpreter.define_class(&tokens[name], &chunk.functions[methods..methods + count])
+            }
+            Op::Import(path) => interpreter.import(&tokens[path])?,
+            Op::Return => unreachable!("run handles returns"),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compile,
+        interpreter::{Backend, Interpreter},
+    };
+    use std::{cell::RefCell, io::Write, rc::Rc};
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Runs the source on one backend, returning what it printed and the error it stopped with.
+    fn run(backend: Backend, source: &str) -> (String, Option<String>) {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        interpreter.backend = backend;
+        let error = interpreter
+            .interpret(compile(source).unwrap())
+            .err()
+            .map(|err| err.to_string());
+        let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        (printed, error)
+    }
+
+    fn assert_backends_agree(source: &str) -> (String, Option<String>) {
+        let vm = run(Backend::Vm, source);
+        assert_eq!(vm, run(Backend::TreeWalker, source));
+        vm
+    }
+
+    #[test]
+    fn loops_unwind_scopes_and_handlers() {
+        let (printed, error) = assert_backends_agree(
+            "var i = 0;
+             while (true) {
+                 i = i + 1;
+                 try {
+                     var j = i;
+                     if (j == 2) continue;
+                     if (j > 3) { var k = j; break; }
+                     print j;
+                 } catch (e) {}
+             }
+             do { try { throw i; } catch (e) { print e; break; } } while (true);
+             print i;",
+        );
+        assert_eq!(printed, "1\n3\n4\n4\n");
+        assert_eq!(error, None);
+    }
+
+    #[test]
+    fn closures_see_the_scopes_they_were_made_in() {
+        let (printed, _) = assert_backends_agree(
+            "var fs = [];
+             var n = 0;
+             while (n < 2) {
+                 if (true) {
+                     var m = n;
+                     fun show(extra = m * 10) { { print [m, extra]; } }
+                     fs = [show, fs];
+                 }
+                 n = n + 1;
+             }
+             var [last, earlier] = fs;
+             var [first, _] = earlier;
+             last();
+             first(5);",
+        );
+        assert_eq!(printed, "[1, 10]\n[0, 5]\n");
+
+        let (printed, _) = assert_backends_agree(
+            "fun counter() {
+                 var count = 0;
+                 { { fun next() { count = count + 1; return count; } return next; } }
+             }
+             var next = counter();
+             next();
+             print next();
+             class Box { init(v) { this.v = v; } get() { { return this.v; } } }
+             print Box(3).get();",
+        );
+        assert_eq!(printed, "2\n3\n");
+    }
+
+    #[test]
+    fn errors_match_the_tree_walker() {
+        let (_, error) =
+            assert_backends_agree("var a = 1;\nfun f() {\n  return a - \"x\";\n}\nf();");
+        assert_eq!(
+            error.unwrap(),
+            "[line 3] RuntimeError: Mismatched types for Minus: Number and String\n  at f (line 5)"
+        );
+        assert_backends_agree("var n = nil;\nprint n?.a.b;\nprint n.a;");
+        assert_backends_agree(
+            "switch (2) { case 1: print 1; case 1 + 1: print 2; default: print 3; }",
+        );
+        assert_backends_agree("var x = 1;\nx.y = undefined;");
+        assert_backends_agree("assert [1] == [2];");
+    }
+}
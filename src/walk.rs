@@ -0,0 +1,284 @@
+use crate::{
+    expr::{Expr, ExprVisitor, LiteralValue},
+    lexer::Token,
+    stmt::{FunctionDeclaration, Stmt, StmtVisitor},
+};
+use std::rc::Rc;
+
+// Called for every node walk_program reaches. Both methods do nothing by default, so a pass only
+// implements the kinds of node it cares about.
+pub trait Walker {
+    fn statement(&mut self, _statement: &Stmt) {}
+    fn expression(&mut self, _expression: &Expr) {}
+}
+
+// Walks every statement and expression in a program, including function and method bodies. Each
+// node is passed to the walker before its children, and children come in source order.
+pub fn walk_program(statements: &[Stmt], walker: &mut dyn Walker) {
+    Walk { walker }.statements(statements);
+}
+
+struct Walk<'a> {
+    walker: &'a mut dyn Walker,
+}
+
+impl Walk<'_> {
+    fn statement(&mut self, statement: &Stmt) {
+        self.walker.statement(statement);
+        statement.accept(self);
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn expression(&mut self, expression: &Expr) {
+        self.walker.expression(expression);
+        expression.accept(self);
+    }
+
+    fn expressions(&mut self, expressions: &[Expr]) {
+        for expression in expressions {
+            self.expression(expression);
+        }
+    }
+
+    fn function(&mut self, declaration: &FunctionDeclaration) {
+        for param in &declaration.params {
+            if let Some(default) = &param.default {
+                self.expression(default);
+            }
+        }
+        self.statements(&declaration.body);
+    }
+}
+
+impl StmtVisitor<()> for Walk<'_> {
+    fn visit_assert(&mut self, _: &Token, expression: &Expr, message: Option<&Expr>) {
+        self.expression(expression);
+        if let Some(message) = message {
+            self.expression(message);
+        }
+    }
+
+    fn visit_block(&mut self, statements: &[Stmt]) {
+        self.statements(statements);
+    }
+
+    fn visit_break(&mut self) {}
+
+    fn visit_class(&mut self, _: &Token, methods: &[Rc<FunctionDeclaration>]) {
+        for method in methods {
+            self.function(method);
+        }
+    }
+
+    fn visit_continue(&mut self) {}
+
+    fn visit_do_while(&mut self, body: &Stmt, condition: &Expr) {
+        self.statement(body);
+        self.expression(condition);
+    }
+
+    fn visit_expression(&mut self, expression: &Expr) {
+        self.expression(expression);
+    }
+
+    fn visit_function(&mut self, declaration: &Rc<FunctionDeclaration>) {
+        self.function(declaration);
+    }
+
+    fn visit_if(&mut self, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) {
+        self.expression(condition);
+        self.statement(then_branch);
+        if let Some(else_branch) = else_branch {
+            self.statement(else_branch);
+        }
+    }
+
+    fn visit_import(&mut self, _: &Token) {}
+
+    fn visit_print(&mut self, _: &Token, expression: &Expr) {
+        self.expression(expression);
+    }
+
+    fn visit_return(&mut self, _: &Token, value: Option<&Expr>) {
+        if let Some(value) = value {
+            self.expression(value);
+        }
+    }
+
+    fn visit_switch(
+        &mut self,
+        subject: &Expr,
+        cases: &[(Expr, Vec<Stmt>)],
+        default: Option<&[Stmt]>,
+    ) {
+        self.expression(subject);
+        for (value, body) in cases {
+            self.expression(value);
+            self.statements(body);
+        }
+        if let Some(default) = default {
+            self.statements(default);
+        }
+    }
+
+    fn visit_throw(&mut self, _: &Token, value: &Expr) {
+        self.expression(value);
+    }
+
+    fn visit_try(&mut self, body: &[Stmt], _: &Token, handler: &[Stmt]) {
+        self.statements(body);
+        self.statements(handler);
+    }
+
+    fn visit_var(&mut self, _: &Token, initialiser: Option<&Expr>) {
+        if let Some(initialiser) = initialiser {
+            self.expression(initialiser);
+        }
+    }
+
+    fn visit_var_destructure(&mut self, _: &Token, _: &[Token], initialiser: &Expr) {
+        self.expression(initialiser);
+    }
+
+    fn visit_while(&mut self, condition: &Expr, body: &Stmt) {
+        self.expression(condition);
+        self.statement(body);
+    }
+}
+
+impl ExprVisitor<()> for Walk<'_> {
+    fn visit_assign(&mut self, _: &Token, value: &Expr) {
+        self.expression(value);
+    }
+
+    fn visit_binary(&mut self, left: &Expr, _: &Token, right: &Expr) {
+        self.expression(left);
+        self.expression(right);
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _: &Token, arguments: &[Expr]) {
+        self.expression(callee);
+        self.expressions(arguments);
+    }
+
+    fn visit_get(&mut self, object: &Expr, _: &Token, _: bool) {
+        self.expression(object);
+    }
+
+    fn visit_grouping(&mut self, expression: &Expr) {
+        self.expression(expression);
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) {
+        self.expressions(elements);
+    }
+
+    fn visit_literal(&mut self, _: &LiteralValue) {}
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) {
+        for (key, value) in entries {
+            self.expression(key);
+            self.expression(value);
+        }
+    }
+
+    fn visit_set(&mut self, object: &Expr, _: &Token, value: &Expr) {
+        self.expression(object);
+        self.expression(value);
+    }
+
+    fn visit_this(&mut self, _: &Token) {}
+
+    fn visit_unary(&mut self, _: &Token, right: &Expr) {
+        self.expression(right);
+    }
+
+    fn visit_variable(&mut self, _: &Token) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+
+    // Records a short label for every node in the order it's reached.
+    #[derive(Default)]
+    struct Trace(Vec<String>);
+
+    impl Walker for Trace {
+        fn statement(&mut self, statement: &Stmt) {
+            let label = statement.to_string();
+            let head = label.split_whitespace().next().unwrap_or_default();
+            self.0.push(head.trim_end_matches(')').to_string());
+        }
+
+        fn expression(&mut self, expression: &Expr) {
+            self.0.push(expression.to_string());
+        }
+    }
+
+    fn trace(source: &str) -> Vec<String> {
+        let mut trace = Trace::default();
+        walk_program(&compile(source).unwrap(), &mut trace);
+        trace.0
+    }
+
+    #[test]
+    fn walks_parents_before_children_in_source_order() {
+        let source = "
+            var a = 1;
+            fun f(x, y = 2) {
+                if (x) { print x; } else return y;
+            }
+            while (a) a = a - 1;";
+        assert_eq!(
+            trace(source),
+            [
+                "(var",
+                "1",
+                "(fun",
+                "2",
+                "(if",
+                "var x",
+                "(block",
+                "(print",
+                "var x",
+                "(return",
+                "var y",
+                "(while",
+                "var a",
+                "(;",
+                "(= a (- var a 1))",
+                "(- var a 1)",
+                "var a",
+                "1",
+            ]
+        );
+    }
+
+    #[test]
+    fn walks_into_methods_and_handlers() {
+        let source = "
+            class A { get() { return this.v; } }
+            try { throw 1; } catch (e) { print e; }";
+        assert_eq!(
+            trace(source),
+            [
+                "(class",
+                "(return",
+                "(. this v)",
+                "this",
+                "(try",
+                "(throw",
+                "1",
+                "(print",
+                "var e",
+            ]
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    fs,
+    thread::sleep,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(500);
+
+// Decides when a watched file needs running again, given its modification time at each poll.
+#[derive(Default)]
+pub struct ChangeDetector {
+    last_run: Option<SystemTime>,
+}
+
+impl ChangeDetector {
+    // True on the first poll that sees the file, then whenever its modification time moves. A
+    // missing file (None) never triggers a run, so a save that replaces the file is picked up once
+    // it's back.
+    pub fn changed(&mut self, modified: Option<SystemTime>) -> bool {
+        match modified {
+            Some(modified) if self.last_run != Some(modified) => {
+                self.last_run = Some(modified);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Calls `run` whenever the file at `path` changes, polling every `interval`. Never returns; the
+// process ends with Ctrl-C.
+pub fn watch(path: &str, interval: Duration, mut run: impl FnMut()) -> ! {
+    let mut detector = ChangeDetector::default();
+    loop {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if detector.changed(modified) {
+            println!("{}", separator(SystemTime::now()));
+            run();
+        }
+        sleep(interval);
+    }
+}
+
+// A line marking the start of a run, stamped with the UTC time of day.
+fn separator(now: SystemTime) -> String {
+    let seconds = now
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let (hours, minutes, seconds) = (seconds / 3600 % 24, seconds / 60 % 60, seconds % 60);
+    format!(
+        "---- run at {:02}:{:02}:{:02} UTC ----",
+        hours, minutes, seconds
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: u64) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn runs_on_first_sight_and_after_each_change() {
+        let mut detector = ChangeDetector::default();
+        assert!(detector.changed(at(10)));
+        assert!(!detector.changed(at(10)));
+        assert!(!detector.changed(at(10)));
+        assert!(detector.changed(at(12)));
+        assert!(!detector.changed(at(12)));
+        // Restoring an older file counts as a change too.
+        assert!(detector.changed(at(11)));
+    }
+
+    #[test]
+    fn missing_file_waits_until_it_reappears() {
+        let mut detector = ChangeDetector::default();
+        assert!(!detector.changed(None));
+        assert!(detector.changed(at(5)));
+        assert!(!detector.changed(None));
+        assert!(!detector.changed(at(5)));
+        assert!(detector.changed(at(6)));
+    }
+
+    #[test]
+    fn separator_shows_time_of_day() {
+        let now = UNIX_EPOCH + Duration::from_secs(3 * 86400 + 13 * 3600 + 5 * 60 + 9);
+        assert_eq!(separator(now), "---- run at 13:05:09 UTC ----");
+    }
+}
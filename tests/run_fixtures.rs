@@ -0,0 +1,164 @@
+// Runs every program in tests/programs and checks it against the expectations written in its
+// comments:
+//
+//   print 1 + 2; // expect: 3
+//   // expect-error: runtime: undefined variable 'x'
+//
+// `expect` lines give the printed output in order. `expect-error` names the kind of failure,
+// compile or runtime, and a substring of its message. Programs without one must succeed.
+//
+// Each program also runs on the VM and with constant folding, which must print the same and report
+// runtime errors identically.
+
+use kadom::{
+    compile,
+    error::KadomError,
+    interpreter::{Backend, Interpreter},
+};
+use std::{cell::RefCell, fs, io::Write, path::Path, rc::Rc};
+
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Compile,
+    Runtime,
+}
+
+struct Expectations {
+    output: String,
+    error: Option<(Kind, String)>,
+}
+
+fn expectations(source: &str) -> Result<Expectations, String> {
+    let mut expected = Expectations {
+        output: String::new(),
+        error: None,
+    };
+    for line in source.lines() {
+        let Some((_, comment)) = line.split_once("//") else {
+            continue;
+        };
+        let comment = comment.trim();
+        if let Some(value) = comment.strip_prefix("expect:") {
+            expected.output.push_str(value.trim());
+            expected.output.push('\n');
+        } else if let Some(error) = comment.strip_prefix("expect-error:") {
+            let (kind, message) = error
+                .trim()
+                .split_once(':')
+                .ok_or("expect-error needs a kind, as in `expect-error: runtime: message`")?;
+            let kind = match kind {
+                "compile" => Kind::Compile,
+                "runtime" => Kind::Runtime,
+                other => return Err(format!("unknown error kind '{}'", other)),
+            };
+            expected.error = Some((kind, message.trim().to_string()));
+        }
+    }
+    Ok(expected)
+}
+
+struct Run {
+    printed: String,
+    error: Option<(Kind, String)>,
+    // The error as reported, with its line and trace.
+    report: Option<String>,
+}
+
+// Compiles and runs the program as run_source does. The kind of any failure comes from the
+// error's variant.
+fn run(source: &str, backend: Backend, optimize: bool) -> Run {
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    interpreter.backend = backend;
+    interpreter.optimize = optimize;
+    let result = compile(source).and_then(|statements| interpreter.interpret(statements));
+    let (error, report) = match result {
+        Ok(()) => (None, None),
+        Err(KadomError::Runtime(err)) => (
+            Some((Kind::Runtime, err.message.clone())),
+            Some(err.to_string()),
+        ),
+        Err(err) => (Some((Kind::Compile, err.to_string())), None),
+    };
+
+    let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+    Run {
+        printed,
+        error,
+        report,
+    }
+}
+
+fn check(path: &Path) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let expected = expectations(&source)?;
+    let tree_walker = run(&source, Backend::TreeWalker, false);
+    let variants = [
+        ("the VM", run(&source, Backend::Vm, false)),
+        ("constant folding", run(&source, Backend::TreeWalker, true)),
+    ];
+    for (variant, other) in variants {
+        if (&other.printed, &other.report) != (&tree_walker.printed, &tree_walker.report) {
+            return Err(format!(
+                "with {} it printed\n{}{:?}\nbut the tree-walker printed\n{}{:?}",
+                variant, other.printed, other.report, tree_walker.printed, tree_walker.report
+            ));
+        }
+    }
+
+    let Run { printed, error, .. } = tree_walker;
+
+    if printed != expected.output {
+        return Err(format!(
+            "printed\n{}\nbut expected\n{}",
+            printed, expected.output
+        ));
+    }
+    match (error, expected.error) {
+        (None, None) => Ok(()),
+        (Some((kind, message)), Some((expected_kind, substring)))
+            if kind == expected_kind && message.contains(&substring) =>
+        {
+            Ok(())
+        }
+        (error, expected) => Err(format!(
+            "failed with {:?} but expected {:?}",
+            error, expected
+        )),
+    }
+}
+
+#[test]
+fn fixture_programs() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut paths: Vec<_> = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "kdm"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {}", directory.display());
+
+    let failures: Vec<String> = paths
+        .iter()
+        .filter_map(|path| {
+            check(path)
+                .err()
+                .map(|err| format!("{}: {}", path.display(), err))
+        })
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
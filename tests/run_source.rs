@@ -0,0 +1,86 @@
+use kadom::{error::KadomError, interpreter::Interpreter, run_source};
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+// Output sink that can still be read after the interpreter takes it.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn runs_a_program_and_captures_its_output() {
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+
+    run_source(
+        &mut interpreter,
+        "fun greet(name) { return \"hello \" + name; }
+         class Counter {
+             init() { this.count = 0; }
+             bump() { this.count = this.count + 1; return this.count; }
+         }
+         var counter = Counter();
+         counter.bump();
+         print greet(\"kadom\");
+         print counter.bump();",
+    )
+    .unwrap();
+    run_source(&mut interpreter, "print typeof(greet);").unwrap();
+
+    let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+    assert_eq!(printed, "hello kadom\n2\nfunction\n");
+}
+
+#[test]
+fn reports_errors_by_kind() {
+    let mut interpreter = Interpreter::with_output(Box::new(std::io::sink()));
+
+    assert_eq!(
+        run_source(&mut interpreter, "print 1 +;"),
+        Err(KadomError::Parse {
+            line: 1,
+            message: "Expected expression".to_string()
+        })
+    );
+    assert!(matches!(
+        run_source(&mut interpreter, "print 1;\nvar a = @;"),
+        Err(KadomError::Scan { line: 2, .. })
+    ));
+    assert!(matches!(
+        run_source(&mut interpreter, "return 1;"),
+        Err(KadomError::Resolve { line: 1, .. })
+    ));
+
+    let err = run_source(&mut interpreter, "print missing;").unwrap_err();
+    assert!(matches!(&err, KadomError::Runtime(err) if err.line == 1));
+    assert_eq!(
+        err.to_string(),
+        "[line 1] RuntimeError: undefined variable 'missing'"
+    );
+}
+
+#[test]
+fn embedders_can_define_natives_and_read_results() {
+    let mut interpreter = Interpreter::with_output(Box::new(std::io::sink()));
+    interpreter.define_native("double", 1, |args| match &args[0] {
+        kadom::expr::LiteralValue::Number(n) => Ok(kadom::expr::LiteralValue::Number(n * 2.0)),
+        other => Err(format!(
+            "double() needs a number, got {}",
+            other.type_name()
+        )),
+    });
+
+    run_source(&mut interpreter, "var x = double(21);").unwrap();
+    assert_eq!(
+        interpreter.evaluate_expression("x").unwrap().to_string(),
+        "42"
+    );
+}